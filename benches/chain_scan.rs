@@ -0,0 +1,72 @@
+// Compares full and header-only scans of a chain saved as compact JSON, the
+// default chain file, with the same chain saved as a memory-mapped archive.
+// Run with `cargo bench --features mmap`, BENCH_BLOCKS sets the chain length.
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use blockchain_basic::params::ChainParams;
+use blockchain_basic::store::{self, ChainFormat};
+use blockchain_basic::units::BlockHeight;
+use blockchain_basic::{App, BlockBody};
+
+const DEFAULT_BLOCKS: u64 = 2_000;
+const ROUNDS: u32 = 5;
+// low enough to build the chain in a moment, retargeting is off so it stays there
+const BENCH_DIFFICULTY: u32 = 4;
+
+fn main() {
+    let blocks = std::env::var("BENCH_BLOCKS")
+        .ok()
+        .and_then(|blocks| blocks.parse().ok())
+        .unwrap_or(DEFAULT_BLOCKS);
+    let mut app = App::new(ChainParams {
+        initial_difficulty: BENCH_DIFFICULTY,
+        retarget_interval: 0,
+        ..ChainParams::default()
+    });
+    app.mine_n_blocks(blocks, |height| {
+        BlockBody::new(format!("bench block {} {}", height, "x".repeat(200)), vec![])
+    })
+    .expect("can mine bench chain");
+
+    let dir = std::env::temp_dir();
+    let json = dir.join(format!("chain-scan-{}.json", std::process::id()));
+    let archive = dir.join(format!("chain-scan-{}.archive", std::process::id()));
+    app.export_to_file(&json, ChainFormat::Compact)
+        .expect("can write json chain");
+    app.export_to_file(&archive, ChainFormat::Archive)
+        .expect("can write archive");
+
+    // the last tenth of the chain, as an audit of recent blocks would read
+    let tip = app.get_last_block().header.id;
+    let recent = BlockHeight(tip.0 - tip.0 / 10)..BlockHeight(tip.0 + 1);
+    println!("{} blocks, best of {} rounds", app.blocks.len(), ROUNDS);
+    for (name, path) in [("json", &json), ("archive", &archive)] {
+        let full = best_of(|| store::load_chain::<BlockBody>(path).map(|blocks| blocks.len()));
+        let headers = best_of(|| store::load_headers(path, recent.clone()).map(|headers| headers.len()));
+        println!(
+            "{:<8} {:>9} bytes  full scan {:>10.2?}  recent headers {:>10.2?}",
+            name,
+            file_size(path),
+            full,
+            headers
+        );
+    }
+    let _ = std::fs::remove_file(&json);
+    let _ = std::fs::remove_file(&archive);
+}
+
+fn best_of(mut scan: impl FnMut() -> Result<usize, String>) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            scan().expect("can scan chain");
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
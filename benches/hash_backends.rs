@@ -0,0 +1,70 @@
+// Compares mining hash backends, each with every hash algorithm it can run, on
+// the binary preimage of a block header. Run with `cargo bench --bench
+// hash_backends`, BENCH_BATCHES sets how many nonce batches each round hashes.
+// Backends plugged in elsewhere are added to `backends` to be compared here.
+use std::time::{Duration, Instant};
+
+use blockchain_basic::hashing::{HashAlgorithm, HashBackend, PortableBackend, NONCE_BATCH};
+use blockchain_basic::params::ChainParams;
+use blockchain_basic::target::Target;
+use blockchain_basic::{preimage, App};
+
+const DEFAULT_BATCHES: u64 = 20_000;
+const ROUNDS: u32 = 5;
+
+fn main() {
+    let batches = std::env::var("BENCH_BATCHES")
+        .ok()
+        .and_then(|batches| batches.parse().ok())
+        .unwrap_or(DEFAULT_BATCHES);
+    let app: App = App::new(ChainParams::default());
+    let mut header = app.get_last_block().header.clone();
+    header.version = preimage::BINARY_PREIMAGE;
+    let preimage = preimage::binary(&header);
+
+    let hashes = batches * NONCE_BATCH as u64;
+    println!("{} hashes per round, best of {} rounds", hashes, ROUNDS);
+    for (algorithm, backend) in backends() {
+        let elapsed = best_of(|| {
+            let mut preimage = preimage.clone();
+            let mut nonces: Vec<u64> = (0..NONCE_BATCH as u64).collect();
+            for _ in 0..batches {
+                let found = backend.search(&mut preimage, &nonces, &Target::ZERO);
+                assert!(found.is_none(), "nothing meets the difficulty");
+                for nonce in &mut nonces {
+                    *nonce += NONCE_BATCH as u64;
+                }
+            }
+        });
+        println!(
+            "{:<10} {:<10} {:>10.2?}  {:>12.0} H/s",
+            backend.name(),
+            format!("{:?}", algorithm).to_lowercase(),
+            elapsed,
+            hashes as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+fn backends() -> Vec<(HashAlgorithm, Box<dyn HashBackend>)> {
+    HashAlgorithm::ALL
+        .into_iter()
+        .map(|algorithm| {
+            (
+                algorithm,
+                Box::new(PortableBackend(algorithm.hasher())) as Box<dyn HashBackend>,
+            )
+        })
+        .collect()
+}
+
+fn best_of(mut round: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            round();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
@@ -0,0 +1,270 @@
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{select, spawn};
+
+use crate::analytics;
+use crate::api::NodeApi;
+use crate::events::EventFilter;
+use crate::journal::Since;
+use crate::transaction::Transaction;
+use crate::units::{BlockHeight, Timestamp};
+
+// One JSON object per line, e.g. {"cmd": "create_block", "data": "hello"}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum AdminCommand {
+    Chain,
+    LastBlock,
+    Peers,
+    CreateBlock {
+        data: String,
+    },
+    Mine {
+        n: u64,
+    },
+    // heights `from..to`, the whole chain by default
+    MinerLeaderboard {
+        from: Option<BlockHeight>,
+        to: Option<BlockHeight>,
+    },
+    // as CSV text instead of JSON when `csv` is true
+    DifficultyHistory {
+        from: Option<BlockHeight>,
+        to: Option<BlockHeight>,
+        #[serde(default)]
+        csv: bool,
+    },
+    BlockTimeHistogram {
+        from: Option<BlockHeight>,
+        to: Option<BlockHeight>,
+        #[serde(default)]
+        csv: bool,
+    },
+    SendDirect {
+        peer: String,
+        body: String,
+    },
+    Inbox,
+    SubmitTransactions {
+        transactions: Vec<Transaction>,
+    },
+    Status,
+    // a block template for an external miner, see NodeApi::get_work
+    GetWork {
+        #[serde(default)]
+        data: String,
+    },
+    SubmitWork {
+        work_id: u64,
+        nonce: u64,
+    },
+    // logged chain events from a height or a unix timestamp on, one of the two
+    EventsSince {
+        height: Option<BlockHeight>,
+        timestamp: Option<Timestamp>,
+    },
+    // Streams the events passing `filter`, one per line after the response. The
+    // connection takes no further commands.
+    Subscribe {
+        #[serde(default)]
+        filter: EventFilter,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AdminResponse {
+    fn from_result<T: Serialize>(result: Result<T, String>) -> Self {
+        match result.and_then(|value| serde_json::to_value(value).map_err(|e| e.to_string())) {
+            Ok(value) => AdminResponse {
+                ok: true,
+                result: Some(value),
+                error: None,
+            },
+            Err(e) => AdminResponse {
+                ok: false,
+                result: None,
+                error: Some(e),
+            },
+        }
+    }
+}
+
+pub async fn execute<A: NodeApi>(api: &A, command: AdminCommand) -> AdminResponse {
+    match command {
+        AdminCommand::Chain => AdminResponse::from_result(api.chain().await),
+        AdminCommand::LastBlock => AdminResponse::from_result(api.last_block().await),
+        AdminCommand::Peers => AdminResponse::from_result(
+            api.peers()
+                .await
+                .map(|peers| peers.iter().map(|p| p.to_string()).collect::<Vec<_>>()),
+        ),
+        AdminCommand::CreateBlock { data } => AdminResponse::from_result(api.create_block(data).await),
+        AdminCommand::Mine { n } => AdminResponse::from_result(api.mine(n).await),
+        AdminCommand::SendDirect { peer, body } => AdminResponse::from_result(match peer.parse() {
+            Ok(peer) => api.send_direct(peer, body).await,
+            Err(_) => Err(format!("invalid peer id {}", peer)),
+        }),
+        AdminCommand::Inbox => AdminResponse::from_result(api.inbox().await),
+        AdminCommand::SubmitTransactions { transactions } => {
+            AdminResponse::from_result(api.submit_transactions(transactions).await)
+        }
+        AdminCommand::Status => AdminResponse::from_result(api.status().await),
+        AdminCommand::GetWork { data } => AdminResponse::from_result(api.get_work(data).await),
+        AdminCommand::SubmitWork { work_id, nonce } => {
+            AdminResponse::from_result(api.submit_work(work_id, nonce).await)
+        }
+        AdminCommand::EventsSince { height, timestamp } => AdminResponse::from_result(match (height, timestamp) {
+            (Some(height), None) => api.events_since(Since::Height(height)).await,
+            (None, Some(timestamp)) => api.events_since(Since::Timestamp(timestamp)).await,
+            _ => Err("events_since takes either a height or a timestamp".to_string()),
+        }),
+        AdminCommand::Subscribe { .. } => {
+            AdminResponse::from_result::<()>(Err("subscriptions need a connection of their own".to_string()))
+        }
+        AdminCommand::MinerLeaderboard { from, to } => AdminResponse::from_result(
+            api.miner_leaderboard(from.unwrap_or(BlockHeight::GENESIS)..to.unwrap_or(BlockHeight::MAX))
+                .await,
+        ),
+        AdminCommand::DifficultyHistory { from, to, csv } => {
+            let history = api
+                .difficulty_history(from.unwrap_or(BlockHeight::GENESIS)..to.unwrap_or(BlockHeight::MAX))
+                .await;
+            if csv {
+                AdminResponse::from_result(history.map(|history| analytics::difficulty_history_csv(&history)))
+            } else {
+                AdminResponse::from_result(history)
+            }
+        }
+        AdminCommand::BlockTimeHistogram { from, to, csv } => {
+            let histogram = api
+                .block_time_histogram(from.unwrap_or(BlockHeight::GENESIS)..to.unwrap_or(BlockHeight::MAX))
+                .await;
+            if csv {
+                AdminResponse::from_result(histogram.map(|histogram| analytics::block_time_histogram_csv(&histogram)))
+            } else {
+                AdminResponse::from_result(histogram)
+            }
+        }
+    }
+}
+
+async fn handle_connection<A: NodeApi>(stream: UnixStream, api: A) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<AdminCommand>(&line) {
+            Ok(AdminCommand::Subscribe { filter }) => {
+                let mut events = match api.subscribe(filter).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        write_line(&mut writer, &AdminResponse::from_result::<()>(Err(e))).await?;
+                        continue;
+                    }
+                };
+                write_line(&mut writer, &AdminResponse::from_result(Ok(()))).await?;
+                loop {
+                    select! {
+                        event = events.recv() => match event {
+                            Some(event) => write_line(&mut writer, &event).await?,
+                            None => return Ok(()),
+                        },
+                        // anything but the client going away is ignored
+                        line = lines.next_line() => if line?.is_none() {
+                            return Ok(());
+                        },
+                    }
+                }
+            }
+            Ok(command) => execute(&api, command).await,
+            Err(e) => AdminResponse::from_result::<()>(Err(format!("invalid command: {}", e))),
+        };
+        write_line(&mut writer, &response).await?;
+    }
+    Ok(())
+}
+
+async fn write_line<T: Serialize>(writer: &mut OwnedWriteHalf, value: &T) -> io::Result<()> {
+    let mut json = serde_json::to_string(value).expect("can jsonify admin response");
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await
+}
+
+// Serves the admin socket until accepting fails. The socket is only accessible
+// to the user running the node.
+// Binds the socket in a directory only this user can enter and moves it to `path`
+// once it is 0600, so no one else can connect in between.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".bind");
+    let private = path.with_file_name(name);
+    if private.exists() {
+        fs::remove_dir_all(&private)?;
+    }
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join("admin.sock");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    fs::remove_dir_all(&private)?;
+    bound
+}
+
+pub async fn serve<A: NodeApi + Clone + 'static>(path: &Path, api: A) -> io::Result<()> {
+    let listener = bind(path)?;
+    info!("admin socket listening on {}", path.display());
+
+    loop {
+        let (stream, _addr) = listener.accept().await?;
+        let api = api.clone();
+        spawn(async move {
+            if let Err(e) = handle_connection(stream, api).await {
+                error!("admin connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socket_is_private_and_replaces_a_stale_one() {
+        let dir = std::env::temp_dir().join(format!("admin-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("admin.sock");
+        fs::write(&path, "stale").unwrap();
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // only the socket is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let (_client, accepted) = tokio::join!(UnixStream::connect(&path), listener.accept());
+        assert!(accepted.is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
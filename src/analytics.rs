@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::units::{BlockHeight, Timestamp};
+use crate::{Block, Payload};
+
+// width of the block time histogram buckets
+pub const BLOCK_TIME_BUCKET_SECS: i64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DifficultyPoint {
+    pub height: BlockHeight,
+    pub timestamp: Timestamp,
+    // as in the header, leading zero bits or a compact target depending on its version
+    pub difficulty: u32,
+    // expected hashes to meet the difficulty, comparable across header versions
+    pub work: u128,
+    // seconds since the parent block, none for genesis
+    pub block_time: Option<i64>,
+}
+
+// blocks whose time since their parent is in `from_secs..from_secs + BLOCK_TIME_BUCKET_SECS`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockTimeBucket {
+    pub from_secs: i64,
+    pub blocks: u64,
+}
+
+// Only header fields are read.
+pub fn difficulty_history<T: Payload>(chain: &[Block<T>], heights: Range<BlockHeight>) -> Vec<DifficultyPoint> {
+    chain
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| heights.contains(&block.header.id))
+        .map(|(index, block)| DifficultyPoint {
+            height: block.header.id,
+            timestamp: block.header.timestamp,
+            difficulty: block.header.difficulty,
+            work: block.header.work(),
+            block_time: index.checked_sub(1).map(|parent| {
+                block
+                    .header
+                    .timestamp
+                    .saturating_secs_since(chain[parent].header.timestamp)
+            }),
+        })
+        .collect()
+}
+
+// Summary of how the chain has been mined, see App::stats. `history` has every
+// block's difficulty and the time it took to mine, i.e. since its parent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub blocks: usize,
+    // seconds, none until there are two blocks after genesis
+    pub average_block_interval: Option<f64>,
+    // of the tip, and what the retarget asks of the next block
+    pub difficulty: u32,
+    pub next_difficulty: u32,
+    pub total_work: u128,
+    pub transactions: usize,
+    // encoded sizes, see Block::encoded_size and Transaction::encoded_size
+    pub chain_bytes: usize,
+    pub transaction_bytes: usize,
+    pub history: Vec<DifficultyPoint>,
+}
+
+// The time from genesis to the first block is left out, a genesis block is usually
+// much older than the chain mined on it.
+pub fn average_block_interval(history: &[DifficultyPoint]) -> Option<f64> {
+    let intervals: Vec<i64> = history
+        .iter()
+        .filter(|point| point.height.index() > 1)
+        .filter_map(|point| point.block_time)
+        .collect();
+    if intervals.is_empty() {
+        return None;
+    }
+    Some(intervals.iter().sum::<i64>() as f64 / intervals.len() as f64)
+}
+
+pub fn block_time_histogram<T: Payload>(chain: &[Block<T>], heights: Range<BlockHeight>) -> Vec<BlockTimeBucket> {
+    let mut buckets: BTreeMap<i64, u64> = BTreeMap::new();
+    for point in difficulty_history(chain, heights) {
+        if let Some(block_time) = point.block_time {
+            let from_secs = block_time.div_euclid(BLOCK_TIME_BUCKET_SECS) * BLOCK_TIME_BUCKET_SECS;
+            *buckets.entry(from_secs).or_default() += 1;
+        }
+    }
+    buckets
+        .into_iter()
+        .map(|(from_secs, blocks)| BlockTimeBucket { from_secs, blocks })
+        .collect()
+}
+
+pub fn difficulty_history_csv(history: &[DifficultyPoint]) -> String {
+    let mut csv = String::from("height,timestamp,difficulty,block_time,work\n");
+    for point in history {
+        let block_time = point.block_time.map(|secs| secs.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            point.height, point.timestamp, point.difficulty, block_time, point.work
+        ));
+    }
+    csv
+}
+
+pub fn block_time_histogram_csv(histogram: &[BlockTimeBucket]) -> String {
+    let mut csv = String::from("from_secs,to_secs,blocks\n");
+    for bucket in histogram {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            bucket.from_secs,
+            bucket.from_secs + BLOCK_TIME_BUCKET_SECS,
+            bucket.blocks
+        ));
+    }
+    csv
+}
+
+pub fn write_csv(path: &Path, csv: &str) -> Result<(), String> {
+    fs::write(path, csv).map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn_blocking;
+
+use crate::analytics::{BlockTimeBucket, DifficultyPoint};
+use crate::direct::ReceivedMessage;
+use crate::events::{EventFilter, NodeEvent};
+use crate::journal::{LoggedEvent, Since};
+use crate::names::NameRecord;
+use crate::p2p::EventType;
+use crate::relay::RelayTip;
+use crate::resources::ResourceUsage;
+use crate::transaction::Transaction;
+use crate::units::BlockHeight;
+use crate::work::WorkTemplate;
+use crate::{Block, BlockHeader, MinerRank};
+
+// The node's external API. Transports (admin socket, HTTP, ...) are adapters over
+// this trait, and `NodeHandle` implements it in-process against a running node.
+#[async_trait]
+pub trait NodeApi: Send + Sync {
+    async fn chain(&self) -> Result<Vec<Block>, String>;
+    async fn last_block(&self) -> Result<Block, String>;
+    async fn peers(&self) -> Result<Vec<PeerId>, String>;
+    async fn create_block(&self, data: String) -> Result<Block, String>;
+    // creates `n` blocks one after the other, each accepted before the next is mined
+    async fn mine(&self, n: u64) -> Result<Vec<Block>, String>;
+    async fn miner_leaderboard(&self, range: Range<BlockHeight>) -> Result<Vec<MinerRank>, String>;
+    async fn difficulty_history(&self, range: Range<BlockHeight>) -> Result<Vec<DifficultyPoint>, String>;
+    async fn block_time_histogram(&self, range: Range<BlockHeight>) -> Result<Vec<BlockTimeBucket>, String>;
+    // queued for delivery, the peer's acknowledgement is only logged
+    async fn send_direct(&self, peer: PeerId, body: String) -> Result<(), String>;
+    async fn inbox(&self) -> Result<Vec<ReceivedMessage>, String>;
+    // Admits a batch to the mempool in one go, no other event is handled in between.
+    // One result per transaction, in the order given; a rejected one doesn't stop the rest.
+    async fn submit_transactions(&self, transactions: Vec<Transaction>) -> Result<Vec<SubmittedTransaction>, String>;
+    async fn status(&self) -> Result<NodeStatus, String>;
+    // the node's own memory, disk, connection and queue footprint
+    async fn resources(&self) -> Result<ResourceUsage, String>;
+    // last header relayed from the chain with `spec`
+    async fn relay_tip(&self, spec: String) -> Result<Option<RelayTip>, String>;
+    // queues headers of another chain to be mined into the next blocks, returns how
+    // many were new
+    async fn relay_headers(&self, headers: Vec<BlockHeader>) -> Result<usize, String>;
+    // the node's events that pass `filter`, until the receiver is dropped
+    async fn subscribe(&self, filter: EventFilter) -> Result<mpsc::UnboundedReceiver<NodeEvent>, String>;
+    async fn transaction_status(&self, id: String) -> Result<TransactionStatus, String>;
+    // the node's logged chain events from `since` on, see journal::EventLog
+    async fn events_since(&self, since: Since) -> Result<Vec<LoggedEvent>, String>;
+    // who holds `name` as of the tip, none if it is free
+    async fn resolve_name(&self, name: String) -> Result<Option<NameRecord>, String>;
+    // the next block with `data`, for an external miner to find the nonce of
+    async fn get_work(&self, data: String) -> Result<WorkTemplate, String>;
+    // adds and broadcasts the block of the template with `work_id` sealed with `nonce`
+    async fn submit_work(&self, work_id: u64, nonce: u64) -> Result<Block, String>;
+}
+
+// Outcome of one transaction of a batch submission.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubmittedTransaction {
+    // the txid, see Transaction::id
+    pub id: String,
+    pub wtxid: String,
+    // why it was rejected, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Summary of a running node, e.g. one line of the multi-chain runner's status.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    // spec hash of the chain the node follows
+    pub spec: String,
+    pub height: BlockHeight,
+    pub tip: String,
+    pub peers: usize,
+    pub mempool: usize,
+}
+
+// Where a transaction stands on the node, see payments::await_confirmation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Confirmed {
+        height: BlockHeight,
+        block_hash: String,
+        // blocks on top of it, its own included
+        confirmations: u64,
+    },
+    Pending,
+    // neither on the chain nor in the mempool
+    Unknown,
+}
+
+// Requests forwarded to the swarm loop, each with a channel for the reply.
+#[derive(Debug)]
+pub enum ApiRequest {
+    Chain(oneshot::Sender<Vec<Block>>),
+    Peers(oneshot::Sender<Vec<PeerId>>),
+    CreateBlock(String, oneshot::Sender<Result<Block, String>>),
+    MinerLeaderboard(Range<BlockHeight>, oneshot::Sender<Vec<MinerRank>>),
+    DifficultyHistory(Range<BlockHeight>, oneshot::Sender<Vec<DifficultyPoint>>),
+    BlockTimeHistogram(Range<BlockHeight>, oneshot::Sender<Vec<BlockTimeBucket>>),
+    SendDirect(PeerId, String, oneshot::Sender<Result<(), String>>),
+    Inbox(oneshot::Sender<Vec<ReceivedMessage>>),
+    // signatures are checked before the request is sent, those that failed are
+    // passed along as their final result
+    SubmitTransactions(
+        Vec<Result<Transaction, SubmittedTransaction>>,
+        oneshot::Sender<Vec<SubmittedTransaction>>,
+    ),
+    Status(oneshot::Sender<NodeStatus>),
+    Resources(oneshot::Sender<ResourceUsage>),
+    RelayTip(String, oneshot::Sender<Option<RelayTip>>),
+    RelayHeaders(Vec<BlockHeader>, oneshot::Sender<usize>),
+    Subscribe(EventFilter, oneshot::Sender<mpsc::UnboundedReceiver<NodeEvent>>),
+    TransactionStatus(String, oneshot::Sender<TransactionStatus>),
+    EventsSince(Since, oneshot::Sender<Vec<LoggedEvent>>),
+    ResolveName(String, oneshot::Sender<Option<NameRecord>>),
+    GetWork(String, oneshot::Sender<Result<WorkTemplate, String>>),
+    SubmitWork(u64, u64, oneshot::Sender<Result<Block, String>>),
+}
+
+#[derive(Clone)]
+pub struct NodeHandle {
+    events: mpsc::UnboundedSender<EventType>,
+}
+
+impl NodeHandle {
+    pub fn new(events: mpsc::UnboundedSender<EventType>) -> Self {
+        Self { events }
+    }
+
+    async fn request<T>(&self, make_request: impl FnOnce(oneshot::Sender<T>) -> ApiRequest) -> Result<T, String> {
+        let (reply_sender, reply_rcv) = oneshot::channel();
+        self.events
+            .send(EventType::Api(make_request(reply_sender)))
+            .map_err(|_| "node is not running".to_string())?;
+        reply_rcv.await.map_err(|_| "node dropped the request".to_string())
+    }
+}
+
+#[async_trait]
+impl NodeApi for NodeHandle {
+    async fn chain(&self) -> Result<Vec<Block>, String> {
+        self.request(ApiRequest::Chain).await
+    }
+
+    async fn last_block(&self) -> Result<Block, String> {
+        let chain = self.chain().await?;
+        chain.last().cloned().ok_or_else(|| "chain is empty".to_string())
+    }
+
+    async fn peers(&self) -> Result<Vec<PeerId>, String> {
+        self.request(ApiRequest::Peers).await
+    }
+
+    async fn create_block(&self, data: String) -> Result<Block, String> {
+        self.request(|reply| ApiRequest::CreateBlock(data, reply)).await?
+    }
+
+    async fn mine(&self, n: u64) -> Result<Vec<Block>, String> {
+        let mut mined = vec![];
+        for i in 1..=n {
+            mined.push(self.create_block(format!("mined block {} of {}", i, n)).await?);
+        }
+        Ok(mined)
+    }
+
+    async fn miner_leaderboard(&self, range: Range<BlockHeight>) -> Result<Vec<MinerRank>, String> {
+        self.request(|reply| ApiRequest::MinerLeaderboard(range, reply)).await
+    }
+
+    async fn difficulty_history(&self, range: Range<BlockHeight>) -> Result<Vec<DifficultyPoint>, String> {
+        self.request(|reply| ApiRequest::DifficultyHistory(range, reply)).await
+    }
+
+    async fn block_time_histogram(&self, range: Range<BlockHeight>) -> Result<Vec<BlockTimeBucket>, String> {
+        self.request(|reply| ApiRequest::BlockTimeHistogram(range, reply)).await
+    }
+
+    async fn send_direct(&self, peer: PeerId, body: String) -> Result<(), String> {
+        self.request(|reply| ApiRequest::SendDirect(peer, body, reply)).await?
+    }
+
+    async fn inbox(&self) -> Result<Vec<ReceivedMessage>, String> {
+        self.request(ApiRequest::Inbox).await
+    }
+
+    async fn submit_transactions(&self, transactions: Vec<Transaction>) -> Result<Vec<SubmittedTransaction>, String> {
+        // thousands of signature checks would stall the event loop
+        let checked = spawn_blocking(move || {
+            transactions
+                .into_iter()
+                .map(|transaction| match transaction.verify() {
+                    Ok(()) => Ok(transaction),
+                    Err(e) => Err(SubmittedTransaction {
+                        id: transaction.id(),
+                        wtxid: transaction.wtxid(),
+                        error: Some(e),
+                    }),
+                })
+                .collect()
+        })
+        .await
+        .map_err(|e| format!("could not check signatures: {}", e))?;
+        self.request(|reply| ApiRequest::SubmitTransactions(checked, reply))
+            .await
+    }
+
+    async fn status(&self) -> Result<NodeStatus, String> {
+        self.request(ApiRequest::Status).await
+    }
+
+    async fn resources(&self) -> Result<ResourceUsage, String> {
+        self.request(ApiRequest::Resources).await
+    }
+
+    async fn relay_tip(&self, spec: String) -> Result<Option<RelayTip>, String> {
+        self.request(|reply| ApiRequest::RelayTip(spec, reply)).await
+    }
+
+    async fn relay_headers(&self, headers: Vec<BlockHeader>) -> Result<usize, String> {
+        self.request(|reply| ApiRequest::RelayHeaders(headers, reply)).await
+    }
+
+    async fn subscribe(&self, filter: EventFilter) -> Result<mpsc::UnboundedReceiver<NodeEvent>, String> {
+        self.request(|reply| ApiRequest::Subscribe(filter, reply)).await
+    }
+
+    async fn transaction_status(&self, id: String) -> Result<TransactionStatus, String> {
+        self.request(|reply| ApiRequest::TransactionStatus(id, reply)).await
+    }
+
+    async fn events_since(&self, since: Since) -> Result<Vec<LoggedEvent>, String> {
+        self.request(|reply| ApiRequest::EventsSince(since, reply)).await
+    }
+
+    async fn resolve_name(&self, name: String) -> Result<Option<NameRecord>, String> {
+        self.request(|reply| ApiRequest::ResolveName(name, reply)).await
+    }
+
+    async fn get_work(&self, data: String) -> Result<WorkTemplate, String> {
+        self.request(|reply| ApiRequest::GetWork(data, reply)).await?
+    }
+
+    async fn submit_work(&self, work_id: u64, nonce: u64) -> Result<Block, String> {
+        self.request(|reply| ApiRequest::SubmitWork(work_id, nonce, reply))
+            .await?
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::units::BlockHeight;
+use crate::{Block, BlockHeader, Payload};
+
+// start of every archive file, never the start of a JSON one
+const MAGIC: &[u8; 8] = b"BCARCH01";
+const LEN_SIZE: usize = 4;
+const OFFSET_SIZE: usize = 8;
+
+// Chain file layout for archival nodes with long chains. Blocks are stored one
+// record each, a little-endian u32 length and the block's compact JSON, in height
+// order right after MAGIC, so a scan reads the file front to back. An index of
+// u64 record offsets follows, then the u64 number of records, so any height can
+// be found without reading what comes before it.
+//
+// Archives are read through a memory map: the page cache holds the chain rather
+// than a copy of it on the heap, and the kernel is told to read ahead.
+pub struct Archive {
+    map: Mmap,
+    offsets: Vec<usize>,
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| magic == *MAGIC)
+}
+
+pub fn write_archive<T: Payload>(path: &Path, blocks: &[Block<T>]) -> Result<(), String> {
+    let mut bytes = MAGIC.to_vec();
+    let mut offsets = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let json = serde_json::to_vec(block).map_err(|e| format!("could not encode chain: {}", e))?;
+        let len = u32::try_from(json.len()).map_err(|_| format!("block {} is too large", block.header.id))?;
+        offsets.push(bytes.len() as u64);
+        bytes.extend(len.to_le_bytes());
+        bytes.extend(json);
+    }
+    for offset in &offsets {
+        bytes.extend(offset.to_le_bytes());
+    }
+    bytes.extend((offsets.len() as u64).to_le_bytes());
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes).map_err(|e| format!("could not write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {}", path.display(), e))
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        // the archive is only ever replaced by renaming a new file over it, so the
+        // mapped file itself isn't changed under us
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| format!("could not map {}: {}", path.display(), e))?;
+        #[cfg(unix)]
+        let _ = map.advise(memmap2::Advice::Sequential);
+        let offsets = read_index(&map).map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+        Ok(Self { map, offsets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    // the JSON of the `index`th block
+    fn record(&self, index: usize) -> Result<&[u8], String> {
+        let offset = self.offsets[index];
+        let len = read_u32(&self.map, offset)? as usize;
+        let start = offset + LEN_SIZE;
+        self.map
+            .get(start..start + len)
+            .ok_or_else(|| format!("record {} runs past the end", index))
+    }
+
+    pub fn block<T: Payload>(&self, index: usize) -> Result<Block<T>, String> {
+        serde_json::from_slice(self.record(index)?).map_err(|e| format!("record {}: {}", index, e))
+    }
+
+    // the body is skipped while parsing, not decoded
+    pub fn header(&self, index: usize) -> Result<BlockHeader, String> {
+        serde_json::from_slice(self.record(index)?).map_err(|e| format!("record {}: {}", index, e))
+    }
+
+    pub fn blocks<T: Payload>(&self) -> impl Iterator<Item = Result<Block<T>, String>> + '_ {
+        (0..self.len()).map(|index| self.block(index))
+    }
+
+    // blocks are stored by height from genesis on, so only the records in `heights`
+    // are read
+    pub fn headers(&self, heights: Range<BlockHeight>) -> impl Iterator<Item = Result<BlockHeader, String>> + '_ {
+        let start = heights.start.index().min(self.len());
+        let end = heights.end.index().clamp(start, self.len());
+        (start..end).map(|index| self.header(index))
+    }
+}
+
+fn read_index(map: &[u8]) -> Result<Vec<usize>, String> {
+    if !map.starts_with(MAGIC) {
+        return Err("not an archive".to_string());
+    }
+    let count_at = map.len().checked_sub(OFFSET_SIZE).ok_or("archive is truncated")?;
+    let count = read_u64(map, count_at)?;
+    let index_at = usize::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(OFFSET_SIZE))
+        .and_then(|size| count_at.checked_sub(size))
+        .filter(|index_at| *index_at >= MAGIC.len())
+        .ok_or("archive index is corrupt")?;
+    (0..count as usize)
+        .map(|i| {
+            let offset = read_u64(map, index_at + i * OFFSET_SIZE)? as usize;
+            if offset < MAGIC.len() || offset >= index_at {
+                return Err(format!("record {} is outside the archive", i));
+            }
+            Ok(offset)
+        })
+        .collect()
+}
+
+fn read_u32(map: &[u8], at: usize) -> Result<u32, String> {
+    map.get(at..at + LEN_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| "archive is truncated".to_string())
+}
+
+fn read_u64(map: &[u8], at: usize) -> Result<u64, String> {
+    map.get(at..at + OFFSET_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| "archive is truncated".to_string())
+}
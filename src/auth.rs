@@ -0,0 +1,33 @@
+use libp2p::PeerId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    // a new connection, or a peer found through mdns
+    Connection,
+    Block,
+    Checkpoint,
+    ChainRequest,
+    ChainResponse,
+    DirectMessage,
+    Transaction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    Allow,
+    Deny,
+}
+
+// Network policy hook. AppBehaviour asks it before accepting connections and
+// before acting on any message, denied peers are disconnected or ignored.
+pub trait PeerAuthorizer: Send + Sync {
+    fn authorize(&self, peer: &PeerId, kind: MessageKind) -> Authorization;
+}
+
+pub struct AllowAll;
+
+impl PeerAuthorizer for AllowAll {
+    fn authorize(&self, _peer: &PeerId, _kind: MessageKind) -> Authorization {
+        Authorization::Allow
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::merkle::{merkle_proof, merkle_root, verify_proof, MerkleProof};
+use crate::{Block, Payload};
+
+// Experimental data availability sampling, behind the da-sampling feature. Blocks
+// with a large body are cut into chunks plus a parity chunk and the chunks are
+// gossiped on their own topic. A node holds back such a block until a few chunks
+// picked at random have arrived and match it, i.e. until the network has shown it
+// can serve the data. Receivers still get the whole block, so this only exercises
+// the mechanics, nothing is saved on bandwidth yet.
+
+// topic name, see p2p::Topics
+pub const CHUNK_TOPIC: &str = "chunks";
+pub const CHUNK_SIZE: usize = 4 * 1024;
+// bodies smaller than this are accepted as before
+pub const MIN_SAMPLED_SIZE: usize = 16 * 1024;
+// chunks checked per block
+pub const SAMPLES: usize = 4;
+// held blocks whose samples don't all arrive in time are dropped
+pub const SAMPLE_TIMEOUT: Duration = Duration::from_secs(30);
+// most blocks held and blocks with early chunks kept at once
+const MAX_HELD: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub block_hash: String,
+    // chunks of the block, including the parity chunk
+    pub total: usize,
+    // hex encoded
+    pub data: String,
+    // against the Merkle root over all of the block's chunks
+    pub proof: MerkleProof,
+}
+
+// The body's JSON in chunks of CHUNK_SIZE, the last one zero padded, followed by
+// their XOR. Any one missing chunk can be rebuilt from the others; a stand-in for
+// a proper Reed-Solomon code, which would survive losing half of them.
+pub fn encode(body: &[u8]) -> Vec<Vec<u8>> {
+    let mut chunks: Vec<Vec<u8>> = body
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(CHUNK_SIZE, 0);
+            chunk
+        })
+        .collect();
+    let mut parity = vec![0; CHUNK_SIZE];
+    for chunk in &chunks {
+        for (parity, byte) in parity.iter_mut().zip(chunk) {
+            *parity ^= byte;
+        }
+    }
+    chunks.push(parity);
+    chunks
+}
+
+// the body's JSON from the chunks of `encode`, at most one of them missing
+pub fn reconstruct(mut chunks: Vec<Option<Vec<u8>>>, len: usize) -> Option<Vec<u8>> {
+    let missing: Vec<usize> = (0..chunks.len()).filter(|index| chunks[*index].is_none()).collect();
+    match missing[..] {
+        [] => {}
+        [index] => {
+            let mut rebuilt = vec![0; CHUNK_SIZE];
+            for chunk in chunks.iter().flatten() {
+                for (rebuilt, byte) in rebuilt.iter_mut().zip(chunk) {
+                    *rebuilt ^= byte;
+                }
+            }
+            chunks[index] = Some(rebuilt);
+        }
+        _ => return None,
+    }
+    chunks.pop();
+    let mut body: Vec<u8> = chunks.into_iter().flatten().flatten().collect();
+    body.truncate(len);
+    Some(body)
+}
+
+fn encoded_body<T: Payload>(block: &Block<T>) -> Vec<u8> {
+    serde_json::to_vec(&block.data).expect("can jsonify block body")
+}
+
+pub fn needs_sampling<T: Payload>(block: &Block<T>) -> bool {
+    encoded_body(block).len() >= MIN_SAMPLED_SIZE
+}
+
+// what the block's producer gossips on CHUNK_TOPIC
+pub fn chunks_of<T: Payload>(block: &Block<T>) -> Vec<Chunk> {
+    let chunks = encode(&encoded_body(block));
+    (0..chunks.len())
+        .map(|index| Chunk {
+            block_hash: block.header.hash.clone(),
+            total: chunks.len(),
+            data: hex::encode(&chunks[index]),
+            proof: merkle_proof(&chunks, index).expect("index is in range"),
+        })
+        .collect()
+}
+
+struct HeldBlock {
+    block: Block,
+    // the peer it came from, for alerts once it is accepted
+    source: PeerId,
+    // the block's own chunks, which a sampled chunk has to equal as well as prove
+    // its place under the root, so it can't stand in for another index
+    chunks: Vec<Vec<u8>>,
+    root: String,
+    samples: Vec<usize>,
+    since: Instant,
+}
+
+// Blocks waiting for their samples, and chunks that arrived before their block.
+#[derive(Default)]
+pub struct AvailabilitySampler {
+    held: Vec<HeldBlock>,
+    chunks: HashMap<String, HashMap<usize, Chunk>>,
+}
+
+impl AvailabilitySampler {
+    // Holds `block` until its samples arrive. Returns it right away if the chunks
+    // already did.
+    pub fn hold(&mut self, block: Block, source: PeerId) -> Option<(Block, PeerId)> {
+        let chunks = encode(&encoded_body(&block));
+        let mut rng = rand::thread_rng();
+        let samples = (0..SAMPLES)
+            .map(|_| (rng.gen::<u64>() % chunks.len() as u64) as usize)
+            .collect();
+        if self.held.len() >= MAX_HELD {
+            self.held.remove(0);
+        }
+        self.held.push(HeldBlock {
+            root: merkle_root(&chunks),
+            chunks,
+            block,
+            source,
+            samples,
+            since: Instant::now(),
+        });
+        self.take_available().pop()
+    }
+
+    // Keeps `chunk` if it could belong to a held or future block, and returns the
+    // blocks it completes.
+    pub fn add_chunk(&mut self, chunk: Chunk) -> Vec<(Block, PeerId)> {
+        if !self.chunks.contains_key(&chunk.block_hash) && self.chunks.len() >= MAX_HELD {
+            let held: Vec<&String> = self.held.iter().map(|held| &held.block.header.hash).collect();
+            let stale = self.chunks.keys().find(|hash| !held.contains(hash)).cloned();
+            match stale {
+                Some(hash) => self.chunks.remove(&hash),
+                None => return vec![],
+            };
+        }
+        self.chunks
+            .entry(chunk.block_hash.clone())
+            .or_default()
+            .insert(chunk.proof.index, chunk);
+        self.take_available()
+    }
+
+    // held blocks dropped for lack of samples
+    pub fn expire(&mut self, now: Instant) -> Vec<(Block, PeerId)> {
+        let (expired, held) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|held| now.duration_since(held.since) >= SAMPLE_TIMEOUT);
+        self.held = held;
+        expired
+            .into_iter()
+            .map(|held: HeldBlock| {
+                self.chunks.remove(&held.block.header.hash);
+                (held.block, held.source)
+            })
+            .collect()
+    }
+
+    fn take_available(&mut self) -> Vec<(Block, PeerId)> {
+        let chunks = &self.chunks;
+        let (available, held) = std::mem::take(&mut self.held).into_iter().partition(|held| {
+            let received = chunks.get(&held.block.header.hash);
+            held.samples.iter().all(|index| {
+                received
+                    .and_then(|received| received.get(index))
+                    .is_some_and(|chunk| held.matches(*index, chunk))
+            })
+        });
+        self.held = held;
+        available
+            .into_iter()
+            .map(|held: HeldBlock| {
+                self.chunks.remove(&held.block.header.hash);
+                (held.block, held.source)
+            })
+            .collect()
+    }
+}
+
+impl HeldBlock {
+    fn matches(&self, index: usize, chunk: &Chunk) -> bool {
+        chunk.total == self.chunks.len()
+            && hex::decode(&chunk.data).is_ok_and(|data| {
+                verify_proof(&self.root, &data, &chunk.proof) && Some(&data) == self.chunks.get(index)
+            })
+    }
+}
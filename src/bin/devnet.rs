@@ -0,0 +1,40 @@
+use blockchain_basic::devnet::DevnetScript;
+use log::{error, info};
+
+fn usage() -> ! {
+    eprintln!("usage: devnet script <scenario.yaml>");
+    std::process::exit(2);
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("script"), Some(path)) => path,
+        _ => usage(),
+    };
+
+    let yaml = std::fs::read_to_string(path).expect("can read devnet script");
+    let run = DevnetScript::from_yaml(&yaml).and_then(|script| Ok((script.addresses()?, script.run()?)));
+    let (addresses, app) = match run {
+        Ok(run) => run,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    for (name, address) in &addresses {
+        info!("account {} is {}, balance {}", name, address, app.balance_of(address));
+    }
+    info!(
+        "built {} blocks, tip {}",
+        app.blocks.len(),
+        app.get_last_block().header.hash
+    );
+    // the chain goes to stdout so runs can be diffed or saved as fixtures
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&app.blocks).expect("can jsonify chain")
+    );
+}
@@ -0,0 +1,321 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use blockchain_basic::encoding::Encoding;
+use blockchain_basic::p2p::{self, ChainRequest, ChainResponse, ChainSyncCodec};
+use blockchain_basic::params::ChainParams;
+use libp2p::floodsub::{Floodsub, FloodsubEvent, FloodsubMessage};
+use libp2p::futures::StreamExt;
+use libp2p::mdns::{Mdns, MdnsEvent};
+use libp2p::request_response::{RequestResponse, RequestResponseEvent, RequestResponseMessage};
+use libp2p::swarm::{NetworkBehaviourEventProcess, SwarmBuilder};
+use libp2p::{NetworkBehaviour, PeerId, Swarm};
+use log::{debug, error, info};
+use serde::Deserialize;
+use tokio::time::{sleep_until, Instant};
+use tokio::{select, spawn};
+
+const TARGET_PLACEHOLDER: &str = "{target}";
+
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    #[serde(default = "default_discovery_timeout")]
+    discovery_timeout_secs: u64,
+    #[serde(default = "default_response_timeout")]
+    response_timeout_secs: u64,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    name: String,
+    // prefixed with the chain's spec hash like every topic, see p2p::Topics. Steps
+    // without a topic are sent as direct chain sync requests.
+    #[serde(default)]
+    topic: Option<String>,
+    #[serde(flatten)]
+    payload: Payload,
+    #[serde(default)]
+    expect: Expect,
+}
+
+// `json` payloads are re-serialized as-is, `raw` payloads are sent byte for byte so
+// malformed input can be scripted too. `{target}` is replaced by the target peer id.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Json(serde_json::Value),
+    Raw(String),
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Expect {
+    Response,
+    Silence,
+    #[default]
+    Any,
+}
+
+fn default_discovery_timeout() -> u64 {
+    10
+}
+
+fn default_response_timeout() -> u64 {
+    3
+}
+
+impl Payload {
+    fn render(&self, target: &PeerId) -> Vec<u8> {
+        let text = match self {
+            Payload::Json(value) => value.to_string(),
+            Payload::Raw(raw) => raw.clone(),
+        };
+        text.replace(TARGET_PLACEHOLDER, &target.to_string()).into_bytes()
+    }
+}
+
+#[derive(NetworkBehaviour)]
+struct TesterBehaviour {
+    floodsub: Floodsub,
+    mdns: Mdns,
+    chain_sync: RequestResponse<ChainSyncCodec>,
+    #[behaviour(ignore)]
+    received: Vec<FloodsubMessage>,
+    #[behaviour(ignore)]
+    direct_responses: Vec<(PeerId, String)>,
+    // of the target's chain, which scenario topics are prefixed with
+    #[behaviour(ignore)]
+    spec: String,
+}
+
+impl NetworkBehaviourEventProcess<MdnsEvent> for TesterBehaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        match event {
+            MdnsEvent::Discovered(discovered_list) => {
+                for (peer, _addr) in discovered_list {
+                    self.floodsub.add_node_to_partial_view(peer);
+                }
+            }
+            MdnsEvent::Expired(expired_list) => {
+                for (peer, _addr) in expired_list {
+                    if !self.mdns.has_node(&peer) {
+                        self.floodsub.remove_node_from_partial_view(&peer);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<FloodsubEvent> for TesterBehaviour {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        if let FloodsubEvent::Message(msg) = event {
+            self.received.push(msg);
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<ChainRequest, ChainResponse>> for TesterBehaviour {
+    fn inject_event(&mut self, event: RequestResponseEvent<ChainRequest, ChainResponse>) {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => {
+                let summary = format!(
+                    "chain response #{} with {} blocks",
+                    response.request_id,
+                    response.blocks.len()
+                );
+                self.direct_responses.push((peer, summary));
+            }
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                error!("chain request to {} failed: {:?}", peer, error);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct StepReport {
+    name: String,
+    passed: bool,
+    responses: usize,
+    first_response: Option<String>,
+}
+
+async fn run_for(swarm: &mut Swarm<TesterBehaviour>, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    loop {
+        select! {
+            _ = sleep_until(deadline) => break,
+            event = swarm.select_next_some() => debug!("swarm event: {:?}", event),
+        }
+    }
+}
+
+async fn discover_target(
+    swarm: &mut Swarm<TesterBehaviour>,
+    wanted: Option<PeerId>,
+    timeout: Duration,
+) -> Option<PeerId> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        run_for(swarm, Duration::from_millis(500)).await;
+        let discovered: HashSet<PeerId> = swarm.behaviour().mdns.discovered_nodes().cloned().collect();
+        match wanted {
+            Some(peer) if discovered.contains(&peer) => return Some(peer),
+            None => {
+                if let Some(peer) = discovered.into_iter().next() {
+                    return Some(peer);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+async fn run_step(swarm: &mut Swarm<TesterBehaviour>, step: &Step, target: &PeerId, window: Duration) -> StepReport {
+    let behaviour = swarm.behaviour_mut();
+    behaviour.received.clear();
+    behaviour.direct_responses.clear();
+    let payload = step.payload.render(target);
+    match &step.topic {
+        Some(topic) => behaviour.floodsub.publish(p2p::topic(&behaviour.spec, topic), payload),
+        None => match serde_json::from_slice::<ChainRequest>(&payload) {
+            Ok(request) => {
+                behaviour.chain_sync.send_request(target, request);
+            }
+            Err(e) => {
+                error!("step {} is not a valid chain request: {}", step.name, e);
+                return StepReport {
+                    name: step.name.clone(),
+                    passed: false,
+                    responses: 0,
+                    first_response: None,
+                };
+            }
+        },
+    }
+    run_for(swarm, window).await;
+
+    let behaviour = swarm.behaviour();
+    let responses: Vec<String> = behaviour
+        .received
+        .iter()
+        .filter(|msg| msg.source == *target)
+        .map(|msg| String::from_utf8_lossy(&msg.data).chars().take(120).collect())
+        .chain(
+            behaviour
+                .direct_responses
+                .iter()
+                .filter(|(peer, _)| peer == target)
+                .map(|(_, summary)| summary.clone()),
+        )
+        .collect();
+    let passed = match step.expect {
+        Expect::Response => !responses.is_empty(),
+        Expect::Silence => responses.is_empty(),
+        Expect::Any => true,
+    };
+    StepReport {
+        name: step.name.clone(),
+        passed,
+        responses: responses.len(),
+        first_response: responses.into_iter().next(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: p2p-tester <scenario.json> [--peer <peer id>]");
+    std::process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let scenario_path = args.first().unwrap_or_else(|| usage());
+    let wanted = match args.get(1).map(String::as_str) {
+        Some("--peer") => Some(
+            args.get(2)
+                .unwrap_or_else(|| usage())
+                .parse::<PeerId>()
+                .expect("can parse target peer id"),
+        ),
+        Some(_) => usage(),
+        None => None,
+    };
+
+    let scenario_json = std::fs::read_to_string(scenario_path).expect("can read scenario file");
+    let scenario: Scenario = serde_json::from_str(&scenario_json).expect("can parse scenario file");
+
+    // the target is expected to follow the chain of the same CHAIN_* variables
+    let spec = ChainParams::from_env().expect("can read chain params").spec_hash();
+    info!("Tester Peer Id: {}", p2p::PEER_ID.clone());
+    let mut behaviour = TesterBehaviour {
+        floodsub: Floodsub::new(*p2p::PEER_ID),
+        mdns: Mdns::new(Default::default()).await.expect("can create mdns"),
+        chain_sync: p2p::new_chain_sync(Encoding::Json),
+        received: vec![],
+        direct_responses: vec![],
+        spec,
+    };
+    let topics = p2p::Topics::new(&behaviour.spec);
+    behaviour.floodsub.subscribe(topics.blocks);
+    behaviour.floodsub.subscribe(topics.checkpoints);
+
+    let mut swarm = SwarmBuilder::new(p2p::build_transport(&p2p::KEYS), behaviour, *p2p::PEER_ID)
+        .executor(Box::new(|fut| {
+            spawn(fut);
+        }))
+        .build();
+    Swarm::listen_on(
+        &mut swarm,
+        "/ip4/0.0.0.0/tcp/0".parse().expect("can get a local socket"),
+    )
+    .expect("swarm can be started");
+
+    let target = match discover_target(&mut swarm, wanted, Duration::from_secs(scenario.discovery_timeout_secs)).await {
+        Some(target) => target,
+        None => {
+            error!("no target node discovered");
+            std::process::exit(1);
+        }
+    };
+    info!("testing node {}", target);
+    // give floodsub a moment to exchange subscriptions before the first step
+    run_for(&mut swarm, Duration::from_secs(1)).await;
+
+    let window = Duration::from_secs(scenario.response_timeout_secs);
+    let mut reports = vec![];
+    for step in &scenario.steps {
+        let report = run_step(&mut swarm, step, &target, window).await;
+        info!(
+            "[{}] {} ({} responses)",
+            if report.passed { "PASS" } else { "FAIL" },
+            report.name,
+            report.responses
+        );
+        if let Some(first) = &report.first_response {
+            info!("  first response: {}", first);
+        }
+        reports.push(report);
+    }
+
+    let still_reachable = swarm.behaviour().mdns.has_node(&target);
+    let failed = reports.iter().filter(|r| !r.passed).count();
+    info!(
+        "{} steps, {} passed, {} failed, target still discoverable: {}",
+        reports.len(),
+        reports.len() - failed,
+        failed,
+        still_reachable
+    );
+    if failed > 0 || !still_reachable {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,7 @@
+use blockchain_basic::schema::protocol_schema;
+
+// Prints the JSON Schema of all network messages, e.g. to check it into docs/.
+fn main() {
+    let schema = protocol_schema();
+    println!("{}", serde_json::to_string_pretty(&schema).expect("can jsonify schema"));
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use blockchain_basic::signing::SignatureAlgorithm;
+use blockchain_basic::wallet::{self, Keystore, WalletAccount};
+
+fn usage() -> ! {
+    eprintln!("usage: WALLET_PASSWORD=... wallet generate --count <n> --out <keystore> [--scheme ed25519|secp256k1]");
+    std::process::exit(2);
+}
+
+// Creates accounts fully offline: no runtime, no network. The keystore holds the
+// encrypted keys, and a plain list of the addresses is written next to it.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) != Some("generate") {
+        usage();
+    }
+    let mut count = None;
+    let mut out = None;
+    let mut scheme = SignatureAlgorithm::default();
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        match (flag.as_str(), rest.next()) {
+            ("--count", Some(n)) => count = Some(n.parse::<usize>().unwrap_or_else(|_| usage())),
+            ("--out", Some(path)) => out = Some(PathBuf::from(path)),
+            ("--scheme", Some(name)) => scheme = name.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+    let (Some(count), Some(out)) = (count, out) else {
+        usage();
+    };
+    let Ok(password) = std::env::var("WALLET_PASSWORD") else {
+        eprintln!("WALLET_PASSWORD must be set to encrypt the keystore");
+        std::process::exit(2);
+    };
+
+    let accounts: Vec<WalletAccount> = (0..count).map(|_| WalletAccount::generate(scheme)).collect();
+    let result = Keystore::encrypt(&accounts, &password).and_then(|keystore| keystore.save(&out));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let address_list = wallet::address_list_path(&out);
+    let addresses: String = accounts
+        .iter()
+        .map(|account| format!("{}\n", account.address))
+        .collect();
+    if let Err(e) = std::fs::write(&address_list, addresses) {
+        eprintln!("could not write {}: {}", address_list.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "wrote {} accounts to {} and {}",
+        count,
+        out.display(),
+        address_list.display()
+    );
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+
+use blst::min_pk::{AggregateSignature, PublicKey, SecretKey, Signature};
+use blst::BLST_ERROR;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::signing_payload;
+use crate::parse_env;
+use crate::units::BlockHeight;
+
+// Checkpoints signed by a set of validators rather than a single authority, behind
+// the bls feature. Each validator gossips a BLS vote for its tip on their own
+// topic, and once enough validators agree on a block their votes are aggregated
+// into one AggregateCheckpoint: a bitmap of who signed and a single 96 byte
+// signature, which a light client checks with one pairing whatever the number of
+// signers.
+
+// topic name, see p2p::Topics
+pub const BLS_CHECKPOINT_TOPIC: &str = "bls-checkpoints";
+// the proof of possession ciphersuite. Validator keys are configured by the
+// operator, who has to have checked their proofs, which is what makes aggregating
+// votes for the same message safe from rogue keys.
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+// blocks votes are collected for at once, the oldest are dropped
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlsMessage {
+    Vote(CheckpointVote),
+    Aggregate(AggregateCheckpoint),
+}
+
+// one validator's signature of a (height, hash) pair, `validator` being its index
+// in the validator set
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CheckpointVote {
+    pub height: BlockHeight,
+    pub hash: String,
+    pub validator: usize,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AggregateCheckpoint {
+    pub height: BlockHeight,
+    pub hash: String,
+    // hex encoded bitmap of the validators that signed, lowest index first
+    pub signers: String,
+    pub signature: String,
+}
+
+// The validator set checkpoints are checked against, in order, and how many of
+// them have to sign one.
+#[derive(Debug, Clone)]
+pub struct BlsValidators {
+    keys: Vec<PublicKey>,
+    threshold: usize,
+}
+
+impl BlsValidators {
+    // hex encoded 48 byte public keys separated by commas. More than two thirds of
+    // them have to sign unless `threshold` says otherwise.
+    pub fn parse(list: &str, threshold: Option<usize>) -> Result<Self, String> {
+        let keys = list
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| {
+                let bytes = hex::decode(key.trim()).map_err(|e| format!("invalid BLS public key hex: {}", e))?;
+                PublicKey::key_validate(&bytes).map_err(|e| format!("invalid BLS public key: {:?}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let threshold = threshold.unwrap_or(keys.len() * 2 / 3 + 1);
+        if keys.is_empty() || threshold == 0 || threshold > keys.len() {
+            return Err(format!(
+                "invalid BLS threshold {} of {} validators",
+                threshold,
+                keys.len()
+            ));
+        }
+        Ok(Self { keys, threshold })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn index_of(&self, key: &PublicKey) -> Option<usize> {
+        self.keys.iter().position(|k| k == key)
+    }
+}
+
+// a validator's own key, and where it is in the set
+pub struct BlsSigner {
+    pub index: usize,
+    secret: SecretKey,
+}
+
+impl BlsSigner {
+    // hex encoded 32 byte secret key, which has to belong to one of `validators`
+    pub fn parse(hex_secret: &str, validators: &BlsValidators) -> Result<Self, String> {
+        let bytes = hex::decode(hex_secret.trim()).map_err(|e| format!("invalid BLS secret key hex: {}", e))?;
+        let secret = SecretKey::from_bytes(&bytes).map_err(|e| format!("invalid BLS secret key: {:?}", e))?;
+        let index = validators
+            .index_of(&secret.sk_to_pk())
+            .ok_or("BLS key is not one of the validators")?;
+        Ok(Self { index, secret })
+    }
+
+    pub fn vote(&self, height: BlockHeight, hash: String) -> CheckpointVote {
+        let signature = self.secret.sign(&signing_payload(height, &hash), DST, &[]);
+        CheckpointVote {
+            height,
+            hash,
+            validator: self.index,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+fn decode_signature(signature: &str) -> Option<Signature> {
+    Signature::from_bytes(&hex::decode(signature).ok()?).ok()
+}
+
+impl CheckpointVote {
+    pub fn verify(&self, validators: &BlsValidators) -> bool {
+        match (validators.keys.get(self.validator), decode_signature(&self.signature)) {
+            (Some(key), Some(signature)) => {
+                let payload = signing_payload(self.height, &self.hash);
+                signature.verify(true, &payload, DST, &[], key, false) == BLST_ERROR::BLST_SUCCESS
+            }
+            _ => false,
+        }
+    }
+}
+
+impl AggregateCheckpoint {
+    // indices of the validators that signed, in order
+    pub fn signers(&self) -> Option<Vec<usize>> {
+        let bitmap = hex::decode(&self.signers).ok()?;
+        Some(
+            (0..bitmap.len() * 8)
+                .filter(|i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+                .collect(),
+        )
+    }
+
+    // Enough of `validators` signed, and the signature is their aggregate. One
+    // pairing check, as all of them signed the same payload.
+    pub fn verify(&self, validators: &BlsValidators) -> bool {
+        let Some(signers) = self.signers() else {
+            return false;
+        };
+        let keys: Option<Vec<&PublicKey>> = signers.iter().map(|i| validators.keys.get(*i)).collect();
+        match (keys, decode_signature(&self.signature)) {
+            (Some(keys), Some(signature)) if keys.len() >= validators.threshold => {
+                let payload = signing_payload(self.height, &self.hash);
+                signature.fast_aggregate_verify(true, &payload, DST, &keys) == BLST_ERROR::BLST_SUCCESS
+            }
+            _ => false,
+        }
+    }
+}
+
+// Votes seen for recent blocks, until their threshold is reached.
+#[derive(Default)]
+pub struct CheckpointVotes {
+    pending: BTreeMap<(BlockHeight, String), BTreeMap<usize, Signature>>,
+}
+
+impl CheckpointVotes {
+    // Adds a vote verified against `validators`. Returns the aggregate checkpoint
+    // for the vote's block once, when the vote brings it to the threshold.
+    pub fn add(&mut self, validators: &BlsValidators, vote: &CheckpointVote) -> Option<AggregateCheckpoint> {
+        let signature = decode_signature(&vote.signature)?;
+        self.prune();
+        let key = (vote.height, vote.hash.clone());
+        let votes = self.pending.entry(key).or_default();
+        if votes.insert(vote.validator, signature).is_some() || votes.len() != validators.threshold {
+            return None;
+        }
+        let mut bitmap = vec![0u8; validators.len().div_ceil(8)];
+        for index in votes.keys() {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+        let signatures: Vec<&Signature> = votes.values().collect();
+        let signature = AggregateSignature::aggregate(&signatures, false).ok()?.to_signature();
+        Some(AggregateCheckpoint {
+            height: vote.height,
+            hash: vote.hash.clone(),
+            signers: hex::encode(bitmap),
+            signature: hex::encode(signature.to_bytes()),
+        })
+    }
+
+    fn prune(&mut self) {
+        while self.pending.len() >= MAX_PENDING {
+            self.pending.pop_first();
+        }
+    }
+}
+
+// Everything a node needs for BLS checkpoints, read from CHECKPOINT_BLS_VALIDATORS,
+// CHECKPOINT_BLS_THRESHOLD and, on validators, CHECKPOINT_BLS_KEY. None without
+// validators.
+pub struct BlsCheckpoints {
+    pub validators: BlsValidators,
+    pub signer: Option<BlsSigner>,
+    pub votes: CheckpointVotes,
+}
+
+impl BlsCheckpoints {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(list) = std::env::var("CHECKPOINT_BLS_VALIDATORS") else {
+            return Ok(None);
+        };
+        let validators = BlsValidators::parse(&list, parse_env("CHECKPOINT_BLS_THRESHOLD")?)?;
+        let signer = match std::env::var("CHECKPOINT_BLS_KEY") {
+            Ok(key) => Some(BlsSigner::parse(&key, &validators)?),
+            Err(_) => None,
+        };
+        Ok(Some(Self {
+            validators,
+            signer,
+            votes: CheckpointVotes::default(),
+        }))
+    }
+}
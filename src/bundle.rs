@@ -0,0 +1,231 @@
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use libp2p::identity::{Keypair, PublicKey};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::units::BlockHeight;
+use crate::{chain_work, keys, App, Block};
+
+// how long downloading a bundle may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(300);
+// decompressed, so a small download can't expand to fill the memory
+const MAX_BUNDLE_BYTES: u64 = 1 << 30;
+// compressed, as downloaded. Chain JSON gzips to well under this at MAX_BUNDLE_BYTES.
+const MAX_DOWNLOAD_BYTES: u64 = 1 << 28;
+
+// A chain published by an operator for late joiners, gzipped JSON signed with the
+// operator's key. A new node downloads it at startup and imports it before it
+// joins gossip, instead of syncing the whole chain from peers.
+//
+// The signature covers the genesis and tip, which the blocks' hashes link to
+// everything else, so the blocks are still validated in full on import; what the
+// key vouches for is that this is the chain the operator follows. Nodes keep whole
+// chains and replay them into their state, so the bundle carries the blocks.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainBundle {
+    pub genesis_hash: String,
+    pub height: BlockHeight,
+    pub tip_hash: String,
+    pub signature: String,
+    pub blocks: Vec<Block>,
+}
+
+pub fn signing_payload(genesis_hash: &str, height: BlockHeight, tip_hash: &str) -> Vec<u8> {
+    format!("bundle:{}:{}:{}", genesis_hash, height, tip_hash).into_bytes()
+}
+
+impl ChainBundle {
+    pub fn sign(keys: &Keypair, blocks: Vec<Block>) -> Result<Self, String> {
+        let (Some(genesis), Some(tip)) = (blocks.first(), blocks.last()) else {
+            return Err("can't bundle an empty chain".to_string());
+        };
+        let (genesis_hash, height, tip_hash) = (genesis.header.hash.clone(), tip.header.id, tip.header.hash.clone());
+        let signature = keys
+            .sign(&signing_payload(&genesis_hash, height, &tip_hash))
+            .map_err(|e| format!("could not sign bundle: {}", e))?;
+        Ok(Self {
+            genesis_hash,
+            height,
+            tip_hash,
+            signature: hex::encode(signature),
+            blocks,
+        })
+    }
+
+    // signed by `signer`, and the blocks are the chain that was signed
+    pub fn verify(&self, signer: &PublicKey) -> Result<(), String> {
+        let payload = signing_payload(&self.genesis_hash, self.height, &self.tip_hash);
+        let signed = hex::decode(&self.signature).is_ok_and(|signature| signer.verify(&payload, &signature));
+        if !signed {
+            return Err("bundle is not signed by the bootstrap key".to_string());
+        }
+        let ends = self
+            .blocks
+            .first()
+            .zip(self.blocks.last())
+            .map(|(genesis, tip)| (genesis.header.hash.as_str(), tip.header.id, tip.header.hash.as_str()));
+        if ends != Some((self.genesis_hash.as_str(), self.height, self.tip_hash.as_str())) {
+            return Err("bundle blocks don't match what was signed".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        serde_json::to_writer(&mut encoder, self).map_err(|e| format!("could not encode bundle: {}", e))?;
+        encoder
+            .finish()
+            .map_err(|e| format!("could not compress bundle: {}", e))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut json = vec![];
+        GzDecoder::new(bytes)
+            .take(MAX_BUNDLE_BYTES + 1)
+            .read_to_end(&mut json)
+            .map_err(|e| format!("could not decompress bundle: {}", e))?;
+        if json.len() as u64 > MAX_BUNDLE_BYTES {
+            return Err(format!("bundle is over {} bytes", MAX_BUNDLE_BYTES));
+        }
+        serde_json::from_slice(&json).map_err(|e| format!("could not parse bundle: {}", e))
+    }
+
+    // for the operator to publish at a URL, see Bootstrap
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let bytes = self.encode()?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes).map_err(|e| format!("could not write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {}", path.display(), e))
+    }
+}
+
+// Where a new node fetches its chain bundle from, and whose signature it needs.
+pub struct Bootstrap {
+    pub url: String,
+    pub signer: PublicKey,
+}
+
+impl Bootstrap {
+    // from BOOTSTRAP_URL and BOOTSTRAP_KEY, the hex public key of the operator
+    // signing the bundle, none without a URL
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(url) = std::env::var("BOOTSTRAP_URL") else {
+            return Ok(None);
+        };
+        let key = std::env::var("BOOTSTRAP_KEY").map_err(|_| "BOOTSTRAP_URL needs BOOTSTRAP_KEY".to_string())?;
+        let signer = keys::decode_public_key(&key).map_err(|e| format!("invalid BOOTSTRAP_KEY: {}", e))?;
+        Ok(Some(Self { url, signer }))
+    }
+
+    pub async fn fetch(&self) -> Result<ChainBundle, String> {
+        let mut response = reqwest::Client::new()
+            .get(self.url.as_str())
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format!("could not download {}: {}", self.url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", self.url, response.status()));
+        }
+        let too_large = || format!("{} is over {} bytes", self.url, MAX_DOWNLOAD_BYTES);
+        if response.content_length().is_some_and(|len| len > MAX_DOWNLOAD_BYTES) {
+            return Err(too_large());
+        }
+        // the length may be missing or wrong, so count as it arrives
+        let mut bytes = vec![];
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("could not download {}: {}", self.url, e))?
+        {
+            if (bytes.len() + chunk.len()) as u64 > MAX_DOWNLOAD_BYTES {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        let bundle = ChainBundle::decode(&bytes)?;
+        bundle.verify(&self.signer)?;
+        Ok(bundle)
+    }
+
+    // Imports the bundle into `app` if it is a valid chain with more work than the
+    // local one, which a restarted node may already have. Returns whether it did.
+    pub async fn run(&self, app: &mut App) -> Result<bool, String> {
+        let bundle = self.fetch().await?;
+        if bundle.genesis_hash != app.genesis_hash() {
+            return Err("bundle is of another chain".to_string());
+        }
+        if chain_work(&bundle.blocks) <= chain_work(&app.blocks) {
+            info!("bundle at height {} is no better than the local chain", bundle.height);
+            return Ok(false);
+        }
+        app.import_chain(bundle.blocks, &self.url)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dev_params;
+    use crate::BlockBody;
+
+    fn blocks() -> Vec<Block> {
+        let mut app: App = App::new(dev_params());
+        app.mine_n_blocks(2, |height| BlockBody::new(format!("bundled {}", height), vec![]))
+            .unwrap();
+        app.blocks
+    }
+
+    #[test]
+    fn signed_bundle_round_trips() {
+        let keys = Keypair::generate_ed25519();
+        let bundle = ChainBundle::sign(&keys, blocks()).unwrap();
+        let decoded = ChainBundle::decode(&bundle.encode().unwrap()).unwrap();
+        assert_eq!(decoded.verify(&keys.public()), Ok(()));
+        assert_eq!(decoded.height, BlockHeight(2));
+        assert_eq!(decoded.tip_hash, bundle.blocks[2].header.hash);
+    }
+
+    #[test]
+    fn empty_chain_is_not_bundled() {
+        assert!(ChainBundle::sign(&Keypair::generate_ed25519(), vec![]).is_err());
+    }
+
+    #[test]
+    fn other_signer_is_rejected() {
+        let bundle = ChainBundle::sign(&Keypair::generate_ed25519(), blocks()).unwrap();
+        assert!(bundle.verify(&Keypair::generate_ed25519().public()).is_err());
+    }
+
+    #[test]
+    fn tampered_bundle_is_rejected() {
+        let keys = Keypair::generate_ed25519();
+
+        // a tip other than the one signed
+        let mut bundle = ChainBundle::sign(&keys, blocks()).unwrap();
+        bundle.tip_hash = bundle.blocks[1].header.hash.clone();
+        assert!(bundle.verify(&keys.public()).is_err());
+
+        // the signed ends, but blocks cut short of them
+        let mut bundle = ChainBundle::sign(&keys, blocks()).unwrap();
+        bundle.blocks.pop();
+        assert!(bundle.verify(&keys.public()).is_err());
+    }
+
+    #[test]
+    fn corrupt_download_does_not_decode() {
+        let bytes = ChainBundle::sign(&Keypair::generate_ed25519(), blocks())
+            .unwrap()
+            .encode()
+            .unwrap();
+        assert!(ChainBundle::decode(&bytes[..bytes.len() / 2]).is_err());
+        assert!(ChainBundle::decode(b"not gzip").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::ops::BitOr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Optional protocols and roles a node supports, exchanged in the chain sync
+// handshake. Unknown bits are kept but ignored, so newer flags don't upset older
+// nodes, and nodes that send no flags at all are treated as full archival peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    // keeps and serves full chains, block bodies included. The bits below it are
+    // free, a flag only gets one once something checks it.
+    pub const ARCHIVAL: Capabilities = Capabilities(1 << 3);
+
+    const NAMES: [(Capabilities, &'static str); 1] = [(Self::ARCHIVAL, "archival")];
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_bits_round_trip_but_are_not_named() {
+        let capabilities: Capabilities = serde_json::from_str("9").unwrap();
+        assert!(capabilities.contains(Capabilities::ARCHIVAL));
+        assert_eq!(capabilities.names(), vec!["archival"]);
+        assert_eq!(serde_json::to_string(&capabilities).unwrap(), "9");
+    }
+}
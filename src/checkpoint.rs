@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use libp2p::identity::{Keypair, PublicKey};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::units::BlockHeight;
+
+// A (height, hash) pair signed by the checkpoint authority. Nodes that trust the
+// authority pin the block and refuse any chain that disagrees with it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SignedCheckpoint {
+    pub height: BlockHeight,
+    pub hash: String,
+    pub signature: String,
+}
+
+// Checkpoints an operator pins by hand, as "height:hash" pairs separated by commas
+pub fn parse_checkpoints(list: &str) -> Result<BTreeMap<BlockHeight, String>, String> {
+    list.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (height, hash) = pair
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("invalid checkpoint {}, expected height:hash", pair))?;
+            let height = height
+                .parse()
+                .map_err(|_| format!("invalid checkpoint height {}", height))?;
+            Ok((BlockHeight(height), hash.to_string()))
+        })
+        .collect()
+}
+
+// read from CHECKPOINTS, none if it is not set
+pub fn checkpoints_from_env() -> Result<BTreeMap<BlockHeight, String>, String> {
+    match std::env::var("CHECKPOINTS") {
+        Ok(list) => parse_checkpoints(&list),
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn signing_payload(height: BlockHeight, hash: &str) -> Vec<u8> {
+    format!("checkpoint:{}:{}", height, hash).into_bytes()
+}
+
+impl SignedCheckpoint {
+    pub fn sign(keys: &Keypair, height: BlockHeight, hash: String) -> Result<SignedCheckpoint, String> {
+        let signature = keys
+            .sign(&signing_payload(height, &hash))
+            .map_err(|e| format!("could not sign checkpoint: {}", e))?;
+        Ok(SignedCheckpoint {
+            height,
+            hash,
+            signature: hex::encode(signature),
+        })
+    }
+
+    pub fn verify(&self, authority: &PublicKey) -> bool {
+        match hex::decode(&self.signature) {
+            Ok(signature) => authority.verify(&signing_payload(self.height, &self.hash), &signature),
+            Err(_) => false,
+        }
+    }
+}
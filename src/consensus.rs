@@ -0,0 +1,604 @@
+use std::sync::Arc;
+
+use libp2p::identity::{Keypair, PublicKey};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::ChainError;
+use crate::hashing::{BlockHasher, HashBackend, PortableBackend};
+use crate::keys;
+use crate::nonce::{NonceStrategy, RandomStart};
+use crate::params::ChainParams;
+use crate::parse_env;
+use crate::progress::MiningProgress;
+use crate::units::{Amount, BlockHeight, Timestamp};
+use crate::{Block, BlockHeader, Payload};
+
+// How blocks are made acceptable to the network, and checked for it. App checks
+// what every chain shares (heights, links, difficulty schedule, timestamps and
+// body) and leaves the seal to its engine, proof of work unless another is set.
+pub trait ConsensusEngine<T>: Send + Sync {
+    // completes `block`, whose header is otherwise final, so that `verify` accepts it
+    fn seal(&self, block: &mut Block<T>) -> Result<(), String>;
+
+    // as seal, giving up once `cancel` is and reporting to `progress` meanwhile. Only
+    // worth overriding for engines whose seal takes a while.
+    fn seal_until_cancelled(
+        &self,
+        block: &mut Block<T>,
+        cancel: &CancellationToken,
+        _progress: Option<&mpsc::UnboundedSender<MiningProgress>>,
+    ) -> Result<(), String> {
+        if cancel.is_cancelled() {
+            return Err("mining cancelled".to_string());
+        }
+        self.seal(block)
+    }
+
+    // checks the seal of `block` as the child of `parent`
+    fn verify(&self, parent: &BlockHeader, block: &Block<T>) -> Result<(), ChainError>;
+
+    // what can be checked of `block` without its parent, to turn away junk orphans
+    fn check_detached(&self, _block: &Block<T>) -> Result<(), ChainError> {
+        Ok(())
+    }
+
+    // whether this node is due to seal the child of `parent` at `now`, for engines
+    // that produce blocks on a schedule instead of when asked to
+    fn should_propose(&self, _parent: &BlockHeader, _now: Timestamp) -> bool {
+        false
+    }
+}
+
+// The engine `params` call for: proof of stake if the genesis config stakes any
+// validators, proof of authority if it lists authorities, proof of work otherwise.
+pub fn engine_for<T: Payload>(params: &ChainParams) -> Result<Arc<dyn ConsensusEngine<T>>, String> {
+    let genesis = &params.genesis;
+    match (genesis.stakes.is_empty(), genesis.authorities.is_empty()) {
+        (true, true) => Ok(Arc::new(ProofOfWork::new(params))),
+        (false, true) => Ok(Arc::new(ProofOfStake::new(params)?)),
+        (true, false) => Ok(Arc::new(ProofOfAuthority::new(params)?)),
+        (false, false) => Err("a chain can't have both validator stakes and authorities".to_string()),
+    }
+}
+
+// the hash is the header's, signed blocks are not mined
+fn check_hash<T: Payload>(hasher: &dyn BlockHasher, block: &Block<T>) -> Result<(), ChainError> {
+    if hex::encode(block.header.calculate_hash(hasher)) != block.header.hash {
+        return Err(ChainError::HashMismatch);
+    }
+    Ok(())
+}
+
+fn hash_and_sign<T: Payload>(hasher: &dyn BlockHasher, keys: &Keypair, block: &mut Block<T>) -> Result<(), String> {
+    block.header.nonce = 0;
+    block.header.hash = hex::encode(block.header.calculate_hash(hasher));
+    block.sign(keys)
+}
+
+// Nonces are searched until the header hashes below its difficulty, on one thread
+// from a random start unless set otherwise.
+pub struct ProofOfWork {
+    hasher: &'static dyn BlockHasher,
+    // mines with `hasher` one nonce at a time unless set otherwise
+    backend: Box<dyn HashBackend>,
+    threads: usize,
+    nonces: Box<dyn NonceStrategy>,
+}
+
+impl ProofOfWork {
+    pub fn new(params: &ChainParams) -> Self {
+        Self {
+            hasher: params.hasher(),
+            backend: Box::new(PortableBackend(params.hasher())),
+            threads: 1,
+            nonces: Box::new(RandomStart),
+        }
+    }
+
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self { threads, ..self }
+    }
+
+    pub fn with_nonces(self, nonces: Box<dyn NonceStrategy>) -> Self {
+        Self { nonces, ..self }
+    }
+
+    pub fn with_backend(self, backend: Box<dyn HashBackend>) -> Self {
+        Self { backend, ..self }
+    }
+}
+
+// read from MINING_THREADS, every core by default
+pub fn mining_threads_from_env() -> Result<usize, String> {
+    match parse_env::<usize>("MINING_THREADS")? {
+        Some(0) => Err("MINING_THREADS has to be at least 1".to_string()),
+        Some(threads) => Ok(threads),
+        None => Ok(std::thread::available_parallelism().map_or(1, |threads| threads.get())),
+    }
+}
+
+impl<T: Payload> ConsensusEngine<T> for ProofOfWork {
+    fn seal(&self, block: &mut Block<T>) -> Result<(), String> {
+        self.seal_until_cancelled(block, &CancellationToken::new(), None)
+    }
+
+    fn seal_until_cancelled(
+        &self,
+        block: &mut Block<T>,
+        cancel: &CancellationToken,
+        progress: Option<&mpsc::UnboundedSender<MiningProgress>>,
+    ) -> Result<(), String> {
+        if !block.mine_parallel(
+            self.backend.as_ref(),
+            self.nonces.as_ref(),
+            self.threads,
+            cancel,
+            progress,
+        ) {
+            return Err("mining cancelled".to_string());
+        }
+        Ok(())
+    }
+
+    fn verify(&self, _parent: &BlockHeader, block: &Block<T>) -> Result<(), ChainError> {
+        block.header.check_proof_of_work(self.hasher)
+    }
+
+    fn check_detached(&self, block: &Block<T>) -> Result<(), ChainError> {
+        block.header.check_proof_of_work(self.hasher)
+    }
+}
+
+// Blocks are signed by a validator instead of mined. Time is cut into slots of the
+// target block time, and the proposer of a slot is drawn from the validators staked
+// in the genesis config, weighted by stake and seeded by the parent's hash. A block
+// is valid if it is signed by the proposer of the slot its timestamp falls in, so
+// when a proposer is offline the next slot's takes over. That slot has to be after
+// its parent's and at most one past the local clock's, or a validator could pick
+// among future timestamps until it draws itself.
+pub struct ProofOfStake {
+    hasher: &'static dyn BlockHasher,
+    slot_secs: i64,
+    // ordered by key, with their stakes
+    validators: Vec<(PublicKey, Amount)>,
+    total_stake: u64,
+    // signs the blocks this node proposes, it only validates without
+    keys: Option<Keypair>,
+}
+
+impl ProofOfStake {
+    pub fn new(params: &ChainParams) -> Result<Self, String> {
+        let mut validators = vec![];
+        let mut total_stake: u64 = 0;
+        for (key, stake) in &params.genesis.stakes {
+            if stake.is_zero() {
+                continue;
+            }
+            let public_key = keys::decode_public_key(key).map_err(|e| format!("validator {}: {}", key, e))?;
+            total_stake = total_stake.checked_add(stake.0).ok_or("total stake overflows")?;
+            validators.push((public_key, *stake));
+        }
+        if validators.is_empty() {
+            return Err("proof of stake needs at least one validator with a stake".to_string());
+        }
+        Ok(Self {
+            hasher: params.hasher(),
+            slot_secs: params.target_block_time_secs.max(1),
+            validators,
+            total_stake,
+            keys: None,
+        })
+    }
+
+    pub fn with_keys(mut self, keys: Keypair) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    fn slot(&self, timestamp: Timestamp) -> i64 {
+        timestamp.0.div_euclid(self.slot_secs)
+    }
+
+    // verify, against the local clock at `now`
+    fn verify_at<T: Payload>(&self, parent: &BlockHeader, block: &Block<T>, now: Timestamp) -> Result<(), ChainError> {
+        check_hash(self.hasher, block)?;
+        let slot = self.slot(block.header.timestamp);
+        if slot <= self.slot(parent.timestamp) || slot > self.slot(now).saturating_add(1) {
+            return Err(ChainError::InvalidSlot(slot));
+        }
+        if !block.is_signed_by(self.proposer(&block.header.previous_hash, slot)) {
+            return Err(ChainError::IneligibleProposer);
+        }
+        Ok(())
+    }
+
+    // the validator allowed to propose the child of `parent_hash` in `slot`
+    fn proposer(&self, parent_hash: &str, slot: i64) -> &PublicKey {
+        let seed = Sha256::digest(format!("{}:{}", parent_hash, slot).as_bytes());
+        let mut draw = u64::from_be_bytes(seed[..8].try_into().expect("digest has 8 bytes")) % self.total_stake;
+        for (key, stake) in &self.validators {
+            if draw < stake.0 {
+                return key;
+            }
+            draw -= stake.0;
+        }
+        unreachable!("draw is below the total stake")
+    }
+}
+
+impl<T: Payload> ConsensusEngine<T> for ProofOfStake {
+    fn seal(&self, block: &mut Block<T>) -> Result<(), String> {
+        let keys = self
+            .keys
+            .as_ref()
+            .ok_or("proof of stake: this node has no staking key")?;
+        let header = &block.header;
+        let slot = self.slot(header.timestamp);
+        if *self.proposer(&header.previous_hash, slot) != keys.public() {
+            return Err(format!(
+                "proof of stake: slot {} belongs to another validator, the next starts in {}s",
+                slot,
+                (slot + 1) * self.slot_secs - header.timestamp.0
+            ));
+        }
+        hash_and_sign(self.hasher, keys, block)
+    }
+
+    fn verify(&self, parent: &BlockHeader, block: &Block<T>) -> Result<(), ChainError> {
+        self.verify_at(parent, block, Timestamp::now())
+    }
+
+    // signed by some validator, whether it was their slot needs the parent
+    fn check_detached(&self, block: &Block<T>) -> Result<(), ChainError> {
+        check_hash(self.hasher, block)?;
+        if !self
+            .validators
+            .iter()
+            .any(|(validator, _)| block.is_signed_by(validator))
+        {
+            return Err(ChainError::IneligibleProposer);
+        }
+        Ok(())
+    }
+
+    // once the clock is past the parent's slot and in one of this validator's
+    fn should_propose(&self, parent: &BlockHeader, now: Timestamp) -> bool {
+        let Some(keys) = &self.keys else {
+            return false;
+        };
+        let slot = self.slot(now);
+        slot > self.slot(parent.timestamp) && *self.proposer(&parent.hash, slot) == keys.public()
+    }
+}
+
+// Blocks are signed by a fixed set of authorities listed in the genesis config,
+// taking turns in their listed order: the block at height h is the turn of
+// authority h mod n, due one target block time after its parent. So that an
+// offline authority doesn't stall the chain, the authority k places after it may
+// sign instead once k turn timeouts have passed since the parent. Such a block
+// can't be dated more than half a block time past the local clock, or the next
+// authority could take every turn by dating its blocks ahead. Blocks signed by
+// anyone else, or by an authority before its fallback turn, are rejected.
+pub struct ProofOfAuthority {
+    hasher: &'static dyn BlockHasher,
+    authorities: Vec<PublicKey>,
+    block_time_secs: i64,
+    // signs the blocks of this node's turns, it only validates without
+    keys: Option<Keypair>,
+}
+
+// turn timeouts are this many target block times
+const TURN_TIMEOUT_BLOCK_TIMES: i64 = 2;
+
+impl ProofOfAuthority {
+    pub fn new(params: &ChainParams) -> Result<Self, String> {
+        let authorities = params
+            .genesis
+            .authorities
+            .iter()
+            .map(|key| keys::decode_public_key(key).map_err(|e| format!("authority {}: {}", key, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if authorities.is_empty() {
+            return Err("proof of authority needs at least one authority".to_string());
+        }
+        Ok(Self {
+            hasher: params.hasher(),
+            authorities,
+            block_time_secs: params.target_block_time_secs.max(1),
+            keys: None,
+        })
+    }
+
+    pub fn with_keys(mut self, keys: Keypair) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    // how many places after the authority whose turn `height` is `key` comes, if
+    // it is an authority at all
+    fn turn_offset(&self, height: BlockHeight, key: &PublicKey) -> Option<u64> {
+        let n = self.authorities.len() as u64;
+        let index = self.authorities.iter().position(|authority| authority == key)? as u64;
+        Some((index + n - height.0 % n) % n)
+    }
+
+    // seconds after its parent that the authority `offset` places after the one
+    // whose turn it is may sign a block
+    fn turn_starts_after(&self, offset: u64) -> i64 {
+        match offset {
+            0 => 0,
+            offset => (offset as i64).saturating_mul(TURN_TIMEOUT_BLOCK_TIMES.saturating_mul(self.block_time_secs)),
+        }
+    }
+
+    // verify, against the local clock at `now`
+    fn verify_at<T: Payload>(&self, parent: &BlockHeader, block: &Block<T>, now: Timestamp) -> Result<(), ChainError> {
+        check_hash(self.hasher, block)?;
+        let offset = self
+            .authorities
+            .iter()
+            .filter(|authority| block.is_signed_by(authority))
+            .find_map(|authority| self.turn_offset(block.header.id, authority))
+            .ok_or(ChainError::UnknownAuthority)?;
+        if offset == 0 {
+            return Ok(());
+        }
+        let elapsed = block.header.timestamp.saturating_secs_since(parent.timestamp);
+        if elapsed < self.turn_starts_after(offset)
+            || block.header.timestamp > now.saturating_add_secs(self.block_time_secs / 2)
+        {
+            return Err(ChainError::OutOfTurn);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Payload> ConsensusEngine<T> for ProofOfAuthority {
+    // the turn depends on the parent, so only whether this node is an authority at
+    // all is checked here and the turn when the block is added
+    fn seal(&self, block: &mut Block<T>) -> Result<(), String> {
+        let keys = self
+            .keys
+            .as_ref()
+            .ok_or("proof of authority: this node has no authority key")?;
+        if !self.authorities.contains(&keys.public()) {
+            return Err("proof of authority: this node's key is not an authority".to_string());
+        }
+        hash_and_sign(self.hasher, keys, block)
+    }
+
+    fn verify(&self, parent: &BlockHeader, block: &Block<T>) -> Result<(), ChainError> {
+        self.verify_at(parent, block, Timestamp::now())
+    }
+
+    // signed by some authority, whether it was their turn needs the parent
+    fn check_detached(&self, block: &Block<T>) -> Result<(), ChainError> {
+        check_hash(self.hasher, block)?;
+        if !self.authorities.iter().any(|authority| block.is_signed_by(authority)) {
+            return Err(ChainError::UnknownAuthority);
+        }
+        Ok(())
+    }
+
+    // one block time after the parent in this authority's own turn, or once its
+    // fallback turn has come
+    fn should_propose(&self, parent: &BlockHeader, now: Timestamp) -> bool {
+        let Some(keys) = &self.keys else {
+            return false;
+        };
+        let Some(height) = parent.id.next() else {
+            return false;
+        };
+        let Some(offset) = self.turn_offset(height, &keys.public()) else {
+            return false;
+        };
+        let due = self.turn_starts_after(offset).max(self.block_time_secs);
+        now.saturating_secs_since(parent.timestamp) >= due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dev_params;
+    use crate::BlockBody;
+
+    fn staked(validators: &[&Keypair]) -> ChainParams {
+        let mut params = dev_params();
+        params.genesis.stakes = validators
+            .iter()
+            .map(|keys| (keys::encode_public_key(keys).unwrap(), Amount(1)))
+            .collect();
+        params
+    }
+
+    // the child of `parent` at `timestamp`, signed by `keys`
+    fn signed(params: &ChainParams, parent: &BlockHeader, timestamp: Timestamp, keys: &Keypair) -> Block {
+        let mut block = Block::unmined(parent, BlockBody::default(), None, parent.difficulty, timestamp);
+        hash_and_sign(params.hasher(), keys, &mut block).unwrap();
+        block
+    }
+
+    // start of the first slot from `slot` on whose proposer is `keys`
+    fn slot_of(engine: &ProofOfStake, parent: &BlockHeader, slot: i64, keys: &Keypair) -> Timestamp {
+        let slot = (slot..)
+            .find(|&slot| *engine.proposer(&parent.hash, slot) == keys.public())
+            .unwrap();
+        Timestamp(slot * engine.slot_secs)
+    }
+
+    #[test]
+    fn stake_accepts_only_the_proposer_of_the_slot() {
+        let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let params = staked(&[&alice, &bob]);
+        let engine = ProofOfStake::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let timestamp = slot_of(&engine, &genesis, engine.slot(genesis.timestamp) + 1, &alice);
+        let now = timestamp.saturating_add_secs(engine.slot_secs);
+
+        assert_eq!(
+            engine.verify_at(&genesis, &signed(&params, &genesis, timestamp, &alice), now),
+            Ok(())
+        );
+        assert_eq!(
+            engine.verify_at(&genesis, &signed(&params, &genesis, timestamp, &bob), now),
+            Err(ChainError::IneligibleProposer)
+        );
+        let outsider = Keypair::generate_ed25519();
+        assert_eq!(
+            engine.verify_at(&genesis, &signed(&params, &genesis, timestamp, &outsider), now),
+            Err(ChainError::IneligibleProposer)
+        );
+    }
+
+    #[test]
+    fn stake_rejects_slots_ahead_of_the_clock() {
+        let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let params = staked(&[&alice, &bob]);
+        let engine = ProofOfStake::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let now = genesis.timestamp.saturating_add_secs(100 * engine.slot_secs);
+        let now_slot = engine.slot(now);
+
+        // a proposer whose clock runs a little ahead is let through
+        let next_slot = Timestamp((now_slot + 1) * engine.slot_secs);
+        let keys = if *engine.proposer(&genesis.hash, now_slot + 1) == alice.public() {
+            &alice
+        } else {
+            &bob
+        };
+        assert_eq!(
+            engine.verify_at(&genesis, &signed(&params, &genesis, next_slot, keys), now),
+            Ok(())
+        );
+
+        let future = slot_of(&engine, &genesis, now_slot + 2, &alice);
+        assert_eq!(
+            engine.verify_at(&genesis, &signed(&params, &genesis, future, &alice), now),
+            Err(ChainError::InvalidSlot(engine.slot(future)))
+        );
+    }
+
+    #[test]
+    fn stake_proposes_in_its_own_slots_only() {
+        let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let params = staked(&[&alice, &bob]);
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let engine = ProofOfStake::new(&params).unwrap().with_keys(alice.clone());
+        let ours = slot_of(&engine, &genesis, engine.slot(genesis.timestamp) + 1, &alice);
+        let theirs = slot_of(&engine, &genesis, engine.slot(genesis.timestamp) + 1, &bob);
+        assert!(ConsensusEngine::<BlockBody>::should_propose(&engine, &genesis, ours));
+        assert!(!ConsensusEngine::<BlockBody>::should_propose(&engine, &genesis, theirs));
+        // nor twice in a slot
+        let mut tip = genesis.clone();
+        tip.timestamp = ours;
+        assert!(!ConsensusEngine::<BlockBody>::should_propose(&engine, &tip, ours));
+        // nor without a key
+        let validator = ProofOfStake::new(&params).unwrap();
+        assert!(!ConsensusEngine::<BlockBody>::should_propose(
+            &validator, &genesis, ours
+        ));
+    }
+
+    #[test]
+    fn stake_rejects_slots_not_after_the_parent() {
+        let alice = Keypair::generate_ed25519();
+        let params = staked(&[&alice]);
+        let engine = ProofOfStake::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let block = signed(&params, &genesis, genesis.timestamp, &alice);
+        assert_eq!(
+            engine.verify_at(&genesis, &block, Timestamp::now()),
+            Err(ChainError::InvalidSlot(engine.slot(genesis.timestamp)))
+        );
+    }
+
+    fn authorities(authorities: &[&Keypair]) -> ChainParams {
+        let mut params = dev_params();
+        params.genesis.authorities = authorities
+            .iter()
+            .map(|keys| keys::encode_public_key(keys).unwrap())
+            .collect();
+        params
+    }
+
+    #[test]
+    fn authority_in_turn_is_accepted() {
+        let keys: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        let params = authorities(&keys.iter().collect::<Vec<_>>());
+        let engine = ProofOfAuthority::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        // block 1 is the second authority's turn, whenever it comes
+        for delay in [1, 1000] {
+            let timestamp = genesis.timestamp.saturating_add_secs(delay);
+            let block = signed(&params, &genesis, timestamp, &keys[1]);
+            assert_eq!(engine.verify_at(&genesis, &block, timestamp), Ok(()));
+        }
+    }
+
+    #[test]
+    fn authority_out_of_turn_is_rejected_until_its_fallback_turn() {
+        let keys: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        let params = authorities(&keys.iter().collect::<Vec<_>>());
+        let engine = ProofOfAuthority::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let timeout = TURN_TIMEOUT_BLOCK_TIMES * params.target_block_time_secs;
+        let at = |secs| genesis.timestamp.saturating_add_secs(secs);
+
+        // the third authority is next in line, the first the one after it
+        let early = signed(&params, &genesis, at(timeout - 1), &keys[2]);
+        assert_eq!(
+            engine.verify_at(&genesis, &early, at(timeout - 1)),
+            Err(ChainError::OutOfTurn)
+        );
+        let fallback = signed(&params, &genesis, at(timeout), &keys[2]);
+        assert_eq!(engine.verify_at(&genesis, &fallback, at(timeout)), Ok(()));
+        let last = signed(&params, &genesis, at(timeout), &keys[0]);
+        assert_eq!(
+            engine.verify_at(&genesis, &last, at(timeout)),
+            Err(ChainError::OutOfTurn)
+        );
+        let last = signed(&params, &genesis, at(2 * timeout), &keys[0]);
+        assert_eq!(engine.verify_at(&genesis, &last, at(2 * timeout)), Ok(()));
+
+        // nor can a fallback turn be taken early by dating the block ahead
+        assert_eq!(engine.verify_at(&genesis, &fallback, at(1)), Err(ChainError::OutOfTurn));
+    }
+
+    #[test]
+    fn non_authority_is_rejected() {
+        let keys: Vec<Keypair> = (0..2).map(|_| Keypair::generate_ed25519()).collect();
+        let params = authorities(&keys.iter().collect::<Vec<_>>());
+        let engine = ProofOfAuthority::new(&params).unwrap();
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let timestamp = genesis.timestamp.saturating_add_secs(1000);
+        let block = signed(&params, &genesis, timestamp, &Keypair::generate_ed25519());
+        assert_eq!(
+            engine.verify_at(&genesis, &block, timestamp),
+            Err(ChainError::UnknownAuthority)
+        );
+        assert_eq!(engine.check_detached(&block), Err(ChainError::UnknownAuthority));
+    }
+
+    #[test]
+    fn authority_proposes_in_its_turn_then_as_fallback() {
+        let keys: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        let params = authorities(&keys.iter().collect::<Vec<_>>());
+        let genesis = Block::<BlockBody>::genesis(&params).unwrap().header;
+        let block_time = params.target_block_time_secs;
+        let at = |secs| genesis.timestamp.saturating_add_secs(secs);
+        let due = |keys: &Keypair, secs| {
+            let engine = ProofOfAuthority::new(&params).unwrap().with_keys(keys.clone());
+            ConsensusEngine::<BlockBody>::should_propose(&engine, &genesis, at(secs))
+        };
+        assert!(!due(&keys[1], block_time - 1));
+        assert!(due(&keys[1], block_time));
+        let timeout = TURN_TIMEOUT_BLOCK_TIMES * block_time;
+        assert!(!due(&keys[2], timeout - 1));
+        assert!(due(&keys[2], timeout));
+        assert!(!due(&keys[0], 2 * timeout - 1));
+        assert!(due(&keys[0], 2 * timeout));
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::{error, info, LevelFilter, Log, Metadata, Record};
+use tokio::signal::unix::{signal, SignalKind};
+
+// runs the node in the background, controlled over the admin socket only
+pub const DAEMON_FLAG: &str = "--daemon";
+// set in the environment of the background process
+const DETACHED_ENV: &str = "NODE_DETACHED";
+// the log file is rotated once it reaches this size
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+// rotated log files kept next to the current one
+const LOG_FILES: usize = 5;
+
+pub fn is_detached() -> bool {
+    std::env::var_os(DETACHED_ENV).is_some()
+}
+
+// Starts this program again with the same arguments and environment, in a session
+// of its own so it has no controlling terminal, and returns its pid.
+pub fn spawn_detached() -> Result<u32, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("could not find own executable: {}", e))?;
+    let mut command = Command::new(exe);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(DETACHED_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // runs in the forked child, which is never a process group leader, so
+    // setsid can't fail for being one
+    unsafe {
+        command.pre_exec(|| match libc::setsid() {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    let child = command.spawn().map_err(|e| format!("could not start daemon: {}", e))?;
+    Ok(child.id())
+}
+
+// Runs `run` until it ends or the process gets SIGTERM or SIGINT, so whatever the
+// caller holds, the pid file included, is dropped on the way out.
+pub async fn until_terminated(run: impl Future<Output = ()>) {
+    let (mut terminate, mut interrupt) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+        (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("could not listen for signals: {}", e);
+            return run.await;
+        }
+    };
+    tokio::select! {
+        _ = run => {}
+        _ = terminate.recv() => info!("terminated, shutting down"),
+        _ = interrupt.recv() => info!("interrupted, shutting down"),
+    }
+}
+
+// DATA_DIR, or the working directory. Holds the pid file, logs and admin socket.
+pub fn data_dir() -> Result<PathBuf, String> {
+    let dir = PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| ".".to_string()));
+    fs::create_dir_all(&dir).map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+// node.pid in the data dir, holding this process's pid while it runs. Removed
+// again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    // Fails if the pid already in the file is still running. A stale file left by
+    // a killed daemon is overwritten.
+    pub fn create(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join("node.pid");
+        if let Ok(pid) = fs::read_to_string(&path) {
+            if let Ok(pid) = pid.trim().parse::<libc::pid_t>() {
+                if is_running(pid) {
+                    return Err(format!("node is already running as pid {}", pid));
+                }
+            }
+        }
+        fs::write(&path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// signal 0 checks the process exists without signalling it. EPERM means it does,
+// it just belongs to someone else.
+fn is_running(pid: libc::pid_t) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Sends the log to node.log in `data_dir` instead of stderr, at the level in
+// RUST_LOG if it is a plain level name, info otherwise.
+pub fn init_logging(data_dir: &Path) -> Result<(), String> {
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Info);
+    let log = RotatingLog::open(data_dir.join("node.log"))?;
+    log::set_boxed_logger(Box::new(FileLogger {
+        level,
+        log: Mutex::new(log),
+    }))
+    .map_err(|e| format!("could not install logger: {}", e))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+struct FileLogger {
+    level: LevelFilter,
+    log: Mutex<RotatingLog>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {} {}: {}\n",
+            Utc::now().to_rfc3339(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Ok(mut log) = self.log.lock() {
+            // nowhere left to report a failed write
+            let _ = log.write(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut log) = self.log.lock() {
+            let _ = log.file.flush();
+        }
+    }
+}
+
+// node.log, moved to node.log.1 when full, node.log.1 to node.log.2 and so on
+struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingLog {
+    fn open(path: PathBuf) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(Self { path, file, size })
+    }
+
+    fn write(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..LOG_FILES).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        *self = Self::open(self.path.clone()).map_err(std::io::Error::other)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_is_exclusive_and_removed_on_drop() {
+        let dir = std::env::temp_dir().join(format!("daemon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pid_file = PidFile::create(&dir).unwrap();
+        // this process is the one running
+        assert!(PidFile::create(&dir).is_err());
+        let path = pid_file.path().to_path_buf();
+        drop(pid_file);
+        assert!(!path.exists());
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_pid_file_is_replaced() {
+        let dir = std::env::temp_dir().join(format!("daemon-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // above the kernel's pid limit, so never running
+        fs::write(dir.join("node.pid"), "2147483646\n").unwrap();
+        assert!(!is_running(2147483646));
+        drop(PidFile::create(&dir).unwrap());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use libp2p::identity::Keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::keys;
+use crate::params::ChainParams;
+use crate::transaction::Transaction;
+use crate::units::{Amount, Timestamp};
+use crate::{App, Block, BlockBody};
+
+// A scripted devnet: every key, timestamp and nonce seed is fixed, so running the
+// same script always produces the same chain. Unknown keys are rejected instead
+// of silently ignored.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DevnetScript {
+    pub genesis_timestamp: Timestamp,
+    #[serde(default)]
+    pub seed: u64,
+    // e.g. a trivial difficulty so scripts run instantly
+    #[serde(default)]
+    pub params: ChainParams,
+    // by name, funded in the genesis block
+    #[serde(default)]
+    pub accounts: BTreeMap<String, ScriptedAccount>,
+    pub blocks: Vec<ScriptedBlock>,
+}
+
+// An account whose key is derived from the script seed and its name, so its
+// address is the same on every run.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptedAccount {
+    #[serde(default)]
+    pub balance: Amount,
+}
+
+// A transfer signed by the account named `from` as of its block's timestamp.
+// `to` is an account name or an address.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptedTransfer {
+    pub from: String,
+    pub to: String,
+    pub amount: Amount,
+    #[serde(default)]
+    pub fee: Amount,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptedBlock {
+    pub data: String,
+    // signed elsewhere, included as they are
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+    // signed by the script's accounts, after `transactions`
+    #[serde(default)]
+    pub transfers: Vec<ScriptedTransfer>,
+    pub timestamp: Timestamp,
+    #[serde(default)]
+    pub miner: Option<String>,
+    // defaults to the script seed plus the block height
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl DevnetScript {
+    pub fn from_yaml(yaml: &str) -> Result<Self, String> {
+        serde_yaml::from_str(yaml).map_err(|e| format!("invalid devnet script: {}", e))
+    }
+
+    // the key of every account, by name
+    pub fn account_keys(&self) -> Result<BTreeMap<String, Keypair>, String> {
+        self.accounts
+            .keys()
+            .map(|name| {
+                let secret = Sha256::digest(format!("devnet:{}:{}", self.seed, name).as_bytes());
+                Ok((name.clone(), keys::decode_keypair(&hex::encode(secret))?))
+            })
+            .collect()
+    }
+
+    pub fn addresses(&self) -> Result<BTreeMap<String, String>, String> {
+        self.account_keys()?
+            .into_iter()
+            .map(|(name, keys)| {
+                let address = keys::address(&keys.public()).ok_or("account key has no address")?;
+                Ok((name, address))
+            })
+            .collect()
+    }
+
+    pub fn run(&self) -> Result<App, String> {
+        let account_keys = self.account_keys()?;
+        let addresses = self.addresses()?;
+        let mut params = self.params.clone();
+        params.genesis.timestamp = self.genesis_timestamp;
+        for (name, account) in &self.accounts {
+            params
+                .genesis
+                .allocations
+                .insert(addresses[name].clone(), account.balance);
+        }
+        let mut app = App::new(params);
+        for scripted in &self.blocks {
+            let difficulty = app.next_difficulty();
+            let parent = app.get_last_block();
+            let Some(id) = parent.header.id.next() else {
+                return Err("chain is full".to_string());
+            };
+            if scripted.timestamp < app.min_next_timestamp() {
+                return Err(format!("block {} is not later than the median time past", id));
+            }
+            let seed = scripted.seed.unwrap_or_else(|| self.seed.wrapping_add(id.0));
+            let mut transactions = scripted.transactions.clone();
+            for transfer in &scripted.transfers {
+                let keys = account_keys
+                    .get(&transfer.from)
+                    .ok_or_else(|| format!("block {}: unknown account {}", id, transfer.from))?;
+                let recipient = addresses.get(&transfer.to).unwrap_or(&transfer.to).clone();
+                transactions.push(Transaction::new_at(
+                    keys,
+                    parent.header.spec.clone(),
+                    recipient,
+                    transfer.amount,
+                    transfer.fee,
+                    scripted.timestamp,
+                )?);
+            }
+            let block = Block::mine_at(
+                &parent.header,
+                BlockBody::new(scripted.data.clone(), transactions),
+                scripted.miner.clone(),
+                difficulty,
+                scripted.timestamp,
+                app.params.hasher(),
+                &mut StdRng::seed_from_u64(seed),
+            );
+            app.try_add_block(block).map_err(|e| format!("block {}: {}", id, e))?;
+        }
+        Ok(app)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = include_str!("../scenarios/devnet.yaml");
+
+    #[test]
+    fn script_builds_the_same_funded_chain_every_run() {
+        let script = DevnetScript::from_yaml(SCENARIO).unwrap();
+        let (first, second) = (script.run().unwrap(), script.run().unwrap());
+        assert_eq!(first.get_last_block().header.hash, second.get_last_block().header.hash);
+        let addresses = script.addresses().unwrap();
+        let balance = |name: &str| first.balance_of(&addresses[name]);
+        assert_eq!(balance("alice"), Amount(895));
+        assert_eq!(balance("bob"), Amount(29));
+        assert_eq!(balance("carol"), Amount(125));
+    }
+
+    #[test]
+    fn transfers_need_a_known_sender() {
+        let script = DevnetScript::from_yaml(
+            "genesis_timestamp: 1700000000
+params:
+  initial_difficulty: 1
+blocks:
+  - data: x
+    timestamp: 1700000060
+    transfers:
+      - {from: mallory, to: bob, amount: 1}",
+        )
+        .unwrap();
+        let error = script.run().err().unwrap();
+        assert!(error.contains("unknown account mallory"), "{}", error);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::forks::ChainView;
+use crate::params::ChainParams;
+use crate::target::Target;
+use crate::{preimage, Payload};
+
+// Difficulty is the number of leading zero bits the hash must start with, so each
+// step doubles the expected work. Headers from version COMPACT_TARGET on carry a
+// compact target instead, which retargets in proportion to how far off the block
+// times were. The constants are the defaults of ChainParams.
+pub const INITIAL_DIFFICULTY: u32 = 16;
+pub const MIN_DIFFICULTY: u32 = 1;
+pub const RETARGET_INTERVAL: u64 = 10;
+pub const TARGET_BLOCK_TIME_SECS: i64 = 10;
+// most leading zero bits App::mine_n_blocks mines at, a few hundred hashes a block
+pub const MAX_DEV_DIFFICULTY: u32 = 8;
+
+// Difficulty required of the block that extends `chain`. It is recomputed every
+// retarget interval from the time the previous interval took, moving one step at
+// a time when blocks came more than twice as fast or slow as targeted. During the
+// first `bootstrap_blocks` blocks it is recomputed every block from the tip's own
+// block time instead. `version` is the new block's, a compact target following a
+// tip with zero bits starts from the same target.
+pub fn next_difficulty<'a, T: Payload + 'a>(
+    chain: impl Into<ChainView<'a, T>>,
+    params: &ChainParams,
+    version: u8,
+) -> u32 {
+    let chain = chain.into();
+    let compact = version >= preimage::COMPACT_TARGET;
+    let tip = match chain.last() {
+        Some(tip) => tip,
+        None if compact => return Target::from_zero_bits(params.initial_difficulty).to_compact(),
+        None => return params.initial_difficulty,
+    };
+    match (adjustment(chain, params), compact) {
+        (Some((elapsed, expected)), true) => retarget(tip.header.target(), elapsed, expected).to_compact(),
+        (Some((elapsed, expected)), false) => step(tip.header.difficulty, elapsed, expected),
+        (None, true) => tip.header.target().to_compact(),
+        (None, false) => tip.header.difficulty,
+    }
+}
+
+// the time the blocks just before the next one took, and what it should have been,
+// if the difficulty is recomputed there
+fn adjustment<T: Payload>(chain: ChainView<T>, params: &ChainParams) -> Option<(i64, i64)> {
+    let tip = chain.last()?;
+    let height = tip.header.id.0.saturating_add(1);
+    if height < params.bootstrap_blocks {
+        let parent = chain.get(chain.len().checked_sub(2)?)?;
+        return Some((
+            tip.header.timestamp.saturating_secs_since(parent.header.timestamp),
+            params.target_block_time_secs,
+        ));
+    }
+    let interval = params.retarget_interval;
+    if interval == 0 || height % interval != 0 || height < interval {
+        return None;
+    }
+    let first = chain.get((height - interval) as usize)?;
+    Some((
+        tip.header.timestamp.saturating_secs_since(first.header.timestamp),
+        params.target_block_time_secs * (interval as i64 - 1),
+    ))
+}
+
+// one step up or down when `elapsed` is off from `expected` by more than twice
+fn step(difficulty: u32, elapsed: i64, expected: i64) -> u32 {
+    if elapsed < expected / 2 {
+        difficulty + 1
+    } else if elapsed > expected * 2 {
+        difficulty.saturating_sub(1).max(MIN_DIFFICULTY)
+    } else {
+        difficulty
+    }
+}
+
+// `target` scaled by `elapsed / expected`, by at most 4 times either way and never
+// easier than MIN_DIFFICULTY
+fn retarget(target: Target, elapsed: i64, expected: i64) -> Target {
+    let expected = expected.max(1);
+    let elapsed = elapsed.clamp(expected / 4, expected * 4).max(1);
+    target
+        .scaled(elapsed as u64, expected as u64)
+        .min(Target::from_zero_bits(MIN_DIFFICULTY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{BlockHeight, Timestamp};
+    use crate::{Block, BlockHeader};
+
+    // a genesis at time 0 and one block per entry of `block_times`, that many seconds
+    // after its parent, each at the difficulty next_difficulty asks of it
+    fn grow(params: &ChainParams, version: u8, block_times: &[i64]) -> Vec<Block<String>> {
+        let mut blocks: Vec<Block<String>> = Vec::new();
+        let mut timestamp = Timestamp(0);
+        for (height, block_time) in std::iter::once(0).chain(block_times.iter().copied()).enumerate() {
+            timestamp = timestamp.saturating_add_secs(block_time);
+            let difficulty = next_difficulty(&blocks, params, version);
+            blocks.push(Block {
+                header: BlockHeader {
+                    id: BlockHeight(height as u64),
+                    timestamp,
+                    nonce: 0,
+                    hash: height.to_string(),
+                    previous_hash: String::new(),
+                    merkle_root: String::new(),
+                    miner: None,
+                    difficulty,
+                    spec: String::new(),
+                    version,
+                },
+                data: String::new(),
+                signature: None,
+            });
+        }
+        blocks
+    }
+
+    fn difficulties(blocks: &[Block<String>]) -> Vec<u32> {
+        blocks.iter().map(|block| block.header.difficulty).collect()
+    }
+
+    #[test]
+    fn step_moves_one_bit_past_twice_off() {
+        assert_eq!(step(10, 44, 90), 11);
+        assert_eq!(step(10, 45, 90), 10);
+        assert_eq!(step(10, 180, 90), 10);
+        assert_eq!(step(10, 181, 90), 9);
+        assert_eq!(step(MIN_DIFFICULTY, 1000, 90), MIN_DIFFICULTY);
+    }
+
+    #[test]
+    fn retarget_is_clamped_to_four_times_either_way() {
+        let target = Target::from_zero_bits(32);
+        assert_eq!(retarget(target, 200, 100), target.scaled(2, 1));
+        assert_eq!(retarget(target, 50, 100), target.scaled(1, 2));
+        assert_eq!(retarget(target, 400, 100), target.scaled(4, 1));
+        assert_eq!(retarget(target, 100 * 100, 100), target.scaled(4, 1));
+        assert_eq!(retarget(target, 25, 100), target.scaled(1, 4));
+        assert_eq!(retarget(target, 0, 100), target.scaled(1, 4));
+        // timestamps may go backwards within the median time rule
+        assert_eq!(retarget(target, -500, 100), target.scaled(1, 4));
+    }
+
+    #[test]
+    fn retarget_never_gets_easier_than_min_difficulty() {
+        let easiest = Target::from_zero_bits(MIN_DIFFICULTY);
+        assert_eq!(retarget(easiest, 400, 100), easiest);
+        assert_eq!(retarget(Target::from_zero_bits(MIN_DIFFICULTY + 1), 400, 100), easiest);
+    }
+
+    #[test]
+    fn difficulty_changes_only_at_the_interval() {
+        let params = ChainParams::default();
+        let blocks = grow(&params, preimage::JSON_PREIMAGE, &[1; 20]);
+        let initial = params.initial_difficulty;
+        assert!(difficulties(&blocks[..10])
+            .iter()
+            .all(|&difficulty| difficulty == initial));
+        assert!(difficulties(&blocks[10..20])
+            .iter()
+            .all(|&difficulty| difficulty == initial + 1));
+        assert_eq!(blocks[20].header.difficulty, initial + 2);
+    }
+
+    #[test]
+    fn window_spans_the_last_interval_only() {
+        let params = ChainParams::default();
+        // a slow first interval doesn't count against a fast second one
+        let mut block_times = vec![1000; 10];
+        block_times.extend([1; 9]);
+        let blocks = grow(&params, preimage::JSON_PREIMAGE, &block_times);
+        assert_eq!(blocks[10].header.difficulty, params.initial_difficulty - 1);
+        assert_eq!(
+            next_difficulty(&blocks, &params, preimage::JSON_PREIMAGE),
+            params.initial_difficulty
+        );
+
+        // the window runs from block height - interval to the tip, interval - 1 block times
+        let on_target = grow(&params, preimage::JSON_PREIMAGE, &[5; 9]);
+        assert_eq!(
+            next_difficulty(&on_target, &params, preimage::JSON_PREIMAGE),
+            params.initial_difficulty
+        );
+        let mut block_times = vec![5; 9];
+        block_times[8] = 4;
+        let fast = grow(&params, preimage::JSON_PREIMAGE, &block_times);
+        assert_eq!(
+            next_difficulty(&fast, &params, preimage::JSON_PREIMAGE),
+            params.initial_difficulty + 1
+        );
+    }
+
+    #[test]
+    fn bootstrap_ramps_every_block_then_hands_over() {
+        let params = ChainParams {
+            bootstrap_blocks: 5,
+            ..ChainParams::default()
+        };
+        let initial = params.initial_difficulty;
+        let blocks = grow(&params, preimage::JSON_PREIMAGE, &[1; 12]);
+        // block 1 has only genesis before it, no block time to go by
+        assert_eq!(
+            difficulties(&blocks),
+            [
+                initial,
+                initial,
+                initial + 1,
+                initial + 2,
+                initial + 3,
+                initial + 3,
+                initial + 3,
+                initial + 3,
+                initial + 3,
+                initial + 3,
+                initial + 4,
+                initial + 4,
+                initial + 4,
+            ]
+        );
+
+        // slow blocks ramp down the same way, only as far as MIN_DIFFICULTY
+        let params = ChainParams {
+            initial_difficulty: 3,
+            ..params
+        };
+        let blocks = grow(&params, preimage::JSON_PREIMAGE, &[100; 6]);
+        assert_eq!(
+            difficulties(&blocks),
+            [3, 3, 2, MIN_DIFFICULTY, MIN_DIFFICULTY, MIN_DIFFICULTY, MIN_DIFFICULTY]
+        );
+    }
+
+    #[test]
+    fn compact_targets_retarget_in_proportion() {
+        // 180 seconds expected of an interval, which divides by 4
+        let params = ChainParams {
+            target_block_time_secs: 20,
+            ..ChainParams::default()
+        };
+        let initial = Target::from_zero_bits(params.initial_difficulty).to_compact();
+        assert_eq!(
+            next_difficulty(&Vec::<Block<String>>::new(), &params, preimage::COMPACT_TARGET),
+            initial
+        );
+        let start = Target::from_compact(initial).unwrap();
+
+        // twice the expected time halves the work
+        let slow = grow(&params, preimage::COMPACT_TARGET, &[40; 10]);
+        assert!(difficulties(&slow[..10]).iter().all(|&bits| bits == initial));
+        assert_eq!(slow[10].header.target(), start.scaled(2, 1));
+
+        // far off either way moves by 4 times at most
+        let stalled = grow(&params, preimage::COMPACT_TARGET, &[10_000; 10]);
+        assert_eq!(stalled[10].header.target(), start.scaled(4, 1));
+        let rushed = grow(&params, preimage::COMPACT_TARGET, &[0; 10]);
+        assert_eq!(rushed[10].header.target(), start.scaled(1, 4));
+    }
+}
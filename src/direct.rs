@@ -0,0 +1,304 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use libp2p::{
+    core::upgrade::ProtocolName,
+    futures::{AsyncRead, AsyncWrite},
+    identity::{Keypair, PublicKey},
+    request_response::{ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig},
+    PeerId,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::keys;
+use crate::p2p::{read_json, write_json};
+use crate::sync::REQUEST_TIMEOUT;
+
+pub const DIRECT_PROTOCOL: &str = "/blockchain-basic/direct/1";
+const MAX_DIRECT_MESSAGE_SIZE: usize = 64 * 1024;
+const INBOX_SIZE: usize = 100;
+// signed messages older or further ahead than this are refused, and their nonces
+// are only remembered for as long
+const MAX_MESSAGE_AGE_SECS: i64 = 5 * 60;
+
+// A message for one peer only. The connection itself is encrypted by noise; the
+// optional signature binds the body to a validator key, the recipient, a
+// timestamp and a random nonce, so it can't be forged by the relaying peer,
+// replayed to someone else or delivered twice.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DirectMessage {
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // unix seconds and a random nonce, set on signed messages only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DirectAck {
+    pub accepted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceivedMessage {
+    pub from: String,
+    // set only when the signature checked out against the validator roster
+    pub validator: Option<String>,
+    pub body: String,
+    pub received_at: i64,
+}
+
+// Every field after a u32 length, so no two field lists sign the same bytes.
+fn signing_payload(recipient: &PeerId, validator: &str, timestamp: i64, nonce: u64, body: &str) -> Vec<u8> {
+    let mut payload = b"direct/2".to_vec();
+    for field in [
+        &recipient.to_bytes()[..],
+        validator.as_bytes(),
+        &timestamp.to_be_bytes(),
+        &nonce.to_be_bytes(),
+        body.as_bytes(),
+    ] {
+        payload.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        payload.extend_from_slice(field);
+    }
+    payload
+}
+
+// This node's validator name and signing key.
+pub struct ValidatorIdentity {
+    pub name: String,
+    pub keys: Keypair,
+}
+
+impl DirectMessage {
+    pub fn new(recipient: &PeerId, body: String, identity: Option<&ValidatorIdentity>) -> Result<Self, String> {
+        let mut message = DirectMessage {
+            body,
+            validator: None,
+            signature: None,
+            timestamp: None,
+            nonce: None,
+        };
+        if let Some(identity) = identity {
+            let (timestamp, nonce) = (Utc::now().timestamp(), rand::random());
+            let signature = identity
+                .keys
+                .sign(&signing_payload(
+                    recipient,
+                    &identity.name,
+                    timestamp,
+                    nonce,
+                    &message.body,
+                ))
+                .map_err(|e| format!("could not sign message: {}", e))?;
+            message.validator = Some(identity.name.clone());
+            message.signature = Some(hex::encode(signature));
+            message.timestamp = Some(timestamp);
+            message.nonce = Some(nonce);
+        }
+        Ok(message)
+    }
+}
+
+// Validators by name, plus what this node has received.
+#[derive(Default)]
+pub struct DirectMessaging {
+    pub identity: Option<ValidatorIdentity>,
+    pub roster: HashMap<String, PublicKey>,
+    pub inbox: VecDeque<ReceivedMessage>,
+    // (validator, nonce) of recent signed messages, with their timestamps
+    seen: HashMap<(String, u64), i64>,
+}
+
+impl DirectMessaging {
+    // VALIDATORS="alice=<hex public key>,bob=<hex public key>", and for this node
+    // VALIDATOR_NAME plus VALIDATOR_KEY (hex secret key)
+    pub fn from_env() -> Result<Self, String> {
+        let mut roster = HashMap::new();
+        if let Ok(validators) = std::env::var("VALIDATORS") {
+            for entry in validators.split(',').filter(|entry| !entry.is_empty()) {
+                let (name, key) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid validator entry: {}", entry))?;
+                roster.insert(name.to_string(), keys::decode_public_key(key)?);
+            }
+        }
+        let identity = match (std::env::var("VALIDATOR_NAME"), std::env::var("VALIDATOR_KEY")) {
+            (Ok(name), Ok(key)) => Some(ValidatorIdentity {
+                name,
+                keys: keys::decode_keypair(&key)?,
+            }),
+            _ => None,
+        };
+        Ok(Self {
+            identity,
+            roster,
+            ..Self::default()
+        })
+    }
+
+    // Checks the signature, if any, and stores the message. Messages claiming to be
+    // from a validator are refused unless the signature matches the roster, the
+    // timestamp is recent and the nonce hasn't been seen from that validator.
+    pub fn receive(&mut self, from: &PeerId, local: &PeerId, message: DirectMessage) -> Result<(), String> {
+        let now = Utc::now().timestamp();
+        let validator = match (&message.validator, &message.signature) {
+            (None, None) => None,
+            (Some(name), Some(signature)) => {
+                let (Some(timestamp), Some(nonce)) = (message.timestamp, message.nonce) else {
+                    return Err("signed message has no timestamp and nonce".to_string());
+                };
+                let key = self
+                    .roster
+                    .get(name)
+                    .ok_or_else(|| format!("unknown validator {}", name))?;
+                let signature = hex::decode(signature).map_err(|_| "malformed signature".to_string())?;
+                let payload = signing_payload(local, name, timestamp, nonce, &message.body);
+                if !key.verify(&payload, &signature) {
+                    return Err(format!("bad signature for validator {}", name));
+                }
+                if now.abs_diff(timestamp) > MAX_MESSAGE_AGE_SECS as u64 {
+                    return Err(format!("message from validator {} is stale", name));
+                }
+                self.seen
+                    .retain(|_, seen| now.abs_diff(*seen) <= MAX_MESSAGE_AGE_SECS as u64);
+                if self.seen.insert((name.clone(), nonce), timestamp).is_some() {
+                    return Err(format!("message from validator {} was already received", name));
+                }
+                Some(name.clone())
+            }
+            _ => return Err("validator name and signature must come together".to_string()),
+        };
+        if self.inbox.len() == INBOX_SIZE {
+            self.inbox.pop_front();
+        }
+        self.inbox.push_back(ReceivedMessage {
+            from: from.to_string(),
+            validator,
+            body: message.body,
+            received_at: now,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectProtocol;
+
+impl ProtocolName for DirectProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        DIRECT_PROTOCOL.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DirectCodec;
+
+#[async_trait]
+impl RequestResponseCodec for DirectCodec {
+    type Protocol = DirectProtocol;
+    type Request = DirectMessage;
+    type Response = DirectAck;
+
+    async fn read_request<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<DirectMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_DIRECT_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<DirectAck>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_DIRECT_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T>(&mut self, _: &DirectProtocol, io: &mut T, req: DirectMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &DirectProtocol, io: &mut T, res: DirectAck) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &res).await
+    }
+}
+
+pub fn new_direct_messaging() -> RequestResponse<DirectCodec> {
+    let mut cfg = RequestResponseConfig::default();
+    cfg.set_request_timeout(REQUEST_TIMEOUT);
+    RequestResponse::new(
+        DirectCodec,
+        std::iter::once((DirectProtocol, ProtocolSupport::Full)),
+        cfg,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messaging() -> (DirectMessaging, ValidatorIdentity) {
+        let keys = Keypair::generate_ed25519();
+        let mut messaging = DirectMessaging::default();
+        messaging.roster.insert("alice".to_string(), keys.public());
+        let identity = ValidatorIdentity {
+            name: "alice".to_string(),
+            keys,
+        };
+        (messaging, identity)
+    }
+
+    #[test]
+    fn signed_message_is_delivered_once() {
+        let (mut messaging, identity) = messaging();
+        let (sender, local) = (PeerId::random(), PeerId::random());
+        let message = DirectMessage::new(&local, "hi".to_string(), Some(&identity)).unwrap();
+        messaging.receive(&sender, &local, message.clone()).unwrap();
+        assert_eq!(messaging.inbox[0].validator.as_deref(), Some("alice"));
+        assert!(messaging.receive(&sender, &local, message).is_err());
+        assert_eq!(messaging.inbox.len(), 1);
+    }
+
+    #[test]
+    fn signature_covers_recipient_body_and_time() {
+        let (mut messaging, identity) = messaging();
+        let (sender, local) = (PeerId::random(), PeerId::random());
+        let elsewhere = DirectMessage::new(&PeerId::random(), "hi".to_string(), Some(&identity)).unwrap();
+        assert!(messaging.receive(&sender, &local, elsewhere).is_err());
+        let mut tampered = DirectMessage::new(&local, "hi".to_string(), Some(&identity)).unwrap();
+        tampered.body = "bye".to_string();
+        assert!(messaging.receive(&sender, &local, tampered).is_err());
+        let mut stale = DirectMessage::new(&local, "hi".to_string(), Some(&identity)).unwrap();
+        let timestamp = Utc::now().timestamp() - MAX_MESSAGE_AGE_SECS - 1;
+        let payload = signing_payload(&local, "alice", timestamp, stale.nonce.unwrap(), "hi");
+        stale.signature = Some(hex::encode(identity.keys.sign(&payload).unwrap()));
+        stale.timestamp = Some(timestamp);
+        assert!(messaging.receive(&sender, &local, stale).is_err());
+        assert!(messaging.inbox.is_empty());
+    }
+
+    #[test]
+    fn fields_are_length_prefixed() {
+        let recipient = PeerId::random();
+        assert_ne!(
+            signing_payload(&recipient, "a", 0, 0, "b:c"),
+            signing_payload(&recipient, "a:b", 0, 0, "c")
+        );
+    }
+}
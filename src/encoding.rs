@@ -0,0 +1,286 @@
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "bincode")]
+use serde_json::{Map, Number, Value};
+
+use crate::parse_env;
+
+// first byte of binary messages, never the start of a JSON document
+#[cfg(feature = "bincode")]
+const BINARY_TAG: u8 = 0;
+// same nesting limit as serde_json
+#[cfg(feature = "bincode")]
+const MAX_DEPTH: usize = 128;
+
+// How this node encodes gossip and chain sync messages. JSON is what every node
+// understands. Bincode, with the bincode feature, is much smaller, but only nodes
+// built with the feature can read it. Either way they read both, see `decode`.
+// Only messages are affected: the chain file stays JSON, since store::load_headers
+// streams headers out of it and archives have their own format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "json" => Ok(Self::Json),
+            #[cfg(feature = "bincode")]
+            "bincode" => Ok(Self::Bincode),
+            #[cfg(not(feature = "bincode"))]
+            "bincode" => Err("built without the bincode feature".to_string()),
+            _ => Err(format!("unknown encoding {}", s)),
+        }
+    }
+}
+
+impl Encoding {
+    // WIRE_ENCODING, json unless set
+    pub fn from_env() -> Result<Self, String> {
+        Ok(parse_env("WIRE_ENCODING")?.unwrap_or_default())
+    }
+
+    pub fn encode<M: Serialize + ?Sized>(self, msg: &M) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Json => serde_json::to_vec(msg).map_err(|e| e.to_string()),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => encode_binary(msg),
+        }
+    }
+}
+
+// Decodes a message in either encoding, whatever this node sends itself.
+pub fn decode<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, String> {
+    #[cfg(feature = "bincode")]
+    if let Some((&BINARY_TAG, binary)) = bytes.split_first() {
+        return decode_binary(binary);
+    }
+    serde_json::from_slice(bytes).map_err(|e| e.to_string())
+}
+
+// Binary messages carry the JSON value tree of the message rather than the message
+// itself, so serde attributes such as flatten and skip_serializing_if, which
+// bincode can't handle, keep working. The tree is flattened into tokens, object
+// keys are stored once per message and hex strings as raw bytes.
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+struct BinaryMessage {
+    keys: Vec<String>,
+    tokens: Vec<Token>,
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+enum Token {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Hex(Vec<u8>),
+    // followed by this many values
+    Array(usize),
+    // followed by this many pairs of a Key and a value
+    Object(usize),
+    // index into the message's keys
+    Key(usize),
+}
+
+#[cfg(feature = "bincode")]
+fn encode_binary<M: Serialize + ?Sized>(msg: &M) -> Result<Vec<u8>, String> {
+    use bincode::Options;
+
+    let value = serde_json::to_value(msg).map_err(|e| e.to_string())?;
+    let mut message = BinaryMessage {
+        keys: vec![],
+        tokens: vec![],
+    };
+    message.push(&value);
+    let mut bytes = vec![BINARY_TAG];
+    bytes.extend(
+        bincode::DefaultOptions::new()
+            .serialize(&message)
+            .map_err(|e| e.to_string())?,
+    );
+    Ok(bytes)
+}
+
+#[cfg(feature = "bincode")]
+fn decode_binary<M: DeserializeOwned>(bytes: &[u8]) -> Result<M, String> {
+    use bincode::Options;
+
+    let message: BinaryMessage = bincode::DefaultOptions::new()
+        .deserialize(bytes)
+        .map_err(|e| e.to_string())?;
+    let mut tokens = message.tokens.into_iter();
+    let value = read_value(&mut tokens, &message.keys, 0)?;
+    if tokens.next().is_some() {
+        return Err("trailing tokens".to_string());
+    }
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "bincode")]
+impl BinaryMessage {
+    fn push(&mut self, value: &Value) {
+        let token = match value {
+            Value::Null => Token::Null,
+            Value::Bool(b) => Token::Bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => Token::Unsigned(n),
+                (_, Some(n)) => Token::Signed(n),
+                _ => Token::Float(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) if is_hex(s) => Token::Hex(hex::decode(s).expect("checked to be hex")),
+            Value::String(s) => Token::String(s.clone()),
+            Value::Array(values) => {
+                self.tokens.push(Token::Array(values.len()));
+                values.iter().for_each(|value| self.push(value));
+                return;
+            }
+            Value::Object(fields) => {
+                self.tokens.push(Token::Object(fields.len()));
+                for (key, value) in fields {
+                    let index = match self.keys.iter().position(|known| known == key) {
+                        Some(index) => index,
+                        None => {
+                            self.keys.push(key.clone());
+                            self.keys.len() - 1
+                        }
+                    };
+                    self.tokens.push(Token::Key(index));
+                    self.push(value);
+                }
+                return;
+            }
+        };
+        self.tokens.push(token);
+    }
+}
+
+// only lowercase hex of whole bytes, so the string comes back exactly as it was
+#[cfg(feature = "bincode")]
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.len().is_multiple_of(2) && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(feature = "bincode")]
+fn read_value(tokens: &mut impl Iterator<Item = Token>, keys: &[String], depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("message nested too deeply".to_string());
+    }
+    Ok(match tokens.next().ok_or("message ends early")? {
+        Token::Null => Value::Null,
+        Token::Bool(b) => Value::Bool(b),
+        Token::Unsigned(n) => Value::from(n),
+        Token::Signed(n) => Value::from(n),
+        Token::Float(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        Token::String(s) => Value::String(s),
+        Token::Hex(bytes) => Value::String(hex::encode(bytes)),
+        Token::Array(len) => {
+            let mut values = vec![];
+            for _ in 0..len {
+                values.push(read_value(tokens, keys, depth + 1)?);
+            }
+            Value::Array(values)
+        }
+        Token::Object(len) => {
+            let mut fields = Map::new();
+            for _ in 0..len {
+                let key = match tokens.next() {
+                    Some(Token::Key(index)) => keys.get(index).ok_or("unknown key")?.clone(),
+                    _ => return Err("expected a key".to_string()),
+                };
+                fields.insert(key, read_value(tokens, keys, depth + 1)?);
+            }
+            Value::Object(fields)
+        }
+        Token::Key(_) => return Err("unexpected key".to_string()),
+    })
+}
+
+#[cfg(all(test, feature = "bincode"))]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::capabilities::Capabilities;
+    use crate::checkpoint::SignedCheckpoint;
+    use crate::p2p::ChainResponse;
+    use crate::testing::dev_params;
+    use crate::transaction::{Coinbase, Transaction};
+    use crate::units::{Amount, BlockHeight};
+    use crate::{App, Block, BlockBody};
+
+    // decodes to the same value from either encoding
+    fn round_trips<M: Serialize + DeserializeOwned>(msg: &M) {
+        let expected = serde_json::to_value(msg).unwrap();
+        for encoding in [Encoding::Json, Encoding::Bincode] {
+            let bytes = encoding.encode(msg).unwrap();
+            let decoded: M = decode(&bytes).unwrap();
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), expected, "{:?}", encoding);
+        }
+    }
+
+    // a mined chain whose tip also has a transaction, a coinbase and a signature
+    fn blocks() -> Vec<Block> {
+        let mut app: App = App::new(dev_params());
+        app.mine_n_blocks(2, |height| BlockBody::new(format!("block {}", height), vec![]))
+            .unwrap();
+        let mut blocks = app.blocks.clone();
+        let keys = Keypair::generate_ed25519();
+        let tip = blocks.last_mut().unwrap();
+        let transaction = Transaction::new(
+            &keys,
+            tip.header.spec.clone(),
+            "recipient".to_string(),
+            Amount(5),
+            Amount(1),
+        );
+        tip.data.transactions.push(transaction.unwrap());
+        tip.data.coinbase = Some(Coinbase {
+            recipient: "miner".to_string(),
+            amount: Amount(50),
+        });
+        tip.sign(&keys).unwrap();
+        blocks
+    }
+
+    #[test]
+    fn block_round_trips() {
+        for block in blocks() {
+            round_trips(&block);
+        }
+    }
+
+    #[test]
+    fn chain_response_round_trips() {
+        round_trips(&ChainResponse {
+            blocks: blocks(),
+            request_id: 7,
+            capabilities: None,
+        });
+        round_trips(&ChainResponse {
+            blocks: blocks(),
+            request_id: 8,
+            capabilities: Some(Capabilities::ARCHIVAL),
+        });
+    }
+
+    #[test]
+    fn signed_checkpoint_round_trips() {
+        let keys = Keypair::generate_ed25519();
+        let checkpoint = SignedCheckpoint::sign(&keys, BlockHeight(2), blocks()[2].header.hash.clone()).unwrap();
+        round_trips(&checkpoint);
+    }
+}
@@ -0,0 +1,94 @@
+use thiserror::Error;
+
+use crate::units::{Amount, BlockHeight, Timestamp};
+use crate::{Block, BlockBody, Payload};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChainError {
+    #[error("first block is not this network's genesis block")]
+    GenesisMismatch,
+    #[error("expected block id {expected}, got {got}")]
+    NonSequentialId { expected: BlockHeight, got: BlockHeight },
+    #[error("previous hash does not match the parent block")]
+    InvalidPreviousHash,
+    #[error("block belongs to a chain with different params")]
+    SpecMismatch,
+    #[error("expected difficulty {expected}, got {got}")]
+    InvalidDifficulty { expected: u32, got: u32 },
+    #[error("hash does not meet the block's difficulty")]
+    InsufficientWork,
+    #[error("hash does not match the block header")]
+    HashMismatch,
+    #[error("header version {0} is unknown or older than its parent's")]
+    InvalidHeaderVersion(u8),
+    #[error("body does not match the header's Merkle root")]
+    MerkleRootMismatch,
+    #[error("transaction {0} has an invalid signature")]
+    InvalidTransaction(String),
+    #[error("relayed header {0} does not extend the relayed chain")]
+    InvalidRelay(String),
+    #[error("relayed header {0} is from a chain this one doesn't relay")]
+    UnknownRelaySource(String),
+    #[error("transaction {0} is not signed with the chain's signature scheme")]
+    WrongSignatureScheme(String),
+    #[error("transaction {0} is signed for another chain")]
+    WrongChain(String),
+    #[error("contract op {0} is malformed or over the block's gas")]
+    InvalidContractOp(usize),
+    #[error("transaction {0} is already on the chain or twice in the block")]
+    DuplicateTransaction(String),
+    #[error("transaction {0} spends more than its sender has")]
+    Overspend(String),
+    #[error("crediting {0} overflows its balance")]
+    BalanceOverflow(String),
+    #[error("the block's fees add up to more than an amount can hold")]
+    FeeOverflow,
+    #[error("{0} is not a valid name")]
+    InvalidName(String),
+    #[error("name {0} is held by another address")]
+    NameTaken(String),
+    #[error("name transaction pays a fee of {fee}, the minimum is {min}")]
+    NameFeeTooLow { fee: Amount, min: Amount },
+    #[error("coinbase pays {amount}, more than the {max} reward and fees")]
+    InvalidCoinbase { amount: Amount, max: Amount },
+    #[error("timestamp {0} is before the median time past or too far in the future")]
+    InvalidTimestamp(Timestamp),
+    #[error("data is {size} bytes, the limit is {max}")]
+    DataTooLarge { size: usize, max: usize },
+    #[error("block is {size} bytes, the limit is {max}")]
+    BlockTooLarge { size: usize, max: usize },
+    #[error("block conflicts with the checkpoint at height {0}")]
+    CheckpointConflict(BlockHeight),
+    #[error("height {height} is already pinned to {pinned}")]
+    CheckpointPinned { height: BlockHeight, pinned: String },
+    #[error("block is missing a valid signet signature")]
+    MissingSignature,
+    #[error("slot {0} is not after its parent's or is more than one slot ahead of the local clock")]
+    InvalidSlot(i64),
+    #[error("block is not signed by the proposer of its slot")]
+    IneligibleProposer,
+    #[error("block is not signed by the authority whose turn it is")]
+    OutOfTurn,
+    #[error("block is not signed by any of the chain's authorities")]
+    UnknownAuthority,
+    #[error("block is already known")]
+    DuplicateBlock,
+    #[error("competing block lost the tie-break")]
+    LostTieBreak,
+    #[error("block data breaks the payload rules: {0}")]
+    InvalidPayload(String),
+    #[error("chain spec requires payload rules {0}, which this node doesn't have")]
+    UnknownPayloadRules(String),
+    #[error("parent block is unknown, kept until it arrives")]
+    Orphan,
+}
+
+// Why a chain was rejected: the first block that broke a rule, its index in the
+// chain and the rule it broke.
+#[derive(Debug, Clone, Error)]
+#[error("block {index} ({hash}) is invalid: {rule}", hash = .block.header.hash)]
+pub struct ValidationReport<T: Payload = BlockBody> {
+    pub index: usize,
+    pub rule: ChainError,
+    pub block: Box<Block<T>>,
+}
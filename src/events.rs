@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::progress::MiningProgress;
+use crate::transaction::Transaction;
+use crate::units::BlockHeight;
+use crate::{Block, Payload};
+
+// blocks remembered per subscription to report them as disconnected on a reorg
+const MAX_DELIVERED: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Block,
+    Transaction,
+    PendingTransaction,
+    Disconnected,
+    MiningProgress,
+}
+
+// What subscribers to the node's events are sent, see EventFilter.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NodeEvent {
+    // a block reached the subscription's confirmations on the chain
+    Block {
+        height: BlockHeight,
+        hash: String,
+        miner: Option<String>,
+        confirmations: u64,
+    },
+    // a transaction of such a block
+    Transaction {
+        height: BlockHeight,
+        block_hash: String,
+        transaction: Transaction,
+    },
+    // a transaction entered the mempool
+    PendingTransaction {
+        transaction: Transaction,
+    },
+    // a block reported before was rolled back, its transactions with it
+    Disconnected {
+        height: BlockHeight,
+        hash: String,
+    },
+    // periodically while the node mines a block
+    MiningProgress(MiningProgress),
+}
+
+// Which events a subscriber wants, checked on the node so consumers of a busy
+// network don't have to receive and throw away everything else. Empty fields
+// don't filter.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventFilter {
+    // blocks `from..to`; from the next block on if `from` is not set, earlier ones
+    // are replayed. Pending transactions have no height and pass.
+    pub from: Option<BlockHeight>,
+    pub to: Option<BlockHeight>,
+    // blocks paying it and transactions from or to it
+    pub address: Option<String>,
+    pub kinds: Vec<EventKind>,
+    // blocks are reported once this many blocks, themselves included, are on the
+    // chain. 0 counts as 1, i.e. as soon as they are accepted.
+    pub min_confirmations: u64,
+}
+
+impl EventFilter {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+
+    fn in_range(&self, height: BlockHeight) -> bool {
+        self.from.is_none_or(|from| height >= from) && self.to.is_none_or(|to| height < to)
+    }
+
+    fn involves(&self, transaction: &Transaction) -> bool {
+        match &self.address {
+            Some(address) => transaction.recipient == *address || transaction.sender().as_ref() == Ok(address),
+            None => true,
+        }
+    }
+
+    fn pays<T: Payload>(&self, block: &Block<T>) -> bool {
+        match &self.address {
+            Some(address) => {
+                block
+                    .data
+                    .coinbase()
+                    .is_some_and(|coinbase| coinbase.recipient == *address)
+                    || block
+                        .data
+                        .transactions()
+                        .iter()
+                        .any(|transaction| self.involves(transaction))
+            }
+            None => true,
+        }
+    }
+}
+
+struct Subscription {
+    filter: EventFilter,
+    sender: mpsc::UnboundedSender<NodeEvent>,
+    // height of the next block to report
+    next: BlockHeight,
+    // height and hash of blocks passed on so far, newest last, and whether any
+    // event was sent for them
+    delivered: VecDeque<(BlockHeight, String, bool)>,
+}
+
+// The node's event subscriptions. `update` is run after every event the node
+// handles and reports whatever changed on the chain since.
+#[derive(Default)]
+pub struct EventHub {
+    subscriptions: Vec<Subscription>,
+}
+
+impl EventHub {
+    pub fn subscribe(&mut self, filter: EventFilter, tip: BlockHeight) -> mpsc::UnboundedReceiver<NodeEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let next = filter.from.or(tip.next()).unwrap_or(BlockHeight::MAX);
+        self.subscriptions.push(Subscription {
+            filter,
+            sender,
+            next,
+            delivered: VecDeque::new(),
+        });
+        receiver
+    }
+
+    pub fn transaction_pending(&mut self, transaction: &Transaction) {
+        for subscription in &self.subscriptions {
+            let filter = &subscription.filter;
+            if filter.wants(EventKind::PendingTransaction) && filter.involves(transaction) {
+                let _ = subscription.sender.send(NodeEvent::PendingTransaction {
+                    transaction: transaction.clone(),
+                });
+            }
+        }
+    }
+
+    // only to subscribers that ask for it, it isn't of the chain
+    pub fn mining_progress(&mut self, progress: MiningProgress) {
+        for subscription in &self.subscriptions {
+            if subscription.filter.kinds.contains(&EventKind::MiningProgress) {
+                let _ = subscription.sender.send(NodeEvent::MiningProgress(progress));
+            }
+        }
+    }
+
+    pub fn update<T: Payload>(&mut self, chain: &[Block<T>]) {
+        self.subscriptions
+            .retain(|subscription| !subscription.sender.is_closed());
+        for subscription in &mut self.subscriptions {
+            subscription.update(chain);
+        }
+    }
+}
+
+impl Subscription {
+    fn update<T: Payload>(&mut self, chain: &[Block<T>]) {
+        // reported blocks that are no longer on the chain
+        while let Some((height, hash, reported)) = self.delivered.back() {
+            if chain
+                .get(height.index())
+                .is_some_and(|block| block.header.hash == *hash)
+            {
+                break;
+            }
+            if *reported && self.filter.wants(EventKind::Disconnected) {
+                let _ = self.sender.send(NodeEvent::Disconnected {
+                    height: *height,
+                    hash: hash.clone(),
+                });
+            }
+            self.next = *height;
+            self.delivered.pop_back();
+        }
+
+        let confirmations = self.filter.min_confirmations.max(1);
+        let confirmed = (chain.len() as u64).saturating_sub(confirmations - 1);
+        while self.next.0 < confirmed && self.filter.to.is_none_or(|to| self.next < to) {
+            let block = &chain[self.next.index()];
+            let reported = self.report(block, chain.len() as u64 - self.next.0);
+            if self.delivered.len() >= MAX_DELIVERED {
+                self.delivered.pop_front();
+            }
+            self.delivered
+                .push_back((block.header.id, block.header.hash.clone(), reported));
+            match self.next.next() {
+                Some(next) => self.next = next,
+                None => break,
+            }
+        }
+    }
+
+    // sends the events of `block` the filter asks for, returns whether there were any
+    fn report<T: Payload>(&self, block: &Block<T>, confirmations: u64) -> bool {
+        let filter = &self.filter;
+        let header = &block.header;
+        if !filter.in_range(header.id) {
+            return false;
+        }
+        let mut reported = false;
+        if filter.wants(EventKind::Block) && filter.pays(block) {
+            reported |= self
+                .sender
+                .send(NodeEvent::Block {
+                    height: header.id,
+                    hash: header.hash.clone(),
+                    miner: header.miner.clone(),
+                    confirmations,
+                })
+                .is_ok();
+        }
+        if filter.wants(EventKind::Transaction) {
+            for transaction in block.data.transactions() {
+                if filter.involves(transaction) {
+                    reported |= self
+                        .sender
+                        .send(NodeEvent::Transaction {
+                            height: header.id,
+                            block_hash: header.hash.clone(),
+                            transaction: transaction.clone(),
+                        })
+                        .is_ok();
+                }
+            }
+        }
+        reported
+    }
+}
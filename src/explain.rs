@@ -0,0 +1,42 @@
+use serde::Serialize;
+
+use crate::error::ChainError;
+use crate::units::BlockHeight;
+
+// Every rule a block was checked against and how each went, for seeing why a
+// block passed or failed. Unlike validation, which stops at the first broken rule,
+// all of them are evaluated. See App::explain_block.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockTrace {
+    pub hash: String,
+    pub height: BlockHeight,
+    pub rules: Vec<RuleOutcome>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleOutcome {
+    pub rule: &'static str,
+    // none if the block follows the rule
+    pub error: Option<String>,
+}
+
+impl BlockTrace {
+    pub fn new(hash: String, height: BlockHeight) -> Self {
+        Self {
+            hash,
+            height,
+            rules: vec![],
+        }
+    }
+
+    pub fn record(&mut self, rule: &'static str, result: Result<(), ChainError>) {
+        self.rules.push(RuleOutcome {
+            rule,
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.rules.iter().all(|outcome| outcome.error.is_none())
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use crate::{Block, BlockBody, Payload};
+
+// most side chain blocks kept, the lowest ones are dropped to make room
+pub const MAX_SIDE_BLOCKS: usize = 1024;
+
+// A chain from genesis, as the chain's blocks up to a fork followed by a branch,
+// so a side chain can be checked without copying the blocks it shares.
+pub struct ChainView<'a, T = BlockBody> {
+    base: &'a [Block<T>],
+    branch: &'a [Block<T>],
+}
+
+impl<T> Clone for ChainView<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ChainView<'_, T> {}
+
+impl<'a, T> ChainView<'a, T> {
+    pub fn new(base: &'a [Block<T>], branch: &'a [Block<T>]) -> Self {
+        Self { base, branch }
+    }
+
+    pub fn len(&self) -> usize {
+        self.base.len() + self.branch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a Block<T>> {
+        match index.checked_sub(self.base.len()) {
+            Some(index) => self.branch.get(index),
+            None => self.base.get(index),
+        }
+    }
+
+    pub fn last(&self) -> Option<&'a Block<T>> {
+        self.branch.last().or(self.base.last())
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'a Block<T>> {
+        self.base.iter().chain(self.branch)
+    }
+}
+
+impl<'a, T> From<&'a [Block<T>]> for ChainView<'a, T> {
+    fn from(chain: &'a [Block<T>]) -> Self {
+        Self::new(chain, &[])
+    }
+}
+
+impl<'a, T> From<&'a Vec<Block<T>>> for ChainView<'a, T> {
+    fn from(chain: &'a Vec<Block<T>>) -> Self {
+        Self::new(chain, &[])
+    }
+}
+
+// Valid blocks that build on some block of the chain other than its tip, and on
+// each other, i.e. the forks that lost so far. App switches to one of them as
+// soon as it has more work than the chain, and the blocks it rolls back end up
+// here in turn.
+pub struct SideChains<T = BlockBody> {
+    blocks: HashMap<String, Block<T>>,
+}
+
+impl<T: Payload> Default for SideChains<T> {
+    fn default() -> Self {
+        Self { blocks: HashMap::new() }
+    }
+}
+
+impl<T: Payload> SideChains<T> {
+    pub fn contains(&self, hash: &str) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn insert(&mut self, block: Block<T>) {
+        if self.blocks.len() >= MAX_SIDE_BLOCKS {
+            let lowest = self
+                .blocks
+                .values()
+                .min_by_key(|block| block.header.id)
+                .map(|block| block.header.hash.clone());
+            if let Some(hash) = lowest {
+                self.blocks.remove(&hash);
+            }
+        }
+        self.blocks.insert(block.header.hash.clone(), block);
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block<T>> {
+        self.blocks.get(hash)
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<Block<T>> {
+        self.blocks.remove(hash)
+    }
+
+    // the side chain blocks leading up to and including the one with `hash`, oldest
+    // first. The first one's parent is not a side chain block.
+    pub fn branch(&self, hash: &str) -> Vec<Block<T>> {
+        let mut branch = vec![];
+        let mut next = self.blocks.get(hash);
+        while let Some(block) = next {
+            branch.push(block.clone());
+            next = self.blocks.get(&block.header.previous_hash);
+        }
+        branch.reverse();
+        branch
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
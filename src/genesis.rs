@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::hashing::HashAlgorithm;
+use crate::signing::SignatureAlgorithm;
+use crate::units::{Amount, Timestamp};
+use crate::{Block, Payload};
+
+// The first block of a network. Every node builds it from the same config, so all
+// nodes started with the same file share an identical genesis, whenever they start.
+// See Block::genesis for how the block is derived from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenesisConfig {
+    pub timestamp: Timestamp,
+    // any JSON the chain's payload type can be read from, a BlockBody on the network
+    pub data: Value,
+    // used as is if it meets the initial difficulty, otherwise the seed to mine with
+    pub nonce: u64,
+    // expected hash of the resulting block, nodes refuse to start on a different one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    // address -> starting balance. Not in the block itself, but part of the chain
+    // spec it carries, so different allocations make a different genesis hash.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub allocations: BTreeMap<String, Amount>,
+    // hex public key -> stake of each validator. Any make the chain proof of stake,
+    // see consensus::ProofOfStake.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stakes: BTreeMap<String, Amount>,
+    // hex public keys taking turns at producing blocks. Any make the chain proof of
+    // authority, see consensus::ProofOfAuthority.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authorities: Vec<String>,
+    // of every block's proof of work, see hashing
+    #[serde(skip_serializing_if = "HashAlgorithm::is_default")]
+    pub hash_algorithm: HashAlgorithm,
+    // of every transaction, see signing
+    #[serde(skip_serializing_if = "SignatureAlgorithm::is_default")]
+    pub signature_scheme: SignatureAlgorithm,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            timestamp: Timestamp(1_640_995_200),
+            data: serde_json::json!({ "data": "genesis!" }),
+            nonce: 12_173_462_091_086_854_660,
+            hash: None,
+            allocations: BTreeMap::new(),
+            stakes: BTreeMap::new(),
+            authorities: vec![],
+            hash_algorithm: HashAlgorithm::default(),
+            signature_scheme: SignatureAlgorithm::default(),
+        }
+    }
+}
+
+impl GenesisConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("could not parse {}: {}", path.display(), e))
+    }
+
+    pub fn check<T: Payload>(&self, genesis: &Block<T>) -> Result<(), String> {
+        match &self.hash {
+            Some(hash) if *hash != genesis.header.hash => Err(format!(
+                "genesis block hashes to {}, the genesis config expects {}",
+                genesis.header.hash, hash
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+use crate::preimage;
+use crate::target::Target;
+
+// Hashes block headers for their proof of work. Merkle trees, transaction ids and
+// the chain spec stay on SHA-256 whatever the chain's algorithm.
+pub trait BlockHasher: Sync {
+    fn hash(&self, data: &[u8]) -> Vec<u8>;
+}
+
+pub struct Sha256Hasher;
+
+impl BlockHasher for Sha256Hasher {
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+}
+
+pub struct Blake3Hasher;
+
+impl BlockHasher for Blake3Hasher {
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        blake3::hash(data).as_bytes().to_vec()
+    }
+}
+
+pub struct Keccak256Hasher;
+
+impl BlockHasher for Keccak256Hasher {
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        Keccak256::digest(data).to_vec()
+    }
+}
+
+// nonces a HashBackend is handed at a time when mining
+pub const NONCE_BATCH: usize = 64;
+
+// The innermost loop of mining, hashing a header with nonce after nonce. Backends
+// faster than hashing one nonce at a time through the chain's BlockHasher, e.g.
+// SIMD, assembly or GPU ones, plug in through ProofOfWork::with_backend without
+// changing how mining is driven. They must hash exactly as the chain's hasher does.
+pub trait HashBackend: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn hash(&self, data: &[u8]) -> Vec<u8>;
+
+    // The first of `nonces` whose hash of `preimage` meets `target`, with that
+    // hash. `preimage` is a binary one ending in the nonce, see preimage::binary,
+    // whose last 8 bytes may be left overwritten.
+    fn search(&self, preimage: &mut [u8], nonces: &[u64], target: &Target) -> Option<(u64, Vec<u8>)> {
+        nonces.iter().find_map(|&nonce| {
+            preimage::set_nonce(preimage, nonce);
+            let hash = self.hash(preimage);
+            target.is_met_by(&hash).then_some((nonce, hash))
+        })
+    }
+}
+
+// One nonce after the other through a BlockHasher, what every chain can mine with.
+pub struct PortableBackend(pub &'static dyn BlockHasher);
+
+impl HashBackend for PortableBackend {
+    fn name(&self) -> &str {
+        "portable"
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        self.0.hash(data)
+    }
+}
+
+// Which BlockHasher a chain mines with, part of its genesis config so all of its
+// nodes agree. SHA-256 unless set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [Self::Sha256, Self::Blake3, Self::Keccak256];
+
+    pub fn hasher(self) -> &'static dyn BlockHasher {
+        match self {
+            Self::Sha256 => &Sha256Hasher,
+            Self::Blake3 => &Blake3Hasher,
+            Self::Keccak256 => &Keccak256Hasher,
+        }
+    }
+
+    // left out of the genesis config, so SHA-256 chains keep their spec
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            "keccak256" => Ok(Self::Keccak256),
+            _ => Err(format!("unknown hash algorithm {}", s)),
+        }
+    }
+}
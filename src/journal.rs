@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::Utc;
+use libp2p::PeerId;
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::units::{BlockHeight, Timestamp};
+use crate::{Block, Payload};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorgRecord {
+    pub at: i64,
+    // the peer whose block or chain caused the switch
+    pub peer: String,
+    pub depth: usize,
+    pub old_tip: String,
+    pub new_tip: String,
+    // hashes of the local blocks that were dropped, and of the blocks that replaced them
+    pub discarded: Vec<String>,
+    pub applied: Vec<String>,
+}
+
+impl ReorgRecord {
+    // the reorg from `old` to `new`, none when `new` only extends `old`
+    pub fn between<T: Payload>(old: &[Block<T>], new: &[Block<T>], peer: &PeerId) -> Option<Self> {
+        let common = old
+            .iter()
+            .zip(new)
+            .take_while(|(old, new)| old.header.hash == new.header.hash)
+            .count();
+        if common == old.len() {
+            return None;
+        }
+        Some(Self {
+            at: Utc::now().timestamp(),
+            peer: peer.to_string(),
+            depth: old.len() - common,
+            old_tip: old.last()?.header.hash.clone(),
+            new_tip: new.last()?.header.hash.clone(),
+            discarded: old[common..].iter().map(|block| block.header.hash.clone()).collect(),
+            applied: new[common..].iter().map(|block| block.header.hash.clone()).collect(),
+        })
+    }
+}
+
+// Every reorg this node went through, appended as one JSON line each to the
+// journal file so chain switches can still be audited after a restart.
+#[derive(Default)]
+pub struct ReorgJournal {
+    pub path: Option<PathBuf>,
+    pub records: Vec<ReorgRecord>,
+}
+
+impl ReorgJournal {
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        Ok(Self {
+            records: read_lines(&path)?,
+            path: Some(path),
+        })
+    }
+
+    // journal file from REORG_JOURNAL, kept in memory only if unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("REORG_JOURNAL") {
+            Ok(path) => Self::open(PathBuf::from(path)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn record(&mut self, record: ReorgRecord) {
+        if let Some(path) = &self.path {
+            append_line(path, &record);
+        }
+        self.records.push(record);
+    }
+}
+
+// one JSON value per line, none if the file doesn't exist yet
+fn read_lines<R: DeserializeOwned>(path: &Path) -> Result<Vec<R>, String> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let journal = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    journal
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("could not parse {}: {}", path.display(), e)))
+        .collect()
+}
+
+fn append_line<R: Serialize>(path: &Path, record: &R) {
+    let mut line = serde_json::to_string(record).expect("can jsonify journal record");
+    line.push('\n');
+    let appended = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = appended {
+        error!("could not append to {}: {}", path.display(), e);
+    }
+}
+
+// blocks remembered to tell a reorg from the chain growing
+const MAX_RECENT_BLOCKS: usize = 256;
+
+// What the chain went through, as recorded in the EventLog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChainEvent {
+    BlockAccepted { height: BlockHeight, hash: String },
+    // `depth` blocks were rolled back, the new blocks follow as BlockAccepted
+    Reorg { depth: usize, old_tip: String },
+    // a checkpoint was pinned above any before it
+    FinalityAdvanced { height: BlockHeight, hash: String },
+    // the configured validators differ from those of the last run
+    ValidatorSetChanged { validators: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    pub seq: u64,
+    pub at: Timestamp,
+    // of the chain's tip when the event was logged
+    pub height: BlockHeight,
+    #[serde(flatten)]
+    pub event: ChainEvent,
+}
+
+// Where a consumer catching up on the event log left off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    Height(BlockHeight),
+    Timestamp(Timestamp),
+}
+
+// "height <n>" or "time <unix seconds>"
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parsed = match s.split_whitespace().collect::<Vec<_>>()[..] {
+            ["height", height] => height.parse().ok().map(|height| Since::Height(BlockHeight(height))),
+            ["time", secs] => secs.parse().ok().map(|secs| Since::Timestamp(Timestamp(secs))),
+            _ => None,
+        };
+        parsed.ok_or_else(|| format!("expected height <n> or time <unix seconds>, got {}", s))
+    }
+}
+
+// High-level chain events, appended as one JSON line each to the log file so
+// consumers that were down can catch up with `since` instead of replaying blocks.
+// `update` is run after every event the node handles, like EventHub's.
+#[derive(Default)]
+pub struct EventLog {
+    pub path: Option<PathBuf>,
+    pub events: Vec<LoggedEvent>,
+    // height and hash of the latest blocks logged as accepted, newest last
+    recent: VecDeque<(BlockHeight, String)>,
+    // of the latest FinalityAdvanced
+    finalized: Option<BlockHeight>,
+}
+
+impl EventLog {
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let events: Vec<LoggedEvent> = read_lines(&path)?;
+        let mut log = Self {
+            path: Some(path),
+            events: vec![],
+            recent: VecDeque::new(),
+            finalized: None,
+        };
+        for logged in events {
+            log.remember(&logged.event);
+            log.events.push(logged);
+        }
+        Ok(log)
+    }
+
+    // log file from EVENT_LOG, kept in memory only if unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("EVENT_LOG") {
+            Ok(path) => Self::open(PathBuf::from(path)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    // events at or after `since`, oldest first
+    pub fn since(&self, since: Since) -> Vec<LoggedEvent> {
+        self.events
+            .iter()
+            .filter(|logged| match since {
+                Since::Height(height) => logged.height >= height,
+                Since::Timestamp(at) => logged.at >= at,
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn remember(&mut self, event: &ChainEvent) {
+        match event {
+            ChainEvent::BlockAccepted { height, hash } => {
+                self.recent.retain(|(recent, _)| recent < height);
+                if self.recent.len() >= MAX_RECENT_BLOCKS {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back((*height, hash.clone()));
+            }
+            ChainEvent::FinalityAdvanced { height, .. } => self.finalized = Some(*height),
+            ChainEvent::Reorg { .. } | ChainEvent::ValidatorSetChanged { .. } => {}
+        }
+    }
+
+    fn log(&mut self, height: BlockHeight, event: ChainEvent) {
+        self.remember(&event);
+        let logged = LoggedEvent {
+            seq: self.events.last().map_or(0, |last| last.seq + 1),
+            at: Timestamp::now(),
+            height,
+            event,
+        };
+        if let Some(path) = &self.path {
+            append_line(path, &logged);
+        }
+        self.events.push(logged);
+    }
+
+    // none until a set is logged
+    fn last_validators(&self) -> &[String] {
+        self.events
+            .iter()
+            .rev()
+            .find_map(|logged| match &logged.event {
+                ChainEvent::ValidatorSetChanged { validators } => Some(&validators[..]),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    // logged when they differ from the last ones logged, e.g. on startup
+    pub fn validator_set(&mut self, validators: Vec<String>, tip: BlockHeight) {
+        if self.last_validators() != validators {
+            self.log(tip, ChainEvent::ValidatorSetChanged { validators });
+        }
+    }
+
+    // Logs whatever happened to the chain and its checkpoints since the last update.
+    // Without a logged block to go on, i.e. on a new log or after a reorg deeper than
+    // the blocks remembered, only the tip is logged rather than the whole chain.
+    pub fn update<T: Payload>(&mut self, chain: &[Block<T>], checkpoints: &BTreeMap<BlockHeight, String>) {
+        let Some(tip) = chain.last().map(|block| block.header.id) else {
+            return;
+        };
+        let old_tip = self.recent.back().map(|(_, hash)| hash.clone()).unwrap_or_default();
+        let mut depth = 0;
+        while let Some((height, hash)) = self.recent.back() {
+            if chain
+                .get(height.index())
+                .is_some_and(|block| block.header.hash == *hash)
+            {
+                break;
+            }
+            self.recent.pop_back();
+            depth += 1;
+        }
+        if depth > 0 {
+            self.log(tip, ChainEvent::Reorg { depth, old_tip });
+        }
+        let next = match self.recent.back() {
+            Some((height, _)) => height.index() + 1,
+            None => chain.len() - 1,
+        };
+        for block in chain.iter().skip(next) {
+            let event = ChainEvent::BlockAccepted {
+                height: block.header.id,
+                hash: block.header.hash.clone(),
+            };
+            self.log(tip, event);
+        }
+        if let Some((height, hash)) = checkpoints.last_key_value() {
+            if self.finalized.is_none_or(|finalized| *height > finalized) {
+                let event = ChainEvent::FinalityAdvanced {
+                    height: *height,
+                    hash: hash.clone(),
+                };
+                self.log(tip, event);
+            }
+        }
+    }
+}
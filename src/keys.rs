@@ -0,0 +1,37 @@
+use libp2p::identity::{Keypair, PublicKey};
+
+use crate::signing::SignatureAlgorithm;
+
+// hex encoded public key, as printed by a node for the keys it signs with. Either
+// scheme's, they can be told apart by length.
+pub fn decode_public_key(hex_key: &str) -> Result<PublicKey, String> {
+    let bytes = hex::decode(hex_key.trim()).map_err(|e| format!("invalid public key hex: {}", e))?;
+    SignatureAlgorithm::ALL
+        .into_iter()
+        .find_map(|algorithm| algorithm.scheme().decode_public_key(&bytes).ok())
+        .ok_or_else(|| "invalid public key: neither ed25519 nor secp256k1".to_string())
+}
+
+// hex encoded 32 byte secret key, ed25519 unless prefixed by its scheme as in
+// "secp256k1:<hex>"
+pub fn decode_keypair(hex_secret: &str) -> Result<Keypair, String> {
+    let (algorithm, hex_secret) = match hex_secret.trim().split_once(':') {
+        Some((algorithm, hex_secret)) => (algorithm.parse()?, hex_secret),
+        None => (SignatureAlgorithm::Ed25519, hex_secret.trim()),
+    };
+    let bytes = hex::decode(hex_secret).map_err(|e| format!("invalid secret key hex: {}", e))?;
+    algorithm.scheme().decode_keypair(bytes)
+}
+
+// account address of a public key, see SignatureScheme::address
+pub fn address(key: &PublicKey) -> Option<String> {
+    SignatureAlgorithm::of(key)?.scheme().address(key)
+}
+
+pub fn encode_public_key(keys: &Keypair) -> Option<String> {
+    let key = keys.public();
+    SignatureAlgorithm::of(&key)?
+        .scheme()
+        .encode_public_key(&key)
+        .map(hex::encode)
+}
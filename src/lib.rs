@@ -1,171 +1,1697 @@
-use chrono::Utc;
-use log::info;
-use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use libp2p::identity::{Keypair, PublicKey};
+use log::{error, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
+
+use crate::analytics::{BlockTimeBucket, ChainStats, DifficultyPoint};
+use crate::consensus::ConsensusEngine;
+use crate::error::{ChainError, ValidationReport};
+use crate::explain::BlockTrace;
+use crate::forks::{ChainView, SideChains};
+use crate::hashing::{BlockHasher, HashBackend, NONCE_BATCH};
+use crate::names::NameRecord;
+use crate::nonce::NonceStrategy;
+use crate::orphans::OrphanPool;
+use crate::params::ChainParams;
+use crate::progress::{MiningProgress, ProgressMeter, ATTEMPTS_PER_UPDATE};
+use crate::rules::PayloadRules;
+use crate::state::State;
+use crate::store::ChainFormat;
+use crate::target::Target;
+use crate::transaction::{Coinbase, Mempool, Transaction, MAX_BLOCK_TRANSACTIONS};
+use crate::units::{Amount, BlockHeight, Timestamp};
+
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+pub(crate) fn parse_env<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid value for {}: {}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+pub(crate) const GENESIS_PREVIOUS_HASH: &str = "genesis";
+
+pub struct App<T = BlockBody> {
+    pub params: ChainParams,
+    // replace through set_chain, which keeps `state` and the hash index in sync
+    pub blocks: Vec<Block<T>>,
+    // hash -> height of every block in `blocks`
+    index: HashMap<String, BlockHeight>,
+    // height -> hash of blocks every valid chain must contain
+    pub checkpoints: BTreeMap<BlockHeight, String>,
+    pub race_stats: RaceStats,
+    // signet mode: every block after genesis must be signed by this key
+    pub signet_challenge: Option<PublicKey>,
+    // balances after the last block
+    pub state: State,
+    pub orphans: OrphanPool<T>,
+    pub side_chains: SideChains<T>,
+    // seals new blocks and checks the seals of received ones
+    pub consensus: Arc<dyn ConsensusEngine<T>>,
+    // what block payloads have to satisfy on top of Payload::validate
+    pub payload_rules: PayloadRules<T>,
+}
+
+// most recent races remembered, so a block received again isn't counted twice
+const RECENT_RACES: usize = 256;
+
+// Counts competing blocks/chains of equal work seen by this node, once per pair of
+// competing tips however often either arrives.
+#[derive(Debug, Default, Clone)]
+pub struct RaceStats {
+    pub races: u64,
+    pub won_by_remote: u64,
+    // the competing tips' hashes, lower first, oldest race first
+    recent: VecDeque<(String, String)>,
+}
+
+impl RaceStats {
+    fn record(&mut self, local_tip: &str, remote_tip: &str, won_by_remote: bool) {
+        let race = match local_tip < remote_tip {
+            true => (local_tip.to_string(), remote_tip.to_string()),
+            false => (remote_tip.to_string(), local_tip.to_string()),
+        };
+        if self.recent.contains(&race) {
+            return;
+        }
+        if self.recent.len() == RECENT_RACES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(race);
+        self.races += 1;
+        self.won_by_remote += u64::from(won_by_remote);
+    }
+}
+
+// Deterministic tie-break between two valid blocks of equal work: the lower hash wins,
+// so every node converges on the same tip regardless of arrival order.
+fn wins_tie<T: Payload>(candidate: &Block<T>, current: &Block<T>) -> bool {
+    candidate.header.hash < current.header.hash
+}
+
+pub fn chain_work<'a, T: Payload + 'a>(chain: impl Into<ChainView<'a, T>>) -> u128 {
+    chain.into().iter().map(Block::work).sum()
+}
+
+// most cumulative work wins, then the longer chain, then the tip tie-break
+fn prefers_remote<'a, T: Payload + 'a>(
+    race_stats: &mut RaceStats,
+    local: impl Into<ChainView<'a, T>>,
+    remote: impl Into<ChainView<'a, T>>,
+) -> bool {
+    let (local, remote) = (local.into(), remote.into());
+    let (local_work, remote_work) = (chain_work(local), chain_work(remote));
+    if local_work != remote_work {
+        return remote_work > local_work;
+    }
+    if local.len() != remote.len() {
+        return remote.len() > local.len();
+    }
+    match (local.last(), remote.last()) {
+        (Some(local_tip), Some(remote_tip)) if local_tip.header.hash != remote_tip.header.hash => {
+            let won = wins_tie(remote_tip, local_tip);
+            race_stats.record(&local_tip.header.hash, &remote_tip.header.hash, won);
+            won
+        }
+        _ => false,
+    }
+}
+
+fn check_authorized<T: Payload>(signet_challenge: &Option<PublicKey>, block: &Block<T>) -> Result<(), ChainError> {
+    match signet_challenge {
+        Some(challenge) if !block.is_signed_by(challenge) => Err(ChainError::MissingSignature),
+        _ => Ok(()),
+    }
+}
+
+// the first block of a chain has to be exactly the genesis this node derived from its params
+fn check_genesis<T: Payload>(params: &ChainParams, genesis_hash: &str, block: &Block<T>) -> Result<(), ChainError> {
+    let header = &block.header;
+    if header.id != BlockHeight::GENESIS
+        || header.hash != genesis_hash
+        || hex::encode(header.calculate_hash(params.hasher())) != header.hash
+    {
+        return Err(ChainError::GenesisMismatch);
+    }
+    block.check_body()
+}
+
+fn check_checkpoint<T: Payload>(
+    checkpoints: &BTreeMap<BlockHeight, String>,
+    block: &Block<T>,
+) -> Result<(), ChainError> {
+    match checkpoints.get(&block.header.id) {
+        Some(hash) if *hash != block.header.hash => Err(ChainError::CheckpointConflict(block.header.id)),
+        _ => Ok(()),
+    }
+}
 
-const DIFFICULTY_PREFIX: &str = "00";
+// median timestamp of the last `span` blocks, the next block has to be later than it
+fn median_time_past<T: Payload>(chain: ChainView<T>, span: usize) -> Timestamp {
+    let mut timestamps: Vec<Timestamp> = chain
+        .iter()
+        .rev()
+        .take(span.max(1))
+        .map(|b| b.header.timestamp)
+        .collect();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied().unwrap_or(Timestamp::MIN)
+}
+
+fn check_timestamp<T: Payload>(
+    params: &ChainParams,
+    chain: ChainView<T>,
+    block: &Block<T>,
+    now: Timestamp,
+) -> Result<(), ChainError> {
+    if block.header.timestamp <= median_time_past(chain, params.median_time_span)
+        || block.header.timestamp > now.saturating_add_secs(params.max_future_drift_secs)
+    {
+        return Err(ChainError::InvalidTimestamp(block.header.timestamp));
+    }
+    Ok(())
+}
+
+// checks `block` as the next block of the non-empty `chain`
+fn check_extends<T: Payload>(
+    params: &ChainParams,
+    consensus: &dyn ConsensusEngine<T>,
+    payload_rules: &PayloadRules<T>,
+    chain: ChainView<T>,
+    block: &Block<T>,
+    now: Timestamp,
+) -> Result<(), ChainError> {
+    let parent = chain.last().expect("chain has at least a genesis block");
+    parent.header.check_next(
+        &block.header,
+        difficulty::next_difficulty(chain, params, block.header.version),
+    )?;
+    consensus.verify(&parent.header, block)?;
+    block.check_body()?;
+    payload_rules.check(params, &block.data)?;
+    check_timestamp(params, chain, block, now)
+}
+
+// check_extends, recording each of its rules in `trace` instead of stopping at the
+// first one broken
+fn trace_extends<T: Payload>(
+    params: &ChainParams,
+    consensus: &dyn ConsensusEngine<T>,
+    payload_rules: &PayloadRules<T>,
+    chain: ChainView<T>,
+    block: &Block<T>,
+    trace: &mut BlockTrace,
+) {
+    let parent = chain.last().expect("chain has at least a genesis block");
+    let difficulty = difficulty::next_difficulty(chain, params, block.header.version);
+    trace.record("follows parent", parent.header.check_next(&block.header, difficulty));
+    trace.record("consensus seal", consensus.verify(&parent.header, block));
+    trace.record("body", block.check_body());
+    trace.record("payload rules", payload_rules.check(params, &block.data));
+    trace.record("timestamp", check_timestamp(params, chain, block, Timestamp::now()));
+}
+
+fn check_limits<T: Payload>(params: &ChainParams, block: &Block<T>) -> Result<(), ChainError> {
+    if block.data.size() > params.max_data_size {
+        return Err(ChainError::DataTooLarge {
+            size: block.data.size(),
+            max: params.max_data_size,
+        });
+    }
+    let size = block.encoded_size();
+    if size > params.max_block_size {
+        return Err(ChainError::BlockTooLarge {
+            size,
+            max: params.max_block_size,
+        });
+    }
+    Ok(())
+}
+
+fn validate_chain<T: Payload>(
+    params: &ChainParams,
+    consensus: &dyn ConsensusEngine<T>,
+    payload_rules: &PayloadRules<T>,
+    checkpoints: &BTreeMap<BlockHeight, String>,
+    signet_challenge: &Option<PublicKey>,
+    genesis_hash: &str,
+    chain: &[Block<T>],
+) -> Result<(), ValidationReport<T>> {
+    let now = Timestamp::now();
+    let mut state = State::new(&params.genesis.allocations);
+    for (index, block) in chain.iter().enumerate() {
+        let result = check_checkpoint(checkpoints, block)
+            .and_then(|_| check_limits(params, block))
+            .and_then(|_| match index {
+                0 => check_genesis(params, genesis_hash, block),
+                _ => check_authorized(signet_challenge, block)
+                    .and_then(|_| check_extends(params, consensus, payload_rules, chain[..index].into(), block, now)),
+            })
+            .and_then(|_| state.apply_block(params, block));
+        if let Err(rule) = result {
+            return Err(ValidationReport {
+                index,
+                rule,
+                block: Box::new(block.clone()),
+            });
+        }
+    }
+    Ok(())
+}
+
+// The rules a chain is checked against, detached from App so full chain checks
+// can run on a blocking thread.
+#[derive(Clone)]
+pub struct ChainValidator<T = BlockBody> {
+    params: ChainParams,
+    consensus: Arc<dyn ConsensusEngine<T>>,
+    payload_rules: PayloadRules<T>,
+    checkpoints: BTreeMap<BlockHeight, String>,
+    signet_challenge: Option<PublicKey>,
+    genesis_hash: String,
+}
+
+impl<T: Payload> ChainValidator<T> {
+    pub fn validate_chain(&self, chain: &[Block<T>]) -> Result<(), ValidationReport<T>> {
+        validate_chain(
+            &self.params,
+            self.consensus.as_ref(),
+            &self.payload_rules,
+            &self.checkpoints,
+            &self.signet_challenge,
+            &self.genesis_hash,
+            chain,
+        )
+    }
 
-fn hash_to_binary_representation(hash: &[u8]) -> String {
-    let mut res = String::default();
-    for c in hash {
-        res.push_str(&format!("{:b}", c))
+    pub fn is_chain_valid(&self, chain: &[Block<T>]) -> bool {
+        self.validate_chain(chain).is_ok()
     }
-    res
 }
 
-pub struct App {
-    pub blocks: Vec<Block>,
+// What blocks carry. The network's blocks carry a BlockBody, but a chain embedded in
+// another program can store any serde type without encoding it into a string first.
+pub trait Payload: Serialize + DeserializeOwned + Clone + std::fmt::Debug {
+    // bytes counted against ChainParams::max_data_size
+    fn size(&self) -> usize {
+        serde_json::to_vec(self).map(|json| json.len()).unwrap_or(usize::MAX)
+    }
+
+    // what the header's Merkle root is built over, by default the whole payload as
+    // one leaf. Payloads made of several records can return one leaf per record.
+    fn leaves(&self) -> Vec<Vec<u8>> {
+        vec![serde_json::to_vec(self).unwrap_or_default()]
+    }
+
+    // payload specific rules every block has to follow
+    fn validate(&self) -> Result<(), ChainError> {
+        Ok(())
+    }
+
+    // transfers applied to the balances in App::state
+    fn transactions(&self) -> &[Transaction] {
+        &[]
+    }
+
+    // reward paid to the block's miner, after its transactions
+    fn coinbase(&self) -> Option<&Coinbase> {
+        None
+    }
+
+    // headers of other chains, see relay::check_relayed
+    fn relayed_headers(&self) -> &[BlockHeader] {
+        &[]
+    }
+
+    // deployments and calls run against App::state after the transactions
+    #[cfg(feature = "vm")]
+    fn contract_ops(&self) -> &[vm::ContractOp] {
+        &[]
+    }
+}
+
+impl Payload for String {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+// Body of the network's blocks: free-form data plus transactions from the mempool,
+// headers relayed from other chains and, with the vm feature, contract ops.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct BlockBody {
+    #[serde(default)]
+    pub data: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<Transaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relayed_headers: Vec<BlockHeader>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<Coinbase>,
+    #[cfg(feature = "vm")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contract_ops: Vec<vm::ContractOp>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
-    pub id: u64,
-    pub timestamp: i64,
+impl BlockBody {
+    pub fn new(data: String, transactions: Vec<Transaction>) -> Self {
+        Self {
+            data,
+            transactions,
+            relayed_headers: vec![],
+            coinbase: None,
+            #[cfg(feature = "vm")]
+            contract_ops: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "vm")]
+        if !self.contract_ops.is_empty() {
+            return false;
+        }
+        self.data.is_empty()
+            && self.transactions.is_empty()
+            && self.relayed_headers.is_empty()
+            && self.coinbase.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.transactions.clear();
+        self.relayed_headers.clear();
+        self.coinbase = None;
+        #[cfg(feature = "vm")]
+        self.contract_ops.clear();
+    }
+}
+
+impl Payload for BlockBody {
+    // the coinbase is left out, so it never pushes a full block over the limit
+    fn size(&self) -> usize {
+        #[cfg(feature = "vm")]
+        let contract_ops = self
+            .contract_ops
+            .iter()
+            .map(|op| serde_json::to_vec(op).expect("can jsonify contract op").len())
+            .sum::<usize>();
+        #[cfg(not(feature = "vm"))]
+        let contract_ops = 0;
+        contract_ops
+            + self.data.len()
+            + self.transactions.iter().map(Transaction::encoded_size).sum::<usize>()
+            + self
+                .relayed_headers
+                .iter()
+                .map(BlockHeader::encoded_size)
+                .sum::<usize>()
+    }
+
+    // the data, then one leaf per transaction and per relayed header, then the
+    // coinbase if there is one and one leaf per contract op
+    fn leaves(&self) -> Vec<Vec<u8>> {
+        #[cfg(feature = "vm")]
+        let contract_ops = self
+            .contract_ops
+            .iter()
+            .map(|op| serde_json::to_vec(op).expect("can jsonify contract op"));
+        #[cfg(not(feature = "vm"))]
+        let contract_ops = std::iter::empty();
+        std::iter::once(self.data.as_bytes().to_vec())
+            .chain(
+                self.transactions
+                    .iter()
+                    .map(|transaction| serde_json::to_vec(transaction).expect("can jsonify transaction")),
+            )
+            .chain(
+                self.relayed_headers
+                    .iter()
+                    .map(|header| serde_json::to_vec(header).expect("can jsonify header")),
+            )
+            .chain(
+                self.coinbase
+                    .iter()
+                    .map(|coinbase| serde_json::to_vec(coinbase).expect("can jsonify coinbase")),
+            )
+            .chain(contract_ops)
+            .collect()
+    }
+
+    fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    fn coinbase(&self) -> Option<&Coinbase> {
+        self.coinbase.as_ref()
+    }
+
+    fn relayed_headers(&self) -> &[BlockHeader] {
+        &self.relayed_headers
+    }
+
+    #[cfg(feature = "vm")]
+    fn contract_ops(&self) -> &[vm::ContractOp] {
+        &self.contract_ops
+    }
+
+    fn validate(&self) -> Result<(), ChainError> {
+        let mut ids = HashSet::new();
+        for transaction in &self.transactions {
+            if transaction.verify().is_err() {
+                return Err(ChainError::InvalidTransaction(transaction.id()));
+            }
+            if !ids.insert(transaction.id()) {
+                return Err(ChainError::DuplicateTransaction(transaction.id()));
+            }
+        }
+        #[cfg(feature = "vm")]
+        vm::validate(&self.contract_ops)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MinerRank {
+    pub miner: String,
+    pub blocks: u64,
+}
+
+// Everything the proof of work covers. The body is only committed to through the
+// Merkle root, so headers can be synced and checked without the data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct BlockHeader {
+    pub id: BlockHeight,
+    pub timestamp: Timestamp,
     pub nonce: u64,
     pub hash: String,
     pub previous_hash: String,
-    pub data: String,
+    // root of the Merkle tree over the body's leaves, see Payload::leaves
+    pub merkle_root: String,
+    // identity of whoever produced the block, self-declared but covered by the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub miner: Option<String>,
+    // what the hash has to meet, as leading zero bits or from version COMPACT_TARGET
+    // on a compact target, see target() and difficulty::next_difficulty
+    pub difficulty: u32,
+    // short commitment to the chain params, see ChainParams::spec_hash. Every block
+    // repeats its parent's, so blocks of differently configured chains never connect.
+    #[serde(default)]
+    pub spec: String,
+    // how the header is encoded for hashing, see preimage
+    #[serde(default, skip_serializing_if = "preimage::is_json")]
+    pub version: u8,
 }
 
-fn calculate_hash(id: u64, timestamp: i64, previous_hash: &str, data: &str, nonce: u64) -> Vec<u8> {
-    let data = serde_json::json!({
-        "id": id,
-        "timestamp": timestamp,
-        "previous_hash": previous_hash,
-        "data": data,
-        "nonce": nonce,
-    });
-    let mut hasher = Sha256::new();
-    hasher.update(data.to_string().as_bytes());
-    hasher.finalize().as_slice().to_owned()
+// The header's fields are flattened into the block's JSON, so blocks look the same
+// on the wire and on disk as before the header was split out.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Block<T = BlockBody> {
+    #[serde(flatten)]
+    pub header: BlockHeader,
+    pub data: T,
+    // signature over the block hash by the challenge key in signet mode, or by the
+    // proposer under proof of stake or authority. Not part of the hash itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
-fn mine_block(id: u64, timestamp: i64, previous_hash: &str, data: &str) -> (u64, String) {
-    info!("Mining block..");
-    let mut rng = rand::thread_rng();
-    let mut nonce = 0;
-    let mut iteration = 0;
+impl BlockHeader {
+    fn calculate_hash(&self, hasher: &dyn BlockHasher) -> Vec<u8> {
+        hasher.hash(&preimage::preimage(self))
+    }
+
+    // searches nonces from `rng` until the hash meets the difficulty
+    fn mine<R: Rng>(&mut self, hasher: &dyn BlockHasher, rng: &mut R) {
+        self.mine_until_cancelled(hasher, rng, &CancellationToken::new());
+    }
+
+    // as mine, but gives up once `cancel` is, returning whether a hash was found
+    fn mine_until_cancelled<R: Rng>(
+        &mut self,
+        hasher: &dyn BlockHasher,
+        rng: &mut R,
+        cancel: &CancellationToken,
+    ) -> bool {
+        info!("Mining block..");
+        let target = self.target();
+        let mut attempts: u64 = 0;
 
-    loop {
-        if iteration % 100000 == 0 {
-            info!("iteration {}", iteration);
+        loop {
+            if cancel.is_cancelled() {
+                info!("mining cancelled after {} attempts", attempts);
+                return false;
+            }
+            if attempts.is_multiple_of(ATTEMPTS_PER_UPDATE) {
+                power::wait_while_under_pressure(cancel);
+                if cancel.is_cancelled() {
+                    continue;
+                }
+            }
+            attempts += 1;
+
+            let hash = self.calculate_hash(hasher);
+            if target.is_met_by(&hash) {
+                info!(
+                    "mined! nonce: {}, hash: {}, after {} attempts",
+                    self.nonce,
+                    hex::encode(&hash),
+                    attempts
+                );
+                self.hash = hex::encode(hash);
+                return true;
+            }
+            self.nonce = rng.gen();
+        }
+    }
+
+    // Mines on `threads` workers that split the nonces between them as `nonces` has
+    // it, see NonceStrategy. The first to find a hash stops the others. Progress
+    // goes to `progress` every PROGRESS_INTERVAL.
+    fn mine_parallel(
+        &mut self,
+        backend: &dyn HashBackend,
+        nonces: &dyn NonceStrategy,
+        threads: usize,
+        cancel: &CancellationToken,
+        progress: Option<&mpsc::UnboundedSender<MiningProgress>>,
+    ) -> bool {
+        let threads = threads.max(1) as u64;
+        info!(
+            "Mining block on {} threads with the {} backend..",
+            threads,
+            backend.name()
+        );
+        let first = nonces.first();
+        // cancelled by the worker that finds a hash, or along with `cancel`
+        let stop = cancel.child_token();
+        let meter = ProgressMeter::new(progress);
+        let mined = if threads == 1 {
+            self.search(backend, nonces, first, 1, &stop, &meter)
+        } else {
+            let header = &*self;
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads)
+                    .map(|worker| {
+                        let (stop, meter) = (&stop, &meter);
+                        scope.spawn(move || {
+                            header.search(backend, nonces, first.wrapping_add(worker), threads, stop, meter)
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .filter_map(|worker| worker.join().expect("mining worker panicked"))
+                    .next()
+            })
+        };
+        let progress = meter.finish();
+        match mined {
+            Some(header) => {
+                info!(
+                    "mined! nonce: {}, hash: {}, after {} attempts at {:.0} H/s",
+                    header.nonce, header.hash, progress.attempts, progress.hashrate
+                );
+                *self = header;
+                true
+            }
+            None => {
+                info!("mining cancelled after {} attempts", progress.attempts);
+                false
+            }
+        }
+    }
+
+    // one worker's share of mine_parallel, the header with the first nonce from
+    // `nonce` on that meets the difficulty, unless the search is stopped first
+    fn search(
+        &self,
+        backend: &dyn HashBackend,
+        nonces: &dyn NonceStrategy,
+        nonce: u64,
+        workers: u64,
+        stop: &CancellationToken,
+        meter: &ProgressMeter,
+    ) -> Option<BlockHeader> {
+        let mut header = self.clone();
+        let target = header.target();
+        // binary preimages end in the nonce, so they are built once and patched
+        let mut binary = (header.version != preimage::JSON_PREIMAGE).then(|| preimage::binary(&header));
+        let mut batch = Vec::with_capacity(NONCE_BATCH);
+        let mut next = nonce;
+        let mut attempts: u64 = 0;
+        while !stop.is_cancelled() {
+            if attempts >= ATTEMPTS_PER_UPDATE {
+                meter.add(attempts);
+                attempts = 0;
+                power::wait_while_under_pressure(stop);
+                if stop.is_cancelled() {
+                    break;
+                }
+            }
+            batch.clear();
+            for _ in 0..NONCE_BATCH {
+                batch.push(next);
+                next = nonces.next(next, workers);
+            }
+            let found = match &mut binary {
+                Some(binary) => backend.search(binary, &batch, &target),
+                None => batch.iter().find_map(|&nonce| {
+                    header.nonce = nonce;
+                    let hash = backend.hash(&preimage::preimage(&header));
+                    target.is_met_by(&hash).then_some((nonce, hash))
+                }),
+            };
+            let Some((nonce, hash)) = found else {
+                attempts += batch.len() as u64;
+                continue;
+            };
+            stop.cancel();
+            let tried = batch
+                .iter()
+                .position(|&tried| tried == nonce)
+                .map_or(batch.len(), |i| i + 1);
+            meter.add(attempts + tried as u64);
+            header.nonce = nonce;
+            header.hash = hex::encode(hash);
+            return Some(header);
         }
-        iteration += 1;
+        meter.add(attempts);
+        None
+    }
 
-        let hash = calculate_hash(id, timestamp, previous_hash, data, nonce);
-        let binary_hash = hash_to_binary_representation(&hash);
-        if binary_hash.starts_with(DIFFICULTY_PREFIX) {
-            info!("mined! nonce: {}, hash: {}", nonce, hex::encode(&hash));
-            return (nonce, hex::encode(hash));
+    pub fn encoded_size(&self) -> usize {
+        serde_json::to_vec(self).expect("can jsonify header").len()
+    }
+
+    // the largest hash this header may have
+    pub fn target(&self) -> Target {
+        if self.version >= preimage::COMPACT_TARGET {
+            // an invalid encoding is met by nothing
+            Target::from_compact(self.difficulty).unwrap_or(Target::ZERO)
+        } else {
+            Target::from_zero_bits(self.difficulty)
         }
-        nonce = rng.gen();
+    }
+
+    // expected number of hashes needed to meet this block's difficulty
+    pub fn work(&self) -> u128 {
+        self.target().work()
+    }
+
+    // checks `next` as the header following this one, without looking at any body
+    // and short of its seal, see ConsensusEngine::verify
+    pub fn check_next(&self, next: &BlockHeader, expected_difficulty: u32) -> Result<(), ChainError> {
+        let expected = self.id.next();
+        if Some(next.id) != expected {
+            return Err(ChainError::NonSequentialId {
+                expected: expected.unwrap_or(self.id),
+                got: next.id,
+            });
+        }
+        if next.previous_hash != self.hash {
+            return Err(ChainError::InvalidPreviousHash);
+        }
+        if next.spec != self.spec {
+            return Err(ChainError::SpecMismatch);
+        }
+        preimage::check_version(self, next)?;
+        if next.difficulty != expected_difficulty {
+            return Err(ChainError::InvalidDifficulty {
+                expected: expected_difficulty,
+                got: next.difficulty,
+            });
+        }
+        Ok(())
+    }
+
+    // the hash is this header's and meets its own difficulty, whether or not that's the right one
+    pub(crate) fn check_proof_of_work(&self, hasher: &dyn BlockHasher) -> Result<(), ChainError> {
+        if self.version > preimage::CURRENT_VERSION {
+            return Err(ChainError::InvalidHeaderVersion(self.version));
+        }
+        match hex::decode(&self.hash) {
+            Ok(decoded_hash) if self.target().is_met_by(&decoded_hash) => {}
+            _ => return Err(ChainError::InsufficientWork),
+        }
+
+        if hex::encode(self.calculate_hash(hasher)) != self.hash {
+            return Err(ChainError::HashMismatch);
+        }
+        Ok(())
     }
 }
 
-impl Block {
-    pub fn new(id: u64, previous_hash: String, data: String) -> Block {
-        let timestamp = Utc::now().timestamp();
-        let (nonce, hash) = mine_block(id, timestamp, &previous_hash, &data);
+impl<T: Payload> Block<T> {
+    pub fn new(
+        parent: &BlockHeader,
+        data: T,
+        miner: Option<String>,
+        difficulty: u32,
+        hasher: &dyn BlockHasher,
+    ) -> Block<T> {
+        Self::mine_at(
+            parent,
+            data,
+            miner,
+            difficulty,
+            Timestamp::now(),
+            hasher,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    // mines the child of `parent` with a fixed timestamp and nonce source, so seeded
+    // runs produce identical blocks
+    pub fn mine_at<R: Rng>(
+        parent: &BlockHeader,
+        data: T,
+        miner: Option<String>,
+        difficulty: u32,
+        timestamp: Timestamp,
+        hasher: &dyn BlockHasher,
+        rng: &mut R,
+    ) -> Block<T> {
+        let mut block = Self::unmined(parent, data, miner, difficulty, timestamp);
+        block.mine(hasher, rng);
+        block
+    }
+
+    // the child of `parent` before any proof of work, e.g. to check its limits first
+    pub fn unmined(
+        parent: &BlockHeader,
+        data: T,
+        miner: Option<String>,
+        difficulty: u32,
+        timestamp: Timestamp,
+    ) -> Block<T> {
         Block {
-            id,
-            timestamp,
-            nonce,
-            hash,
-            previous_hash,
+            header: BlockHeader {
+                id: parent.id.next().expect("chain is shorter than u64::MAX blocks"),
+                timestamp,
+                nonce: 0,
+                hash: String::new(),
+                previous_hash: parent.hash.clone(),
+                merkle_root: merkle::merkle_root(&data.leaves()),
+                miner,
+                difficulty,
+                spec: parent.spec.clone(),
+                version: preimage::CURRENT_VERSION,
+            },
             data,
+            signature: None,
         }
     }
 
-    fn calculate_hash(&self) -> Vec<u8> {
-        return calculate_hash(self.id, self.timestamp, &self.previous_hash, &self.data, self.nonce);
+    pub fn mine<R: Rng>(&mut self, hasher: &dyn BlockHasher, rng: &mut R) {
+        self.header.mine(hasher, rng);
     }
 
-    fn can_extend_to(&self, next_block: &Block) -> bool {
-        if next_block.id != self.id + 1 {
-            return false;
+    pub fn mine_until_cancelled<R: Rng>(
+        &mut self,
+        hasher: &dyn BlockHasher,
+        rng: &mut R,
+        cancel: &CancellationToken,
+    ) -> bool {
+        self.header.mine_until_cancelled(hasher, rng, cancel)
+    }
+
+    pub fn mine_parallel(
+        &mut self,
+        backend: &dyn HashBackend,
+        nonces: &dyn NonceStrategy,
+        threads: usize,
+        cancel: &CancellationToken,
+        progress: Option<&mpsc::UnboundedSender<MiningProgress>>,
+    ) -> bool {
+        self.header.mine_parallel(backend, nonces, threads, cancel, progress)
+    }
+
+    // Seals the block with `consensus` on the blocking pool, so an event loop awaiting
+    // it keeps running. Fails if `cancel` is cancelled before the block is sealed.
+    // Engines that mine send their progress to `progress` meanwhile.
+    pub async fn mine_async(
+        mut self,
+        consensus: Arc<dyn ConsensusEngine<T>>,
+        cancel: CancellationToken,
+        progress: Option<mpsc::UnboundedSender<MiningProgress>>,
+    ) -> Result<Block<T>, String>
+    where
+        T: Send + 'static,
+    {
+        spawn_blocking(move || {
+            consensus.seal_until_cancelled(&mut self, &cancel, progress.as_ref())?;
+            Ok(self)
+        })
+        .await
+        .map_err(|e| format!("mining task failed: {}", e))?
+    }
+
+    // The genesis block of a network with these params. The configured nonce is used
+    // if it meets the initial difficulty, otherwise the block is mined with the nonce
+    // as seed. Either way every node derives the same, self-consistent block.
+    pub fn genesis(params: &ChainParams) -> Result<Block<T>, String> {
+        let config = &params.genesis;
+        let data: T = serde_json::from_value(config.data.clone())
+            .map_err(|e| format!("genesis data does not fit the block payload type: {}", e))?;
+        let mut header = BlockHeader {
+            id: BlockHeight::GENESIS,
+            timestamp: config.timestamp,
+            nonce: config.nonce,
+            hash: String::new(),
+            previous_hash: GENESIS_PREVIOUS_HASH.to_string(),
+            merkle_root: merkle::merkle_root(&data.leaves()),
+            miner: None,
+            difficulty: params.initial_difficulty,
+            spec: params.spec_hash(),
+            // so genesis hashes pinned in configs hold
+            version: preimage::JSON_PREIMAGE,
+        };
+        let hash = header.calculate_hash(params.hasher());
+        if header.target().is_met_by(&hash) {
+            header.hash = hex::encode(hash);
+        } else {
+            header.mine(params.hasher(), &mut StdRng::seed_from_u64(config.nonce));
         }
-        if next_block.previous_hash != self.hash {
-            return false;
+        Ok(Block {
+            header,
+            data,
+            signature: None,
+        })
+    }
+
+    pub fn sign(&mut self, keys: &Keypair) -> Result<(), String> {
+        let signature = keys
+            .sign(self.header.hash.as_bytes())
+            .map_err(|e| format!("could not sign block: {}", e))?;
+        self.signature = Some(hex::encode(signature));
+        Ok(())
+    }
+
+    pub fn is_signed_by(&self, key: &PublicKey) -> bool {
+        match self.signature.as_ref().map(hex::decode) {
+            Some(Ok(signature)) => key.verify(self.header.hash.as_bytes(), &signature),
+            _ => false,
         }
+    }
 
-        if let Ok(decoded_hash) = hex::decode(&next_block.hash) {
-            if !hash_to_binary_representation(&decoded_hash).starts_with(DIFFICULTY_PREFIX) {
-                return false;
-            }
-        } else {
-            return false;
+    pub fn work(&self) -> u128 {
+        self.header.work()
+    }
+
+    // the body has to be what the header's Merkle root commits to
+    fn check_body(&self) -> Result<(), ChainError> {
+        if merkle::merkle_root(&self.data.leaves()) != self.header.merkle_root {
+            return Err(ChainError::MerkleRootMismatch);
         }
+        self.data.validate()
+    }
 
-        hex::encode(next_block.calculate_hash()) == next_block.hash
+    // size of the block as it is sent over the wire
+    pub fn encoded_size(&self) -> usize {
+        serde_json::to_vec(self).expect("can jsonify block").len()
     }
+}
+
+impl Block {
+    // the next block with `data` and the oldest transactions waiting in `mempool`
+    pub fn mine_next_block(
+        &self,
+        data: String,
+        mempool: &Mempool,
+        miner: Option<String>,
+        difficulty: u32,
+        hasher: &dyn BlockHasher,
+    ) -> Block {
+        let transactions = mempool.batch(MAX_BLOCK_TRANSACTIONS, usize::MAX);
+        Block::new(
+            &self.header,
+            BlockBody::new(data, transactions),
+            miner,
+            difficulty,
+            hasher,
+        )
+    }
+}
 
-    pub fn mine_next_block(&self, data: String) -> Block {
-        Block::new(self.id + 1, self.hash.clone(), data)
+impl<T: Payload> Default for App<T> {
+    fn default() -> Self {
+        Self::new(ChainParams::default())
     }
 }
 
-impl App {
-    pub fn new() -> Self {
-        let mut app = Self { blocks: vec![] };
+impl<T: Payload> App<T> {
+    // panics if the genesis data in `params` can't be read as a `T`, or its
+    // validators are invalid
+    pub fn new(params: ChainParams) -> Self {
+        let mut app = Self {
+            consensus: consensus::engine_for(&params).unwrap_or_else(|e| panic!("{}", e)),
+            params,
+            blocks: vec![],
+            index: HashMap::new(),
+            checkpoints: BTreeMap::new(),
+            race_stats: RaceStats::default(),
+            signet_challenge: None,
+            state: State::default(),
+            orphans: OrphanPool::default(),
+            side_chains: SideChains::default(),
+            payload_rules: PayloadRules::default(),
+        };
         app.genesis();
         app
     }
 
     pub fn genesis(&mut self) {
-        let genesis_block = Block {
-            id: 0,
-            timestamp: Utc::now().timestamp(),
-            hash: "0000f816a87f806bb0073dcf026a64fb40c946b5abee2573702828694d5b4c43".to_string(),
-            previous_hash: String::from("genesis"),
-            data: String::from("genesis!"),
-            nonce: 2836,
+        let genesis_block = Block::genesis(&self.params).unwrap_or_else(|e| panic!("{}", e));
+        self.set_chain(vec![genesis_block])
+            .unwrap_or_else(|e| panic!("invalid genesis block: {}", e));
+    }
+
+    // replaces the local chain, replaying its blocks for the new state. The
+    // chain is left untouched if one of them overspends.
+    pub fn set_chain(&mut self, blocks: Vec<Block<T>>) -> Result<(), ChainError> {
+        self.state = State::replay(&self.params, &blocks)?;
+        self.index = blocks
+            .iter()
+            .map(|block| (block.header.hash.clone(), block.header.id))
+            .collect();
+        self.blocks = blocks;
+        Ok(())
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block<T>> {
+        self.index.get(hash).and_then(|height| self.get_block_by_id(*height))
+    }
+
+    pub fn get_block_by_id(&self, id: BlockHeight) -> Option<&Block<T>> {
+        self.blocks.get(id.index())
+    }
+
+    // the chain or side chain block with `hash`
+    pub fn find_known_block(&self, hash: &str) -> Option<&Block<T>> {
+        self.get_block_by_hash(hash).or_else(|| self.side_chains.get(hash))
+    }
+
+    // the blocks from genesis up to the one with `hash`, be it on the chain or on a
+    // side chain whose fork point is still on the chain
+    fn chain_up_to(&self, hash: &str) -> Option<Vec<Block<T>>> {
+        if let Some(height) = self.index.get(hash) {
+            return Some(self.blocks[..=height.index()].to_vec());
+        }
+        let mut branch = self.side_chains.branch(hash);
+        let fork = branch.first()?.header.id.index();
+        let fork_parent = self.blocks.get(fork.checked_sub(1)?)?;
+        if fork_parent.header.hash != branch[0].header.previous_hash {
+            return None;
+        }
+        let mut chain = self.blocks[..fork].to_vec();
+        chain.append(&mut branch);
+        Some(chain)
+    }
+
+    // Checks `block` against every rule as if it had just arrived, on top of its
+    // parent wherever that is. Blocks without a known parent get the checks orphans
+    // get. For explaining blocks, not validating them: it replays the parent chain.
+    pub fn explain_block(&self, block: &Block<T>) -> BlockTrace {
+        let mut trace = BlockTrace::new(block.header.hash.clone(), block.header.id);
+        trace.record("checkpoint", check_checkpoint(&self.checkpoints, block));
+        trace.record("size limits", check_limits(&self.params, block));
+        if block.header.previous_hash == GENESIS_PREVIOUS_HASH {
+            trace.record("genesis", check_genesis(&self.params, self.genesis_hash(), block));
+            return trace;
+        }
+        trace.record("signet signature", check_authorized(&self.signet_challenge, block));
+        let Some(chain) = self.chain_up_to(&block.header.previous_hash) else {
+            trace.record("parent known", Err(ChainError::Orphan));
+            trace.record("consensus seal", self.consensus.check_detached(block));
+            trace.record("body", block.check_body());
+            trace.record("payload rules", self.payload_rules.check(&self.params, &block.data));
+            return trace;
         };
-        self.blocks.push(genesis_block);
+        trace_extends(
+            &self.params,
+            self.consensus.as_ref(),
+            &self.payload_rules,
+            (&chain).into(),
+            block,
+            &mut trace,
+        );
+        let state = State::replay(&self.params, &chain).and_then(|mut state| state.apply_block(&self.params, block));
+        trace.record("state transition", state);
+        trace
     }
 
-    pub fn try_add_block(&mut self, block: Block) -> Result<(), String> {
-        if self.blocks.last().unwrap().can_extend_to(&block) {
-            self.blocks.push(block);
-        } else {
-            return Err("could not add invalid block".to_string());
+    // the chain from genesis to tip
+    pub fn iter(&self) -> std::slice::Iter<'_, Block<T>> {
+        self.blocks.iter()
+    }
+
+    // blocks with heights in `range`, as far as the chain goes
+    pub fn blocks_in_range(&self, range: Range<BlockHeight>) -> &[Block<T>] {
+        let end = range.end.index().min(self.blocks.len());
+        let start = range.start.index().min(end);
+        &self.blocks[start..end]
+    }
+
+    // blocks `predicate` holds for, lowest first
+    pub fn find_blocks<'a>(
+        &'a self,
+        predicate: impl Fn(&Block<T>) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a Block<T>> + 'a {
+        self.blocks.iter().filter(move |block| predicate(block))
+    }
+
+    pub fn balance_of(&self, address: &str) -> Amount {
+        self.state.balance_of(address)
+    }
+
+    // the holder of `name` as of the tip, see names.rs
+    pub fn resolve_name(&self, name: &str) -> Option<&NameRecord> {
+        self.state.resolve(name, self.get_last_block().header.id)
+    }
+
+    // names `address` holds as of the tip, sorted
+    pub fn names_of(&self, address: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .state
+            .names_of(address, self.get_last_block().header.id)
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn export_to_file(&self, path: &Path, format: ChainFormat) -> Result<(), String> {
+        store::export_chain(path, &self.blocks, format)
+    }
+
+    // Replaces the local chain with the one in `path`, in either export format,
+    // if it is valid. It doesn't have to be heavier than the local one.
+    pub fn import_from_file(&mut self, path: &Path) -> Result<(), String> {
+        let blocks = store::load_chain(path)?;
+        self.import_chain(blocks, &path.display().to_string())
+    }
+
+    // as import_from_file, for blocks from `source`, see bundle
+    pub fn import_chain(&mut self, blocks: Vec<Block<T>>, source: &str) -> Result<(), String> {
+        if blocks.is_empty() {
+            return Err(format!("chain in {} is empty", source));
         }
+        self.validate_chain(&blocks)
+            .map_err(|report| format!("chain in {} is invalid: {}", source, report))?;
+        info!(
+            "importing {} blocks from {}, rolling back {} local blocks",
+            blocks.len(),
+            source,
+            self.fork_depth(&blocks)
+        );
+        self.set_chain(blocks).map_err(|e| e.to_string())?;
+        self.connect_orphans(self.get_last_block().header.hash.clone());
         Ok(())
     }
 
-    pub fn is_chain_valid(&self, chain: &Vec<Block>) -> bool {
-        for i in 1..chain.len() {
-            if !chain[i - 1].can_extend_to(&chain[i]) {
-                return false;
+    pub fn genesis_hash(&self) -> &str {
+        &self.blocks[0].header.hash
+    }
+
+    // size limits only, cheap enough to run on a block before mining it. Mining only
+    // fills in the nonce and hash, which can't take it far over the limits.
+    pub fn check_limits(&self, block: &Block<T>) -> Result<(), ChainError> {
+        check_limits(&self.params, block)
+    }
+
+    // Adds `block` to the chain or one of its side chains, reorganizing onto the side
+    // chain if that makes it the better one, then any orphans that now connect. A
+    // block ahead of the tip whose parent is unknown is kept as an orphan. Returns
+    // the blocks rolled back by reorgs, oldest first, none when the chain only grew.
+    pub fn try_add_block(&mut self, block: Block<T>) -> Result<Vec<Block<T>>, ChainError> {
+        let hash = block.header.hash.clone();
+        match self.add_block(block) {
+            Ok(mut rolled_back) => {
+                rolled_back.extend(self.connect_orphans(hash));
+                Ok(rolled_back)
             }
+            // kept as a side chain, orphans building on it may still win
+            Err(ChainError::LostTieBreak) => match self.connect_orphans(hash) {
+                rolled_back if rolled_back.is_empty() => Err(ChainError::LostTieBreak),
+                rolled_back => Ok(rolled_back),
+            },
+            Err(e) => Err(e),
         }
+    }
+
+    fn connect_orphans(&mut self, parent_hash: String) -> Vec<Block<T>> {
+        let mut rolled_back = vec![];
+        let mut parents = vec![parent_hash];
+        while let Some(parent_hash) = parents.pop() {
+            for child in self.orphans.take_children(&parent_hash) {
+                let hash = child.header.hash.clone();
+                match self.add_block(child) {
+                    Ok(blocks) => {
+                        info!("attached orphan block {}", hash);
+                        rolled_back.extend(blocks);
+                        parents.push(hash);
+                    }
+                    // kept as a side chain, its children may still outweigh the chain
+                    Err(ChainError::LostTieBreak) => parents.push(hash),
+                    Err(e) => info!("dropping orphan block {}: {}", hash, e),
+                }
+            }
+        }
+        rolled_back
+    }
 
+    fn add_block(&mut self, block: Block<T>) -> Result<Vec<Block<T>>, ChainError> {
+        check_checkpoint(&self.checkpoints, &block)?;
+        check_authorized(&self.signet_challenge, &block)?;
+        check_limits(&self.params, &block)?;
+        let now = Timestamp::now();
+        let tip = self.blocks.last().expect("chain has a genesis block");
+        if block.header.previous_hash == tip.header.hash {
+            check_extends(
+                &self.params,
+                self.consensus.as_ref(),
+                &self.payload_rules,
+                (&self.blocks).into(),
+                &block,
+                now,
+            )?;
+            self.state.apply_block(&self.params, &block)?;
+            self.index.insert(block.header.hash.clone(), block.header.id);
+            self.blocks.push(block);
+            return Ok(vec![]);
+        }
+        if self.index.contains_key(&block.header.hash) || self.side_chains.contains(&block.header.hash) {
+            return Err(ChainError::DuplicateBlock);
+        }
+        let parent_on_chain = self.index.contains_key(&block.header.previous_hash);
+        if parent_on_chain || self.side_chains.contains(&block.header.previous_hash) {
+            return self.add_side_block(block, now);
+        }
+        // ahead of the tip, so its parent is just missing here for now
+        if block.header.id > tip.header.id {
+            // e.g. from another chain run on the same network
+            if block.header.spec != tip.header.spec {
+                return Err(ChainError::SpecMismatch);
+            }
+            self.consensus.check_detached(&block)?;
+            block.check_body()?;
+            self.payload_rules.check(&self.params, &block.data)?;
+            self.orphans.add(block, now);
+            return Err(ChainError::Orphan);
+        }
+        Err(ChainError::InvalidPreviousHash)
+    }
+
+    // `block` builds on a block of the chain other than the tip, or on a side chain
+    fn add_side_block(&mut self, block: Block<T>, now: Timestamp) -> Result<Vec<Block<T>>, ChainError> {
+        let mut branch = self.side_chains.branch(&block.header.previous_hash);
+        let first = &branch.first().unwrap_or(&block).header;
+        // height of the first block that differs from the chain
+        let fork = first.id.index();
+        let fork_parent = fork.checked_sub(1).and_then(|parent| self.blocks.get(parent));
+        if fork_parent.is_none_or(|parent| parent.header.hash != first.previous_hash) {
+            // the branch's start was evicted
+            return Err(ChainError::InvalidPreviousHash);
+        }
+        // the branch may predate a checkpoint that rules it out
+        for side_block in &branch {
+            check_checkpoint(&self.checkpoints, side_block)?;
+        }
+        // the chain the block would extend, borrowed up to the fork
+        check_extends(
+            &self.params,
+            self.consensus.as_ref(),
+            &self.payload_rules,
+            ChainView::new(&self.blocks[..fork], &branch),
+            &block,
+            now,
+        )?;
+        branch.push(block);
+        let candidate = ChainView::new(&self.blocks[..fork], &branch);
+        if !prefers_remote(&mut self.race_stats, &self.blocks, candidate) {
+            let tie = chain_work(candidate) == self.total_work() && candidate.len() == self.blocks.len();
+            let block = branch.pop().expect("the block was just pushed");
+            self.side_chains.insert(block);
+            return if tie { Err(ChainError::LostTieBreak) } else { Ok(vec![]) };
+        }
+        let state = State::replay(&self.params, candidate.iter())?;
+        let tip = &branch.last().expect("the block was just pushed").header;
+        info!(
+            "reorganizing onto {}, rolling back {} blocks",
+            tip.hash,
+            self.blocks.len() - fork
+        );
+        let rolled_back = self.blocks.split_off(fork);
+        for applied in &branch {
+            self.side_chains.remove(&applied.header.hash);
+            self.index.insert(applied.header.hash.clone(), applied.header.id);
+        }
+        for block in &rolled_back {
+            self.index.remove(&block.header.hash);
+            self.side_chains.insert(block.clone());
+        }
+        self.blocks.append(&mut branch);
+        self.state = state;
+        Ok(rolled_back)
+    }
+
+    // Pins the block at `height` to `hash`. Chains, branches and blocks that disagree
+    // are rejected from then on, however much work they have. A pinned height is
+    // never moved, so a signed checkpoint can't override one the operator set.
+    pub fn add_checkpoint(&mut self, height: BlockHeight, hash: String) -> Result<(), ChainError> {
+        match self.checkpoints.get(&height) {
+            Some(pinned) if *pinned == hash => return Ok(()),
+            Some(pinned) => {
+                return Err(ChainError::CheckpointPinned {
+                    height,
+                    pinned: pinned.clone(),
+                })
+            }
+            None => {}
+        }
+        if let Some(block) = self.blocks.get(height.index()) {
+            if block.header.hash != hash {
+                error!("local chain conflicts with checkpoint at height {}", height);
+            }
+        }
+        self.checkpoints.insert(height, hash);
+        Ok(())
+    }
+
+    pub fn validate_chain(&self, chain: &[Block<T>]) -> Result<(), ValidationReport<T>> {
+        validate_chain(
+            &self.params,
+            self.consensus.as_ref(),
+            &self.payload_rules,
+            &self.checkpoints,
+            &self.signet_challenge,
+            self.genesis_hash(),
+            chain,
+        )
+    }
+
+    pub fn is_chain_valid(&self, chain: &[Block<T>]) -> bool {
+        self.validate_chain(chain).is_ok()
+    }
+
+    // snapshot of the current validation rules, for validating off the event loop
+    pub fn validator(&self) -> ChainValidator<T> {
+        ChainValidator {
+            params: self.params.clone(),
+            consensus: self.consensus.clone(),
+            payload_rules: self.payload_rules.clone(),
+            checkpoints: self.checkpoints.clone(),
+            signet_challenge: self.signet_challenge.clone(),
+            genesis_hash: self.genesis_hash().to_string(),
+        }
+    }
+
+    // earliest timestamp the next block on the local chain may have
+    pub fn min_next_timestamp(&self) -> Timestamp {
+        median_time_past((&self.blocks).into(), self.params.median_time_span).saturating_add_secs(1)
+    }
+
+    // difficulty the next block on the local chain has to meet
+    pub fn next_difficulty(&self) -> u32 {
+        difficulty::next_difficulty(&self.blocks, &self.params, preimage::CURRENT_VERSION)
+    }
+
+    pub fn total_work(&self) -> u128 {
+        chain_work(&self.blocks)
+    }
+
+    // number of local blocks that `other` does not share, i.e. how deep switching to it reorgs
+    pub fn fork_depth(&self, other: &[Block<T>]) -> usize {
+        let common = self
+            .blocks
+            .iter()
+            .zip(other)
+            .take_while(|(local, remote)| local.header.hash == remote.header.hash)
+            .count();
+        self.blocks.len() - common
+    }
+
+    // `remote` must already have passed a `validator()` snapshot. Checkpoints are
+    // checked again since new ones may have arrived while it was being validated.
+    pub fn adopt_validated_chain(&mut self, remote: Vec<Block<T>>) -> bool {
+        if remote
+            .iter()
+            .any(|block| check_checkpoint(&self.checkpoints, block).is_err())
+        {
+            return false;
+        }
+        if !prefers_remote(&mut self.race_stats, &self.blocks, &remote) {
+            return false;
+        }
+        if self.set_chain(remote).is_err() {
+            return false;
+        }
+        self.connect_orphans(self.get_last_block().header.hash.clone());
         true
     }
 
-    pub fn choose_chain(&mut self, local: Vec<Block>, remote: Vec<Block>) -> Vec<Block> {
-        let is_local_valid = self.is_chain_valid(&local);
-        let is_remote_valid = self.is_chain_valid(&remote);
-        if is_local_valid && is_remote_valid {
-            if local.len() >= remote.len() {
-                return local;
+    // The better of two chains, falling back to whichever one is valid. Fails with
+    // the remote chain's report only when neither chain is valid.
+    pub fn choose_chain(
+        &mut self,
+        local: Vec<Block<T>>,
+        remote: Vec<Block<T>>,
+    ) -> Result<Vec<Block<T>>, ValidationReport<T>> {
+        match (self.validate_chain(&local), self.validate_chain(&remote)) {
+            (Ok(()), Ok(())) => Ok(if prefers_remote(&mut self.race_stats, &local, &remote) {
+                remote
+            } else {
+                local
+            }),
+            (Ok(()), Err(_)) => Ok(local),
+            (Err(_), Ok(())) => Ok(remote),
+            (Err(local_report), Err(remote_report)) => {
+                error!("local chain is invalid too: {}", local_report);
+                Err(remote_report)
             }
-            return remote;
         }
+    }
 
-        if !is_local_valid && !is_remote_valid {
-            panic!("local and remote chains are both invalid");
+    // Mines `n` blocks on the local chain, `data` gives each block's data from its
+    // height. Only for tests and tutorials on a dev chain: one with a fixed
+    // difficulty of at most MAX_DEV_DIFFICULTY, so mining stays instant.
+    pub fn mine_n_blocks(&mut self, n: u64, mut data: impl FnMut(BlockHeight) -> T) -> Result<Vec<Block<T>>, String> {
+        if !self.params.has_fixed_difficulty() || self.params.initial_difficulty > difficulty::MAX_DEV_DIFFICULTY {
+            return Err(format!(
+                "mining bursts need a dev chain, without retargeting and at difficulty {} or less",
+                difficulty::MAX_DEV_DIFFICULTY
+            ));
+        }
+        let mut mined = vec![];
+        for _ in 0..n {
+            let parent = self.get_last_block();
+            let id = parent.header.id.next().expect("chain is shorter than u64::MAX blocks");
+            let mut block = Block::unmined(
+                &parent.header,
+                data(id),
+                None,
+                self.next_difficulty(),
+                Timestamp::now().max(self.min_next_timestamp()),
+            );
+            self.check_limits(&block).map_err(|e| e.to_string())?;
+            self.consensus.seal(&mut block)?;
+            self.try_add_block(block.clone()).map_err(|e| e.to_string())?;
+            mined.push(block);
         }
+        Ok(mined)
+    }
+
+    // producers ranked by blocks mined at heights in `range`, blocks without a miner are skipped
+    pub fn miner_leaderboard(&self, range: Range<BlockHeight>) -> Vec<MinerRank> {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for block in self.blocks_in_range(range) {
+            if let Some(miner) = &block.header.miner {
+                *counts.entry(miner).or_default() += 1;
+            }
+        }
+        let mut leaderboard: Vec<MinerRank> = counts
+            .into_iter()
+            .map(|(miner, blocks)| MinerRank {
+                miner: miner.to_string(),
+                blocks,
+            })
+            .collect();
+        leaderboard.sort_by(|a, b| b.blocks.cmp(&a.blocks).then_with(|| a.miner.cmp(&b.miner)));
+        leaderboard
+    }
 
-        if is_local_valid {
-            return local;
+    // difficulty and time since the parent of every block at heights in `range`
+    pub fn difficulty_history(&self, range: Range<BlockHeight>) -> Vec<DifficultyPoint> {
+        analytics::difficulty_history(&self.blocks, range)
+    }
+
+    pub fn block_time_histogram(&self, range: Range<BlockHeight>) -> Vec<BlockTimeBucket> {
+        analytics::block_time_histogram(&self.blocks, range)
+    }
+
+    // how difficulty, block times and sizes have evolved over the local chain
+    pub fn stats(&self) -> ChainStats {
+        let history = self.difficulty_history(BlockHeight::GENESIS..BlockHeight::MAX);
+        let transactions = || self.blocks.iter().flat_map(|block| block.data.transactions());
+        ChainStats {
+            blocks: self.blocks.len(),
+            average_block_interval: analytics::average_block_interval(&history),
+            difficulty: self.get_last_block().header.difficulty,
+            next_difficulty: self.next_difficulty(),
+            total_work: self.total_work(),
+            transactions: transactions().count(),
+            chain_bytes: self.encoded_size(),
+            transaction_bytes: transactions().map(Transaction::encoded_size).sum(),
+            history,
         }
-        return remote;
     }
 
-    pub fn get_last_block(&self) -> &Block {
+    pub fn get_last_block(&self) -> &Block<T> {
         self.blocks.last().unwrap()
     }
+
+    pub fn encoded_size(&self) -> usize {
+        self.blocks.iter().map(Block::encoded_size).sum()
+    }
 }
 
+#[cfg(unix)]
+pub mod admin;
+pub mod analytics;
+pub mod api;
+#[cfg(feature = "mmap")]
+pub mod archive;
+pub mod auth;
+#[cfg(feature = "da-sampling")]
+pub mod availability;
+#[cfg(feature = "bls")]
+pub mod bls;
+pub mod bundle;
+pub mod capabilities;
+pub mod checkpoint;
+pub mod consensus;
+#[cfg(unix)]
+pub mod daemon;
+pub mod devnet;
+pub mod difficulty;
+pub mod direct;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod explain;
+pub mod forks;
+pub mod genesis;
+pub mod hashing;
+pub mod journal;
+pub mod keys;
+pub mod merkle;
+pub mod miner;
+pub mod names;
+pub mod node;
+pub mod nonce;
+pub mod orphans;
+pub mod outbound;
 pub mod p2p;
+pub mod params;
+pub mod payments;
+pub mod power;
+pub mod preimage;
+pub mod progress;
+pub mod relay;
+pub mod resources;
+pub mod rules;
+pub mod schema;
+pub mod selftest;
+pub mod signing;
+pub mod state;
+pub mod store;
+pub mod sync;
+pub mod target;
+#[cfg(test)]
+mod testing;
+pub mod transaction;
+pub mod units;
+#[cfg(feature = "vm")]
+pub mod vm;
+pub mod wallet;
+pub mod watchtower;
+pub mod work;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dev_params;
+
+    fn body(height: BlockHeight) -> BlockBody {
+        BlockBody::new(format!("block {}", height), vec![])
+    }
+
+    #[test]
+    fn mine_n_blocks_keeps_a_dev_chain_at_its_difficulty() {
+        let mut app: App = App::new(dev_params());
+        let mined = app.mine_n_blocks(5, body).unwrap();
+        assert_eq!(mined.len(), 5);
+        let first = mined[0].header.target();
+        assert!(mined.iter().all(|block| block.header.target() == first));
+        // the first compact target is the initial difficulty's, rounded to three bytes
+        assert_eq!(first.to_compact(), Target::from_zero_bits(1).to_compact());
+    }
+
+    #[test]
+    fn mine_n_blocks_refuses_chains_that_retarget() {
+        let mut app: App = App::new(ChainParams {
+            initial_difficulty: 1,
+            ..ChainParams::default()
+        });
+        assert!(app.mine_n_blocks(1, body).is_err());
+        let mut app: App = App::new(ChainParams {
+            initial_difficulty: difficulty::MAX_DEV_DIFFICULTY + 1,
+            ..dev_params()
+        });
+        assert!(app.mine_n_blocks(1, body).is_err());
+    }
+
+    // a chain of `blocks` blocks mined on top of the dev genesis, told apart by `tag`
+    fn mined(tag: &str, blocks: u64) -> Vec<Block> {
+        let mut app: App = App::new(dev_params());
+        app.mine_n_blocks(blocks, |height| BlockBody::new(format!("{} {}", tag, height), vec![]))
+            .unwrap()
+    }
+
+    fn hashes(blocks: &[Block]) -> Vec<String> {
+        blocks.iter().map(|block| block.header.hash.clone()).collect()
+    }
+
+    // try_add_block, with the rolled back blocks as hashes
+    fn add(app: &mut App, block: &Block) -> Result<Vec<String>, ChainError> {
+        app.try_add_block(block.clone()).map(|rolled_back| hashes(&rolled_back))
+    }
+
+    #[test]
+    fn heavier_branch_reorganizes_the_chain() {
+        let mut app: App = App::new(dev_params());
+        let local = mined("local", 2);
+        for block in &local {
+            assert_eq!(add(&mut app, block), Ok(vec![]));
+        }
+        let remote = mined("remote", 3);
+        // less work than the chain, kept as a side chain
+        assert_eq!(add(&mut app, &remote[0]), Ok(vec![]));
+        // as much work, the lower tip hash wins
+        let remote_wins_tie = remote[1].header.hash < local[1].header.hash;
+        let (tie, heavier) = match remote_wins_tie {
+            true => (Ok(hashes(&local)), Ok(vec![])),
+            false => (Err(ChainError::LostTieBreak), Ok(hashes(&local))),
+        };
+        assert_eq!(add(&mut app, &remote[1]), tie);
+        assert_eq!(add(&mut app, &remote[2]), heavier);
+        assert_eq!(hashes(&app.blocks[1..]), hashes(&remote));
+        assert_eq!(app.state, State::replay(&app.params, &app.blocks).unwrap());
+        // the blocks rolled back are a side chain now
+        assert_eq!(add(&mut app, &local[1]), Err(ChainError::DuplicateBlock));
+    }
+
+    #[test]
+    fn orphans_attach_once_their_parent_arrives() {
+        let mut app: App = App::new(dev_params());
+        let remote = mined("remote", 3);
+        assert_eq!(add(&mut app, &remote[2]), Err(ChainError::Orphan));
+        assert_eq!(add(&mut app, &remote[1]), Err(ChainError::Orphan));
+        assert_eq!(app.blocks.len(), 1);
+        assert_eq!(add(&mut app, &remote[0]), Ok(vec![]));
+        assert_eq!(hashes(&app.blocks[1..]), hashes(&remote));
+        assert!(app.orphans.is_empty());
+    }
+
+    #[test]
+    fn orphans_reorganize_past_a_lost_tie_break() {
+        let mut app: App = App::new(dev_params());
+        let local = mined("local", 1);
+        assert_eq!(add(&mut app, &local[0]), Ok(vec![]));
+        let remote = mined("remote", 3);
+        assert_eq!(add(&mut app, &remote[2]), Err(ChainError::Orphan));
+        assert_eq!(add(&mut app, &remote[1]), Err(ChainError::Orphan));
+        // remote[0] ties with the tip, whichever way that goes its orphans outweigh it
+        assert_eq!(add(&mut app, &remote[0]), Ok(hashes(&local)));
+        assert_eq!(hashes(&app.blocks[1..]), hashes(&remote));
+        assert!(app.orphans.is_empty());
+    }
+
+    #[test]
+    fn stats_total_the_encoded_sizes() {
+        let script = devnet::DevnetScript::from_yaml(include_str!("../scenarios/devnet.yaml")).unwrap();
+        let app = script.run().unwrap();
+        let stats = app.stats();
+        let transactions: Vec<&Transaction> = app.blocks.iter().flat_map(|block| block.data.transactions()).collect();
+        assert_eq!(stats.transactions, 3);
+        assert_eq!(stats.transactions, transactions.len());
+        assert_eq!(
+            stats.transaction_bytes,
+            transactions
+                .iter()
+                .map(|transaction| transaction.encoded_size())
+                .sum::<usize>()
+        );
+        assert_eq!(
+            stats.chain_bytes,
+            app.blocks.iter().map(Block::encoded_size).sum::<usize>()
+        );
+        assert!(stats.transaction_bytes < stats.chain_bytes);
+    }
+
+    #[test]
+    fn races_are_counted_once_per_pair_of_tips() {
+        let genesis: App = App::new(dev_params());
+        let mut local = genesis.blocks.clone();
+        local.extend(mined("local", 1));
+        let mut remote = genesis.blocks.clone();
+        remote.extend(mined("remote", 1));
+        let mut stats = RaceStats::default();
+        let won = prefers_remote(&mut stats, &local, &remote);
+        // the same chain again, and the loser's side of the same race
+        assert_eq!(prefers_remote(&mut stats, &local, &remote), won);
+        assert_eq!(prefers_remote(&mut stats, &remote, &local), !won);
+        assert_eq!(stats.races, 1);
+        assert_eq!(stats.won_by_remote, u64::from(won));
+    }
+
+    #[test]
+    fn pinned_checkpoints_cannot_be_moved() {
+        let mut app: App = App::new(dev_params());
+        let genesis = app.genesis_hash().to_string();
+        app.add_checkpoint(BlockHeight(0), genesis.clone()).unwrap();
+        app.add_checkpoint(BlockHeight(0), genesis.clone()).unwrap();
+        assert_eq!(
+            app.add_checkpoint(BlockHeight(0), "other".to_string()),
+            Err(ChainError::CheckpointPinned {
+                height: BlockHeight(0),
+                pinned: genesis.clone(),
+            })
+        );
+        assert_eq!(app.checkpoints.get(&BlockHeight(0)), Some(&genesis));
+    }
+}
@@ -1,104 +1,352 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
-use blockchain_basic::{p2p, App};
-use libp2p::futures::StreamExt;
-use libp2p::noise::{Keypair, X25519Spec};
-use libp2p::swarm::SwarmBuilder;
-use libp2p::{core::upgrade, mplex::MplexConfig, noise::NoiseConfig, tcp::TokioTcpConfig};
-use libp2p::{Swarm, Transport};
+#[cfg(unix)]
+use blockchain_basic::admin;
+use blockchain_basic::api::{NodeApi, NodeHandle};
+#[cfg(feature = "bls")]
+use blockchain_basic::bls::BlsCheckpoints;
+use blockchain_basic::bundle::Bootstrap;
+use blockchain_basic::consensus::{self, ProofOfAuthority, ProofOfStake, ProofOfWork};
+#[cfg(unix)]
+use blockchain_basic::daemon;
+use blockchain_basic::direct::DirectMessaging;
+use blockchain_basic::encoding::Encoding;
+use blockchain_basic::journal::{EventLog, ReorgJournal};
+use blockchain_basic::node::{self, Node};
+use blockchain_basic::nonce::NonceOrder;
+use blockchain_basic::params::ChainParams;
+use blockchain_basic::transaction::{Mempool, SenderLimits};
+use blockchain_basic::watchtower::Watchtower;
+use blockchain_basic::{checkpoint, keys, p2p, power, relay, selftest, store};
+use libp2p::futures::future::join_all;
 use log::{error, info};
 use tokio::io::{stdin, AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
 use tokio::time::sleep;
-use tokio::{select, spawn};
+use tokio::{join, spawn};
+
+const CHECKPOINT_INTERVAL_SECS: u64 = 60;
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    // checks this build and platform, then exits
+    if std::env::args().nth(1).as_deref() == Some("self-test") {
+        let mut failed = false;
+        for check in selftest::run() {
+            match check.result {
+                Ok(()) => println!("{:<10} ok", check.name),
+                Err(e) => {
+                    println!("{:<10} FAILED: {}", check.name, e);
+                    failed = true;
+                }
+            }
+        }
+        std::process::exit(i32::from(failed));
+    }
+    // held until main returns, which removes the pid file
+    #[cfg(unix)]
+    let daemon = start_daemon();
+    #[cfg(not(unix))]
+    let daemon: Option<(PathBuf, ())> = None;
+    if daemon.is_none() {
+        pretty_env_logger::init();
+    }
+    let data_dir = daemon.as_ref().map(|(dir, _)| dir.clone());
+
+    match power::SystemPowerMonitor::from_env() {
+        Ok(Some(monitor)) => power::set_monitor(Box::new(monitor)).expect("can install power monitor"),
+        Ok(None) => {}
+        Err(e) => panic!("{}", e),
+    }
+
+    // several independent chains in one process instead of the one configured below
+    if let Ok(path) = std::env::var("NODES_FILE") {
+        let nodes = run_nodes(Path::new(&path), data_dir.as_deref());
+        match data_dir {
+            #[cfg(unix)]
+            Some(_) => daemon::until_terminated(nodes).await,
+            _ => nodes.await,
+        }
+        return;
+    }
+
+    let params = ChainParams::from_env().expect("can read chain params");
+    info!("Chain params: {:?}", params);
+    let chain_file = std::env::var("CHAIN_FILE").ok().map(PathBuf::from);
+    let checkpoints = checkpoint::checkpoints_from_env().expect("can read CHECKPOINTS");
+    // before the chain file is loaded, so a saved chain is held to it too
+    let signet_challenge = std::env::var("SIGNET_CHALLENGE")
+        .ok()
+        .map(|key| keys::decode_public_key(&key).expect("can decode SIGNET_CHALLENGE"));
+    let mut app = match node::load_app(params, checkpoints, signet_challenge, chain_file.as_deref()) {
+        Ok(app) => app,
+        Err(e) => panic!("{}", e),
+    };
+    let signet_key = std::env::var("SIGNET_KEY")
+        .ok()
+        .map(|key| keys::decode_keypair(&key).expect("can decode SIGNET_KEY"));
+    if let Some(challenge) = &app.signet_challenge {
+        info!("Signet mode, blocks must be signed by {:?}", challenge);
+    }
+    let genesis = &app.params.genesis;
+    if app.signet_challenge.is_some() && !(genesis.stakes.is_empty() && genesis.authorities.is_empty()) {
+        panic!("signet mode can't be used on a proof of stake or authority chain, both sign blocks");
+    }
+    if app.params.genesis.stakes.is_empty() && app.params.genesis.authorities.is_empty() {
+        let threads = consensus::mining_threads_from_env().expect("can read MINING_THREADS");
+        let nonces = NonceOrder::from_env().expect("can read MINING_NONCES");
+        info!("Proof of work, mining on {} threads, {:?} nonces", threads, nonces);
+        let engine = ProofOfWork::new(&app.params).with_threads(threads);
+        app.consensus = Arc::new(engine.with_nonces(nonces.strategy()));
+    }
+    if !app.params.genesis.stakes.is_empty() {
+        info!("Proof of stake, {} validators", app.params.genesis.stakes.len());
+        // proposes a block in each of this validator's slots, see p2p::handle_propose
+        if let Ok(key) = std::env::var("STAKE_KEY") {
+            let keys = keys::decode_keypair(&key).expect("can decode STAKE_KEY");
+            info!(
+                "Proposing as validator {}",
+                keys::encode_public_key(&keys).expect("stake key is ed25519")
+            );
+            let engine = ProofOfStake::new(&app.params).expect("can read validators");
+            app.consensus = Arc::new(engine.with_keys(keys));
+        }
+    }
+    if !app.params.genesis.authorities.is_empty() {
+        info!(
+            "Proof of authority, {} authorities",
+            app.params.genesis.authorities.len()
+        );
+        // produces a block in each of this authority's turns, or in its fallback turn when the
+        // authorities before it are offline, see p2p::handle_propose
+        if let Ok(key) = std::env::var("AUTHORITY_KEY") {
+            let keys = keys::decode_keypair(&key).expect("can decode AUTHORITY_KEY");
+            info!(
+                "Producing blocks as authority {}",
+                keys::encode_public_key(&keys).expect("authority key is ed25519")
+            );
+            let engine = ProofOfAuthority::new(&app.params).expect("can read authorities");
+            app.consensus = Arc::new(engine.with_keys(keys));
+        }
+    }
 
-    info!("Peer Id: {}", p2p::PEER_ID.clone());
-    let (response_sender, mut response_rcv) = mpsc::unbounded_channel();
-    let (init_sender, mut init_rcv) = mpsc::unbounded_channel();
+    // a chain bundle published by the operator, imported before joining gossip
+    if let Some(bootstrap) = Bootstrap::from_env().expect("can read BOOTSTRAP_URL") {
+        info!("Bootstrapping from {}", bootstrap.url);
+        match bootstrap.run(&mut app).await {
+            Ok(true) => {
+                info!("Bootstrapped to height {}", app.get_last_block().header.id);
+                if let Some(path) = &chain_file {
+                    store::save_chain(path, &app.blocks).expect("can save bootstrapped chain");
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!("bootstrap failed, syncing from peers instead: {}", e),
+        }
+    }
+
+    let mut node = Node::new("main".to_string(), app, &p2p::KEYS).await;
+
+    node.operator_keys = std::env::var("CHECKPOINT_KEY")
+        .ok()
+        .map(|key| keys::decode_keypair(&key).expect("can decode CHECKPOINT_KEY"));
+    if let Some(keys) = &node.operator_keys {
+        info!(
+            "Publishing checkpoints as authority {}",
+            keys::encode_public_key(keys).expect("checkpoint key is ed25519")
+        );
+    }
+    #[cfg(feature = "bls")]
+    let votes_for_checkpoints = {
+        let bls = BlsCheckpoints::from_env().expect("can read BLS checkpoint config");
+        if let Some(bls) = &bls {
+            info!(
+                "BLS checkpoints need {} of {} validators",
+                bls.validators.threshold(),
+                bls.validators.len()
+            );
+        }
+        if let Some(signer) = bls.as_ref().and_then(|bls| bls.signer.as_ref()) {
+            info!("Voting for checkpoints as BLS validator {}", signer.index);
+        }
+        let is_validator = bls.as_ref().is_some_and(|bls| bls.signer.is_some());
+        node.swarm.behaviour_mut().bls_checkpoints = bls;
+        is_validator
+    };
+    #[cfg(not(feature = "bls"))]
+    let votes_for_checkpoints = false;
+    if node.operator_keys.is_some() || votes_for_checkpoints {
+        let checkpoint_sender = node.sender();
+        spawn(async move {
+            loop {
+                sleep(Duration::from_secs(CHECKPOINT_INTERVAL_SECS)).await;
+                if checkpoint_sender.send(p2p::EventType::PublishCheckpoint).is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
-    let auth_keys = Keypair::<X25519Spec>::new()
-        .into_authentic(&p2p::KEYS)
-        .expect("can create auth keys");
+    let admin_socket = std::env::var("ADMIN_SOCKET")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| data_dir.as_ref().map(|dir| dir.join("admin.sock")));
+    #[cfg(unix)]
+    if let Some(path) = admin_socket {
+        serve_admin(path, node.handle());
+    }
 
-    let transp = TokioTcpConfig::new()
-        .upgrade(upgrade::Version::V1)
-        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
-        .multiplex(MplexConfig::new())
-        .boxed();
+    let behaviour = node.swarm.behaviour_mut();
+    behaviour.signet_key = signet_key;
+    behaviour.miner = std::env::var("MINER_ID").ok();
+    behaviour.dev_block_interval = p2p::dev_block_interval_from_env().expect("can read DEV_BLOCK_INTERVAL");
+    if let Some(interval) = behaviour.dev_block_interval {
+        info!("Dev mode, mining at most one block every {}s", interval.as_secs());
+    }
+    behaviour.wallet_key = std::env::var("WALLET_KEY")
+        .ok()
+        .map(|key| keys::decode_keypair(&key).expect("can decode WALLET_KEY"));
+    let wallet_address = behaviour
+        .wallet_key
+        .as_ref()
+        .and_then(|keys| keys::address(&keys.public()));
+    if let Some(address) = &wallet_address {
+        info!("Wallet address: {}", address);
+    }
+    // block rewards go to MINER_ADDRESS, or to the wallet if there is one
+    behaviour.miner_address = std::env::var("MINER_ADDRESS").ok().or(wallet_address);
+    behaviour.chain_file = chain_file;
+    behaviour.set_encoding(Encoding::from_env().expect("can read WIRE_ENCODING"));
+    behaviour.messaging = DirectMessaging::from_env().expect("can read validator config");
+    behaviour.reorg_journal = ReorgJournal::from_env().expect("can open reorg journal");
+    behaviour.event_log = EventLog::from_env().expect("can open event log");
+    // stakers or authorities, whichever the chain has
+    let genesis = &behaviour.app.params.genesis;
+    let validators = genesis.stakes.keys().chain(&genesis.authorities).cloned().collect();
+    let tip = behaviour.app.get_last_block().header.id;
+    behaviour.event_log.validator_set(validators, tip);
+    behaviour.mempool = Mempool::new(SenderLimits::from_env().expect("can read sender limits"));
+    behaviour.watchtower = Watchtower::from_env().expect("can read watchtower config");
+    if let Some(watchtower) = &mut behaviour.watchtower {
+        info!("Watchtower mode, validating and raising alerts only");
+        watchtower.follow(&behaviour.app.blocks);
+    }
+    behaviour.checkpoint_authority = std::env::var("CHECKPOINT_AUTHORITY")
+        .ok()
+        .map(|key| keys::decode_public_key(&key).expect("can decode CHECKPOINT_AUTHORITY"));
 
-    let behaviour = p2p::AppBehaviour::new(App::new(), response_sender, init_sender.clone()).await;
+    node.listen(0).expect("swarm can be started");
 
-    let mut swarm = SwarmBuilder::new(transp, behaviour, *p2p::PEER_ID)
-        .executor(Box::new(|fut| {
-            spawn(fut);
-        }))
-        .build();
+    #[cfg(unix)]
+    if data_dir.is_some() {
+        daemon::until_terminated(node.run()).await;
+        return;
+    }
+    let input_sender = node.sender();
+    let forward_input = async move {
+        let mut stdin = BufReader::new(stdin()).lines();
+        while let Some(line) = stdin.next_line().await.expect("can read line from stdin") {
+            if input_sender.send(p2p::EventType::Input(line)).is_err() {
+                break;
+            }
+        }
+    };
+    join!(node.run(), forward_input);
+}
 
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0".parse().expect("can get a local socket"),
-    )
-    .expect("swarm can be started");
+// With --daemon, starts the node again in the background and exits. In the
+// background process, returns the data dir and the pid file: a daemon has no
+// terminal, it logs to its data dir and takes commands over the admin socket only.
+#[cfg(unix)]
+fn start_daemon() -> Option<(PathBuf, daemon::PidFile)> {
+    if !std::env::args().any(|arg| arg == daemon::DAEMON_FLAG) {
+        return None;
+    }
+    if !daemon::is_detached() {
+        let pid = daemon::spawn_detached().expect("can start daemon");
+        println!("node running in the background as pid {}", pid);
+        std::process::exit(0);
+    }
+    let dir = daemon::data_dir().expect("can create data dir");
+    daemon::init_logging(&dir).expect("can open log file");
+    let pid_file = daemon::PidFile::create(&dir).expect("can write pid file");
+    info!("pid file is {}", pid_file.path().display());
+    Some((dir, pid_file))
+}
 
+#[cfg(unix)]
+fn serve_admin(path: PathBuf, handle: NodeHandle) {
     spawn(async move {
-        sleep(Duration::from_secs(1)).await;
-        info!("sending init event");
-        init_sender.send(p2p::EventType::Init).expect("can send init event");
+        if let Err(e) = admin::serve(&path, handle).await {
+            error!("admin socket stopped: {}", e);
+        }
     });
+}
 
+// Runs every node of the YAML file at `path` on this task. Input lines are
+// "<name> <command>" for one node, or "status" for a line per node. A daemon
+// serves an admin socket per node in `data_dir` instead, named after the node.
+async fn run_nodes(path: &Path, data_dir: Option<&Path>) {
+    let configs = node::load_configs(path).expect("can read nodes file");
+    let relays: Vec<(String, String)> = configs
+        .iter()
+        .filter_map(|config| Some((config.relay_from.clone()?, config.name.clone())))
+        .collect();
+    let mut running = vec![];
+    let mut nodes = vec![];
+    for config in configs {
+        let node = Node::from_config(config).await.expect("can start node");
+        nodes.push((node.name.clone(), node.sender(), node.handle()));
+        running.push(node.run());
+    }
+    info!("running {} chains", nodes.len());
+    let handle_of = |name: &str| {
+        nodes
+            .iter()
+            .find(|(node_name, _, _)| node_name == name)
+            .map(|(_, _, handle)| handle.clone())
+            .expect("relay nodes are checked when loading")
+    };
+    for (source, target) in relays {
+        info!("relaying headers of {} to {}", source, target);
+        spawn(relay::run(handle_of(&source), handle_of(&target)));
+    }
+    if let Some(dir) = data_dir {
+        #[cfg(unix)]
+        for (name, _, handle) in &nodes {
+            serve_admin(dir.join(format!("{}.sock", name)), handle.clone());
+        }
+        join_all(running).await;
+        return;
+    }
+    join!(join_all(running), forward_node_input(&nodes));
+}
+
+async fn forward_node_input(nodes: &[(String, mpsc::UnboundedSender<p2p::EventType>, NodeHandle)]) {
     let mut stdin = BufReader::new(stdin()).lines();
-    loop {
-        let evt = {
-            select! {
-                line = stdin.next_line() => Some(p2p::EventType::Input(line.expect("can get line").expect("can read line from stdin"))),
-                response = response_rcv.recv() => {
-                    Some(response.expect("response exists"))
-                },
-                _init = init_rcv.recv() => {
-                    Some(p2p::EventType::Init)
+    while let Some(line) = stdin.next_line().await.expect("can read line from stdin") {
+        if line == "status" {
+            for (name, _, handle) in nodes {
+                match handle.status().await {
+                    Ok(status) => info!(
+                        "{}: spec {}, height {}, tip {}, {} peers, {} pending transactions",
+                        name, status.spec, status.height, status.tip, status.peers, status.mempool
+                    ),
+                    Err(e) => error!("{}: {}", name, e),
                 }
-                event = swarm.select_next_some() => {
-                    info!("Unhandled Swarm Event: {:?}", event);
-                    None
-                },
             }
-        };
-
-        if let Some(event) = evt {
-            match event {
-                p2p::EventType::Init => {
-                    let peers = p2p::get_list_peers(&swarm);
-                    info!("connected nodes: {}", peers.len());
-                    if !peers.is_empty() {
-                        let req = p2p::LocalChainRequest {
-                            from_peer_id: peers.iter().last().expect("at least one peer").to_string(),
-                        };
-
-                        let json = serde_json::to_string(&req).expect("can jsonify request");
-                        swarm
-                            .behaviour_mut()
-                            .floodsub
-                            .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
-                    }
-                }
-                p2p::EventType::LocalChainResponse(resp) => {
-                    let json = serde_json::to_string(&resp).expect("can jsonify response");
-                    swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
+            continue;
+        }
+        let (name, cmd) = line.split_once(' ').unwrap_or((&line, ""));
+        match nodes.iter().find(|(node_name, _, _)| node_name == name) {
+            Some((_, sender, _)) => {
+                if sender.send(p2p::EventType::Input(cmd.to_string())).is_err() {
+                    error!("{} is not running", name);
                 }
-                p2p::EventType::Input(line) => match line.as_str() {
-                    "ls p" => p2p::handle_print_peers(&swarm),
-                    cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(&swarm),
-                    cmd if cmd.starts_with("create b") => p2p::handle_create_block(cmd, &mut swarm),
-                    _ => error!("unknown command"),
-                },
             }
+            None => error!("unknown node {}, commands are \"<node> <command>\" or \"status\"", name),
         }
     }
 }
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Leaves and inner nodes are hashed with different prefixes so an inner node can't
+// be passed off as a leaf. An odd node at the end of a level is carried up as is
+// rather than paired with itself, so no two leaf lists share a root.
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+fn hash_leaf(leaf: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().as_slice().to_owned()
+}
+
+fn hash_node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().as_slice().to_owned()
+}
+
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// hex encoded root, all zeros for an empty body
+pub fn merkle_root(leaves: &[Vec<u8>]) -> String {
+    let mut level: Vec<Vec<u8>> = leaves.iter().map(|leaf| hash_leaf(leaf)).collect();
+    if level.is_empty() {
+        return hex::encode([0u8; 32]);
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hex::encode(&level[0])
+}
+
+// Sibling hashes from a leaf up to the root, enough for a light client holding only
+// the header to check that one leaf is in the block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    // hex encoded, each with whether it sits left of the running hash
+    pub siblings: Vec<(String, bool)>,
+}
+
+pub fn merkle_proof(leaves: &[Vec<u8>], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut level: Vec<Vec<u8>> = leaves.iter().map(|leaf| hash_leaf(leaf)).collect();
+    let mut position = index;
+    let mut siblings = vec![];
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            siblings.push((hex::encode(hash), sibling < position));
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+    Some(MerkleProof { index, siblings })
+}
+
+pub fn verify_proof(root: &str, leaf: &[u8], proof: &MerkleProof) -> bool {
+    let mut hash = hash_leaf(leaf);
+    for (sibling, is_left) in &proof.siblings {
+        let sibling = match hex::decode(sibling) {
+            Ok(sibling) => sibling,
+            Err(_) => return false,
+        };
+        hash = match is_left {
+            true => hash_node(&sibling, &hash),
+            false => hash_node(&hash, &sibling),
+        };
+    }
+    hex::encode(hash) == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|i| vec![i]).collect()
+    }
+
+    #[test]
+    fn proofs_round_trip_for_every_index() {
+        for count in [1, 2, 3, 5] {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index).unwrap();
+                assert!(verify_proof(&root, leaf, &proof), "leaf {} of {}", index, count);
+                // nor does it prove any other leaf
+                assert!(!verify_proof(&root, &[count], &proof));
+            }
+            assert_eq!(merkle_proof(&leaves, leaves.len()), None);
+        }
+    }
+
+    #[test]
+    fn padded_leaf_lists_have_their_own_root() {
+        for count in [1, 3, 5] {
+            let leaves = leaves(count);
+            let mut padded = leaves.clone();
+            padded.push(leaves.last().unwrap().clone());
+            assert_ne!(merkle_root(&leaves), merkle_root(&padded));
+        }
+    }
+
+    #[test]
+    fn inner_node_is_not_a_leaf() {
+        let leaves = leaves(2);
+        let root = merkle_root(&leaves);
+        let inner = [hash_leaf(&leaves[0]), hash_leaf(&leaves[1])].concat();
+        let proof = MerkleProof {
+            index: 0,
+            siblings: vec![],
+        };
+        assert!(!verify_proof(&root, &inner, &proof));
+        assert_ne!(merkle_root(&[inner]), root);
+    }
+}
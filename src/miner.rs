@@ -0,0 +1,122 @@
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::consensus::ConsensusEngine;
+use crate::p2p::EventType;
+use crate::progress::MiningProgress;
+use crate::resources::TaskGuard;
+use crate::units::Timestamp;
+use crate::{Block, BlockBody};
+
+// weight of each new measurement in the hashrate average, the rest is the average's
+const HASHRATE_SMOOTHING: f64 = 0.3;
+
+// A block for the miner to seal, assembled on the event loop, see p2p::start_mining.
+pub struct MineRequest {
+    pub block: Block,
+    pub consensus: Arc<dyn ConsensusEngine<BlockBody>>,
+    // waited before mining starts, see AppBehaviour::dev_block_interval
+    pub delay: Duration,
+    pub cancel: CancellationToken,
+    pub reply: Option<oneshot::Sender<Result<Block, String>>>,
+}
+
+// Handle to the node's mining task. The task seals one requested block at a time
+// off the event loop and sends each back as EventType::BlockMined, where it is
+// added to the chain and broadcast, with its progress as MiningProgress meanwhile.
+pub struct Miner {
+    requests: mpsc::UnboundedSender<MineRequest>,
+    // hashes per second, an exponentially weighted average over every progress
+    // report so far, none before the first
+    hashrate: Arc<Mutex<Option<f64>>>,
+}
+
+impl Miner {
+    // the task runs until the handle is dropped
+    pub fn spawn(events: mpsc::UnboundedSender<EventType>, task: TaskGuard) -> Self {
+        let (requests, mut receiver) = mpsc::unbounded_channel();
+        let hashrate = Arc::new(Mutex::new(None));
+        let average = hashrate.clone();
+        spawn(async move {
+            let _task = task;
+            while let Some(request) = receiver.recv().await {
+                mine(request, &events, &average).await;
+            }
+        });
+        Self { requests, hashrate }
+    }
+
+    // for comparing hashers and thread counts, see MINING_THREADS
+    pub fn hashrate(&self) -> Option<f64> {
+        *self.hashrate.lock().expect("hashrate lock")
+    }
+
+    pub fn mine(&self, request: MineRequest) -> Result<(), String> {
+        self.requests
+            .send(request)
+            .map_err(|_| "miner is not running".to_string())
+    }
+}
+
+async fn mine(request: MineRequest, events: &mpsc::UnboundedSender<EventType>, hashrate: &Mutex<Option<f64>>) {
+    let MineRequest {
+        mut block,
+        consensus,
+        delay,
+        cancel,
+        reply,
+    } = request;
+    if !delay.is_zero() {
+        info!("dev mode: mining in {:.1}s", delay.as_secs_f64());
+        select! {
+            _ = sleep(delay) => {}
+            _ = cancel.cancelled() => {}
+        }
+        block.header.timestamp = block.header.timestamp.max(Timestamp::now());
+    }
+    let (progress_sender, mut progress) = mpsc::unbounded_channel();
+    let mining = block.mine_async(consensus, cancel, Some(progress_sender));
+    let mut mining = pin!(mining);
+    let mut last = MiningProgress::default();
+    let mut report = |update: MiningProgress| {
+        measure(hashrate, &last, &update);
+        last = update;
+        let _ = events.send(EventType::MiningProgress(update));
+    };
+    let result = loop {
+        select! {
+            result = &mut mining => break result,
+            Some(update) = progress.recv() => report(update),
+        }
+    };
+    // the run's final report, see ProgressMeter::finish
+    while let Ok(update) = progress.try_recv() {
+        report(update);
+    }
+    let result = result.map(Box::new);
+    if events.send(EventType::BlockMined { result, reply }).is_err() {
+        error!("could not deliver mined block");
+    }
+}
+
+// folds the rate between two reports of a run into the average
+fn measure(hashrate: &Mutex<Option<f64>>, last: &MiningProgress, update: &MiningProgress) {
+    let secs = update.elapsed.saturating_sub(last.elapsed).as_secs_f64();
+    if secs <= 0.0 {
+        return;
+    }
+    let rate = update.attempts.saturating_sub(last.attempts) as f64 / secs;
+    let mut average = hashrate.lock().expect("hashrate lock");
+    *average = Some(match *average {
+        Some(average) => average + HASHRATE_SMOOTHING * (rate - average),
+        None => rate,
+    });
+}
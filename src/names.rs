@@ -0,0 +1,158 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ChainError;
+use crate::units::{Amount, BlockHeight};
+
+// Names registered to addresses, first come first served. A transaction carrying
+// a name claims it for its recipient, or renews it if the recipient already holds
+// it; a name whose lease ran out is free for anyone to claim again.
+
+// blocks a claim or renewal holds a name for
+pub const NAME_LEASE_BLOCKS: u64 = 10_000;
+// least fee a name transaction pays, so squatting many names costs something
+pub const MIN_NAME_FEE: Amount = Amount(1);
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NameRecord {
+    pub owner: String,
+    // the first height the name is no longer held at
+    pub expires: BlockHeight,
+}
+
+impl NameRecord {
+    pub fn is_held_at(&self, height: BlockHeight) -> bool {
+        height < self.expires
+    }
+}
+
+// lowercase letters, digits and dashes, neither starting nor ending with a dash
+pub fn check_name(name: &str) -> Result<(), ChainError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(ChainError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+// The record of `name` after a block at `height` claims it for `owner`, whoever
+// held it being `current`. Renewals extend the lease from where it ends.
+pub fn claim(
+    current: Option<&NameRecord>,
+    name: &str,
+    owner: &str,
+    fee: Amount,
+    height: BlockHeight,
+) -> Result<NameRecord, ChainError> {
+    check_name(name)?;
+    if fee < MIN_NAME_FEE {
+        return Err(ChainError::NameFeeTooLow { fee, min: MIN_NAME_FEE });
+    }
+    let start = match current {
+        Some(record) if record.is_held_at(height) && record.owner != owner => {
+            return Err(ChainError::NameTaken(name.to_string()))
+        }
+        Some(record) if record.is_held_at(height) => record.expires,
+        _ => height,
+    };
+    Ok(NameRecord {
+        owner: owner.to_string(),
+        expires: BlockHeight(start.0.saturating_add(NAME_LEASE_BLOCKS)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(owner: &str, expires: u64) -> NameRecord {
+        NameRecord {
+            owner: owner.to_string(),
+            expires: BlockHeight(expires),
+        }
+    }
+
+    #[test]
+    fn new_name_is_leased_from_the_claim() {
+        assert_eq!(
+            claim(None, "alice", "a", MIN_NAME_FEE, BlockHeight(5)),
+            Ok(held("a", 5 + NAME_LEASE_BLOCKS))
+        );
+    }
+
+    #[test]
+    fn held_name_is_taken() {
+        let record = held("a", 100);
+        assert_eq!(
+            claim(Some(&record), "alice", "b", MIN_NAME_FEE, BlockHeight(99)),
+            Err(ChainError::NameTaken("alice".to_string()))
+        );
+    }
+
+    #[test]
+    fn renewal_extends_from_expiry() {
+        let record = held("a", 100);
+        assert_eq!(
+            claim(Some(&record), "alice", "a", MIN_NAME_FEE, BlockHeight(50)),
+            Ok(held("a", 100 + NAME_LEASE_BLOCKS))
+        );
+    }
+
+    #[test]
+    fn expired_name_is_free_again() {
+        let record = held("a", 100);
+        assert!(!record.is_held_at(BlockHeight(100)));
+        assert_eq!(
+            claim(Some(&record), "alice", "b", MIN_NAME_FEE, BlockHeight(100)),
+            Ok(held("b", 100 + NAME_LEASE_BLOCKS))
+        );
+        // the old owner starts a fresh lease too, nothing carries over
+        assert_eq!(
+            claim(Some(&record), "alice", "a", MIN_NAME_FEE, BlockHeight(150)),
+            Ok(held("a", 150 + NAME_LEASE_BLOCKS))
+        );
+    }
+
+    #[test]
+    fn claim_pays_the_min_fee() {
+        assert_eq!(
+            claim(None, "alice", "a", Amount::ZERO, BlockHeight(5)),
+            Err(ChainError::NameFeeTooLow {
+                fee: Amount::ZERO,
+                min: MIN_NAME_FEE
+            })
+        );
+        assert!(claim(None, "Alice", "a", MIN_NAME_FEE, BlockHeight(5)).is_err());
+    }
+
+    #[test]
+    fn names_are_checked() {
+        for name in ["a", "alice", "a-1", "0x", &"a".repeat(MAX_NAME_LEN)] {
+            assert_eq!(check_name(name), Ok(()), "{}", name);
+        }
+        for name in [
+            "",
+            "-a",
+            "a-",
+            "Alice",
+            "a b",
+            "a.b",
+            "ä",
+            &"a".repeat(MAX_NAME_LEN + 1),
+        ] {
+            assert_eq!(
+                check_name(name),
+                Err(ChainError::InvalidName(name.to_string())),
+                "{:?}",
+                name
+            );
+        }
+    }
+}
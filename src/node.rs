@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libp2p::futures::StreamExt;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::swarm::{SwarmBuilder, SwarmEvent};
+use libp2p::{PeerId, Swarm};
+use log::{error, info};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio::{select, spawn};
+
+use crate::api::NodeHandle;
+use crate::encoding::Encoding;
+use crate::p2p::{self, AppBehaviour, EventType};
+use crate::params::ChainParams;
+use crate::units::BlockHeight;
+use crate::App;
+
+const SYNC_TICK_SECS: u64 = 1;
+
+// One chain of a multi-chain process, see `load_configs`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    // prefixes the node's commands on stdin
+    pub name: String,
+    #[serde(default)]
+    pub params: ChainParams,
+    #[serde(default)]
+    pub chain_file: Option<PathBuf>,
+    // height -> hash of blocks the chain must contain
+    #[serde(default)]
+    pub checkpoints: BTreeMap<BlockHeight, String>,
+    // 0 picks any free port
+    #[serde(default)]
+    pub port: u16,
+    // name of another node whose chain's headers this node relays onto its own
+    #[serde(default)]
+    pub relay_from: Option<String>,
+    // of the node's messages, see Encoding
+    #[serde(default)]
+    pub encoding: Encoding,
+}
+
+// A YAML list of node configs, e.g. a mainnet and a testnet with their own params
+// and chain files. Names must be unique.
+pub fn load_configs(path: &Path) -> Result<Vec<NodeConfig>, String> {
+    let yaml = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let configs: Vec<NodeConfig> =
+        serde_yaml::from_str(&yaml).map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+    let mut names = HashSet::new();
+    if let Some(config) = configs.iter().find(|config| !names.insert(&config.name)) {
+        return Err(format!("node name {} is used twice", config.name));
+    }
+    for config in &configs {
+        let Some(source) = &config.relay_from else {
+            continue;
+        };
+        if *source == config.name {
+            return Err(format!("{} relays from itself", source));
+        }
+        let Some(source) = configs.iter().find(|other| other.name == *source) else {
+            return Err(format!("{} relays from unknown node {}", config.name, source));
+        };
+        // the target only takes headers of the chains its params name
+        if !config.params.relay_sources.contains_key(&source.params.spec_hash()) {
+            return Err(format!(
+                "{} relays from {}, whose spec {} is not in its relay_sources",
+                config.name,
+                source.name,
+                source.params.spec_hash()
+            ));
+        }
+    }
+    Ok(configs)
+}
+
+// The chain for `params`, picked up from `chain_file` if it exists. The saved
+// chain has to agree with `checkpoints` as well, and in signet mode be signed by
+// `signet_challenge`.
+pub fn load_app(
+    params: ChainParams,
+    checkpoints: BTreeMap<BlockHeight, String>,
+    signet_challenge: Option<PublicKey>,
+    chain_file: Option<&Path>,
+) -> Result<App, String> {
+    let mut app = App::new(params);
+    app.signet_challenge = signet_challenge;
+    app.params.genesis.check(&app.blocks[0])?;
+    // the node itself registers none, they come with programs embedding the chain
+    app.payload_rules.check_spec(&app.params).map_err(|e| e.to_string())?;
+    info!("Genesis block: {}", app.genesis_hash());
+    for (height, hash) in checkpoints {
+        info!("checkpoint {} at height {}", hash, height);
+        app.add_checkpoint(height, hash).map_err(|e| e.to_string())?;
+    }
+    if let Some(path) = chain_file.filter(|path| path.exists()) {
+        app.import_from_file(path)?;
+    }
+    Ok(app)
+}
+
+// A swarm following one chain, and the loop that feeds it events. Every node has
+// its own peer id, chain and storage, so several can run side by side on one
+// runtime without sharing any state. Input lines and API requests reach a running
+// node through `sender`.
+pub struct Node {
+    pub name: String,
+    pub swarm: Swarm<AppBehaviour>,
+    // signs the checkpoints published on PublishCheckpoint, none are without it
+    pub operator_keys: Option<Keypair>,
+    sender: mpsc::UnboundedSender<EventType>,
+    response_rcv: mpsc::UnboundedReceiver<EventType>,
+    init_rcv: mpsc::UnboundedReceiver<EventType>,
+}
+
+impl Node {
+    pub async fn new(name: String, app: App, keys: &Keypair) -> Self {
+        let peer_id = PeerId::from(keys.public());
+        info!("{}: peer id {}", name, peer_id);
+        let (response_sender, response_rcv) = mpsc::unbounded_channel();
+        let (init_sender, init_rcv) = mpsc::unbounded_channel();
+        let behaviour = AppBehaviour::new(app, peer_id, response_sender.clone(), init_sender).await;
+        let tasks = behaviour.tasks.clone();
+        let swarm = SwarmBuilder::new(p2p::build_transport(keys), behaviour, peer_id)
+            .executor(Box::new(move |fut| {
+                let task = tasks.track();
+                spawn(async move {
+                    let _task = task;
+                    fut.await
+                });
+            }))
+            .build();
+        Self {
+            name,
+            swarm,
+            operator_keys: None,
+            sender: response_sender,
+            response_rcv,
+            init_rcv,
+        }
+    }
+
+    // a node with a fresh identity, for chains other than the main one
+    pub async fn from_config(config: NodeConfig) -> Result<Self, String> {
+        let app = load_app(config.params, config.checkpoints, None, config.chain_file.as_deref())
+            .map_err(|e| format!("{}: {}", config.name, e))?;
+        let mut node = Self::new(config.name, app, &Keypair::generate_ed25519()).await;
+        node.swarm.behaviour_mut().chain_file = config.chain_file;
+        node.swarm.behaviour_mut().set_encoding(config.encoding);
+        node.listen(config.port)?;
+        Ok(node)
+    }
+
+    pub fn listen(&mut self, port: u16) -> Result<(), String> {
+        let addr = format!("/ip4/0.0.0.0/tcp/{}", port)
+            .parse()
+            .map_err(|e| format!("invalid port {}: {}", port, e))?;
+        Swarm::listen_on(&mut self.swarm, addr)
+            .map(|_| ())
+            .map_err(|e| format!("{}: could not listen on port {}: {}", self.name, port, e))
+    }
+
+    pub fn sender(&self) -> mpsc::UnboundedSender<EventType> {
+        self.sender.clone()
+    }
+
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle::new(self.sender())
+    }
+
+    // runs until the process exits
+    pub async fn run(mut self) {
+        let tick_sender = self.sender();
+        spawn(async move {
+            loop {
+                sleep(Duration::from_secs(SYNC_TICK_SECS)).await;
+                if tick_sender.send(EventType::SyncTick).is_err() {
+                    break;
+                }
+            }
+        });
+        let init_sender = self.swarm.behaviour().init_sender.clone();
+        spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            info!("sending init event");
+            init_sender.send(EventType::Init).expect("can send init event");
+        });
+
+        loop {
+            let evt = {
+                select! {
+                    response = self.response_rcv.recv() => {
+                        Some(response.expect("response exists"))
+                    },
+                    _init = self.init_rcv.recv() => {
+                        Some(EventType::Init)
+                    }
+                    event = self.swarm.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { peer_id, .. } => Some(EventType::PeerConnected(peer_id)),
+                        event => {
+                            info!("Unhandled Swarm Event: {:?}", event);
+                            None
+                        }
+                    },
+                }
+            };
+            if let Some(event) = evt {
+                self.handle_event(event);
+            }
+            // whatever changed, by this event or while polling the swarm
+            let behaviour = self.swarm.behaviour_mut();
+            behaviour.abandon_stale_mining();
+            behaviour.events.update(&behaviour.app.blocks);
+            behaviour
+                .event_log
+                .update(&behaviour.app.blocks, &behaviour.app.checkpoints);
+        }
+    }
+
+    fn handle_event(&mut self, event: EventType) {
+        let swarm = &mut self.swarm;
+        match event {
+            EventType::Init => p2p::handle_init(swarm),
+            EventType::PublishCheckpoint => {
+                if let Some(keys) = &self.operator_keys {
+                    p2p::handle_publish_checkpoint(swarm, keys);
+                }
+                #[cfg(feature = "bls")]
+                p2p::handle_publish_bls_vote(swarm);
+            }
+            EventType::SyncTick => {
+                p2p::handle_sync_timeouts(swarm);
+                // proposers check every tick whether their slot or turn has come
+                p2p::handle_propose(swarm);
+            }
+            EventType::Api(request) => p2p::handle_api_request(swarm, request),
+            EventType::ChainValidated { peer, blocks, result } => {
+                p2p::handle_validated_chain(swarm, peer, blocks, result)
+            }
+            EventType::PeerConnected(peer) => p2p::handle_peer_connected(swarm, peer),
+            EventType::FlushOutbound => p2p::handle_flush_outbound(swarm),
+            EventType::BlockMined { result, reply } => p2p::handle_block_mined(swarm, result, reply),
+            EventType::MiningProgress(progress) => p2p::handle_mining_progress(swarm, progress),
+            EventType::Input(line) => match line.as_str() {
+                "ls p" => p2p::handle_print_peers(swarm),
+                "ls size" => p2p::handle_print_size(swarm),
+                "ls requests" => p2p::handle_print_requests(swarm),
+                "ls races" => p2p::handle_print_races(swarm),
+                "ls hashrate" => p2p::handle_print_hashrate(swarm),
+                "stats" => p2p::handle_print_stats(swarm),
+                "stop mining" => p2p::handle_stop_mining(swarm),
+                "ls miners" => p2p::handle_print_miners(swarm),
+                "ls alerts" => p2p::handle_print_alerts(swarm),
+                "ls inbox" => p2p::handle_print_inbox(swarm),
+                "ls mempool" => p2p::handle_print_mempool(swarm),
+                "reorg history" => p2p::handle_print_reorgs(swarm),
+                cmd if cmd.starts_with("balance") => p2p::handle_print_balance(cmd, swarm),
+                cmd if cmd.starts_with("explain block ") => p2p::handle_explain_block(cmd, swarm),
+                cmd if cmd.starts_with("events since ") => p2p::handle_print_events(cmd, swarm),
+                cmd if cmd.starts_with("export ") => p2p::handle_export_analytics(cmd, swarm),
+                cmd if cmd.starts_with("save ") => p2p::handle_save_chain(cmd, swarm),
+                cmd if cmd.starts_with("load ") => p2p::handle_load_chain(cmd, swarm),
+                cmd if cmd.starts_with("bundle ") => p2p::handle_write_bundle(cmd, swarm, self.operator_keys.as_ref()),
+                cmd if cmd.starts_with("dm ") => p2p::handle_direct_message(cmd, swarm),
+                cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(swarm),
+                cmd if cmd.starts_with("create tx ") => p2p::handle_create_transaction(cmd, swarm),
+                cmd if cmd.starts_with("create name ") => p2p::handle_create_name(cmd, swarm),
+                cmd if cmd.starts_with("name ") => p2p::handle_print_name(cmd, swarm),
+                cmd if cmd.starts_with("create b") => p2p::handle_create_block(cmd, swarm),
+                #[cfg(feature = "vm")]
+                cmd if cmd.starts_with("deploy ") || cmd.starts_with("call ") => p2p::handle_contract_op(cmd, swarm),
+                #[cfg(feature = "vm")]
+                cmd if cmd.starts_with("receipts ") => p2p::handle_print_receipts(cmd, swarm),
+                _ => error!("unknown command"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::Topics;
+    use crate::state;
+    use crate::store::ChainFormat;
+    use crate::testing::dev_params;
+    use crate::transaction::Transaction;
+    use crate::units::Amount;
+    use crate::BlockBody;
+
+    fn network_chain(name: &str) -> (App, PathBuf) {
+        let mut network = App::new(dev_params());
+        network
+            .mine_n_blocks(3, |height| BlockBody::new(format!("network block {}", height), vec![]))
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        network.export_to_file(&path, ChainFormat::Pretty).unwrap();
+        (network, path)
+    }
+
+    // a node restarted from its chain file mines on the network tip instead of
+    // reusing heights the network already has
+    #[test]
+    fn restarted_node_extends_the_network_tip() {
+        let (mut network, path) = network_chain("restart");
+        let mut restarted = load_app(dev_params(), BTreeMap::new(), None, Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            restarted.get_last_block().header.hash,
+            network.get_last_block().header.hash
+        );
+
+        let mined = restarted
+            .mine_n_blocks(1, |height| BlockBody::new(format!("after restart {}", height), vec![]))
+            .unwrap();
+        let tip = &network.get_last_block().header;
+        assert_eq!(Some(mined[0].header.id), tip.id.next());
+        assert_eq!(mined[0].header.previous_hash, tip.hash);
+        network.try_add_block(mined[0].clone()).unwrap();
+    }
+
+    #[test]
+    fn saved_chain_is_held_to_the_signet_challenge() {
+        let (_, path) = network_chain("signet");
+        let challenge = Keypair::generate_ed25519().public();
+        let loaded = load_app(dev_params(), BTreeMap::new(), Some(challenge), Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err(), "unsigned blocks were accepted in signet mode");
+    }
+
+    // a mainnet and a testnet run from one nodes file
+    #[test]
+    fn chains_of_one_process_stay_apart() {
+        let path = std::env::temp_dir().join(format!("nodes-{}.yaml", std::process::id()));
+        let yaml = "
+- name: mainnet
+  params: { initial_difficulty: 1, retarget_interval: 0 }
+- name: testnet
+  params: { initial_difficulty: 1, retarget_interval: 0, block_reward: 10 }
+";
+        std::fs::write(&path, yaml).unwrap();
+        let configs = load_configs(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut apps: Vec<App> = configs
+            .into_iter()
+            .map(|config| load_app(config.params, config.checkpoints, None, None).unwrap())
+            .collect();
+        let [mainnet, testnet] = &mut apps[..] else {
+            panic!("two chains were configured");
+        };
+
+        // they gossip on topics of their own
+        let testnet_topics = Topics::new(&testnet.params.spec_hash()).all();
+        for topic in Topics::new(&mainnet.params.spec_hash()).all() {
+            assert!(!testnet_topics.contains(&topic), "both chains gossip on {:?}", topic);
+        }
+
+        // and whatever reaches the other chain anyway is refused
+        let block = testnet
+            .mine_n_blocks(1, |height| BlockBody::new(format!("testnet {}", height), vec![]))
+            .unwrap()
+            .remove(0);
+        assert!(mainnet.try_add_block(block).is_err());
+
+        let keys = Keypair::generate_ed25519();
+        let spec = testnet.get_last_block().header.spec.clone();
+        let transaction = Transaction::new(&keys, spec, "recipient".to_string(), Amount(1), Amount::ZERO).unwrap();
+        assert!(state::check_chain(&testnet.get_last_block().header.spec, &transaction).is_ok());
+        assert!(state::check_chain(&mainnet.get_last_block().header.spec, &transaction).is_err());
+        let mined = mainnet.mine_n_blocks(1, |_| BlockBody::new(String::new(), vec![transaction.clone()]));
+        assert!(mined.is_err());
+        assert_eq!(mainnet.get_last_block().header.id, BlockHeight::GENESIS);
+    }
+}
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::parse_env;
+
+// Where the search for a block's nonce starts and how it proceeds. Workers mining
+// together each take every `workers`th nonce, worker i starting at first() + i, so
+// none of them tries a nonce twice or one another worker has.
+pub trait NonceStrategy: Send + Sync {
+    // called once per block
+    fn first(&self) -> u64;
+
+    fn next(&self, nonce: u64, workers: u64) -> u64 {
+        nonce.wrapping_add(workers)
+    }
+}
+
+// From 0 up, so a block mined on one thread always gets the same nonce, e.g. in tests.
+pub struct Sequential;
+
+impl NonceStrategy for Sequential {
+    fn first(&self) -> u64 {
+        0
+    }
+}
+
+// Up from a random nonce, so miners of the same block don't all search the same
+// nonces first.
+pub struct RandomStart;
+
+impl NonceStrategy for RandomStart {
+    fn first(&self) -> u64 {
+        rand::thread_rng().gen()
+    }
+}
+
+// The NonceStrategy a node mines with, random start unless set otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonceOrder {
+    Sequential,
+    #[default]
+    RandomStart,
+}
+
+impl NonceOrder {
+    pub fn strategy(self) -> Box<dyn NonceStrategy> {
+        match self {
+            Self::Sequential => Box::new(Sequential),
+            Self::RandomStart => Box::new(RandomStart),
+        }
+    }
+
+    // read from MINING_NONCES
+    pub fn from_env() -> Result<Self, String> {
+        Ok(parse_env("MINING_NONCES")?.unwrap_or_default())
+    }
+}
+
+impl FromStr for NonceOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "sequential" => Ok(Self::Sequential),
+            "random" => Ok(Self::RandomStart),
+            _ => Err(format!("unknown nonce order {}, expected sequential or random", s)),
+        }
+    }
+}
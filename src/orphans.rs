@@ -0,0 +1,113 @@
+use crate::units::Timestamp;
+use crate::{Block, BlockBody, Payload};
+
+// most orphans kept, the oldest one is dropped to make room
+pub const MAX_ORPHANS: usize = 64;
+// orphans whose parent hasn't arrived after this long are dropped
+pub const ORPHAN_EXPIRY_SECS: i64 = 10 * 60;
+
+// Blocks that arrived before their parent, which floodsub does not rule out. App
+// attaches them as soon as the parent is added to the chain.
+pub struct OrphanPool<T = BlockBody> {
+    // with the time each one arrived, oldest first
+    orphans: Vec<(Timestamp, Block<T>)>,
+}
+
+impl<T: Payload> Default for OrphanPool<T> {
+    fn default() -> Self {
+        Self { orphans: vec![] }
+    }
+}
+
+impl<T: Payload> OrphanPool<T> {
+    pub fn add(&mut self, block: Block<T>, now: Timestamp) {
+        self.expire(now);
+        if self
+            .orphans
+            .iter()
+            .any(|(_, orphan)| orphan.header.hash == block.header.hash)
+        {
+            return;
+        }
+        if self.orphans.len() >= MAX_ORPHANS {
+            self.orphans.remove(0);
+        }
+        self.orphans.push((now, block));
+    }
+
+    // removes and returns the orphans whose parent is the block with `parent_hash`
+    pub fn take_children(&mut self, parent_hash: &str) -> Vec<Block<T>> {
+        let (children, rest) = std::mem::take(&mut self.orphans)
+            .into_iter()
+            .partition(|(_, orphan)| orphan.header.previous_hash == parent_hash);
+        self.orphans = rest;
+        children.into_iter().map(|(_, orphan)| orphan).collect()
+    }
+
+    fn expire(&mut self, now: Timestamp) {
+        self.orphans
+            .retain(|(arrived, _)| now.saturating_secs_since(*arrived) < ORPHAN_EXPIRY_SECS);
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ChainParams;
+
+    fn orphan(parent: &str, hash: &str) -> Block {
+        let genesis = Block::<BlockBody>::genesis(&ChainParams::default()).unwrap();
+        let mut block = Block::unmined(
+            &genesis.header,
+            BlockBody::new(String::new(), vec![]),
+            None,
+            0,
+            Timestamp(0),
+        );
+        block.header.previous_hash = parent.to_string();
+        block.header.hash = hash.to_string();
+        block
+    }
+
+    #[test]
+    fn children_are_taken_once() {
+        let mut pool = OrphanPool::default();
+        pool.add(orphan("a", "b"), Timestamp(0));
+        pool.add(orphan("a", "b"), Timestamp(0));
+        pool.add(orphan("a", "c"), Timestamp(0));
+        pool.add(orphan("b", "d"), Timestamp(0));
+        assert_eq!(pool.len(), 3);
+        let children: Vec<_> = pool
+            .take_children("a")
+            .into_iter()
+            .map(|block| block.header.hash)
+            .collect();
+        assert_eq!(children, ["b", "c"]);
+        assert!(pool.take_children("a").is_empty());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn oldest_orphans_make_room_and_expire() {
+        let mut pool = OrphanPool::default();
+        for i in 0..=MAX_ORPHANS {
+            pool.add(orphan("parent", &i.to_string()), Timestamp(0));
+        }
+        assert_eq!(pool.len(), MAX_ORPHANS);
+        assert!(pool
+            .take_children("parent")
+            .iter()
+            .all(|block| block.header.hash != "0"));
+        pool.add(orphan("a", "b"), Timestamp(0));
+        pool.add(orphan("a", "c"), Timestamp(ORPHAN_EXPIRY_SECS));
+        assert_eq!(pool.len(), 1);
+    }
+}
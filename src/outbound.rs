@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+use libp2p::floodsub::Topic;
+use libp2p::request_response::ResponseChannel;
+use libp2p::PeerId;
+
+use crate::p2p::ChainResponse;
+
+// bulk messages handed to the swarm per batch, anything urgent goes out in between
+pub const BULK_PER_BATCH: usize = 32;
+// bulk messages beyond this are dropped. Relayed transactions stay in the mempool,
+// so peers still get them in a block.
+const MAX_BULK_QUEUE: usize = 4096;
+
+// How urgent an outbound message is, the most urgent go out first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // fresh blocks, whose propagation delay decides races
+    Block,
+    // answers to a peer's chain request, which it is waiting on
+    Sync,
+    // transaction relay, which can wait
+    Bulk,
+}
+
+pub enum Outbound {
+    Publish {
+        topic: Topic,
+        bytes: Vec<u8>,
+    },
+    ChainResponse {
+        peer: PeerId,
+        channel: ResponseChannel<ChainResponse>,
+        response: ChainResponse,
+    },
+}
+
+// Orders what the node sends. All gossip reaches a peer over a single floodsub
+// stream, where a block published behind a burst of transactions waits for every
+// one of them; so transactions are handed over a batch at a time, after any block
+// or chain response queued meanwhile, which keeps each peer's stream short.
+#[derive(Default)]
+pub struct OutboundScheduler {
+    blocks: VecDeque<Outbound>,
+    sync: VecDeque<Outbound>,
+    bulk: VecDeque<Outbound>,
+}
+
+impl OutboundScheduler {
+    // false if the message was dropped, the bulk queue being full
+    pub fn push(&mut self, priority: Priority, message: Outbound) -> bool {
+        let queue = match priority {
+            Priority::Block => &mut self.blocks,
+            Priority::Sync => &mut self.sync,
+            Priority::Bulk if self.bulk.len() >= MAX_BULK_QUEUE => return false,
+            Priority::Bulk => &mut self.bulk,
+        };
+        queue.push_back(message);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len() + self.sync.len() + self.bulk.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // what to send next: every block and chain response, then up to BULK_PER_BATCH
+    // bulk messages
+    pub fn next_batch(&mut self) -> Vec<Outbound> {
+        let bulk = self.bulk.len().min(BULK_PER_BATCH);
+        self.blocks
+            .drain(..)
+            .chain(self.sync.drain(..))
+            .chain(self.bulk.drain(..bulk))
+            .collect()
+    }
+}
@@ -1,71 +1,520 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::Boxed,
+        upgrade::{self, read_length_prefixed, write_length_prefixed, ProtocolName},
+    },
     floodsub::{Floodsub, FloodsubEvent, Topic},
-    identity::Keypair,
+    futures::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    identity::{Keypair, PublicKey},
     mdns::{Mdns, MdnsEvent},
+    mplex::MplexConfig,
+    noise::{self, NoiseConfig, X25519Spec},
+    request_response::{
+        ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig, RequestResponseEvent,
+        RequestResponseMessage,
+    },
     swarm::NetworkBehaviourEventProcess,
-    NetworkBehaviour, PeerId, Swarm,
+    tcp::TokioTcpConfig,
+    NetworkBehaviour, PeerId, Swarm, Transport,
 };
 use log::{error, info};
 use once_cell::sync::Lazy;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
 
-use crate::{App, Block};
+use crate::analytics;
+use crate::api::{ApiRequest, NodeStatus, SubmittedTransaction, TransactionStatus};
+use crate::auth::{AllowAll, Authorization, MessageKind, PeerAuthorizer};
+#[cfg(feature = "da-sampling")]
+use crate::availability::{self, AvailabilitySampler, Chunk, CHUNK_TOPIC};
+#[cfg(feature = "bls")]
+use crate::bls::{AggregateCheckpoint, BlsCheckpoints, BlsMessage, BLS_CHECKPOINT_TOPIC};
+use crate::bundle::ChainBundle;
+use crate::capabilities::Capabilities;
+use crate::checkpoint::SignedCheckpoint;
+use crate::direct::{new_direct_messaging, DirectAck, DirectCodec, DirectMessage, DirectMessaging};
+use crate::encoding::{self, Encoding};
+use crate::error::{ChainError, ValidationReport};
+use crate::events::EventHub;
+use crate::explain::BlockTrace;
+use crate::journal::{EventLog, ReorgJournal, ReorgRecord};
+use crate::keys;
+use crate::miner::{MineRequest, Miner};
+use crate::names::{self, MIN_NAME_FEE};
+use crate::outbound::{Outbound, OutboundScheduler, Priority};
+use crate::progress::MiningProgress;
+use crate::relay::MAX_RELAYED_HEADERS;
+use crate::resources::{self, ResourceUsage, TaskGauge};
+use crate::state;
+use crate::store::{self, ChainFormat};
+use crate::sync::{InFlightRequests, RequestKind, REQUEST_TIMEOUT};
+use crate::transaction::{Coinbase, Mempool, Transaction, MAX_BLOCK_TRANSACTIONS};
+use crate::units::{Amount, BlockHeight, Timestamp};
+#[cfg(feature = "vm")]
+use crate::vm::{self, ContractOp};
+use crate::watchtower::{Alert, Watchtower};
+use crate::work::{WorkProvider, WorkTemplate};
+use crate::{parse_env, App, Block, BlockBody, BlockHeader};
 
 pub static KEYS: Lazy<Keypair> = Lazy::new(Keypair::generate_ed25519);
 pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-pub static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chains"));
-pub static BLOCK_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blocks"));
+pub const CHAIN_SYNC_PROTOCOL: &str = "/blockchain-basic/chain-sync/1";
+// topic names, see Topics
+pub const BLOCK_TOPIC: &str = "blocks";
+pub const CHECKPOINT_TOPIC: &str = "checkpoints";
+pub const TRANSACTION_TOPIC: &str = "transactions";
 
-#[derive(Debug, Serialize, Deserialize)]
+// most relayed headers queued at once
+const MAX_RELAY_QUEUE: usize = 256;
+
+// upper bound for a single length-prefixed chain sync message
+const MAX_SYNC_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ChainRequest {
+    pub request_id: u64,
+    // the requester's capabilities, absent from nodes that predate them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ChainResponse {
     pub blocks: Vec<Block>,
-    pub receiver: String,
+    pub request_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+}
+
+// Chain sync runs over a request-response protocol so requests and responses are
+// delivered to the addressed peer only, rather than broadcast and filtered.
+#[derive(Debug, Clone)]
+pub struct ChainSyncProtocol;
+
+impl ProtocolName for ChainSyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        CHAIN_SYNC_PROTOCOL.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChainSyncCodec {
+    // of the messages this node writes, it reads either
+    encoding: Encoding,
+}
+
+pub(crate) async fn read_json<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: serde::de::DeserializeOwned,
+{
+    let bytes = read_length_prefixed(io, max_size).await?;
+    serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) async fn write_json<T, M>(io: &mut T, msg: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes = serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct LocalChainRequest {
-    pub from_peer_id: String,
+async fn read_message<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: serde::de::DeserializeOwned,
+{
+    let bytes = read_length_prefixed(io, max_size).await?;
+    encoding::decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, M>(io: &mut T, encoding: Encoding, msg: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes = encoding
+        .encode(msg)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl RequestResponseCodec for ChainSyncCodec {
+    type Protocol = ChainSyncProtocol;
+    type Request = ChainRequest;
+    type Response = ChainResponse;
+
+    async fn read_request<T>(&mut self, _: &ChainSyncProtocol, io: &mut T) -> io::Result<ChainRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_SYNC_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &ChainSyncProtocol, io: &mut T) -> io::Result<ChainResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_SYNC_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T>(&mut self, _: &ChainSyncProtocol, io: &mut T, req: ChainRequest) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, self.encoding, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _: &ChainSyncProtocol, io: &mut T, res: ChainResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, self.encoding, &res).await
+    }
+}
+
+pub fn new_chain_sync(encoding: Encoding) -> RequestResponse<ChainSyncCodec> {
+    let mut cfg = RequestResponseConfig::default();
+    cfg.set_request_timeout(REQUEST_TIMEOUT);
+    RequestResponse::new(
+        ChainSyncCodec { encoding },
+        std::iter::once((ChainSyncProtocol, ProtocolSupport::Full)),
+        cfg,
+    )
+}
+
+// The gossip topics of one chain, each name prefixed with the chain's spec hash
+// so chains on the same LAN, or in the same process, never hear each other.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    pub blocks: Topic,
+    pub checkpoints: Topic,
+    pub transactions: Topic,
+    #[cfg(feature = "da-sampling")]
+    pub chunks: Topic,
+    #[cfg(feature = "bls")]
+    pub bls_checkpoints: Topic,
+}
+
+impl Topics {
+    pub fn new(spec: &str) -> Self {
+        Self {
+            blocks: topic(spec, BLOCK_TOPIC),
+            checkpoints: topic(spec, CHECKPOINT_TOPIC),
+            transactions: topic(spec, TRANSACTION_TOPIC),
+            #[cfg(feature = "da-sampling")]
+            chunks: topic(spec, CHUNK_TOPIC),
+            #[cfg(feature = "bls")]
+            bls_checkpoints: topic(spec, BLS_CHECKPOINT_TOPIC),
+        }
+    }
+
+    pub fn all(&self) -> Vec<Topic> {
+        vec![
+            self.blocks.clone(),
+            self.checkpoints.clone(),
+            self.transactions.clone(),
+            #[cfg(feature = "da-sampling")]
+            self.chunks.clone(),
+            #[cfg(feature = "bls")]
+            self.bls_checkpoints.clone(),
+        ]
+    }
+}
+
+// the topic `name` of the chain of spec `spec`
+pub fn topic(spec: &str, name: &str) -> Topic {
+    Topic::new(format!("{}/{}", spec, name))
 }
 
 pub enum EventType {
-    LocalChainResponse(ChainResponse),
     Input(String),
     Init,
+    PublishCheckpoint,
+    SyncTick,
+    Api(ApiRequest),
+    // a peer's chain after validation on the blocking pool
+    ChainValidated {
+        peer: PeerId,
+        blocks: Vec<Block>,
+        result: Result<(), ValidationReport>,
+    },
+    PeerConnected(PeerId),
+    // the outbound scheduler has messages waiting, see AppBehaviour::send_outbound
+    FlushOutbound,
+    // a block sealed off the event loop, or why it wasn't, see start_mining
+    BlockMined {
+        result: Result<Box<Block>, String>,
+        reply: Option<oneshot::Sender<Result<Block, String>>>,
+    },
+    // how far the block being mined has got
+    MiningProgress(MiningProgress),
+}
+
+pub fn build_transport(keys: &Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let auth_keys = noise::Keypair::<X25519Spec>::new()
+        .into_authentic(keys)
+        .expect("can create auth keys");
+
+    TokioTcpConfig::new()
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
+        .multiplex(MplexConfig::new())
+        .boxed()
 }
 
 #[derive(NetworkBehaviour)]
 pub struct AppBehaviour {
     pub floodsub: Floodsub,
     pub mdns: Mdns,
+    pub chain_sync: RequestResponse<ChainSyncCodec>,
+    pub direct: RequestResponse<DirectCodec>,
+    #[behaviour(ignore)]
+    pub peer_id: PeerId,
+    #[behaviour(ignore)]
+    pub topics: Topics,
     #[behaviour(ignore)]
     pub response_sender: mpsc::UnboundedSender<EventType>,
     #[behaviour(ignore)]
     pub init_sender: mpsc::UnboundedSender<EventType>,
     #[behaviour(ignore)]
     pub app: App,
+    #[behaviour(ignore)]
+    pub checkpoint_authority: Option<PublicKey>,
+    #[behaviour(ignore)]
+    pub in_flight: InFlightRequests,
+    #[behaviour(ignore)]
+    pub signet_key: Option<Keypair>,
+    // the block being mined, if one is
+    #[behaviour(ignore)]
+    pub mining: Option<MiningJob>,
+    // seals the blocks this node creates, off the event loop, see miner
+    #[behaviour(ignore)]
+    pub block_miner: Miner,
+    // dev networks: blocks this node mines come at least this far apart, however
+    // easy the difficulty, see dev_block_interval_from_env
+    #[behaviour(ignore)]
+    pub dev_block_interval: Option<Duration>,
+    // recorded as the miner of blocks created by this node
+    #[behaviour(ignore)]
+    pub miner: Option<String>,
+    // paid the coinbase of blocks created by this node, no coinbase without one
+    #[behaviour(ignore)]
+    pub miner_address: Option<String>,
+    #[behaviour(ignore)]
+    pub authorizer: Box<dyn PeerAuthorizer>,
+    // where the chain is saved after every change, if anywhere
+    #[behaviour(ignore)]
+    pub chain_file: Option<PathBuf>,
+    // set until the first chain sync after startup settles, mining waits for it so
+    // new blocks extend the network tip rather than a stale local one
+    #[behaviour(ignore)]
+    pub discovering_tip: bool,
+    #[behaviour(ignore)]
+    pub watchtower: Option<Watchtower>,
+    #[behaviour(ignore)]
+    pub messaging: DirectMessaging,
+    #[behaviour(ignore)]
+    pub reorg_journal: ReorgJournal,
+    // accepted blocks, reorgs, finality and validator changes, see journal
+    #[behaviour(ignore)]
+    pub event_log: EventLog,
+    // as advertised by each peer in chain sync
+    #[behaviour(ignore)]
+    pub peer_capabilities: HashMap<PeerId, Capabilities>,
+    #[behaviour(ignore)]
+    pub mempool: Mempool,
+    // signs transactions created on this node
+    #[behaviour(ignore)]
+    pub wallet_key: Option<Keypair>,
+    // headers of other chains waiting to be mined into a block, see relay
+    #[behaviour(ignore)]
+    pub relay_queue: Vec<BlockHeader>,
+    // of gossip and chain sync messages, see set_encoding
+    #[behaviour(ignore)]
+    pub encoding: Encoding,
+    // subscribers to the node's events, see events
+    #[behaviour(ignore)]
+    pub events: EventHub,
+    // blocks, chain responses and transaction relay waiting to be sent, see outbound
+    #[behaviour(ignore)]
+    pub outbound: OutboundScheduler,
+    // tasks spawned for the node, connection handlers included, see resources
+    #[behaviour(ignore)]
+    pub tasks: TaskGauge,
+    // hashes of blocks to explain once they arrive, see handle_explain_block
+    #[behaviour(ignore)]
+    pub explain: HashSet<String>,
+    // blocks handed out to external miners, see get_work
+    #[behaviour(ignore)]
+    pub work: WorkProvider,
+    // large blocks held until their chunks are sampled, see availability
+    #[cfg(feature = "da-sampling")]
+    #[behaviour(ignore)]
+    pub availability: AvailabilitySampler,
+    // contract ops waiting for the next block this node mines
+    #[cfg(feature = "vm")]
+    #[behaviour(ignore)]
+    pub contract_queue: Vec<ContractOp>,
+    // validator set and pending votes of BLS checkpoints, see bls
+    #[cfg(feature = "bls")]
+    #[behaviour(ignore)]
+    pub bls_checkpoints: Option<BlsCheckpoints>,
 }
 
 impl AppBehaviour {
     pub async fn new(
         app: App,
+        peer_id: PeerId,
         response_sender: mpsc::UnboundedSender<EventType>,
         init_sender: mpsc::UnboundedSender<EventType>,
     ) -> Self {
+        let tasks = TaskGauge::default();
+        let block_miner = Miner::spawn(response_sender.clone(), tasks.track());
+        let topics = Topics::new(&app.params.spec_hash());
         let mut behaviour = Self {
             app,
-            floodsub: Floodsub::new(*PEER_ID),
+            floodsub: Floodsub::new(peer_id),
             mdns: Mdns::new(Default::default()).await.expect("can create mdns"),
+            chain_sync: new_chain_sync(Encoding::default()),
+            direct: new_direct_messaging(),
+            peer_id,
+            topics,
             response_sender,
             init_sender,
+            checkpoint_authority: None,
+            in_flight: InFlightRequests::default(),
+            signet_key: None,
+            mining: None,
+            block_miner,
+            dev_block_interval: None,
+            miner: None,
+            miner_address: None,
+            authorizer: Box::new(AllowAll),
+            chain_file: None,
+            discovering_tip: true,
+            watchtower: None,
+            messaging: DirectMessaging::default(),
+            reorg_journal: ReorgJournal::default(),
+            event_log: EventLog::default(),
+            peer_capabilities: HashMap::new(),
+            mempool: Mempool::default(),
+            wallet_key: None,
+            relay_queue: vec![],
+            encoding: Encoding::default(),
+            events: EventHub::default(),
+            outbound: OutboundScheduler::default(),
+            tasks,
+            explain: HashSet::new(),
+            work: WorkProvider::default(),
+            #[cfg(feature = "da-sampling")]
+            availability: AvailabilitySampler::default(),
+            #[cfg(feature = "vm")]
+            contract_queue: vec![],
+            #[cfg(feature = "bls")]
+            bls_checkpoints: None,
         };
-        behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
-        behaviour.floodsub.subscribe(BLOCK_TOPIC.clone());
+        for topic in behaviour.topics.all() {
+            behaviour.floodsub.subscribe(topic);
+        }
 
         behaviour
     }
+
+    // before the swarm is started, the chain sync codec is replaced as well
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.chain_sync = new_chain_sync(encoding);
+    }
+
+    fn save_chain(&self) {
+        if let Some(path) = &self.chain_file {
+            if let Err(e) = store::save_chain(path, &self.app.blocks) {
+                error!("error saving chain {}", e);
+            }
+        }
+    }
+
+    fn finish_tip_discovery(&mut self) {
+        if self.discovering_tip {
+            info!(
+                "tip discovery done, local tip is {}",
+                self.app.get_last_block().header.id
+            );
+            self.discovering_tip = false;
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match self.watchtower {
+            Some(_) => Capabilities::NONE,
+            None => Capabilities::ARCHIVAL,
+        }
+    }
+
+    fn learn_capabilities(&mut self, peer: PeerId, capabilities: Option<Capabilities>) {
+        if let Some(capabilities) = capabilities {
+            self.peer_capabilities.insert(peer, capabilities);
+        }
+    }
+
+    // peers are assumed to support `capability` until they say otherwise
+    pub fn peer_supports(&self, peer: &PeerId, capability: Capabilities) -> bool {
+        self.peer_capabilities
+            .get(peer)
+            .is_none_or(|capabilities| capabilities.contains(capability))
+    }
+
+    // Queues `message` for the event loop to send, in order of priority. The loop is
+    // woken for the first message queued, the rest go with it.
+    fn send_outbound(&mut self, priority: Priority, message: Outbound) {
+        if self.outbound.is_empty() && self.response_sender.send(EventType::FlushOutbound).is_err() {
+            error!("could not schedule outbound messages");
+        }
+        if !self.outbound.push(priority, message) {
+            info!("outbound queue is full, dropping {:?} message", priority);
+        }
+    }
+
+    // Cancels the block being mined once the tip it builds on isn't the tip any more,
+    // e.g. when a peer's block for the same height came first. It is mined again on
+    // the new tip when the cancellation comes back, see handle_block_mined.
+    pub fn abandon_stale_mining(&mut self) {
+        let tip = &self.app.get_last_block().header.hash;
+        let Some(job) = &mut self.mining else {
+            return;
+        };
+        // stopped or already abandoned
+        if job.cancel.is_cancelled() || job.parent == *tip {
+            return;
+        }
+        info!("tip moved to {}, abandoning the block being mined", tip);
+        job.stale = true;
+        job.cancel.cancel();
+    }
+
+    fn is_authorized(&self, peer: &PeerId, kind: MessageKind) -> bool {
+        if self.authorizer.authorize(peer, kind) == Authorization::Deny {
+            info!("{:?} from {} denied by policy", kind, peer);
+            return false;
+        }
+        true
+    }
 }
 
 impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
@@ -73,7 +522,9 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
         match event {
             MdnsEvent::Discovered(discovered_list) => {
                 for (peer, _addr) in discovered_list {
-                    self.floodsub.add_node_to_partial_view(peer);
+                    if self.is_authorized(&peer, MessageKind::Connection) {
+                        self.floodsub.add_node_to_partial_view(peer);
+                    }
                 }
             }
             MdnsEvent::Expired(expired_list) => {
@@ -87,52 +538,491 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
     }
 }
 
+impl NetworkBehaviourEventProcess<RequestResponseEvent<ChainRequest, ChainResponse>> for AppBehaviour {
+    fn inject_event(&mut self, event: RequestResponseEvent<ChainRequest, ChainResponse>) {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Request { request, channel, .. },
+            } => {
+                if !self.is_authorized(&peer, MessageKind::ChainRequest) {
+                    return;
+                }
+                self.learn_capabilities(peer, request.capabilities);
+                if self.watchtower.is_some() {
+                    info!(
+                        "watchtower does not serve its chain, not answering chain request from {}",
+                        peer
+                    );
+                    return;
+                }
+                info!("sending local chain to {}", peer);
+                let response = ChainResponse {
+                    blocks: self.app.blocks.clone(),
+                    request_id: request.request_id,
+                    capabilities: Some(self.capabilities()),
+                };
+                self.send_outbound(
+                    Priority::Sync,
+                    Outbound::ChainResponse {
+                        peer,
+                        channel,
+                        response,
+                    },
+                );
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => {
+                if !self.is_authorized(&peer, MessageKind::ChainResponse) {
+                    return;
+                }
+                if self.in_flight.complete(response.request_id, &peer).is_none() {
+                    info!("ignoring unsolicited chain response from {}", peer);
+                    return;
+                }
+                self.learn_capabilities(peer, response.capabilities);
+                // a node of another chain that found this one, no reason to validate it
+                let spec = &self.app.blocks[0].header.spec;
+                if response
+                    .blocks
+                    .first()
+                    .is_some_and(|genesis| genesis.header.spec != *spec)
+                {
+                    info!("ignoring chain of another spec from {}", peer);
+                    self.finish_tip_discovery();
+                    return;
+                }
+                info!("Response from {}:", peer);
+                response.blocks.iter().for_each(|r| info!("{:?}", r));
+
+                // full chain checks are too slow for the event loop
+                let validator = self.app.validator();
+                let sender = self.response_sender.clone();
+                let task = self.tasks.track();
+                spawn_blocking(move || {
+                    let _task = task;
+                    let result = validator.validate_chain(&response.blocks);
+                    let event = EventType::ChainValidated {
+                        peer,
+                        blocks: response.blocks,
+                        result,
+                    };
+                    if sender.send(event).is_err() {
+                        error!("could not deliver validated chain from {}", peer);
+                    }
+                });
+            }
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                error!("chain request to {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                error!("chain request from {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<DirectMessage, DirectAck>> for AppBehaviour {
+    fn inject_event(&mut self, event: RequestResponseEvent<DirectMessage, DirectAck>) {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Request { request, channel, .. },
+            } => {
+                if !self.is_authorized(&peer, MessageKind::DirectMessage) {
+                    return;
+                }
+                let ack = match self.messaging.receive(&peer, &self.peer_id, request) {
+                    Ok(()) => {
+                        info!("direct message from {}", peer);
+                        DirectAck {
+                            accepted: true,
+                            reason: None,
+                        }
+                    }
+                    Err(e) => {
+                        error!("refusing direct message from {}: {}", peer, e);
+                        DirectAck {
+                            accepted: false,
+                            reason: Some(e),
+                        }
+                    }
+                };
+                if self.direct.send_response(channel, ack).is_err() {
+                    error!("could not acknowledge direct message from {}, connection closed", peer);
+                }
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => match response.reason {
+                Some(reason) if !response.accepted => error!("{} refused direct message: {}", peer, reason),
+                _ => info!("{} accepted direct message", peer),
+            },
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                error!("direct message to {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                error!("direct message from {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+    }
+}
+
 // incoming event handler
 impl NetworkBehaviourEventProcess<FloodsubEvent> for AppBehaviour {
     fn inject_event(&mut self, event: FloodsubEvent) {
         if let FloodsubEvent::Message(msg) = event {
-            if let Ok(resp) = serde_json::from_slice::<ChainResponse>(&msg.data) {
-                if resp.receiver == PEER_ID.to_string() {
-                    info!("Response from {}:", msg.source);
-                    resp.blocks.iter().for_each(|r| info!("{:?}", r));
-
-                    self.app.blocks = self.app.choose_chain(self.app.blocks.clone(), resp.blocks);
-                }
-            } else if let Ok(resp) = serde_json::from_slice::<LocalChainRequest>(&msg.data) {
-                info!("sending local chain to {}", msg.source.to_string());
-                let peer_id = resp.from_peer_id;
-                if PEER_ID.to_string() == peer_id {
-                    let msg = EventType::LocalChainResponse(ChainResponse {
-                        blocks: self.app.blocks.clone(),
-                        receiver: msg.source.to_string(),
-                    });
-                    if let Err(e) = self.response_sender.send(msg) {
-                        error!("error sending response via channel, {}", e);
+            // nothing valid is larger than a block, don't even parse it
+            if msg.data.len() > self.app.params.max_block_size {
+                info!("dropping {} byte message from {}", msg.data.len(), msg.source);
+                return;
+            }
+            #[cfg(feature = "da-sampling")]
+            if msg.topics.contains(&self.topics.chunks) {
+                if !self.is_authorized(&msg.source, MessageKind::Block) {
+                    return;
+                }
+                match encoding::decode::<Chunk>(&msg.data) {
+                    Ok(chunk) => {
+                        for (block, source) in self.availability.add_chunk(chunk) {
+                            info!("block {} is available", block.header.hash);
+                            self.handle_block(block, source);
+                        }
                     }
+                    Err(e) => error!("invalid chunk from {}: {}", msg.source, e),
                 }
-            } else if let Ok(block) = serde_json::from_slice(&msg.data) {
-                info!("received new block from {}", msg.source.to_string());
-                if let Err(e) = self.app.try_add_block(block) {
-                    error!("error adding block {}", e);
+                return;
+            }
+            #[cfg(feature = "bls")]
+            if msg.topics.contains(&self.topics.bls_checkpoints) {
+                if !self.is_authorized(&msg.source, MessageKind::Checkpoint) {
+                    return;
+                }
+                match encoding::decode::<BlsMessage>(&msg.data) {
+                    Ok(message) => self.handle_bls_message(message, msg.source),
+                    Err(e) => error!("invalid BLS checkpoint message from {}: {}", msg.source, e),
                 }
+                return;
+            }
+            if msg.topics.contains(&self.topics.transactions) {
+                if !self.is_authorized(&msg.source, MessageKind::Transaction) {
+                    return;
+                }
+                match encoding::decode::<Transaction>(&msg.data) {
+                    Ok(transaction) => {
+                        let id = transaction.id();
+                        let added =
+                            check_admissible(self, &transaction).and_then(|_| self.mempool.add(transaction.clone()));
+                        match added {
+                            Ok(()) => {
+                                info!("transaction {} from {} added to the mempool", id, msg.source);
+                                self.events.transaction_pending(&transaction);
+                            }
+                            Err(e) => info!("ignoring transaction from {}: {}", msg.source, e),
+                        }
+                    }
+                    Err(e) => error!("invalid transaction from {}: {}", msg.source, e),
+                }
+            } else if let Ok(checkpoint) = encoding::decode::<SignedCheckpoint>(&msg.data) {
+                if !self.is_authorized(&msg.source, MessageKind::Checkpoint) {
+                    return;
+                }
+                match &self.checkpoint_authority {
+                    Some(authority) if checkpoint.verify(authority) => {
+                        info!(
+                            "received checkpoint {} at height {} from {}",
+                            checkpoint.hash, checkpoint.height, msg.source
+                        );
+                        if let Err(e) = self.app.add_checkpoint(checkpoint.height, checkpoint.hash) {
+                            error!("refusing checkpoint from {}: {}", msg.source, e);
+                        }
+                    }
+                    Some(_) => error!("dropping checkpoint with invalid signature from {}", msg.source),
+                    None => {}
+                }
+            } else if let Ok(block) = encoding::decode::<Block>(&msg.data) {
+                if !self.is_authorized(&msg.source, MessageKind::Block) {
+                    return;
+                }
+                #[cfg(feature = "da-sampling")]
+                if availability::needs_sampling(&block) {
+                    info!("holding block {} until its chunks are sampled", block.header.hash);
+                    if let Some((block, source)) = self.availability.hold(block, msg.source) {
+                        self.handle_block(block, source);
+                    }
+                    return;
+                }
+                self.handle_block(block, msg.source);
             }
         }
     }
 }
 
-pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<String> {
-    info!("Discovered Peers:");
+impl AppBehaviour {
+    fn handle_block(&mut self, block: Block, source: PeerId) {
+        info!("received new block from {}", source.to_string());
+        let (height, hash) = (block.header.id, block.header.hash.clone());
+        if self.explain.remove(&hash) {
+            print_trace(&self.app.explain_block(&block));
+        }
+        let old_len = self.app.blocks.len();
+        match self.app.try_add_block(block) {
+            Ok(rolled_back) => {
+                // where the chain changed, orphans may have been attached after the block
+                let fork = rolled_back
+                    .iter()
+                    .map(|block| block.header.id.index())
+                    .min()
+                    .unwrap_or(old_len);
+                if let Some(record) = ReorgRecord::between(&rolled_back, &self.app.blocks[fork..], &source) {
+                    self.reorg_journal.record(record);
+                }
+                // transactions of rolled back blocks are pending again, unless the new blocks have them
+                for transaction in rolled_back.iter().flat_map(|block| &block.data.transactions) {
+                    let _ = self.mempool.add_verified(transaction.clone());
+                }
+                self.mempool.remove_confirmed(&self.app.blocks[fork..]);
+                if let Some(watchtower) = &mut self.watchtower {
+                    watchtower.follow(&self.app.blocks);
+                }
+                self.save_chain()
+            }
+            // expected when several peers relay the same blocks or race for a height
+            Err(e @ (ChainError::DuplicateBlock | ChainError::LostTieBreak | ChainError::Orphan)) => {
+                info!("ignoring block: {}", e)
+            }
+            Err(e) => {
+                error!("error adding block {}", e);
+                if let Some(watchtower) = &mut self.watchtower {
+                    watchtower.raise(match e {
+                        ChainError::InvalidDifficulty { expected, got } => {
+                            Alert::DifficultyAnomaly { height, expected, got }
+                        }
+                        e => Alert::InvalidBlock {
+                            peer: source.to_string(),
+                            hash,
+                            reason: e.to_string(),
+                        },
+                    });
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "bls")]
+    fn handle_bls_message(&mut self, message: BlsMessage, source: PeerId) {
+        let Some(bls) = &mut self.bls_checkpoints else {
+            return;
+        };
+        match message {
+            BlsMessage::Vote(vote) if vote.verify(&bls.validators) => {
+                if let Some(checkpoint) = bls.votes.add(&bls.validators, &vote) {
+                    self.publish_aggregate_checkpoint(checkpoint);
+                }
+            }
+            BlsMessage::Aggregate(checkpoint) if checkpoint.verify(&bls.validators) => {
+                if self.app.checkpoints.get(&checkpoint.height) != Some(&checkpoint.hash) {
+                    info!(
+                        "received aggregate checkpoint {} at height {} from {}",
+                        checkpoint.hash, checkpoint.height, source
+                    );
+                    if let Err(e) = self.app.add_checkpoint(checkpoint.height, checkpoint.hash) {
+                        error!("refusing aggregate checkpoint from {}: {}", source, e);
+                    }
+                }
+            }
+            _ => error!("dropping BLS checkpoint message with invalid signature from {}", source),
+        }
+    }
+
+    // pinned here and gossiped for nodes that haven't seen enough of the votes
+    #[cfg(feature = "bls")]
+    fn publish_aggregate_checkpoint(&mut self, checkpoint: AggregateCheckpoint) {
+        info!(
+            "validators signed checkpoint {} at height {}",
+            checkpoint.hash, checkpoint.height
+        );
+        let bytes = self
+            .encoding
+            .encode(&BlsMessage::Aggregate(checkpoint.clone()))
+            .expect("can encode checkpoint");
+        if let Err(e) = self.app.add_checkpoint(checkpoint.height, checkpoint.hash) {
+            error!("not publishing checkpoint: {}", e);
+            return;
+        }
+        self.floodsub.publish(self.topics.bls_checkpoints.clone(), bytes);
+    }
+}
+
+fn discovered_peers(swarm: &Swarm<AppBehaviour>) -> Vec<PeerId> {
     let nodes = swarm.behaviour().mdns.discovered_nodes();
     let mut unique_peers = HashSet::new();
     for peer in nodes {
-        unique_peers.insert(peer);
+        unique_peers.insert(*peer);
+    }
+    unique_peers.into_iter().collect()
+}
+
+pub fn node_status(swarm: &Swarm<AppBehaviour>) -> NodeStatus {
+    let behaviour = swarm.behaviour();
+    let tip = &behaviour.app.get_last_block().header;
+    NodeStatus {
+        spec: tip.spec.clone(),
+        height: tip.id,
+        tip: tip.hash.clone(),
+        peers: discovered_peers(swarm).len(),
+        mempool: behaviour.mempool.len(),
+    }
+}
+
+// by txid or wtxid. Recent blocks are searched first, that's where payments being
+// waited on are.
+fn transaction_status(behaviour: &AppBehaviour, id: &str) -> TransactionStatus {
+    for (depth, block) in behaviour.app.blocks.iter().rev().enumerate() {
+        if block.data.transactions.iter().any(|transaction| transaction.has_id(id)) {
+            return TransactionStatus::Confirmed {
+                height: block.header.id,
+                block_hash: block.header.hash.clone(),
+                confirmations: depth as u64 + 1,
+            };
+        }
+    }
+    if behaviour.mempool.iter().any(|transaction| transaction.has_id(id)) {
+        return TransactionStatus::Pending;
+    }
+    TransactionStatus::Unknown
+}
+
+pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<PeerId> {
+    info!("Discovered Peers:");
+    discovered_peers(swarm)
+}
+
+fn send_chain_request(swarm: &mut Swarm<AppBehaviour>, peer: PeerId, request_id: u64) {
+    let behaviour = swarm.behaviour_mut();
+    let request = ChainRequest {
+        request_id,
+        capabilities: Some(behaviour.capabilities()),
+    };
+    behaviour.chain_sync.send_request(&peer, request);
+}
+
+// peers that have not said they can't serve full chains
+fn chain_peers(swarm: &Swarm<AppBehaviour>) -> Vec<PeerId> {
+    let behaviour = swarm.behaviour();
+    discovered_peers(swarm)
+        .into_iter()
+        .filter(|peer| behaviour.peer_supports(peer, Capabilities::ARCHIVAL))
+        .collect()
+}
+
+pub fn request_chain(swarm: &mut Swarm<AppBehaviour>, peer: PeerId) {
+    let request_id = swarm.behaviour_mut().in_flight.start(RequestKind::Chain, peer);
+    send_chain_request(swarm, peer, request_id);
+}
+
+// asks every known peer for its chain, the first answer ends tip discovery
+pub fn handle_init(swarm: &mut Swarm<AppBehaviour>) {
+    let peers = chain_peers(swarm);
+    info!("connected nodes serving chains: {}", peers.len());
+    if peers.is_empty() {
+        swarm.behaviour_mut().finish_tip_discovery();
+    }
+    for peer in peers {
+        request_chain(swarm, peer);
+    }
+}
+
+// Starts sealing the next block if the consensus engine has this node due to, see
+// ConsensusEngine::should_propose. Nothing is due under proof of work, which only
+// mines when asked.
+pub fn handle_propose(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour();
+    if behaviour.mining.is_some() || behaviour.discovering_tip || behaviour.watchtower.is_some() {
+        return;
+    }
+    let tip = &behaviour.app.get_last_block().header;
+    if !behaviour.app.consensus.should_propose(tip, Timestamp::now()) {
+        return;
+    }
+    info!("proposing block {}", tip.id.next().unwrap_or(tip.id));
+    start_mining(swarm, String::new(), None);
+}
+
+pub fn handle_sync_timeouts(swarm: &mut Swarm<AppBehaviour>) {
+    #[cfg(feature = "da-sampling")]
+    for (block, source) in swarm.behaviour_mut().availability.expire(Instant::now()) {
+        error!(
+            "dropping block {} from {}, its chunks never arrived",
+            block.header.hash, source
+        );
+    }
+    let expired = swarm.behaviour_mut().in_flight.expired(Instant::now());
+    if expired.is_empty() {
+        return;
+    }
+    let peers = chain_peers(swarm);
+    for request in expired {
+        if !swarm.behaviour().in_flight.can_retry(&request) {
+            error!(
+                "giving up on {:?} request {} after {} attempts",
+                request.kind, request.id, request.attempts
+            );
+            continue;
+        }
+        let next_peer = peers
+            .iter()
+            .find(|p| !request.tried_peers.contains(p))
+            .or_else(|| peers.first())
+            .copied();
+        let Some(peer) = next_peer else {
+            error!("no peers left to retry {:?} request {}", request.kind, request.id);
+            continue;
+        };
+        info!(
+            "{:?} request {} to {} timed out, retrying with {}",
+            request.kind, request.id, request.peer, peer
+        );
+        let kind = request.kind;
+        let request_id = swarm.behaviour_mut().in_flight.retry(request, peer);
+        match kind {
+            RequestKind::Chain => send_chain_request(swarm, peer, request_id),
+        }
+    }
+    // every chain request gave up, mine on the local tip rather than not at all
+    if swarm.behaviour().in_flight.is_empty() {
+        swarm.behaviour_mut().finish_tip_discovery();
+    }
+}
+
+pub fn handle_print_requests(swarm: &Swarm<AppBehaviour>) {
+    let in_flight = &swarm.behaviour().in_flight;
+    info!("In-flight requests: {}", in_flight.len());
+    for request in in_flight.iter() {
+        info!(
+            "#{} {:?} to {} attempt {} pending for {}s",
+            request.id,
+            request.kind,
+            request.peer,
+            request.attempts,
+            request.started.elapsed().as_secs()
+        );
     }
-    unique_peers.iter().map(|p| p.to_string()).collect()
 }
 
 pub fn handle_print_peers(swarm: &Swarm<AppBehaviour>) {
     let peers = get_list_peers(swarm);
-    peers.iter().for_each(|p| info!("{}", p));
+    for peer in peers {
+        match swarm.behaviour().peer_capabilities.get(&peer) {
+            Some(capabilities) => info!("{} {:?}", peer, capabilities.names()),
+            None => info!("{}", peer),
+        }
+    }
 }
 
 pub fn handle_print_chain(swarm: &Swarm<AppBehaviour>) {
@@ -141,14 +1031,1012 @@ pub fn handle_print_chain(swarm: &Swarm<AppBehaviour>) {
     info!("{}", pretty_json);
 }
 
+pub fn handle_print_size(swarm: &Swarm<AppBehaviour>) {
+    let app = &swarm.behaviour().app;
+    info!(
+        "Blocks: {}, encoded size: {} bytes",
+        app.blocks.len(),
+        app.encoded_size()
+    );
+}
+
+pub fn handle_publish_checkpoint(swarm: &mut Swarm<AppBehaviour>, operator_keys: &Keypair) {
+    let behaviour = swarm.behaviour_mut();
+    let tip = behaviour.app.get_last_block();
+    if behaviour.app.checkpoints.contains_key(&tip.header.id) {
+        return;
+    }
+    match SignedCheckpoint::sign(operator_keys, tip.header.id, tip.header.hash.clone()) {
+        Ok(checkpoint) => {
+            let bytes = behaviour.encoding.encode(&checkpoint).expect("can encode checkpoint");
+            if let Err(e) = behaviour.app.add_checkpoint(checkpoint.height, checkpoint.hash) {
+                error!("not publishing checkpoint: {}", e);
+                return;
+            }
+            info!("publishing checkpoint at height {}", checkpoint.height);
+            behaviour.floodsub.publish(behaviour.topics.checkpoints.clone(), bytes);
+        }
+        Err(e) => error!("{}", e),
+    }
+}
+
+// this validator's vote for the tip, unless it is already pinned
+#[cfg(feature = "bls")]
+pub fn handle_publish_bls_vote(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let tip = &behaviour.app.get_last_block().header;
+    if behaviour.app.checkpoints.contains_key(&tip.id) {
+        return;
+    }
+    let Some(signer) = behaviour.bls_checkpoints.as_ref().and_then(|bls| bls.signer.as_ref()) else {
+        return;
+    };
+    let vote = signer.vote(tip.id, tip.hash.clone());
+    let bytes = behaviour
+        .encoding
+        .encode(&BlsMessage::Vote(vote.clone()))
+        .expect("can encode checkpoint vote");
+    info!("voting for checkpoint at height {}", vote.height);
+    behaviour
+        .floodsub
+        .publish(behaviour.topics.bls_checkpoints.clone(), bytes);
+    // floodsub doesn't deliver our own messages, the vote is counted here
+    let peer_id = behaviour.peer_id;
+    behaviour.handle_bls_message(BlsMessage::Vote(vote), peer_id);
+}
+
+pub fn handle_print_races(swarm: &Swarm<AppBehaviour>) {
+    let stats = &swarm.behaviour().app.race_stats;
+    info!(
+        "Block races: {}, won by the competing block: {}",
+        stats.races, stats.won_by_remote
+    );
+}
+
+// "explain block <hash>", every rule the block is checked against and how it went,
+// now for blocks the node has and once it arrives for others
+pub fn handle_explain_block(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let hash = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["explain", "block", hash] => hash,
+        _ => {
+            error!("usage: explain block <hash>");
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    match behaviour.app.find_known_block(hash) {
+        Some(block) => print_trace(&behaviour.app.explain_block(block)),
+        None => {
+            info!("block {} is not known yet, it will be explained when it arrives", hash);
+            behaviour.explain.insert(hash.to_string());
+        }
+    }
+}
+
+fn print_trace(trace: &BlockTrace) {
+    let verdict = if trace.passed() { "passes" } else { "fails" };
+    info!("block {} at height {} {}:", trace.hash, trace.height, verdict);
+    for outcome in &trace.rules {
+        match &outcome.error {
+            None => info!("  {:<18} ok", outcome.rule),
+            Some(e) => info!("  {:<18} FAILED: {}", outcome.rule, e),
+        }
+    }
+}
+
+pub fn handle_print_hashrate(swarm: &Swarm<AppBehaviour>) {
+    match swarm.behaviour().block_miner.hashrate() {
+        Some(hashrate) => info!("Hashrate: {:.0} H/s", hashrate),
+        None => info!("Hashrate: not measured yet, mine a block first"),
+    }
+}
+
+pub fn send_direct(swarm: &mut Swarm<AppBehaviour>, peer: PeerId, body: String) -> Result<(), String> {
+    let behaviour = swarm.behaviour_mut();
+    let message = DirectMessage::new(&peer, body, behaviour.messaging.identity.as_ref())?;
+    behaviour.direct.send_request(&peer, message);
+    Ok(())
+}
+
+pub fn handle_direct_message(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let rest = cmd.strip_prefix("dm ").unwrap_or_default();
+    let Some((peer, body)) = rest.split_once(' ') else {
+        error!("usage: dm <peer id> <message>");
+        return;
+    };
+    match peer.parse::<PeerId>() {
+        Ok(peer) => {
+            if let Err(e) = send_direct(swarm, peer, body.to_string()) {
+                error!("error sending direct message {}", e);
+            }
+        }
+        Err(_) => error!("invalid peer id {}", peer),
+    }
+}
+
+pub fn handle_print_inbox(swarm: &Swarm<AppBehaviour>) {
+    info!("Direct messages:");
+    for message in &swarm.behaviour().messaging.inbox {
+        let from = message.validator.as_deref().unwrap_or(&message.from);
+        info!("[{}] {}: {}", message.received_at, from, message.body);
+    }
+}
+
+pub fn handle_print_alerts(swarm: &Swarm<AppBehaviour>) {
+    match &swarm.behaviour().watchtower {
+        Some(watchtower) => {
+            match watchtower.headers.last() {
+                Some(tip) => info!("Following tip {} at height {}", tip.hash, tip.id),
+                None => info!("No blocks followed yet"),
+            }
+            info!("Alerts raised:");
+            for (kind, count) in &watchtower.counts {
+                info!("{}: {}", kind, count);
+            }
+        }
+        None => info!("not running as a watchtower"),
+    }
+}
+
+pub fn handle_print_reorgs(swarm: &Swarm<AppBehaviour>) {
+    info!("Reorgs:");
+    for record in &swarm.behaviour().reorg_journal.records {
+        info!(
+            "[{}] depth {} from {}: {} -> {}, discarded {:?}, applied {:?}",
+            record.at, record.depth, record.peer, record.old_tip, record.new_tip, record.discarded, record.applied
+        );
+    }
+}
+
+// "events since height <n>" or "events since time <unix seconds>"
+pub fn handle_print_events(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let since = match cmd.strip_prefix("events since ").unwrap_or_default().parse() {
+        Ok(since) => since,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    info!("Chain events:");
+    for logged in swarm.behaviour().event_log.since(since) {
+        let event = serde_json::to_string(&logged.event).expect("can jsonify chain event");
+        info!("#{} [{}] at height {}: {}", logged.seq, logged.at, logged.height, event);
+    }
+}
+
+pub fn handle_print_miners(swarm: &Swarm<AppBehaviour>) {
+    info!("Miners by blocks mined:");
+    for rank in swarm
+        .behaviour()
+        .app
+        .miner_leaderboard(BlockHeight::GENESIS..BlockHeight::MAX)
+    {
+        info!("{}: {}", rank.miner, rank.blocks);
+    }
+}
+
+// blocks shown by "stats", most recent last
+const STATS_RECENT_BLOCKS: usize = 10;
+
+pub fn resource_usage(swarm: &Swarm<AppBehaviour>) -> ResourceUsage {
+    let behaviour = swarm.behaviour();
+    let network = swarm.network_info();
+    let mut storage = BTreeMap::new();
+    let files = [
+        ("chain", behaviour.chain_file.as_deref()),
+        ("reorg_journal", behaviour.reorg_journal.path.as_deref()),
+        ("event_log", behaviour.event_log.path.as_deref()),
+    ];
+    for (column, path) in files {
+        if let Some(path) = path {
+            storage.insert(column, resources::file_size(path));
+        }
+    }
+    let app = &behaviour.app;
+    let queues = BTreeMap::from([
+        ("mempool", behaviour.mempool.len()),
+        ("outbound", behaviour.outbound.len()),
+        ("orphans", app.orphans.len()),
+        ("side_chains", app.side_chains.len()),
+        ("chain_requests", behaviour.in_flight.len()),
+        ("relay", behaviour.relay_queue.len()),
+    ]);
+    ResourceUsage {
+        storage,
+        chain_bytes: app.encoded_size(),
+        rss_bytes: resources::rss_bytes(),
+        peers: network.num_peers(),
+        connections: network.connection_counters().num_established(),
+        pending_connections: network.connection_counters().num_pending(),
+        tasks: behaviour.tasks.count(),
+        queues,
+    }
+}
+
+pub fn handle_print_stats(swarm: &Swarm<AppBehaviour>) {
+    let stats = swarm.behaviour().app.stats();
+    info!("Blocks: {}", stats.blocks);
+    match stats.average_block_interval {
+        Some(secs) => info!("Average block interval: {:.1}s", secs),
+        None => info!("Average block interval: not enough blocks"),
+    }
+    info!(
+        "Difficulty: {}, next block: {}",
+        stats.difficulty, stats.next_difficulty
+    );
+    info!("Total work: {}", stats.total_work);
+    info!(
+        "Encoded size: {} bytes, {} transactions taking {} bytes",
+        stats.chain_bytes, stats.transactions, stats.transaction_bytes
+    );
+    info!("Recent blocks:");
+    let skip = stats.history.len().saturating_sub(STATS_RECENT_BLOCKS);
+    for point in &stats.history[skip..] {
+        let block_time = point
+            .block_time
+            .map(|secs| format!("{}s", secs))
+            .unwrap_or_else(|| "-".to_string());
+        info!(
+            "height {}: difficulty {}, work {}, mined in {}",
+            point.height, point.difficulty, point.work, block_time
+        );
+    }
+    let usage = resource_usage(swarm);
+    info!("Resources:");
+    match usage.rss_bytes {
+        Some(bytes) => info!("memory: {} bytes resident", bytes),
+        None => info!("memory: unknown"),
+    }
+    info!("chain in memory: {} bytes", usage.chain_bytes);
+    for (column, bytes) in &usage.storage {
+        info!("storage {}: {} bytes", column, bytes);
+    }
+    info!(
+        "peers: {}, connections: {} open, {} pending",
+        usage.peers, usage.connections, usage.pending_connections
+    );
+    info!("tasks: {}", usage.tasks);
+    for (queue, depth) in &usage.queues {
+        info!("queue {}: {}", queue, depth);
+    }
+}
+
+// "export difficulty <file>" or "export blocktimes <file>", over the whole chain
+pub fn handle_export_analytics(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let app = &swarm.behaviour().app;
+    let (csv, path) = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["export", "difficulty", path] => (
+            analytics::difficulty_history_csv(&app.difficulty_history(BlockHeight::GENESIS..BlockHeight::MAX)),
+            path,
+        ),
+        ["export", "blocktimes", path] => (
+            analytics::block_time_histogram_csv(&app.block_time_histogram(BlockHeight::GENESIS..BlockHeight::MAX)),
+            path,
+        ),
+        _ => {
+            error!("usage: export difficulty|blocktimes <file>");
+            return;
+        }
+    };
+    match analytics::write_csv(Path::new(path), &csv) {
+        Ok(()) => info!("exported to {}", path),
+        Err(e) => error!("{}", e),
+    }
+}
+
+// A block being mined off the event loop, see start_mining.
+pub struct MiningJob {
+    cancel: CancellationToken,
+    // hash of the tip it builds on
+    parent: String,
+    // to mine it again on a new tip
+    data: String,
+    // the tip moved on, see AppBehaviour::abandon_stale_mining
+    stale: bool,
+}
+
+// Mines the next block on the blocking pool, so the swarm keeps running meanwhile.
+// The block comes back to the event loop as BlockMined, and the outcome goes to
+// `reply` or the log.
+pub fn start_mining(
+    swarm: &mut Swarm<AppBehaviour>,
+    data: String,
+    reply: Option<oneshot::Sender<Result<Block, String>>>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    let block = match behaviour.mining {
+        Some(_) => Err("already mining a block".to_string()),
+        None => next_block(behaviour, data.clone()),
+    };
+    let block = match block {
+        Ok(block) => block,
+        Err(e) => return report_mined(reply, Err(e)),
+    };
+    let cancel = CancellationToken::new();
+    let job = MiningJob {
+        cancel: cancel.clone(),
+        parent: block.header.previous_hash.clone(),
+        data,
+        stale: false,
+    };
+    let request = MineRequest {
+        block,
+        consensus: behaviour.app.consensus.clone(),
+        delay: mining_delay(behaviour),
+        cancel,
+        reply,
+    };
+    // the miner hands the reply back with the block, so only lost if it is gone
+    match behaviour.block_miner.mine(request) {
+        Ok(()) => behaviour.mining = Some(job),
+        Err(e) => error!("{}", e),
+    }
+}
+
+// Read from DEV_BLOCK_INTERVAL, in seconds. For demos on a trivial difficulty,
+// where blocks would otherwise be mined as fast as they are asked for.
+pub fn dev_block_interval_from_env() -> Result<Option<Duration>, String> {
+    match parse_env::<u64>("DEV_BLOCK_INTERVAL")? {
+        Some(0) => Err("DEV_BLOCK_INTERVAL has to be at least 1".to_string()),
+        secs => Ok(secs.map(Duration::from_secs)),
+    }
+}
+
+// how long mining the next block waits for dev_block_interval to pass since the tip
+fn mining_delay(behaviour: &AppBehaviour) -> Duration {
+    let Some(interval) = behaviour.dev_block_interval else {
+        return Duration::ZERO;
+    };
+    let tip = behaviour.app.get_last_block().header.timestamp;
+    // a tip from the future waits one interval
+    let elapsed = Timestamp::now().0.saturating_sub(tip.0).max(0) as u64;
+    interval.saturating_sub(Duration::from_secs(elapsed))
+}
+
+pub fn handle_block_mined(
+    swarm: &mut Swarm<AppBehaviour>,
+    result: Result<Box<Block>, String>,
+    reply: Option<oneshot::Sender<Result<Block, String>>>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    // a block found just before it was abandoned is stale all the same
+    if let Some(job) = behaviour.mining.take().filter(|job| job.stale) {
+        info!("mining again on the new tip");
+        return start_mining(swarm, job.data, reply);
+    }
+    report_mined(reply, result.and_then(|block| publish_mined_block(behaviour, *block)));
+}
+
+pub fn handle_mining_progress(swarm: &mut Swarm<AppBehaviour>, progress: MiningProgress) {
+    info!(
+        "mining: {} attempts in {:.1}s, {:.0} H/s",
+        progress.attempts,
+        progress.elapsed.as_secs_f64(),
+        progress.hashrate
+    );
+    swarm.behaviour_mut().events.mining_progress(progress);
+}
+
+pub fn handle_stop_mining(swarm: &mut Swarm<AppBehaviour>) {
+    match &mut swarm.behaviour_mut().mining {
+        Some(job) => {
+            info!("stopping mining");
+            // not to be mined again if the tip just moved
+            job.stale = false;
+            job.cancel.cancel();
+        }
+        None => info!("not mining"),
+    }
+}
+
+// a dropped reply channel only means the caller went away
+fn report_mined(reply: Option<oneshot::Sender<Result<Block, String>>>, result: Result<Block, String>) {
+    match (reply, result) {
+        (Some(reply), result) => {
+            let _ = reply.send(result);
+        }
+        (None, Err(e)) => error!("error creating block {}", e),
+        (None, Ok(_)) => {}
+    }
+}
+
+// the next block on the local chain, all but sealed
+fn next_block(behaviour: &mut AppBehaviour, data: String) -> Result<Block, String> {
+    if behaviour.watchtower.is_some() {
+        return Err("watchtower mode does not mine".to_string());
+    }
+    if behaviour.discovering_tip {
+        return Err("still discovering the network tip, try again shortly".to_string());
+    }
+    if behaviour.app.signet_challenge.is_some() && behaviour.signet_key.is_none() {
+        return Err("signet mode: this node has no key to sign blocks with".to_string());
+    }
+    let max_data_size = behaviour.app.params.max_data_size;
+    if data.len() > max_data_size {
+        return Err(format!("data is larger than {} bytes", max_data_size));
+    }
+    let state = &behaviour.app.state;
+    behaviour
+        .relay_queue
+        .retain(|header| state.relay_tip(&header.spec).is_none_or(|tip| header.id > tip.height));
+    let mut relayed_headers = state.relayable(&behaviour.app.params, &behaviour.relay_queue);
+    relayed_headers.truncate(MAX_RELAYED_HEADERS);
+    let relayed_size: usize = relayed_headers.iter().map(BlockHeader::encoded_size).sum();
+    let latest_block = behaviour.app.blocks.last().expect("there is at least one block");
+    let height = latest_block.header.id.next().ok_or("chain is full")?;
+    // whatever room the data and relayed headers leave goes to pending transactions
+    let transactions = behaviour.app.state.spendable(
+        height,
+        behaviour.mempool.batch(
+            MAX_BLOCK_TRANSACTIONS,
+            max_data_size.saturating_sub(data.len() + relayed_size),
+        ),
+    );
+    let difficulty = behaviour.app.next_difficulty();
+    // a clock behind the last few blocks would produce a block peers reject
+    let timestamp = Timestamp::now().max(behaviour.app.min_next_timestamp());
+    let mut body = BlockBody::new(data, transactions);
+    body.relayed_headers = relayed_headers;
+    #[cfg(feature = "vm")]
+    {
+        body.contract_ops = behaviour.contract_queue.clone();
+    }
+    // State::apply_block refuses fees an amount can't hold, so don't mine for nothing
+    let fees = body
+        .transactions
+        .iter()
+        .try_fold(Amount::ZERO, |fees, transaction| fees.checked_add(transaction.fee))
+        .ok_or_else(|| ChainError::FeeOverflow.to_string())?;
+    if let Some(address) = &behaviour.miner_address {
+        let amount = behaviour
+            .app
+            .params
+            .block_reward(height)
+            .checked_add(fees)
+            .ok_or_else(|| ChainError::FeeOverflow.to_string())?;
+        body.coinbase = Some(Coinbase {
+            recipient: address.clone(),
+            amount,
+        });
+    }
+    let next_block = Block::unmined(
+        &latest_block.header,
+        body,
+        behaviour.miner.clone(),
+        difficulty,
+        timestamp,
+    );
+    behaviour.app.check_limits(&next_block).map_err(|e| e.to_string())?;
+    behaviour
+        .app
+        .payload_rules
+        .check(&behaviour.app.params, &next_block.data)
+        .map_err(|e| e.to_string())?;
+    Ok(next_block)
+}
+
+// Adds a freshly sealed block to the local chain and broadcasts it. The tip may
+// have moved on while it was mined, then it is refused like any stale block.
+fn publish_mined_block(behaviour: &mut AppBehaviour, mut next_block: Block) -> Result<Block, String> {
+    if let Some(keys) = &behaviour.signet_key {
+        next_block.sign(keys)?;
+    }
+    let bytes = behaviour.encoding.encode(&next_block).expect("can encode block");
+    behaviour
+        .app
+        .try_add_block(next_block.clone())
+        .map_err(|e| format!("mined an invalid block: {}", e))?;
+    behaviour.mempool.remove_confirmed(std::slice::from_ref(&next_block));
+    // ops queued while the block was mined wait for the next one
+    #[cfg(feature = "vm")]
+    behaviour
+        .contract_queue
+        .retain(|op| !next_block.data.contract_ops.contains(op));
+    behaviour.save_chain();
+    info!("broadcasting new block");
+    behaviour.send_outbound(
+        Priority::Block,
+        Outbound::Publish {
+            topic: behaviour.topics.blocks.clone(),
+            bytes,
+        },
+    );
+    #[cfg(feature = "da-sampling")]
+    if availability::needs_sampling(&next_block) {
+        for chunk in availability::chunks_of(&next_block) {
+            let bytes = behaviour.encoding.encode(&chunk).expect("can encode chunk");
+            behaviour.send_outbound(
+                Priority::Block,
+                Outbound::Publish {
+                    topic: behaviour.topics.chunks.clone(),
+                    bytes,
+                },
+            );
+        }
+    }
+    Ok(next_block)
+}
+
+// the next block as a template for an external miner, proof of work chains only
+fn get_work(behaviour: &mut AppBehaviour, data: String) -> Result<WorkTemplate, String> {
+    let genesis = &behaviour.app.params.genesis;
+    if !(genesis.stakes.is_empty() && genesis.authorities.is_empty()) {
+        return Err("only proof of work chains hand out work".to_string());
+    }
+    let block = next_block(behaviour, data)?;
+    behaviour.work.retain_tip(&block.header.previous_hash);
+    let hash_algorithm = behaviour.app.params.genesis.hash_algorithm;
+    Ok(behaviour.work.issue(block, hash_algorithm))
+}
+
+// adds and broadcasts the block of a solved template, unless the tip moved since
+fn submit_work(behaviour: &mut AppBehaviour, work_id: u64, nonce: u64) -> Result<Block, String> {
+    let block = behaviour.work.solve(work_id, nonce, behaviour.app.params.hasher())?;
+    if block.header.previous_hash != behaviour.app.get_last_block().header.hash {
+        return Err("stale work, the tip has moved on".to_string());
+    }
+    info!("work {} solved by an external miner", work_id);
+    publish_mined_block(behaviour, block)
+}
+
+// headers of chains this one doesn't relay are dropped, they could only fill the queue
+fn queue_relayed_headers(behaviour: &mut AppBehaviour, headers: Vec<BlockHeader>) -> usize {
+    let mut queued = 0;
+    for header in headers {
+        if behaviour.relay_queue.len() >= MAX_RELAY_QUEUE {
+            break;
+        }
+        if !behaviour.app.params.relay_sources.contains_key(&header.spec) {
+            continue;
+        }
+        if !behaviour.relay_queue.iter().any(|queued| queued.hash == header.hash) {
+            behaviour.relay_queue.push(header);
+            queued += 1;
+        }
+    }
+    queued
+}
+
+pub fn submit_transaction(swarm: &mut Swarm<AppBehaviour>, transaction: Transaction) -> Result<String, String> {
+    transaction.verify()?;
+    admit_transaction(swarm.behaviour_mut(), transaction)
+}
+
+// adds a transaction with an already checked signature to the mempool and relays it
+fn admit_transaction(behaviour: &mut AppBehaviour, transaction: Transaction) -> Result<String, String> {
+    let id = transaction.id();
+    check_admissible(behaviour, &transaction)?;
+    let bytes = behaviour.encoding.encode(&transaction).expect("can encode transaction");
+    behaviour.mempool.add_verified(transaction.clone())?;
+    behaviour.events.transaction_pending(&transaction);
+    behaviour.send_outbound(
+        Priority::Bulk,
+        Outbound::Publish {
+            topic: behaviour.topics.transactions.clone(),
+            bytes,
+        },
+    );
+    Ok(id)
+}
+
+// the chain rules a transaction has to meet before it is pooled, besides its signature
+fn check_admissible(behaviour: &AppBehaviour, transaction: &Transaction) -> Result<(), String> {
+    state::check_signature_scheme(&behaviour.app.params, transaction).map_err(|e| e.to_string())?;
+    state::check_chain(&behaviour.app.get_last_block().header.spec, transaction).map_err(|e| e.to_string())?;
+    if behaviour.app.state.is_confirmed(&transaction.id()) {
+        return Err(ChainError::DuplicateTransaction(transaction.id()).to_string());
+    }
+    // a name taken by then may still be freed before the transaction is mined, but
+    // that is rare enough not to pool it meanwhile
+    if let Some(name) = &transaction.name {
+        let height = behaviour
+            .app
+            .get_last_block()
+            .header
+            .id
+            .next()
+            .unwrap_or(BlockHeight::MAX);
+        let current = behaviour.app.state.resolve(name, height);
+        names::claim(current, name, &transaction.recipient, transaction.fee, height).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn submit_checked_transactions(
+    swarm: &mut Swarm<AppBehaviour>,
+    checked: Vec<Result<Transaction, SubmittedTransaction>>,
+) -> Vec<SubmittedTransaction> {
+    let behaviour = swarm.behaviour_mut();
+    let results: Vec<SubmittedTransaction> = checked
+        .into_iter()
+        .map(|checked| match checked {
+            Ok(transaction) => {
+                let id = transaction.id();
+                let wtxid = transaction.wtxid();
+                let error = admit_transaction(behaviour, transaction).err();
+                SubmittedTransaction { id, wtxid, error }
+            }
+            Err(rejected) => rejected,
+        })
+        .collect();
+    let admitted = results.iter().filter(|result| result.error.is_none()).count();
+    info!("admitted {} of {} submitted transactions", admitted, results.len());
+    results
+}
+
+// "create tx <recipient address> <amount> [fee]", signed with the node's wallet key
+pub fn handle_create_transaction(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let (recipient, amount, fee) = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["create", "tx", recipient, amount] => (recipient, amount, "0"),
+        ["create", "tx", recipient, amount, fee] => (recipient, amount, fee),
+        _ => {
+            error!("usage: create tx <recipient> <amount> [fee]");
+            return;
+        }
+    };
+    let Ok(amount) = amount.parse() else {
+        error!("invalid amount {}", amount);
+        return;
+    };
+    let Ok(fee) = fee.parse() else {
+        error!("invalid fee {}", fee);
+        return;
+    };
+    let Some(keys) = &swarm.behaviour().wallet_key else {
+        error!("no WALLET_KEY to sign transactions with");
+        return;
+    };
+    let chain = swarm.behaviour().app.get_last_block().header.spec.clone();
+    let result = Transaction::new(keys, chain, recipient.to_string(), amount, fee)
+        .and_then(|transaction| submit_transaction(swarm, transaction));
+    match result {
+        Ok(id) => info!("submitted transaction {}", id),
+        Err(e) => error!("error submitting transaction {}", e),
+    }
+}
+
+// "deploy <code hex>" or "call <contract> <input hex> <gas limit>", queued for the
+// next block this node mines
+#[cfg(feature = "vm")]
+pub fn handle_contract_op(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let op = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["deploy", code] => ContractOp::Deploy { code: code.to_string() },
+        ["call", contract, input, gas_limit] => match gas_limit.parse() {
+            Ok(gas_limit) => ContractOp::Call {
+                contract: contract.to_string(),
+                input: input.to_string(),
+                gas_limit,
+            },
+            Err(_) => {
+                error!("invalid gas limit {}", gas_limit);
+                return;
+            }
+        },
+        _ => {
+            error!("usage: deploy <code hex> | call <contract> <input hex> <gas limit>");
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    let mut queue = behaviour.contract_queue.clone();
+    queue.push(op.clone());
+    if let Err(e) = vm::validate(&queue) {
+        error!("{}", e);
+        return;
+    }
+    match (&op, behaviour.app.get_last_block().header.id.next()) {
+        (ContractOp::Deploy { code }, Some(height)) => {
+            let code = hex::decode(code).unwrap_or_default();
+            info!(
+                "queued deployment, at {} if this node mines the next block",
+                vm::contract_address(height, queue.len() - 1, &code)
+            );
+        }
+        _ => info!("queued contract op for the next block"),
+    }
+    behaviour.contract_queue = queue;
+}
+
+// "receipts <height>", what the contract ops of that block did
+#[cfg(feature = "vm")]
+pub fn handle_print_receipts(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let Some(Ok(height)) = cmd.strip_prefix("receipts ").map(|height| height.trim().parse()) else {
+        error!("usage: receipts <height>");
+        return;
+    };
+    let receipts = swarm.behaviour().app.state.contracts().receipts(BlockHeight(height));
+    info!("Receipts of block {}, {} ops:", height, receipts.len());
+    for receipt in receipts {
+        match &receipt.error {
+            Some(e) => info!("{}: failed after {} gas: {}", receipt.contract, receipt.gas_used, e),
+            None => info!("{}: ok, {} gas", receipt.contract, receipt.gas_used),
+        }
+    }
+}
+
+pub fn handle_print_mempool(swarm: &Swarm<AppBehaviour>) {
+    let mempool = &swarm.behaviour().mempool;
+    info!("Mempool, {} pending:", mempool.len());
+    for transaction in mempool.iter() {
+        let sender = transaction.sender().unwrap_or_else(|_| transaction.sender_key.clone());
+        info!(
+            "{} {} -> {}: {}",
+            transaction.id(),
+            sender,
+            transaction.recipient,
+            transaction.amount
+        );
+    }
+}
+
+// "create name <name> [fee]", claimed for the node's wallet address with its key
+pub fn handle_create_name(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let (name, fee) = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["create", "name", name] => (name, None),
+        ["create", "name", name, fee] => (name, Some(fee)),
+        _ => {
+            error!("usage: create name <name> [fee]");
+            return;
+        }
+    };
+    let fee = match fee.map(str::parse) {
+        None => MIN_NAME_FEE,
+        Some(Ok(fee)) => fee,
+        Some(Err(_)) => {
+            error!("invalid fee {}", fee.unwrap_or_default());
+            return;
+        }
+    };
+    let Some(keys) = &swarm.behaviour().wallet_key else {
+        error!("no WALLET_KEY to sign transactions with");
+        return;
+    };
+    let Some(address) = keys::address(&keys.public()) else {
+        error!("wallet key has no address");
+        return;
+    };
+    let chain = swarm.behaviour().app.get_last_block().header.spec.clone();
+    let result = Transaction::claim_name(keys, chain, address, name.to_string(), fee)
+        .and_then(|transaction| submit_transaction(swarm, transaction));
+    match result {
+        Ok(id) => info!("submitted claim of {} in transaction {}", name, id),
+        Err(e) => error!("error claiming name {}", e),
+    }
+}
+
+// "name <name>", who holds it and until when
+pub fn handle_print_name(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let name = cmd.strip_prefix("name ").unwrap_or_default().trim();
+    match swarm.behaviour().app.resolve_name(name) {
+        Some(record) => info!("{}: {} until height {}", name, record.owner, record.expires),
+        None => info!("{} is free", name),
+    }
+}
+
+// "balance <address>", or this node's wallet address without one
+pub fn handle_print_balance(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let address = match cmd.strip_prefix("balance").map(str::trim) {
+        Some(address) if !address.is_empty() => address.to_string(),
+        _ => match behaviour
+            .wallet_key
+            .as_ref()
+            .and_then(|wallet| keys::address(&wallet.public()))
+        {
+            Some(address) => address,
+            None => {
+                error!("usage: balance <address>");
+                return;
+            }
+        },
+    };
+    info!("{}: {}", address, behaviour.app.balance_of(&address));
+    let names = behaviour.app.names_of(&address);
+    if !names.is_empty() {
+        info!("names: {}", names.join(", "));
+    }
+}
+
+// "save <file>" as pretty JSON, "save <file> compact", or "save <file> archive"
+// with the mmap feature
+pub fn handle_save_chain(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let (path, format) = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["save", path] => (path, ChainFormat::Pretty),
+        ["save", path, "compact"] => (path, ChainFormat::Compact),
+        #[cfg(feature = "mmap")]
+        ["save", path, "archive"] => (path, ChainFormat::Archive),
+        _ => {
+            error!("usage: save <file> [compact|archive]");
+            return;
+        }
+    };
+    match swarm.behaviour().app.export_to_file(Path::new(path), format) {
+        Ok(()) => info!("saved chain to {}", path),
+        Err(e) => error!("{}", e),
+    }
+}
+
+// "load <file>", replacing the local chain if the file's is valid
+pub fn handle_load_chain(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let path = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["load", path] => path,
+        _ => {
+            error!("usage: load <file>");
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    if let Err(e) = behaviour.app.import_from_file(Path::new(path)) {
+        error!("{}", e);
+        return;
+    }
+    behaviour.mempool.remove_confirmed(&behaviour.app.blocks);
+    behaviour.save_chain();
+    info!(
+        "loaded chain from {}, tip is {}",
+        path,
+        behaviour.app.get_last_block().header.id
+    );
+}
+
+// "bundle <file>", the chain signed with the operator's key for late joiners to
+// bootstrap from once published at a URL, see bundle::Bootstrap
+pub fn handle_write_bundle(cmd: &str, swarm: &Swarm<AppBehaviour>, operator_keys: Option<&Keypair>) {
+    let path = match cmd.split_whitespace().collect::<Vec<_>>()[..] {
+        ["bundle", path] => path,
+        _ => {
+            error!("usage: bundle <file>");
+            return;
+        }
+    };
+    let Some(keys) = operator_keys else {
+        error!("bundles are signed with CHECKPOINT_KEY, which is not set");
+        return;
+    };
+    let bundle = ChainBundle::sign(keys, swarm.behaviour().app.blocks.clone());
+    match bundle.and_then(|bundle| bundle.write(Path::new(path)).map(|_| bundle.height)) {
+        Ok(height) => info!("wrote bundle up to height {} to {}", height, path),
+        Err(e) => error!("{}", e),
+    }
+}
+
 pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
     if let Some(data) = cmd.strip_prefix("create b") {
-        let behaviour = swarm.behaviour_mut();
-        let latest_block = behaviour.app.blocks.last().expect("there is at least one block");
-        let next_block = latest_block.mine_next_block(data.to_owned());
-        let json = serde_json::to_string(&next_block).expect("can jsonify request");
-        behaviour.app.blocks.push(next_block);
-        info!("broadcasting new block");
-        behaviour.floodsub.publish(BLOCK_TOPIC.clone(), json.as_bytes());
+        start_mining(swarm, data.to_owned(), None);
+    }
+}
+
+// Sends the next batch of queued messages, and comes back for more after whatever
+// else is waiting on the event loop.
+pub fn handle_flush_outbound(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    for message in behaviour.outbound.next_batch() {
+        match message {
+            Outbound::Publish { topic, bytes } => behaviour.floodsub.publish(topic, bytes),
+            Outbound::ChainResponse {
+                peer,
+                channel,
+                response,
+            } => {
+                if behaviour.chain_sync.send_response(channel, response).is_err() {
+                    error!("could not send chain response to {}, connection closed", peer);
+                }
+            }
+        }
+    }
+    if !behaviour.outbound.is_empty() && behaviour.response_sender.send(EventType::FlushOutbound).is_err() {
+        error!("could not schedule outbound messages");
+    }
+}
+
+pub fn handle_peer_connected(swarm: &mut Swarm<AppBehaviour>, peer: PeerId) {
+    if !swarm.behaviour().is_authorized(&peer, MessageKind::Connection) {
+        swarm.behaviour_mut().floodsub.remove_node_from_partial_view(&peer);
+        if swarm.disconnect_peer_id(peer).is_err() {
+            error!("could not disconnect {}", peer);
+        }
+    }
+}
+
+pub fn handle_validated_chain(
+    swarm: &mut Swarm<AppBehaviour>,
+    peer: PeerId,
+    blocks: Vec<Block>,
+    result: Result<(), ValidationReport>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    if let Err(report) = result {
+        error!("chain from {} is invalid: {}", peer, report);
+        if let Some(watchtower) = &mut behaviour.watchtower {
+            watchtower.raise(Alert::InvalidChain {
+                peer: peer.to_string(),
+                reason: report.to_string(),
+            });
+        }
+    } else {
+        let depth = behaviour.app.fork_depth(&blocks);
+        let old_tip = behaviour.app.get_last_block().header.hash.clone();
+        let reorg = ReorgRecord::between(&behaviour.app.blocks, &blocks, &peer);
+        let rolled_back = behaviour.app.blocks[behaviour.app.blocks.len() - depth..].to_vec();
+        if behaviour.app.adopt_validated_chain(blocks) {
+            info!("switched to the chain from {}", peer);
+            if let Some(record) = reorg {
+                behaviour.reorg_journal.record(record);
+            }
+            // as in handle_block, transactions of rolled back blocks are pending again
+            for transaction in rolled_back.iter().flat_map(|block| &block.data.transactions) {
+                let _ = behaviour.mempool.add_verified(transaction.clone());
+            }
+            behaviour.mempool.remove_confirmed(&behaviour.app.blocks);
+            if let Some(watchtower) = &mut behaviour.watchtower {
+                watchtower.check_reorg(depth, &old_tip, &behaviour.app.get_last_block().header.hash);
+                watchtower.follow(&behaviour.app.blocks);
+            }
+            behaviour.save_chain();
+        }
+    }
+    behaviour.finish_tip_discovery();
+}
+
+pub fn handle_api_request(swarm: &mut Swarm<AppBehaviour>, request: ApiRequest) {
+    // a dropped reply channel only means the caller went away
+    match request {
+        ApiRequest::Chain(reply) => {
+            let _ = reply.send(swarm.behaviour().app.blocks.clone());
+        }
+        ApiRequest::Peers(reply) => {
+            let _ = reply.send(discovered_peers(swarm));
+        }
+        ApiRequest::CreateBlock(data, reply) => start_mining(swarm, data, Some(reply)),
+        ApiRequest::SendDirect(peer, body, reply) => {
+            let _ = reply.send(send_direct(swarm, peer, body));
+        }
+        ApiRequest::Inbox(reply) => {
+            let _ = reply.send(swarm.behaviour().messaging.inbox.iter().cloned().collect());
+        }
+        ApiRequest::MinerLeaderboard(range, reply) => {
+            let _ = reply.send(swarm.behaviour().app.miner_leaderboard(range));
+        }
+        ApiRequest::DifficultyHistory(range, reply) => {
+            let _ = reply.send(swarm.behaviour().app.difficulty_history(range));
+        }
+        ApiRequest::BlockTimeHistogram(range, reply) => {
+            let _ = reply.send(swarm.behaviour().app.block_time_histogram(range));
+        }
+        ApiRequest::SubmitTransactions(checked, reply) => {
+            let _ = reply.send(submit_checked_transactions(swarm, checked));
+        }
+        ApiRequest::Status(reply) => {
+            let _ = reply.send(node_status(swarm));
+        }
+        ApiRequest::Resources(reply) => {
+            let _ = reply.send(resource_usage(swarm));
+        }
+        ApiRequest::RelayTip(spec, reply) => {
+            let _ = reply.send(swarm.behaviour().app.state.relay_tip(&spec).cloned());
+        }
+        ApiRequest::Subscribe(filter, reply) => {
+            let behaviour = swarm.behaviour_mut();
+            let tip = behaviour.app.get_last_block().header.id;
+            let _ = reply.send(behaviour.events.subscribe(filter, tip));
+        }
+        ApiRequest::RelayHeaders(headers, reply) => {
+            let _ = reply.send(queue_relayed_headers(swarm.behaviour_mut(), headers));
+        }
+        ApiRequest::TransactionStatus(id, reply) => {
+            let _ = reply.send(transaction_status(swarm.behaviour(), &id));
+        }
+        ApiRequest::EventsSince(since, reply) => {
+            let _ = reply.send(swarm.behaviour().event_log.since(since));
+        }
+        ApiRequest::ResolveName(name, reply) => {
+            let _ = reply.send(swarm.behaviour().app.resolve_name(&name).cloned());
+        }
+        ApiRequest::GetWork(data, reply) => {
+            let _ = reply.send(get_work(swarm.behaviour_mut(), data));
+        }
+        ApiRequest::SubmitWork(work_id, nonce, reply) => {
+            let _ = reply.send(submit_work(swarm.behaviour_mut(), work_id, nonce));
+        }
     }
 }
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::genesis::GenesisConfig;
+use crate::hashing::BlockHasher;
+use crate::relay::RelaySource;
+use crate::units::{Amount, BlockHeight};
+use crate::{difficulty, parse_env};
+
+// Of the encoding in spec_hash, bumped only if that changes for every chain.
+pub const SPEC_VERSION: u8 = 1;
+
+// Consensus parameters of a chain. Nodes only agree on blocks if they run with
+// the same params, so a dev chain with trivial difficulty is its own network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainParams {
+    pub initial_difficulty: u32,
+    pub target_block_time_secs: i64,
+    pub retarget_interval: u64,
+    // the first this many blocks retarget one step at a time from their parent's
+    // block time, so a new network finds a workable difficulty quickly
+    pub bootstrap_blocks: u64,
+    // max length of a block's data in bytes
+    pub max_data_size: usize,
+    // max size of a whole block as sent over the wire, see Block::encoded_size
+    pub max_block_size: usize,
+    pub genesis: GenesisConfig,
+    // blocks must be later than the median timestamp of this many previous blocks
+    pub median_time_span: usize,
+    // and at most this far ahead of the local clock
+    pub max_future_drift_secs: i64,
+    // coins minted by each block's coinbase, halved every `halving_interval` blocks
+    pub block_reward: Amount,
+    // 0 never halves the reward
+    pub halving_interval: u64,
+    // name of the payload rules every block has to satisfy, which nodes register
+    // with App::payload_rules. Left out of the spec when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_rules: Option<String>,
+    // spec hash -> other chain whose headers blocks may relay, see relay::check_relayed.
+    // Left out of the spec when there are none.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub relay_sources: BTreeMap<String, RelaySource>,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self {
+            initial_difficulty: difficulty::INITIAL_DIFFICULTY,
+            target_block_time_secs: difficulty::TARGET_BLOCK_TIME_SECS,
+            retarget_interval: difficulty::RETARGET_INTERVAL,
+            bootstrap_blocks: 0,
+            max_data_size: 64 * 1024,
+            max_block_size: 128 * 1024,
+            genesis: GenesisConfig::default(),
+            median_time_span: 11,
+            max_future_drift_secs: 2 * 60 * 60,
+            block_reward: Amount(50),
+            halving_interval: 100_000,
+            payload_rules: None,
+            relay_sources: BTreeMap::new(),
+        }
+    }
+}
+
+impl ChainParams {
+    // First 8 bytes of the hash of these params, in hex. The fields are listed one
+    // by one, so adding a param doesn't change the spec, and with it the genesis, of
+    // chains that already exist. Params added from now on join the spec only when
+    // set to something other than their default. The genesis nonce and its expected
+    // hash are left out since the genesis block carries the spec itself; everything
+    // else that shapes the genesis block is in, so the spec commits to it.
+    pub fn spec_hash(&self) -> String {
+        let genesis = &self.genesis;
+        let mut spec = serde_json::json!({
+            "version": SPEC_VERSION,
+            "initial_difficulty": self.initial_difficulty,
+            "target_block_time_secs": self.target_block_time_secs,
+            "retarget_interval": self.retarget_interval,
+            "bootstrap_blocks": self.bootstrap_blocks,
+            "max_data_size": self.max_data_size,
+            "max_block_size": self.max_block_size,
+            "median_time_span": self.median_time_span,
+            "max_future_drift_secs": self.max_future_drift_secs,
+            "block_reward": self.block_reward,
+            "halving_interval": self.halving_interval,
+            "genesis": {
+                "timestamp": genesis.timestamp,
+                "data": genesis.data,
+                "allocations": genesis.allocations,
+                "stakes": genesis.stakes,
+                "authorities": genesis.authorities,
+                "hash_algorithm": genesis.hash_algorithm,
+                "signature_scheme": genesis.signature_scheme,
+            },
+        });
+        if let Some(rules) = &self.payload_rules {
+            spec["payload_rules"] = serde_json::json!(rules);
+        }
+        if !self.relay_sources.is_empty() {
+            spec["relay_sources"] = serde_json::json!(self.relay_sources);
+        }
+        hex::encode(&Sha256::digest(spec.to_string().as_bytes())[..8])
+    }
+
+    // defaults overridden by CHAIN_DIFFICULTY, CHAIN_BLOCK_TIME, CHAIN_RETARGET_INTERVAL,
+    // CHAIN_BOOTSTRAP_BLOCKS, CHAIN_MAX_DATA_SIZE, CHAIN_MAX_BLOCK_SIZE, CHAIN_MEDIAN_TIME_SPAN, CHAIN_MAX_FUTURE_DRIFT,
+    // CHAIN_BLOCK_REWARD, CHAIN_HALVING_INTERVAL and CHAIN_PAYLOAD_RULES. The genesis block is read from the
+    // JSON file at CHAIN_GENESIS_FILE if set, its hash algorithm and signature scheme
+    // can be overridden by CHAIN_HASH_ALGORITHM and CHAIN_SIGNATURE_SCHEME. Relay
+    // sources can only be set in a nodes file.
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        let mut genesis = match std::env::var("CHAIN_GENESIS_FILE") {
+            Ok(path) => GenesisConfig::load(Path::new(&path))?,
+            Err(_) => defaults.genesis,
+        };
+        if let Some(algorithm) = parse_env("CHAIN_HASH_ALGORITHM")? {
+            genesis.hash_algorithm = algorithm;
+        }
+        if let Some(scheme) = parse_env("CHAIN_SIGNATURE_SCHEME")? {
+            genesis.signature_scheme = scheme;
+        }
+        Ok(Self {
+            initial_difficulty: parse_env("CHAIN_DIFFICULTY")?.unwrap_or(defaults.initial_difficulty),
+            target_block_time_secs: parse_env("CHAIN_BLOCK_TIME")?.unwrap_or(defaults.target_block_time_secs),
+            retarget_interval: parse_env("CHAIN_RETARGET_INTERVAL")?.unwrap_or(defaults.retarget_interval),
+            bootstrap_blocks: parse_env("CHAIN_BOOTSTRAP_BLOCKS")?.unwrap_or(defaults.bootstrap_blocks),
+            max_data_size: parse_env("CHAIN_MAX_DATA_SIZE")?.unwrap_or(defaults.max_data_size),
+            max_block_size: parse_env("CHAIN_MAX_BLOCK_SIZE")?.unwrap_or(defaults.max_block_size),
+            genesis,
+            median_time_span: parse_env("CHAIN_MEDIAN_TIME_SPAN")?.unwrap_or(defaults.median_time_span),
+            max_future_drift_secs: parse_env("CHAIN_MAX_FUTURE_DRIFT")?.unwrap_or(defaults.max_future_drift_secs),
+            block_reward: parse_env("CHAIN_BLOCK_REWARD")?.unwrap_or(defaults.block_reward),
+            halving_interval: parse_env("CHAIN_HALVING_INTERVAL")?.unwrap_or(defaults.halving_interval),
+            payload_rules: std::env::var("CHAIN_PAYLOAD_RULES").ok(),
+            relay_sources: defaults.relay_sources,
+        })
+    }
+
+    // the difficulty never moves from initial_difficulty
+    pub fn has_fixed_difficulty(&self) -> bool {
+        self.retarget_interval == 0 && self.bootstrap_blocks == 0
+    }
+
+    pub fn hasher(&self) -> &'static dyn BlockHasher {
+        self.genesis.hash_algorithm.hasher()
+    }
+
+    // coins the coinbase of the block at `height` may mint, on top of its fees
+    pub fn block_reward(&self, height: BlockHeight) -> Amount {
+        if self.halving_interval == 0 {
+            return self.block_reward;
+        }
+        self.block_reward.halved(height.0 / self.halving_interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every chain on the default params carries this spec, changing it orphans them
+    #[test]
+    fn default_spec_is_pinned() {
+        assert_eq!(ChainParams::default().spec_hash(), "5c7ad77ecf4b86bf");
+    }
+
+    #[test]
+    fn spec_leaves_out_the_genesis_nonce_and_hash() {
+        let mut params = ChainParams::default();
+        params.genesis.nonce = 1;
+        params.genesis.hash = Some("00".to_string());
+        assert_eq!(params.spec_hash(), ChainParams::default().spec_hash());
+    }
+
+    #[test]
+    fn spec_covers_consensus_params() {
+        let defaults = ChainParams::default().spec_hash();
+        let changed = [
+            ChainParams {
+                retarget_interval: 0,
+                ..ChainParams::default()
+            },
+            ChainParams {
+                payload_rules: Some("rules".to_string()),
+                ..ChainParams::default()
+            },
+            ChainParams {
+                relay_sources: BTreeMap::from([(
+                    "source".to_string(),
+                    RelaySource {
+                        genesis_hash: "00".to_string(),
+                        min_difficulty: 1,
+                        hash_algorithm: Default::default(),
+                    },
+                )]),
+                ..ChainParams::default()
+            },
+        ];
+        for params in changed {
+            assert_ne!(params.spec_hash(), defaults);
+        }
+        let mut params = ChainParams::default();
+        params.genesis.allocations.insert("address".to_string(), Amount(1));
+        assert_ne!(params.spec_hash(), defaults);
+    }
+}
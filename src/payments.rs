@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::select;
+use tokio::time::{sleep_until, Instant};
+
+use crate::api::{NodeApi, TransactionStatus};
+use crate::events::{EventFilter, EventKind};
+use crate::units::BlockHeight;
+
+// Where a payment was confirmed, once it was deep enough.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Confirmation {
+    pub height: BlockHeight,
+    pub block_hash: String,
+    pub confirmations: u64,
+}
+
+// Resolves once transaction `id` is `depth` blocks deep on the node's chain, its
+// own block included, e.g. before a merchant hands over the goods. Fails right
+// away if the node has never heard of the transaction, e.g. because it conflicts
+// with the chain, and after `timeout` if it isn't deep enough by then, also when
+// a reorg rolled it back in the meantime.
+pub async fn await_confirmation(
+    api: &impl NodeApi,
+    id: &str,
+    depth: u64,
+    timeout: Duration,
+) -> Result<Confirmation, String> {
+    let deadline = Instant::now() + timeout;
+    // subscribed before the first look, so no block in between goes unnoticed
+    let mut blocks = api
+        .subscribe(EventFilter {
+            kinds: vec![EventKind::Block, EventKind::Disconnected],
+            ..EventFilter::default()
+        })
+        .await?;
+    let mut was_confirmed = false;
+    loop {
+        let status = api.transaction_status(id.to_string()).await?;
+        match &status {
+            TransactionStatus::Confirmed {
+                height,
+                block_hash,
+                confirmations,
+            } => {
+                if *confirmations >= depth {
+                    return Ok(Confirmation {
+                        height: *height,
+                        block_hash: block_hash.clone(),
+                        confirmations: *confirmations,
+                    });
+                }
+                was_confirmed = true;
+            }
+            TransactionStatus::Pending => {}
+            // rolled back, it may still make it into the new branch
+            TransactionStatus::Unknown if was_confirmed => {}
+            TransactionStatus::Unknown => {
+                return Err(format!("transaction {} is neither on the chain nor pending", id));
+            }
+        }
+        select! {
+            event = blocks.recv() => if event.is_none() {
+                return Err("node stopped".to_string());
+            },
+            _ = sleep_until(deadline) => {
+                return Err(match status {
+                    TransactionStatus::Confirmed { confirmations, .. } => {
+                        format!("transaction {} is only {} of {} blocks deep", id, confirmations, depth)
+                    }
+                    _ if was_confirmed => format!("transaction {} was rolled back by a reorg", id),
+                    _ => format!("transaction {} is still pending", id),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::info;
+use once_cell::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+
+use crate::parse_env;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// how soon a paused miner notices its work was cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tells the miner when the machine is under battery or thermal pressure. Embedders
+// can install their own; `SystemPowerMonitor` reads the Linux sysfs.
+pub trait PowerMonitor: Send + Sync {
+    // reason to pause mining, None when it may run
+    fn pressure(&self) -> Option<String>;
+}
+
+static MONITOR: OnceCell<Box<dyn PowerMonitor>> = OnceCell::new();
+
+pub fn set_monitor(monitor: Box<dyn PowerMonitor>) -> Result<(), String> {
+    MONITOR
+        .set(monitor)
+        .map_err(|_| "a power monitor is already installed".to_string())
+}
+
+// Blocks the mining thread while the installed monitor reports pressure, or until
+// `cancel` is, so abandoned work doesn't wait for the pressure to pass.
+pub fn wait_while_under_pressure(cancel: &CancellationToken) {
+    if let Some(monitor) = MONITOR.get() {
+        wait_for(monitor.as_ref(), cancel);
+    }
+}
+
+fn wait_for(monitor: &dyn PowerMonitor, cancel: &CancellationToken) {
+    let Some(reason) = monitor.pressure() else {
+        return;
+    };
+    info!("pausing mining: {}", reason);
+    let mut polled = Instant::now();
+    while !cancel.is_cancelled() {
+        if polled.elapsed() >= POLL_INTERVAL {
+            if monitor.pressure().is_none() {
+                info!("resuming mining");
+                return;
+            }
+            polled = Instant::now();
+        }
+        thread::sleep(CANCEL_CHECK_INTERVAL);
+    }
+    info!("mining cancelled while paused");
+}
+
+// Pauses below a battery level while discharging and/or above a temperature.
+pub struct SystemPowerMonitor {
+    pub min_battery_percent: Option<u8>,
+    pub max_temp_celsius: Option<u32>,
+}
+
+impl SystemPowerMonitor {
+    // MINING_MIN_BATTERY (percent) and MINING_MAX_TEMP (celsius), None if neither is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let min_battery_percent = parse_env("MINING_MIN_BATTERY")?;
+        let max_temp_celsius = parse_env("MINING_MAX_TEMP")?;
+        if min_battery_percent.is_none() && max_temp_celsius.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            min_battery_percent,
+            max_temp_celsius,
+        }))
+    }
+}
+
+impl PowerMonitor for SystemPowerMonitor {
+    fn pressure(&self) -> Option<String> {
+        if let (Some(min), Some(level)) = (self.min_battery_percent, discharging_battery_level()) {
+            if level < min {
+                return Some(format!("battery at {}%", level));
+            }
+        }
+        if let (Some(max), Some(temp)) = (self.max_temp_celsius, highest_temperature()) {
+            if temp > max {
+                return Some(format!("temperature at {}°C", temp));
+            }
+        }
+        None
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+// lowest charge among batteries that are currently discharging
+fn discharging_battery_level() -> Option<u8> {
+    fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| read_trimmed(&path.join("type")).as_deref() == Some("Battery"))
+        .filter(|path| read_trimmed(&path.join("status")).as_deref() == Some("Discharging"))
+        .filter_map(|path| read_trimmed(&path.join("capacity"))?.parse().ok())
+        .min()
+}
+
+fn highest_temperature() -> Option<u32> {
+    fs::read_dir("/sys/class/thermal")
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|entry| read_trimmed(&entry.path().join("temp"))?.parse::<u32>().ok())
+        .map(|millidegrees| millidegrees / 1000)
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AlwaysHot;
+
+    impl PowerMonitor for AlwaysHot {
+        fn pressure(&self) -> Option<String> {
+            Some("test".to_string())
+        }
+    }
+
+    #[test]
+    fn paused_miner_returns_once_cancelled() {
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        let started = Instant::now();
+        let waiter = thread::spawn(move || wait_for(&AlwaysHot, &cancel));
+        thread::sleep(CANCEL_CHECK_INTERVAL);
+        canceller.cancel();
+        waiter.join().unwrap();
+        assert!(started.elapsed() < POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::error::ChainError;
+use crate::BlockHeader;
+
+// What a block header is hashed as, picked by its version. Blocks used to hash a
+// JSON object of the header fields, which costs more to build than to hash on every
+// nonce tried. Newer blocks hash a fixed binary encoding instead. Chains keep
+// their JSON blocks, and the version never goes back down along a chain, see
+// check_version. From COMPACT_TARGET on, the difficulty field is also read
+// differently, see BlockHeader::target.
+
+// the fields as a JSON object, keys sorted, the miner left out if there is none
+pub const JSON_PREIMAGE: u8 = 0;
+// see `binary`
+pub const BINARY_PREIMAGE: u8 = 1;
+// binary, with the difficulty as a compact target rather than leading zero bits
+pub const COMPACT_TARGET: u8 = 2;
+// what this node creates new blocks with
+pub const CURRENT_VERSION: u8 = COMPACT_TARGET;
+
+// serde skips the version when it's the JSON one, so those blocks read as before
+pub fn is_json(version: &u8) -> bool {
+    *version == JSON_PREIMAGE
+}
+
+// `next` is a known version, and no older than its parent's
+pub fn check_version(parent: &BlockHeader, next: &BlockHeader) -> Result<(), ChainError> {
+    if next.version > CURRENT_VERSION || next.version < parent.version {
+        return Err(ChainError::InvalidHeaderVersion(next.version));
+    }
+    Ok(())
+}
+
+pub fn preimage(header: &BlockHeader) -> Vec<u8> {
+    match header.version {
+        JSON_PREIMAGE => json(header),
+        _ => binary(header),
+    }
+}
+
+fn json(header: &BlockHeader) -> Vec<u8> {
+    let mut json = serde_json::json!({
+        "id": header.id,
+        "timestamp": header.timestamp,
+        "previous_hash": header.previous_hash,
+        "merkle_root": header.merkle_root,
+        "difficulty": header.difficulty,
+        "nonce": header.nonce,
+        "spec": header.spec,
+    });
+    if let Some(miner) = &header.miner {
+        json["miner"] = serde_json::json!(miner);
+    }
+    json.to_string().into_bytes()
+}
+
+// The version byte, then the fields in a fixed order: integers big endian, strings
+// as their UTF-8 bytes after a u32 length, the miner after a 0 or 1 byte for
+// whether there is one. The nonce comes last, so mining only rewrites those bytes,
+// see set_nonce.
+pub fn binary(header: &BlockHeader) -> Vec<u8> {
+    let mut bytes = vec![header.version];
+    bytes.extend_from_slice(&header.id.0.to_be_bytes());
+    bytes.extend_from_slice(&header.timestamp.0.to_be_bytes());
+    put_str(&mut bytes, &header.previous_hash);
+    put_str(&mut bytes, &header.merkle_root);
+    match &header.miner {
+        Some(miner) => {
+            bytes.push(1);
+            put_str(&mut bytes, miner);
+        }
+        None => bytes.push(0),
+    }
+    bytes.extend_from_slice(&header.difficulty.to_be_bytes());
+    put_str(&mut bytes, &header.spec);
+    bytes.extend_from_slice(&header.nonce.to_be_bytes());
+    bytes
+}
+
+// the binary preimage of the same header with `nonce` instead
+pub fn set_nonce(binary: &mut [u8], nonce: u64) {
+    let start = binary.len() - 8;
+    binary[start..].copy_from_slice(&nonce.to_be_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{BlockHeight, Timestamp};
+
+    fn header(version: u8, miner: Option<&str>) -> BlockHeader {
+        BlockHeader {
+            id: BlockHeight(1),
+            timestamp: Timestamp(2),
+            nonce: 3,
+            hash: "ignored".to_string(),
+            previous_hash: "ab".to_string(),
+            merkle_root: "cd".to_string(),
+            miner: miner.map(str::to_string),
+            difficulty: 4,
+            spec: "ef".to_string(),
+            version,
+        }
+    }
+
+    #[test]
+    fn binary_layout_is_fixed() {
+        let expected = [
+            "01",
+            "0000000000000001",
+            "0000000000000002",
+            "00000002",
+            "6162",
+            "00000002",
+            "6364",
+            "01",
+            "00000001",
+            "6d",
+            "00000004",
+            "00000002",
+            "6566",
+            "0000000000000003",
+        ]
+        .concat();
+        assert_eq!(hex::encode(preimage(&header(BINARY_PREIMAGE, Some("m")))), expected);
+        // no miner is a single zero byte
+        let without = binary(&header(BINARY_PREIMAGE, None));
+        assert_eq!(without.len(), hex::decode(&expected).unwrap().len() - 5);
+    }
+
+    #[test]
+    fn set_nonce_matches_encoding_the_header() {
+        let mut bytes = binary(&header(COMPACT_TARGET, Some("m")));
+        set_nonce(&mut bytes, 99);
+        let mut header = header(COMPACT_TARGET, Some("m"));
+        header.nonce = 99;
+        assert_eq!(bytes, binary(&header));
+    }
+
+    #[test]
+    fn json_preimage_is_unchanged() {
+        let json = String::from_utf8(preimage(&header(JSON_PREIMAGE, None))).unwrap();
+        assert_eq!(
+            json,
+            r#"{"difficulty":4,"id":1,"merkle_root":"cd","nonce":3,"previous_hash":"ab","spec":"ef","timestamp":2}"#
+        );
+    }
+
+    #[test]
+    fn versions_only_move_forward() {
+        let json = header(JSON_PREIMAGE, None);
+        let binary = header(BINARY_PREIMAGE, None);
+        assert!(check_version(&json, &binary).is_ok());
+        assert_eq!(
+            check_version(&binary, &json),
+            Err(ChainError::InvalidHeaderVersion(JSON_PREIMAGE))
+        );
+        let unknown = header(CURRENT_VERSION + 1, None);
+        assert!(check_version(&binary, &unknown).is_err());
+    }
+}
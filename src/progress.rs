@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+// how often a block being mined reports its progress
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// hashes a mining worker tries between updates of its progress
+pub const ATTEMPTS_PER_UPDATE: u64 = 100_000;
+
+// How far mining a block has got, see Block::mine_async.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MiningProgress {
+    // hashes tried so far, by all workers
+    pub attempts: u64,
+    pub elapsed: Duration,
+    // hashes per second since mining started
+    pub hashrate: f64,
+}
+
+impl MiningProgress {
+    fn new(attempts: u64, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        Self {
+            attempts,
+            elapsed,
+            hashrate: if secs > 0.0 { attempts as f64 / secs } else { 0.0 },
+        }
+    }
+}
+
+// Counts the hashes of one mining run across its workers, and sends its progress
+// to `sender` every PROGRESS_INTERVAL at most.
+pub struct ProgressMeter<'a> {
+    sender: Option<&'a mpsc::UnboundedSender<MiningProgress>>,
+    started: Instant,
+    attempts: AtomicU64,
+    // milliseconds since `started` of the last report
+    reported_at: AtomicU64,
+}
+
+impl<'a> ProgressMeter<'a> {
+    pub fn new(sender: Option<&'a mpsc::UnboundedSender<MiningProgress>>) -> Self {
+        Self {
+            sender,
+            started: Instant::now(),
+            attempts: AtomicU64::new(0),
+            reported_at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, attempts: u64) {
+        let total = self.attempts.fetch_add(attempts, Ordering::Relaxed) + attempts;
+        let Some(sender) = self.sender else {
+            return;
+        };
+        let elapsed = self.started.elapsed();
+        let now = elapsed.as_millis() as u64;
+        let last = self.reported_at.load(Ordering::Relaxed);
+        if now < last + PROGRESS_INTERVAL.as_millis() as u64 {
+            return;
+        }
+        // whichever worker gets here first reports for all of them
+        if self
+            .reported_at
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            let _ = sender.send(MiningProgress::new(total, elapsed));
+        }
+    }
+
+    pub fn progress(&self) -> MiningProgress {
+        MiningProgress::new(self.attempts.load(Ordering::Relaxed), self.started.elapsed())
+    }
+
+    // the last report, sent however soon after the one before, so even runs shorter
+    // than PROGRESS_INTERVAL report once
+    pub fn finish(&self) -> MiningProgress {
+        let progress = self.progress();
+        if let Some(sender) = self.sender {
+            let _ = sender.send(progress);
+        }
+        progress
+    }
+}
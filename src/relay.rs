@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use log::{error, info};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::api::NodeApi;
+use crate::error::ChainError;
+use crate::hashing::HashAlgorithm;
+use crate::target::Target;
+use crate::units::BlockHeight;
+use crate::{BlockHeader, GENESIS_PREVIOUS_HASH};
+
+// source chain headers are relayed once this many blocks are built on top of them
+pub const RELAY_CONFIRMATIONS: u64 = 6;
+// most relayed headers a block may carry
+pub const MAX_RELAYED_HEADERS: usize = 16;
+const RELAY_INTERVAL_SECS: u64 = 10;
+
+// Last header of another chain relayed onto this one. Together the relayed headers
+// form a header chain of the source, starting at its genesis block, so contracts
+// and users on this chain can check that a source block exists and how deep it is
+// without trusting whoever relayed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RelayTip {
+    pub height: BlockHeight,
+    pub hash: String,
+}
+
+// A chain whose headers this one accepts, as configured in ChainParams::relay_sources.
+// Its headers are held to its own genesis, hash algorithm and a floor on their
+// difficulty, so made up headers of another spec can't claim to be its chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelaySource {
+    pub genesis_hash: String,
+    // in leading zero bits, whatever the headers store their difficulty as
+    pub min_difficulty: u32,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+// Checks `header` as the next relayed header of its chain, whose last relayed header
+// is `tip`. Its chain has to be one of `sources`, starting at that source's genesis,
+// and the header has to meet the source's minimum difficulty with its proof of work.
+// Whether its difficulty was the exact one the source's params called for can't be
+// told without the whole source chain.
+pub fn check_relayed(
+    sources: &BTreeMap<String, RelaySource>,
+    tip: Option<&RelayTip>,
+    header: &BlockHeader,
+) -> Result<(), ChainError> {
+    let source = sources
+        .get(&header.spec)
+        .ok_or_else(|| ChainError::UnknownRelaySource(header.hash.clone()))?;
+    if header.target() > Target::from_zero_bits(source.min_difficulty) {
+        return Err(ChainError::InsufficientWork);
+    }
+    let extends = match tip {
+        Some(tip) => tip.height.next() == Some(header.id) && tip.hash == header.previous_hash,
+        None => {
+            header.id == BlockHeight::GENESIS
+                && header.previous_hash == GENESIS_PREVIOUS_HASH
+                && header.hash == source.genesis_hash
+        }
+    };
+    if !extends {
+        return Err(ChainError::InvalidRelay(header.hash.clone()));
+    }
+    header.check_proof_of_work(source.hash_algorithm.hasher())
+}
+
+// Relays the final headers of the chain `source` follows to `target`, every few
+// seconds until either node goes away. `target` mines them into its next blocks.
+pub async fn run<A: NodeApi, B: NodeApi>(source: A, target: B) {
+    loop {
+        sleep(Duration::from_secs(RELAY_INTERVAL_SECS)).await;
+        match relay_once(&source, &target).await {
+            Ok(0) => {}
+            Ok(relayed) => info!("relayed {} headers", relayed),
+            Err(e) => {
+                error!("header relay stopped: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn relay_once<A: NodeApi, B: NodeApi>(source: &A, target: &B) -> Result<usize, String> {
+    let chain = source.chain().await?;
+    let spec = match chain.first() {
+        Some(genesis) => genesis.header.spec.clone(),
+        None => return Ok(0),
+    };
+    let next = match target.relay_tip(spec).await? {
+        Some(tip) => tip.height.next().ok_or("relayed chain is full")?.index(),
+        None => 0,
+    };
+    let last_final = chain
+        .len()
+        .saturating_sub(usize::try_from(RELAY_CONFIRMATIONS).unwrap_or(usize::MAX));
+    let headers: Vec<BlockHeader> = chain
+        .iter()
+        .take(last_final)
+        .skip(next)
+        .take(MAX_RELAYED_HEADERS)
+        .map(|block| block.header.clone())
+        .collect();
+    if headers.is_empty() {
+        return Ok(0);
+    }
+    target.relay_headers(headers).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dev_params;
+    use crate::units::Timestamp;
+    use crate::{preimage, App, BlockBody};
+
+    // a source chain of a few blocks and the relay source that admits it
+    fn source() -> (Vec<BlockHeader>, BTreeMap<String, RelaySource>) {
+        let mut app: App = App::new(dev_params());
+        app.mine_n_blocks(2, |height| BlockBody::new(format!("source {}", height), vec![]))
+            .unwrap();
+        let headers: Vec<BlockHeader> = app.blocks.iter().map(|block| block.header.clone()).collect();
+        let source = RelaySource {
+            genesis_hash: headers[0].hash.clone(),
+            min_difficulty: 1,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        (headers.clone(), BTreeMap::from([(headers[0].spec.clone(), source)]))
+    }
+
+    fn tip(header: &BlockHeader) -> RelayTip {
+        RelayTip {
+            height: header.id,
+            hash: header.hash.clone(),
+        }
+    }
+
+    #[test]
+    fn relays_the_source_chain_from_its_genesis() {
+        let (headers, sources) = source();
+        assert_eq!(check_relayed(&sources, None, &headers[0]), Ok(()));
+        assert_eq!(check_relayed(&sources, Some(&tip(&headers[0])), &headers[1]), Ok(()));
+        // only in order
+        assert!(check_relayed(&sources, None, &headers[1]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_forged_genesis() {
+        let (headers, sources) = source();
+        let mut forged = headers[0].clone();
+        forged.timestamp = Timestamp(1);
+        forged.mine(HashAlgorithm::Sha256.hasher(), &mut rand::thread_rng());
+        assert_eq!(
+            check_relayed(&sources, None, &forged),
+            Err(ChainError::InvalidRelay(forged.hash.clone()))
+        );
+    }
+
+    #[test]
+    fn rejects_headers_below_the_minimum_difficulty() {
+        let (headers, sources) = source();
+        // any hash meets zero leading zero bits
+        let mut easy = headers[1].clone();
+        easy.version = preimage::JSON_PREIMAGE;
+        easy.difficulty = 0;
+        easy.hash = hex::encode(easy.calculate_hash(HashAlgorithm::Sha256.hasher()));
+        assert_eq!(
+            check_relayed(&sources, Some(&tip(&headers[0])), &easy),
+            Err(ChainError::InsufficientWork)
+        );
+        // nor can a genesis at difficulty 0 take the source's place
+        let mut genesis = headers[0].clone();
+        genesis.difficulty = 0;
+        genesis.hash = hex::encode(genesis.calculate_hash(HashAlgorithm::Sha256.hasher()));
+        assert_eq!(
+            check_relayed(&sources, None, &genesis),
+            Err(ChainError::InsufficientWork)
+        );
+    }
+
+    #[test]
+    fn rejects_chains_that_are_not_a_source() {
+        let (headers, _) = source();
+        assert_eq!(
+            check_relayed(&BTreeMap::new(), None, &headers[0]),
+            Err(ChainError::UnknownRelaySource(headers[0].hash.clone()))
+        );
+    }
+}
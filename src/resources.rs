@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+// The node's own footprint, so a small machine running out of memory, disk or
+// connections shows in `stats` before it fails. See p2p::resource_usage.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceUsage {
+    // bytes of each file the node writes, by what it holds
+    pub storage: BTreeMap<&'static str, u64>,
+    // bytes of the chain as held in memory, see App::encoded_size
+    pub chain_bytes: usize,
+    // resident set size, where /proc has it
+    pub rss_bytes: Option<u64>,
+    pub peers: usize,
+    pub connections: u32,
+    pub pending_connections: u32,
+    // tasks the node has spawned that are still running
+    pub tasks: usize,
+    // items waiting in each of the node's queues
+    pub queues: BTreeMap<&'static str, usize>,
+}
+
+// Counts running tasks, each holding a guard from `track` for as long as it runs.
+#[derive(Debug, Clone, Default)]
+pub struct TaskGauge(Arc<AtomicUsize>);
+
+pub struct TaskGuard(Arc<AtomicUsize>);
+
+impl TaskGauge {
+    pub fn track(&self) -> TaskGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        TaskGuard(self.0.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 0 for files that don't exist (yet)
+pub fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+// from the VmRSS line of /proc/self/status, which is in kB
+pub fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::error::ChainError;
+use crate::params::ChainParams;
+
+// Application specific rules on what blocks carry, for chains embedded in another
+// program. Blocks whose payload breaks them are refused like blocks breaking any
+// other rule. Policy validators are this node's own choice; consensus validators
+// are registered under a name, and only enforced when the chain spec names them
+// in ChainParams::payload_rules, so every node of the network checks the same.
+pub trait PayloadValidator<T>: Send + Sync {
+    fn validate(&self, data: &T) -> Result<(), String>;
+}
+
+impl<T, F> PayloadValidator<T> for F
+where
+    F: Fn(&T) -> Result<(), String> + Send + Sync,
+{
+    fn validate(&self, data: &T) -> Result<(), String> {
+        self(data)
+    }
+}
+
+pub struct PayloadRules<T> {
+    policy: Vec<Arc<dyn PayloadValidator<T>>>,
+    // by the name a chain spec refers to them with
+    consensus: BTreeMap<String, Arc<dyn PayloadValidator<T>>>,
+}
+
+impl<T> Default for PayloadRules<T> {
+    fn default() -> Self {
+        Self {
+            policy: vec![],
+            consensus: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Clone for PayloadRules<T> {
+    fn clone(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            consensus: self.consensus.clone(),
+        }
+    }
+}
+
+impl<T> PayloadRules<T> {
+    pub fn add_policy(&mut self, validator: impl PayloadValidator<T> + 'static) {
+        self.policy.push(Arc::new(validator));
+    }
+
+    // one program can serve chains with different rules, each chain's spec picks
+    pub fn register(&mut self, name: &str, validator: impl PayloadValidator<T> + 'static) {
+        self.consensus.insert(name.to_string(), Arc::new(validator));
+    }
+
+    // the consensus rules `params` name are registered here
+    pub fn check_spec(&self, params: &ChainParams) -> Result<(), ChainError> {
+        match &params.payload_rules {
+            Some(name) if !self.consensus.contains_key(name) => Err(ChainError::UnknownPayloadRules(name.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    // the consensus rules of `params` first, then local policy
+    pub fn check(&self, params: &ChainParams, data: &T) -> Result<(), ChainError> {
+        self.check_spec(params)?;
+        let consensus = params.payload_rules.as_ref().and_then(|name| self.consensus.get(name));
+        for validator in consensus.into_iter().chain(&self.policy) {
+            validator.validate(data).map_err(ChainError::InvalidPayload)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::BTreeMap;
+
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use serde::Serialize;
+
+use crate::checkpoint::SignedCheckpoint;
+use crate::direct::{DirectAck, DirectMessage, DIRECT_PROTOCOL};
+use crate::p2p::{ChainRequest, ChainResponse, BLOCK_TOPIC, CHAIN_SYNC_PROTOCOL, CHECKPOINT_TOPIC, TRANSACTION_TOPIC};
+use crate::transaction::Transaction;
+use crate::Block;
+
+// JSON Schema of every message on the wire, generated from the structs themselves
+// so other implementations and the p2p-tester scenarios can't drift from them.
+#[derive(Debug, Serialize)]
+pub struct ProtocolSchema {
+    // floodsub topic -> message published on it. Topics are named "{spec}/<name>",
+    // after the spec hash of the chain, see p2p::Topics.
+    pub topics: BTreeMap<String, RootSchema>,
+    pub chain_sync_protocol: String,
+    pub chain_request: RootSchema,
+    pub chain_response: RootSchema,
+    pub direct_protocol: String,
+    pub direct_message: RootSchema,
+    pub direct_ack: RootSchema,
+}
+
+pub fn protocol_schema() -> ProtocolSchema {
+    let mut topics = BTreeMap::new();
+    let topic = |name: &str| format!("{{spec}}/{}", name);
+    topics.insert(topic(BLOCK_TOPIC), schema_for!(Block));
+    topics.insert(topic(CHECKPOINT_TOPIC), schema_for!(SignedCheckpoint));
+    topics.insert(topic(TRANSACTION_TOPIC), schema_for!(Transaction));
+    ProtocolSchema {
+        topics,
+        chain_sync_protocol: CHAIN_SYNC_PROTOCOL.to_string(),
+        chain_request: schema_for!(ChainRequest),
+        chain_response: schema_for!(ChainResponse),
+        direct_protocol: DIRECT_PROTOCOL.to_string(),
+        direct_message: schema_for!(DirectMessage),
+        direct_ack: schema_for!(DirectAck),
+    }
+}
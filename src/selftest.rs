@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use crate::encoding::{self, Encoding};
+use crate::hashing::HashAlgorithm;
+use crate::keys;
+use crate::params::ChainParams;
+use crate::store::ChainFormat;
+use crate::transaction::Transaction;
+use crate::units::Amount;
+use crate::{App, Block, BlockBody};
+
+// hash of the empty input under each algorithm
+const HASH_VECTORS: [(HashAlgorithm, &str); 3] = [
+    (
+        HashAlgorithm::Sha256,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ),
+    (
+        HashAlgorithm::Blake3,
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+    ),
+    (
+        HashAlgorithm::Keccak256,
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+    ),
+];
+// RFC 8032 test 1: the secret key and its signature of the empty message
+const SIGNING_SECRET: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+const SIGNING_VECTOR: &str = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+// genesis hash of the default params, which covers their JSON, the Merkle leaves
+// and the header encoding. Changes whenever a default param does.
+const GENESIS_VECTOR: &str = "0000c8a63af7010704cb00f8966ab3b09f2bf0c3dc660a2c3f4c062a791dd612";
+// low enough to mine the test chain in a moment
+const TEST_DIFFICULTY: u32 = 4;
+const TEST_BLOCKS: u64 = 3;
+
+// Outcome of one subsystem's checks.
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+// Exercises what a node relies on before it joins a network, so a broken build or
+// platform shows up as a failed check rather than as a node nobody agrees with.
+pub fn run() -> Vec<Check> {
+    vec![
+        Check {
+            name: "hashing",
+            result: check_hashing(),
+        },
+        Check {
+            name: "signing",
+            result: check_signing(),
+        },
+        Check {
+            name: "encoding",
+            result: check_encoding(),
+        },
+        Check {
+            name: "mining",
+            result: mine_test_chain().map(|_| ()),
+        },
+        Check {
+            name: "storage",
+            result: check_storage(),
+        },
+    ]
+}
+
+fn check_hashing() -> Result<(), String> {
+    for (algorithm, expected) in HASH_VECTORS {
+        let hash = hex::encode(algorithm.hasher().hash(&[]));
+        if hash != expected {
+            return Err(format!("{:?} hashes to {}, expected {}", algorithm, hash, expected));
+        }
+    }
+    Ok(())
+}
+
+fn check_signing() -> Result<(), String> {
+    let keys = keys::decode_keypair(SIGNING_SECRET)?;
+    let signature = keys.sign(&[]).map_err(|e| format!("could not sign: {}", e))?;
+    if hex::encode(&signature) != SIGNING_VECTOR {
+        return Err("signature differs from the RFC 8032 vector".to_string());
+    }
+    if !keys.public().verify(&[], &signature) || keys.public().verify(b"tampered", &signature) {
+        return Err("signature verification is broken".to_string());
+    }
+    let transaction = Transaction::new(
+        &keys,
+        "self-test".to_string(),
+        "self-test".to_string(),
+        Amount(1),
+        Amount(0),
+    )?;
+    transaction.verify()
+}
+
+fn check_encoding() -> Result<(), String> {
+    let genesis = Block::<BlockBody>::genesis(&ChainParams::default())?;
+    if genesis.header.hash != GENESIS_VECTOR {
+        return Err(format!(
+            "default genesis hashes to {}, expected {}",
+            genesis.header.hash, GENESIS_VECTOR
+        ));
+    }
+    let encodings = [
+        Encoding::Json,
+        #[cfg(feature = "bincode")]
+        Encoding::Bincode,
+    ];
+    for encoding in encodings {
+        let decoded: Block = encoding::decode(&encoding.encode(&genesis)?)?;
+        if decoded.header != genesis.header || decoded.data != genesis.data {
+            return Err(format!("{:?} does not round-trip a block", encoding));
+        }
+    }
+    Ok(())
+}
+
+fn mine_test_chain() -> Result<App, String> {
+    let params = ChainParams {
+        initial_difficulty: TEST_DIFFICULTY,
+        retarget_interval: 0,
+        ..ChainParams::default()
+    };
+    let mut app = App::new(params);
+    app.mine_n_blocks(TEST_BLOCKS, |height| {
+        BlockBody::new(format!("self-test block {}", height), vec![])
+    })?;
+    app.validate_chain(&app.blocks).map_err(|e| e.to_string())?;
+    Ok(app)
+}
+
+// a chain written in either format reads back block for block
+fn check_storage() -> Result<(), String> {
+    let app = mine_test_chain()?;
+    let formats = [
+        ChainFormat::Pretty,
+        ChainFormat::Compact,
+        #[cfg(feature = "mmap")]
+        ChainFormat::Archive,
+    ];
+    for format in formats {
+        let path = temp_path();
+        let result = app.export_to_file(&path, format).and_then(|_| {
+            let mut imported: App = App::new(app.params.clone());
+            imported.import_from_file(&path)?;
+            if imported.get_last_block().header != app.get_last_block().header {
+                return Err(format!("{:?} chain file does not round-trip", format));
+            }
+            Ok(())
+        });
+        let _ = std::fs::remove_file(&path);
+        result?;
+    }
+    Ok(())
+}
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("self-test-{}.json", std::process::id()))
+}
@@ -0,0 +1,150 @@
+use std::str::FromStr;
+
+use libp2p::identity::{ed25519, secp256k1, Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+// How account keys are read, written and turned into addresses. Public keys of
+// different schemes have different lengths, so a key decodes under exactly one of
+// them, see keys::decode_public_key.
+pub trait SignatureScheme: Sync {
+    fn generate(&self) -> Keypair;
+    fn decode_public_key(&self, bytes: &[u8]) -> Result<PublicKey, String>;
+    fn decode_keypair(&self, secret: Vec<u8>) -> Result<Keypair, String>;
+    // None for keys of another scheme, as are the others
+    fn encode_public_key(&self, key: &PublicKey) -> Option<Vec<u8>>;
+    fn encode_secret_key(&self, keys: &Keypair) -> Option<Vec<u8>>;
+    fn address(&self, key: &PublicKey) -> Option<String>;
+}
+
+pub struct Ed25519Scheme;
+
+impl SignatureScheme for Ed25519Scheme {
+    fn generate(&self) -> Keypair {
+        Keypair::generate_ed25519()
+    }
+
+    fn decode_public_key(&self, bytes: &[u8]) -> Result<PublicKey, String> {
+        let key = ed25519::PublicKey::decode(bytes).map_err(|e| format!("invalid ed25519 public key: {}", e))?;
+        Ok(PublicKey::Ed25519(key))
+    }
+
+    fn decode_keypair(&self, secret: Vec<u8>) -> Result<Keypair, String> {
+        let secret =
+            ed25519::SecretKey::from_bytes(secret).map_err(|e| format!("invalid ed25519 secret key: {}", e))?;
+        Ok(Keypair::Ed25519(secret.into()))
+    }
+
+    fn encode_public_key(&self, key: &PublicKey) -> Option<Vec<u8>> {
+        match key {
+            PublicKey::Ed25519(key) => Some(key.encode().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn encode_secret_key(&self, keys: &Keypair) -> Option<Vec<u8>> {
+        match keys {
+            Keypair::Ed25519(keys) => Some(keys.secret().as_ref().to_vec()),
+            _ => None,
+        }
+    }
+
+    // the first 20 bytes of the key's sha256
+    fn address(&self, key: &PublicKey) -> Option<String> {
+        let digest = Sha256::digest(&self.encode_public_key(key)?);
+        Some(hex::encode(&digest[..20]))
+    }
+}
+
+// ECDSA over secp256k1 with compressed public keys, the key material of Bitcoin
+// and Ethereum.
+pub struct Secp256k1Scheme;
+
+impl SignatureScheme for Secp256k1Scheme {
+    fn generate(&self) -> Keypair {
+        Keypair::generate_secp256k1()
+    }
+
+    fn decode_public_key(&self, bytes: &[u8]) -> Result<PublicKey, String> {
+        let key = secp256k1::PublicKey::decode(bytes).map_err(|e| format!("invalid secp256k1 public key: {}", e))?;
+        Ok(PublicKey::Secp256k1(key))
+    }
+
+    fn decode_keypair(&self, secret: Vec<u8>) -> Result<Keypair, String> {
+        let secret =
+            secp256k1::SecretKey::from_bytes(secret).map_err(|e| format!("invalid secp256k1 secret key: {}", e))?;
+        Ok(Keypair::Secp256k1(secret.into()))
+    }
+
+    fn encode_public_key(&self, key: &PublicKey) -> Option<Vec<u8>> {
+        match key {
+            PublicKey::Secp256k1(key) => Some(key.encode().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn encode_secret_key(&self, keys: &Keypair) -> Option<Vec<u8>> {
+        match keys {
+            Keypair::Secp256k1(keys) => Some(keys.secret().to_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    // Ethereum's: the last 20 bytes of the keccak256 of the uncompressed key
+    // without its 0x04 prefix
+    fn address(&self, key: &PublicKey) -> Option<String> {
+        match key {
+            PublicKey::Secp256k1(key) => {
+                let digest = Keccak256::digest(&key.encode_uncompressed()[1..]);
+                Some(hex::encode(&digest[12..]))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Which SignatureScheme a chain's transactions are signed with, part of its
+// genesis config. Ed25519 unless set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+impl SignatureAlgorithm {
+    pub const ALL: [SignatureAlgorithm; 2] = [Self::Ed25519, Self::Secp256k1];
+
+    pub fn scheme(self) -> &'static dyn SignatureScheme {
+        match self {
+            Self::Ed25519 => &Ed25519Scheme,
+            Self::Secp256k1 => &Secp256k1Scheme,
+        }
+    }
+
+    // the scheme `key` belongs to, if it is one of ours
+    pub fn of(key: &PublicKey) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.scheme().encode_public_key(key).is_some())
+    }
+
+    // left out of the genesis config, so ed25519 chains keep their spec
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl FromStr for SignatureAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ed25519" => Ok(Self::Ed25519),
+            "secp256k1" => Ok(Self::Secp256k1),
+            _ => Err(format!("unknown signature scheme {}", s)),
+        }
+    }
+}
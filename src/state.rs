@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::error::ChainError;
+use crate::names::{self, NameRecord};
+use crate::params::ChainParams;
+use crate::relay::{self, RelayTip};
+use crate::transaction::Transaction;
+use crate::units::{Amount, BlockHeight};
+#[cfg(feature = "vm")]
+use crate::vm::ContractState;
+use crate::{Block, BlockHeader, Payload};
+
+// Balance of every address, derived by replaying a chain's blocks on top of the
+// genesis allocations. Addresses never seen have a zero balance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    balances: HashMap<String, Amount>,
+    // last relayed header of each other chain, by its spec
+    relays: HashMap<String, RelayTip>,
+    // txids of every transaction on the chain, which can't be included again
+    txids: HashSet<String>,
+    // every name ever claimed, expired ones included
+    names: HashMap<String, NameRecord>,
+    #[cfg(feature = "vm")]
+    contracts: ContractState,
+}
+
+impl State {
+    pub fn new(allocations: &BTreeMap<String, Amount>) -> Self {
+        Self {
+            balances: allocations
+                .iter()
+                .map(|(address, amount)| (address.clone(), *amount))
+                .collect(),
+            relays: HashMap::new(),
+            txids: HashSet::new(),
+            names: HashMap::new(),
+            #[cfg(feature = "vm")]
+            contracts: ContractState::default(),
+        }
+    }
+
+    pub fn replay<'a, T: Payload + 'a>(
+        params: &ChainParams,
+        chain: impl IntoIterator<Item = &'a Block<T>>,
+    ) -> Result<Self, ChainError> {
+        let mut state = Self::new(&params.genesis.allocations);
+        for block in chain {
+            state.apply_block(params, block)?;
+        }
+        Ok(state)
+    }
+
+    pub fn balance_of(&self, address: &str) -> Amount {
+        self.balances.get(address).copied().unwrap_or(Amount::ZERO)
+    }
+
+    #[cfg(feature = "vm")]
+    pub fn contracts(&self) -> &ContractState {
+        &self.contracts
+    }
+
+    pub fn relay_tip(&self, spec: &str) -> Option<&RelayTip> {
+        self.relays.get(spec)
+    }
+
+    pub fn is_confirmed(&self, txid: &str) -> bool {
+        self.txids.contains(txid)
+    }
+
+    // the holder of `name` at `height`, if its lease hasn't run out
+    pub fn resolve(&self, name: &str, height: BlockHeight) -> Option<&NameRecord> {
+        self.names.get(name).filter(|record| record.is_held_at(height))
+    }
+
+    // names `address` holds at `height`, in no particular order
+    pub fn names_of<'a>(&'a self, address: &'a str, height: BlockHeight) -> impl Iterator<Item = &'a str> + 'a {
+        self.names
+            .iter()
+            .filter(move |(_, record)| record.owner == address && record.is_held_at(height))
+            .map(|(name, _)| name.as_str())
+    }
+
+    // Applies the block's transactions in order, then pays its coinbase. Changes
+    // nothing if a transaction overspends or replays one already on the chain, a
+    // relayed header doesn't extend its chain or the coinbase claims too much. Fees
+    // no coinbase claims are burned. Balances and fees that would overflow make the
+    // block invalid rather than being capped.
+    pub fn apply_block<T: Payload>(&mut self, params: &ChainParams, block: &Block<T>) -> Result<(), ChainError> {
+        let relays = self.relay_headers(params, &block.header.spec, block.data.relayed_headers())?;
+        let mut touched = HashMap::new();
+        let mut txids = HashSet::new();
+        let mut claims = HashMap::new();
+        let mut fees = Amount::ZERO;
+        for transaction in block.data.transactions() {
+            check_signature_scheme(params, transaction)?;
+            check_chain(&block.header.spec, transaction)?;
+            let txid = transaction.id();
+            if self.txids.contains(&txid) || !txids.insert(txid.clone()) {
+                return Err(ChainError::DuplicateTransaction(txid));
+            }
+            self.transfer(&mut touched, transaction)?;
+            claims.extend(self.claim(&claims, transaction, block.header.id)?);
+            fees = fees.checked_add(transaction.fee).ok_or(ChainError::FeeOverflow)?;
+        }
+        if let Some(coinbase) = block.data.coinbase() {
+            let max = params
+                .block_reward(block.header.id)
+                .checked_add(fees)
+                .ok_or(ChainError::FeeOverflow)?;
+            if coinbase.amount > max {
+                return Err(ChainError::InvalidCoinbase {
+                    amount: coinbase.amount,
+                    max,
+                });
+            }
+            self.credit(&mut touched, &coinbase.recipient, coinbase.amount)?;
+        }
+        // failed ops are rolled back by the VM, they don't make the block invalid. What
+        // each op did is kept, see ContractState::receipts.
+        #[cfg(feature = "vm")]
+        if !block.data.contract_ops().is_empty() {
+            self.contracts.execute(block.header.id, block.data.contract_ops());
+        }
+        self.balances.extend(touched);
+        self.relays.extend(relays);
+        self.txids.extend(txids);
+        self.names.extend(claims);
+        Ok(())
+    }
+
+    // the relay tips after `headers`, which a block of the chain with `spec` carries
+    fn relay_headers(
+        &self,
+        params: &ChainParams,
+        spec: &str,
+        headers: &[BlockHeader],
+    ) -> Result<HashMap<String, RelayTip>, ChainError> {
+        let mut relays: HashMap<String, RelayTip> = HashMap::new();
+        for header in headers {
+            if header.spec == spec {
+                return Err(ChainError::InvalidRelay(header.hash.clone()));
+            }
+            let tip = relays.get(&header.spec).or_else(|| self.relay_tip(&header.spec));
+            relay::check_relayed(&params.relay_sources, tip, header)?;
+            relays.insert(
+                header.spec.clone(),
+                RelayTip {
+                    height: header.id,
+                    hash: header.hash.clone(),
+                },
+            );
+        }
+        Ok(relays)
+    }
+
+    // those of `headers` that a block of the chain with `params` can carry in order,
+    // headers that don't extend their chain are dropped
+    pub fn relayable(&self, params: &ChainParams, headers: &[BlockHeader]) -> Vec<BlockHeader> {
+        let spec = params.spec_hash();
+        let mut relayable = vec![];
+        for header in headers {
+            relayable.push(header.clone());
+            if self.relay_headers(params, &spec, &relayable).is_err() {
+                relayable.pop();
+            }
+        }
+        relayable
+    }
+
+    // those of `transactions` that apply cleanly in order in a block at `height`.
+    // Overspends, transactions already on the chain and names that can't be
+    // claimed are dropped.
+    pub fn spendable(&self, height: BlockHeight, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        let mut touched = HashMap::new();
+        let mut txids = HashSet::new();
+        let mut claims = HashMap::new();
+        transactions
+            .into_iter()
+            .filter(|transaction| {
+                let txid = transaction.id();
+                if self.txids.contains(&txid) || !txids.insert(txid) {
+                    return false;
+                }
+                let Ok(claim) = self.claim(&claims, transaction, height) else {
+                    return false;
+                };
+                if self.transfer(&mut touched, transaction).is_err() {
+                    return false;
+                }
+                claims.extend(claim);
+                true
+            })
+            .collect()
+    }
+
+    // the name `transaction` claims and its record after the claim, names claimed
+    // earlier in the block being in `claims`
+    fn claim(
+        &self,
+        claims: &HashMap<String, NameRecord>,
+        transaction: &Transaction,
+        height: BlockHeight,
+    ) -> Result<Option<(String, NameRecord)>, ChainError> {
+        let Some(name) = &transaction.name else {
+            return Ok(None);
+        };
+        let current = claims.get(name).or_else(|| self.names.get(name));
+        let record = names::claim(current, name, &transaction.recipient, transaction.fee, height)?;
+        Ok(Some((name.clone(), record)))
+    }
+
+    // balances changed so far are in `touched`, which is left as is on error
+    fn transfer(&self, touched: &mut HashMap<String, Amount>, transaction: &Transaction) -> Result<(), ChainError> {
+        let sender = transaction
+            .sender()
+            .map_err(|_| ChainError::InvalidTransaction(transaction.id()))?;
+        let balance = touched
+            .get(&sender)
+            .copied()
+            .unwrap_or_else(|| self.balance_of(&sender));
+        let rest = transaction
+            .amount
+            .checked_add(transaction.fee)
+            .and_then(|cost| balance.checked_sub(cost))
+            .ok_or_else(|| ChainError::Overspend(transaction.id()))?;
+        touched.insert(sender.clone(), rest);
+        if let Err(e) = self.credit(touched, &transaction.recipient, transaction.amount) {
+            touched.insert(sender, balance);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn credit(&self, touched: &mut HashMap<String, Amount>, address: &str, amount: Amount) -> Result<(), ChainError> {
+        let balance = touched
+            .get(address)
+            .copied()
+            .unwrap_or_else(|| self.balance_of(address));
+        let balance = balance
+            .checked_add(amount)
+            .ok_or_else(|| ChainError::BalanceOverflow(address.to_string()))?;
+        touched.insert(address.to_string(), balance);
+        Ok(())
+    }
+}
+
+// transactions are signed under the chain's scheme, see GenesisConfig::signature_scheme
+pub fn check_signature_scheme(params: &ChainParams, transaction: &Transaction) -> Result<(), ChainError> {
+    match transaction.signature_scheme() {
+        Ok(scheme) if scheme == params.genesis.signature_scheme => Ok(()),
+        _ => Err(ChainError::WrongSignatureScheme(transaction.id())),
+    }
+}
+
+// signed for the chain of spec `spec`, and not just replayed from another one
+pub fn check_chain(spec: &str, transaction: &Transaction) -> Result<(), ChainError> {
+    if transaction.chain != spec {
+        return Err(ChainError::WrongChain(transaction.id()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::keys;
+    use crate::transaction::Coinbase;
+    use crate::units::Timestamp;
+    use crate::BlockBody;
+
+    struct Account {
+        keys: Keypair,
+        address: String,
+    }
+
+    fn account() -> Account {
+        let keys = Keypair::generate_ed25519();
+        let address = keys::address(&keys.public()).unwrap();
+        Account { keys, address }
+    }
+
+    fn params(allocations: &[(&Account, u64)]) -> ChainParams {
+        let mut params = ChainParams::default();
+        params.genesis.allocations = allocations
+            .iter()
+            .map(|(account, amount)| (account.address.clone(), Amount(*amount)))
+            .collect();
+        params
+    }
+
+    fn block(params: &ChainParams, height: u64, body: BlockBody) -> Block {
+        let genesis = Block::<BlockBody>::genesis(params).unwrap();
+        let mut block = Block::unmined(&genesis.header, body, None, 0, Timestamp(0));
+        block.header.id = BlockHeight(height);
+        block
+    }
+
+    fn transfer(params: &ChainParams, from: &Account, to: &Account, amount: u64) -> Transaction {
+        Transaction::new(
+            &from.keys,
+            params.spec_hash(),
+            to.address.clone(),
+            Amount(amount),
+            Amount::ZERO,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn transfers_move_balances() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let body = BlockBody::new(String::new(), vec![transfer(&params, &alice, &bob, 4)]);
+        state.apply_block(&params, &block(&params, 1, body)).unwrap();
+        assert_eq!(state.balance_of(&alice.address), Amount(6));
+        assert_eq!(state.balance_of(&bob.address), Amount(4));
+    }
+
+    #[test]
+    fn confirmed_transfer_cannot_be_replayed() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let payment = transfer(&params, &alice, &bob, 4);
+        let body = BlockBody::new(String::new(), vec![payment.clone()]);
+        state.apply_block(&params, &block(&params, 1, body)).unwrap();
+        assert!(state.is_confirmed(&payment.id()));
+
+        let confirmed = state.clone();
+        let body = BlockBody::new(String::new(), vec![payment.clone()]);
+        let result = state.apply_block(&params, &block(&params, 2, body));
+        assert_eq!(result, Err(ChainError::DuplicateTransaction(payment.id())));
+        assert_eq!(state, confirmed);
+    }
+
+    #[test]
+    fn transfer_signed_for_another_chain_is_rejected() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10)]);
+        let testnet = ChainParams {
+            block_reward: Amount(1),
+            ..params.clone()
+        };
+        let mut state = State::new(&params.genesis.allocations);
+        let replayed = transfer(&testnet, &alice, &bob, 4);
+        let body = BlockBody::new(String::new(), vec![replayed.clone()]);
+        let result = state.apply_block(&params, &block(&params, 1, body));
+        assert_eq!(result, Err(ChainError::WrongChain(replayed.id())));
+        assert_eq!(state.balance_of(&alice.address), Amount(10));
+    }
+
+    #[test]
+    fn transaction_twice_in_a_block_is_rejected() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let payment = transfer(&params, &alice, &bob, 4);
+        let body = BlockBody::new(String::new(), vec![payment.clone(), payment.clone()]);
+        let result = state.apply_block(&params, &block(&params, 1, body));
+        assert_eq!(result, Err(ChainError::DuplicateTransaction(payment.id())));
+        assert!(!state.is_confirmed(&payment.id()));
+    }
+
+    #[test]
+    fn spendable_drops_confirmed_and_repeated_transactions() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let confirmed = transfer(&params, &alice, &bob, 1);
+        let body = BlockBody::new(String::new(), vec![confirmed.clone()]);
+        state.apply_block(&params, &block(&params, 1, body)).unwrap();
+        let fresh = transfer(&params, &alice, &bob, 2);
+        let spendable = state.spendable(BlockHeight(2), vec![confirmed, fresh.clone(), fresh.clone()]);
+        assert_eq!(spendable, vec![fresh]);
+    }
+
+    #[test]
+    fn overflowing_transfer_is_rejected_and_changes_nothing() {
+        let (alice, bob) = (account(), account());
+        let params = params(&[(&alice, 10), (&bob, u64::MAX)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let body = BlockBody::new(String::new(), vec![transfer(&params, &alice, &bob, 4)]);
+        let result = state.apply_block(&params, &block(&params, 1, body));
+        assert_eq!(result, Err(ChainError::BalanceOverflow(bob.address.clone())));
+        assert_eq!(state, State::new(&params.genesis.allocations));
+    }
+
+    #[test]
+    fn overflowing_coinbase_is_rejected() {
+        let miner = account();
+        let params = params(&[(&miner, u64::MAX)]);
+        let mut state = State::new(&params.genesis.allocations);
+        let mut body = BlockBody::new(String::new(), vec![]);
+        body.coinbase = Some(Coinbase {
+            recipient: miner.address.clone(),
+            amount: params.block_reward(BlockHeight(1)),
+        });
+        let result = state.apply_block(&params, &block(&params, 1, body));
+        assert_eq!(result, Err(ChainError::BalanceOverflow(miner.address.clone())));
+        assert_eq!(state.balance_of(&miner.address), Amount(u64::MAX));
+    }
+}
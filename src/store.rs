@@ -0,0 +1,250 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[cfg(feature = "mmap")]
+use crate::archive::{self, Archive};
+use crate::units::BlockHeight;
+use crate::{Block, BlockHeader, Payload};
+
+// How an exported chain is written. Pretty and Compact are plain JSON, Archive is
+// for archival nodes with long chains, see archive.rs. All read back the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainFormat {
+    // indented, for reading and diffing
+    Pretty,
+    // no whitespace at all, like the chain file
+    Compact,
+    #[cfg(feature = "mmap")]
+    Archive,
+}
+
+// The chain is kept as a single JSON file, written to a temporary file first so
+// a crash mid-write never leaves a truncated chain behind.
+pub fn load_chain<T: Payload>(path: &Path) -> Result<Vec<Block<T>>, String> {
+    #[cfg(feature = "mmap")]
+    if archive::is_archive(path) {
+        let archive = Archive::open(path)?;
+        return archive
+            .blocks()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("could not parse {}: {}", path.display(), e));
+    }
+    let json = fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("could not parse {}: {}", path.display(), e))
+}
+
+pub fn load_headers(path: &Path, heights: Range<BlockHeight>) -> Result<Vec<BlockHeader>, String> {
+    Storage::open(path)?.iter_headers(heights).collect()
+}
+
+// Header-only view of a saved chain, for work sums, audits and the like on large
+// chains. Headers are read one at a time and block bodies are skipped while
+// parsing instead of being decoded, so a scan never holds more than one block.
+pub struct Storage {
+    path: PathBuf,
+    #[cfg(feature = "mmap")]
+    archive: Option<Archive>,
+}
+
+impl Storage {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            #[cfg(feature = "mmap")]
+            archive: match archive::is_archive(path) {
+                true => Some(Archive::open(path)?),
+                false => None,
+            },
+        })
+    }
+
+    // headers in `heights`, lowest first. Chains are saved from genesis up, so
+    // reading stops at the end of the range.
+    pub fn iter_headers(
+        &self,
+        heights: Range<BlockHeight>,
+    ) -> Box<dyn Iterator<Item = Result<BlockHeader, String>> + '_> {
+        #[cfg(feature = "mmap")]
+        if let Some(archive) = &self.archive {
+            return Box::new(
+                archive
+                    .headers(heights)
+                    .map(|header| header.map_err(|e| format!("could not parse {}: {}", self.path.display(), e))),
+            );
+        }
+        match File::open(&self.path) {
+            Ok(file) => Box::new(JsonHeaders {
+                path: &self.path,
+                reader: BufReader::new(file),
+                heights,
+                opened: false,
+                read: 0,
+                done: false,
+            }),
+            Err(e) => Box::new(std::iter::once(Err(format!(
+                "could not read {}: {}",
+                self.path.display(),
+                e
+            )))),
+        }
+    }
+}
+
+// Walks the top level array of a JSON chain file, one block at a time.
+struct JsonHeaders<'a> {
+    path: &'a Path,
+    reader: BufReader<File>,
+    heights: Range<BlockHeight>,
+    // past the opening bracket
+    opened: bool,
+    read: usize,
+    done: bool,
+}
+
+impl JsonHeaders<'_> {
+    fn next_header(&mut self) -> Result<Option<BlockHeader>, String> {
+        if !self.opened {
+            self.expect(b'[')?;
+            self.opened = true;
+        }
+        if self.peek()? == Some(b']') {
+            return Ok(None);
+        }
+        if self.read > 0 {
+            self.expect(b',')?;
+        }
+        self.read += 1;
+        // blocks are objects, so the deserializer stops right after the closing
+        // brace and reads nothing of the next one
+        let header = BlockHeader::deserialize(&mut serde_json::Deserializer::from_reader(&mut self.reader))
+            .map_err(|e| e.to_string())?;
+        Ok(Some(header))
+    }
+
+    // the next byte that isn't whitespace, left unread
+    fn peek(&mut self) -> Result<Option<u8>, String> {
+        loop {
+            let buf = self.reader.fill_buf().map_err(|e: io::Error| e.to_string())?;
+            let Some(&byte) = buf.first() else {
+                return Ok(None);
+            };
+            if !byte.is_ascii_whitespace() {
+                return Ok(Some(byte));
+            }
+            self.reader.consume(1);
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        match self.peek()? {
+            Some(byte) if byte == expected => {
+                self.reader.consume(1);
+                Ok(())
+            }
+            _ => Err(format!("expected '{}' before block {}", expected as char, self.read)),
+        }
+    }
+}
+
+impl Iterator for JsonHeaders<'_> {
+    type Item = Result<BlockHeader, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.next_header() {
+                Ok(Some(header)) if header.id < self.heights.start => {}
+                Ok(Some(header)) if header.id < self.heights.end => return Some(Ok(header)),
+                Ok(_) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(format!("could not parse {}: {}", self.path.display(), e)));
+                }
+            }
+        }
+        None
+    }
+}
+
+pub fn save_chain(path: &Path, blocks: &[Block]) -> Result<(), String> {
+    export_chain(path, blocks, ChainFormat::Compact)
+}
+
+pub fn export_chain<T: Payload>(path: &Path, blocks: &[Block<T>], format: ChainFormat) -> Result<(), String> {
+    let json = match format {
+        ChainFormat::Pretty => serde_json::to_vec_pretty(blocks),
+        ChainFormat::Compact => serde_json::to_vec(blocks),
+        #[cfg(feature = "mmap")]
+        ChainFormat::Archive => return archive::write_archive(path, blocks),
+    }
+    .map_err(|e| format!("could not encode chain: {}", e))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, json).map_err(|e| format!("could not write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("could not replace {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ChainParams;
+    use crate::{App, BlockBody};
+
+    fn chain(blocks: u64) -> Vec<Block> {
+        let mut app: App = App::new(ChainParams {
+            initial_difficulty: 1,
+            retarget_interval: 0,
+            ..ChainParams::default()
+        });
+        app.mine_n_blocks(blocks, |height| BlockBody::new(format!("block {}", height), vec![]))
+            .unwrap();
+        app.blocks
+    }
+
+    fn heights(storage: &Storage, range: Range<u64>) -> Vec<u64> {
+        storage
+            .iter_headers(BlockHeight(range.start)..BlockHeight(range.end))
+            .map(|header| header.unwrap().id.0)
+            .collect()
+    }
+
+    #[test]
+    fn iter_headers_reads_a_range_of_each_format() {
+        let blocks = chain(6);
+        let formats = [
+            ChainFormat::Pretty,
+            ChainFormat::Compact,
+            #[cfg(feature = "mmap")]
+            ChainFormat::Archive,
+        ];
+        for format in formats {
+            let path = std::env::temp_dir().join(format!("iter-headers-{:?}-{}.json", format, std::process::id()));
+            export_chain(&path, &blocks, format).unwrap();
+            let storage = Storage::open(&path).unwrap();
+            assert_eq!(heights(&storage, 2..5), vec![2, 3, 4], "{:?}", format);
+            assert_eq!(heights(&storage, 0..100), (0..7).collect::<Vec<_>>(), "{:?}", format);
+            let first = storage
+                .iter_headers(BlockHeight(0)..BlockHeight(1))
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(first.hash, blocks[0].header.hash);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn iter_headers_reports_a_truncated_chain() {
+        let path = std::env::temp_dir().join(format!("iter-headers-truncated-{}.json", std::process::id()));
+        let json = serde_json::to_string(&chain(2)).unwrap();
+        fs::write(&path, &json[..json.len() / 2]).unwrap();
+        let headers: Vec<_> = Storage::open(&path)
+            .unwrap()
+            .iter_headers(BlockHeight(0)..BlockHeight(10))
+            .collect();
+        assert!(headers.last().unwrap().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Chain,
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightRequest {
+    pub id: u64,
+    pub kind: RequestKind,
+    pub peer: PeerId,
+    pub tried_peers: Vec<PeerId>,
+    pub attempts: u32,
+    pub started: Instant,
+    pub deadline: Instant,
+}
+
+// Outbound sync requests waiting for an answer. Requests that miss their deadline
+// are handed back by `expired` so the caller can redirect them to another peer.
+pub struct InFlightRequests {
+    next_id: u64,
+    timeout: Duration,
+    max_attempts: u32,
+    requests: HashMap<u64, InFlightRequest>,
+}
+
+impl Default for InFlightRequests {
+    fn default() -> Self {
+        Self::new(REQUEST_TIMEOUT, MAX_ATTEMPTS)
+    }
+}
+
+impl InFlightRequests {
+    pub fn new(timeout: Duration, max_attempts: u32) -> Self {
+        Self {
+            next_id: 1,
+            timeout,
+            max_attempts,
+            requests: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, kind: RequestKind, peer: PeerId) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = Instant::now();
+        self.requests.insert(
+            id,
+            InFlightRequest {
+                id,
+                kind,
+                peer,
+                tried_peers: vec![peer],
+                attempts: 1,
+                started: now,
+                deadline: now + self.timeout,
+            },
+        );
+        id
+    }
+
+    // Only `peer`, the one the request was sent to, can complete it. A response
+    // carrying the id from anyone else leaves it in flight.
+    pub fn complete(&mut self, id: u64, peer: &PeerId) -> Option<InFlightRequest> {
+        match self.requests.get(&id) {
+            Some(request) if request.peer == *peer => self.requests.remove(&id),
+            _ => None,
+        }
+    }
+
+    pub fn expired(&mut self, now: Instant) -> Vec<InFlightRequest> {
+        let ids: Vec<u64> = self
+            .requests
+            .values()
+            .filter(|request| request.deadline <= now)
+            .map(|request| request.id)
+            .collect();
+        ids.into_iter().filter_map(|id| self.requests.remove(&id)).collect()
+    }
+
+    pub fn can_retry(&self, request: &InFlightRequest) -> bool {
+        request.attempts < self.max_attempts
+    }
+
+    // puts an expired request back in flight, addressed to `peer`
+    pub fn retry(&mut self, mut request: InFlightRequest, peer: PeerId) -> u64 {
+        request.attempts += 1;
+        request.deadline = Instant::now() + self.timeout;
+        if !request.tried_peers.contains(&peer) {
+            request.tried_peers.push(peer);
+        }
+        request.peer = peer;
+        let id = request.id;
+        self.requests.insert(id, request);
+        id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InFlightRequest> {
+        self.requests.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_asked_peer_completes_a_request() {
+        let mut requests = InFlightRequests::default();
+        let (asked, other) = (PeerId::random(), PeerId::random());
+        let id = requests.start(RequestKind::Chain, asked);
+        assert!(requests.complete(id, &other).is_none());
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests.complete(id, &asked).map(|request| request.peer), Some(asked));
+        assert!(requests.complete(id, &asked).is_none());
+    }
+
+    #[test]
+    fn a_retried_request_is_completed_by_its_new_peer() {
+        let mut requests = InFlightRequests::new(Duration::ZERO, MAX_ATTEMPTS);
+        let (first, second) = (PeerId::random(), PeerId::random());
+        requests.start(RequestKind::Chain, first);
+        let expired = requests.expired(Instant::now()).pop().unwrap();
+        assert!(requests.can_retry(&expired));
+        let id = requests.retry(expired, second);
+        assert!(requests.complete(id, &first).is_none());
+        assert!(requests.complete(id, &second).is_some());
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+// The largest hash a block may have, as a 256 bit big endian number. Headers store
+// it in 32 bits as a compact target, Bitcoin's nBits: the top byte is the length
+// of the number in bytes, the other three its most significant bytes. Older
+// headers store a number of leading zero bits instead, see BlockHeader::target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target([u8; 32]);
+
+impl Target {
+    // no hash meets it
+    pub const ZERO: Target = Target([0; 32]);
+
+    // hashes starting with at least `bits` zero bits
+    pub fn from_zero_bits(bits: u32) -> Self {
+        let mut target = [0xff; 32];
+        let bits = bits.min(256) as usize;
+        target[..bits / 8].fill(0);
+        if let Some(partial) = target.get_mut(bits / 8) {
+            *partial = 0xff >> (bits % 8);
+        }
+        Target(target)
+    }
+
+    // none for negative or overflowing targets
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = (bits >> 24) as isize;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & 0x0080_0000 != 0 && mantissa != 0 {
+            return None;
+        }
+        let mut target = [0; 32];
+        // the mantissa's bytes are the most significant of a `size` byte number
+        for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            let position = 32 - size + i as isize;
+            if *byte == 0 || position >= 32 {
+                continue;
+            }
+            if position < 0 {
+                return None;
+            }
+            target[position as usize] = *byte;
+        }
+        Some(Target(target))
+    }
+
+    // rounds down to what three bytes of mantissa can hold
+    pub fn to_compact(&self) -> u32 {
+        let Some(first) = self.0.iter().position(|byte| *byte != 0) else {
+            return 0;
+        };
+        let mut size = (32 - first) as u32;
+        let byte = |i: usize| u32::from(self.0.get(i).copied().unwrap_or(0));
+        let mut mantissa = byte(first) << 16 | byte(first + 1) << 8 | byte(first + 2);
+        // the top mantissa bit is the sign
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        size << 24 | mantissa
+    }
+
+    pub fn is_met_by(&self, hash: &[u8]) -> bool {
+        hash.len() == self.0.len() && hash <= &self.0[..]
+    }
+
+    // Expected number of hashes to meet it, 2^256 / (target + 1). That doesn't fit
+    // in 256 bits, but it is !target / (target + 1) + 1, which does.
+    pub fn work(&self) -> u128 {
+        let target = self.limbs();
+        let mut divisor = target;
+        for limb in &mut divisor {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                break;
+            }
+        }
+        // target is all ones, only a hash of all ones is out
+        if divisor == [0; 4] {
+            return 1;
+        }
+        let quotient = divide(target.map(|limb| !limb), divisor);
+        if quotient[2] != 0 || quotient[3] != 0 {
+            return u128::MAX;
+        }
+        (u128::from(quotient[1]) << 64 | u128::from(quotient[0])).saturating_add(1)
+    }
+
+    // little endian 64 bit limbs
+    fn limbs(&self) -> [u64; 4] {
+        let mut limbs = [0; 4];
+        for (i, chunk) in self.0.rchunks(8).enumerate() {
+            limbs[i] = u64::from_be_bytes(chunk.try_into().expect("chunks are 8 bytes"));
+        }
+        limbs
+    }
+
+    // times `numerator / denominator`, as far as 256 bits go
+    pub fn scaled(&self, numerator: u64, denominator: u64) -> Self {
+        // little endian 64 bit limbs, one spare for the product
+        let mut limbs = [0u64; 5];
+        for (i, chunk) in self.0.rchunks(8).enumerate() {
+            limbs[i] = u64::from_be_bytes(chunk.try_into().expect("chunks are 8 bytes"));
+        }
+        let mut carry = 0u128;
+        for limb in &mut limbs {
+            let product = u128::from(*limb) * u128::from(numerator) + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        let denominator = u128::from(denominator.max(1));
+        let mut remainder = 0u128;
+        for limb in limbs.iter_mut().rev() {
+            let dividend = remainder << 64 | u128::from(*limb);
+            *limb = (dividend / denominator) as u64;
+            remainder = dividend % denominator;
+        }
+        if limbs[4] != 0 {
+            return Target([0xff; 32]);
+        }
+        let mut target = [0; 32];
+        for (i, chunk) in target.rchunks_mut(8).enumerate() {
+            chunk.copy_from_slice(&limbs[i].to_be_bytes());
+        }
+        Target(target)
+    }
+}
+
+// Long division of little endian 256 bit numbers, a bit at a time. `divisor` is
+// not zero.
+fn divide(dividend: [u64; 4], divisor: [u64; 4]) -> [u64; 4] {
+    let mut quotient = [0; 4];
+    let mut remainder = [0u64; 4];
+    for bit in (0..256).rev() {
+        // remainder = remainder * 2 + the dividend's next bit
+        let mut carry = dividend[bit / 64] >> (bit % 64) & 1;
+        for limb in &mut remainder {
+            let shifted = *limb >> 63;
+            *limb = *limb << 1 | carry;
+            carry = shifted;
+        }
+        // a bit shifted out the top puts the remainder past any divisor
+        if carry == 1 || !is_less(&remainder, &divisor) {
+            let mut borrow = false;
+            for (limb, subtrahend) in remainder.iter_mut().zip(divisor) {
+                let (difference, under) = limb.overflowing_sub(subtrahend);
+                let (difference, under_again) = difference.overflowing_sub(u64::from(borrow));
+                *limb = difference;
+                borrow = under || under_again;
+            }
+            quotient[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    quotient
+}
+
+fn is_less(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    a.iter().rev().lt(b.iter().rev())
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(hex: &str) -> Target {
+        Target(hex::decode(hex).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn compact_round_trips_bitcoins_genesis_target() {
+        let genesis = Target::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(
+            genesis,
+            target("00000000ffff0000000000000000000000000000000000000000000000000000")
+        );
+        assert_eq!(genesis.to_compact(), 0x1d00ffff);
+    }
+
+    #[test]
+    fn compact_rejects_negative_and_overflowing_targets() {
+        assert_eq!(Target::from_compact(0x04923456), None);
+        assert_eq!(Target::from_compact(0x2201ffff), None);
+        // a set sign bit with no mantissa is just zero
+        assert_eq!(Target::from_compact(0x01800000), Some(Target::ZERO));
+    }
+
+    #[test]
+    fn to_compact_moves_a_sign_bit_into_the_next_byte() {
+        let bits = Target::from_zero_bits(8).to_compact();
+        assert_eq!(bits, 0x2000ffff);
+        assert_eq!(Target::from_compact(bits).unwrap().to_compact(), bits);
+    }
+
+    #[test]
+    fn is_met_by_hashes_at_or_below_it() {
+        let target = Target::from_zero_bits(12);
+        let mut hash = [0u8; 32];
+        hash[1] = 0x0f;
+        assert!(target.is_met_by(&hash));
+        hash[1] = 0x10;
+        assert!(!target.is_met_by(&hash));
+        assert!(!target.is_met_by(&hash[..31]));
+    }
+
+    #[test]
+    fn work_is_exact() {
+        assert_eq!(Target([0xff; 32]).work(), 1);
+        assert_eq!(Target::from_zero_bits(1).work(), 2);
+        assert_eq!(Target::from_zero_bits(32).work(), 1 << 32);
+        // Bitcoin's difficulty 1
+        assert_eq!(Target::from_compact(0x1d00ffff).unwrap().work(), 0x0001_0001_0001);
+        assert_eq!(Target::from_zero_bits(128).work(), u128::MAX);
+        assert_eq!(Target::ZERO.work(), u128::MAX);
+        // 2^128 / 3 rounded down, more digits than an f64 holds
+        let target = target("00000000000000000000000000000002ffffffffffffffffffffffffffffffff");
+        assert_eq!(target.work(), 0x5555_5555_5555_5555_5555_5555_5555_5555);
+    }
+
+    #[test]
+    fn scaled_multiplies_then_divides() {
+        let target = Target::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(target.scaled(2, 1), Target::from_compact(0x1d01fffe).unwrap());
+        assert_eq!(target.scaled(1, 2), Target::from_compact(0x1c7fff80).unwrap());
+        assert_eq!(Target::from_zero_bits(0).scaled(2, 1), Target([0xff; 32]));
+    }
+}
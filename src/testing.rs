@@ -0,0 +1,12 @@
+// Fixtures shared by the unit tests of several modules.
+
+use crate::params::ChainParams;
+
+// a chain at one leading zero bit that never retargets, so blocks mine instantly
+pub fn dev_params() -> ChainParams {
+    ChainParams {
+        initial_difficulty: 1,
+        retarget_interval: 0,
+        ..ChainParams::default()
+    }
+}
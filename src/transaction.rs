@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+
+use libp2p::identity::Keypair;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::signing::SignatureAlgorithm;
+use crate::units::{Amount, Timestamp};
+use crate::{keys, parse_env, Block};
+
+// most transactions a locally mined block takes from the mempool
+pub const MAX_BLOCK_TRANSACTIONS: usize = 100;
+// pending transactions kept before new ones are refused
+pub const MEMPOOL_SIZE: usize = 10_000;
+
+// A transfer signed by the sender's key, of the chain's signature scheme. The
+// sender is identified by that key, and the recipient by an address, see
+// keys::address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Transaction {
+    // spec hash of the chain it is for, signed so it can't be replayed on another
+    // chain, see ChainParams::spec_hash
+    #[serde(default)]
+    pub chain: String,
+    // hex encoded public key of the sender
+    pub sender_key: String,
+    pub recipient: String,
+    pub amount: Amount,
+    // paid on top of the amount, to the miner of the block that includes it
+    #[serde(default)]
+    pub fee: Amount,
+    // tells apart otherwise identical transfers
+    pub timestamp: Timestamp,
+    // a name claimed or renewed for the recipient, see names.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // sender's signature over all the fields above
+    pub signature: String,
+}
+
+// Newly minted coins plus the fees of a block's transactions, paid to its miner.
+// Unsigned, the block's proof of work is what authorizes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Coinbase {
+    pub recipient: String,
+    pub amount: Amount,
+}
+
+impl Transaction {
+    pub fn new(keys: &Keypair, chain: String, recipient: String, amount: Amount, fee: Amount) -> Result<Self, String> {
+        Self::signed(keys, chain, recipient, amount, fee, None, Timestamp::now())
+    }
+
+    // a transfer signed as of `timestamp`, for scripted chains that must come out
+    // the same on every run
+    pub fn new_at(
+        keys: &Keypair,
+        chain: String,
+        recipient: String,
+        amount: Amount,
+        fee: Amount,
+        timestamp: Timestamp,
+    ) -> Result<Self, String> {
+        Self::signed(keys, chain, recipient, amount, fee, None, timestamp)
+    }
+
+    // claims `name` for `recipient`, or renews it, moving no coins
+    pub fn claim_name(
+        keys: &Keypair,
+        chain: String,
+        recipient: String,
+        name: String,
+        fee: Amount,
+    ) -> Result<Self, String> {
+        Self::signed(keys, chain, recipient, Amount::ZERO, fee, Some(name), Timestamp::now())
+    }
+
+    fn signed(
+        keys: &Keypair,
+        chain: String,
+        recipient: String,
+        amount: Amount,
+        fee: Amount,
+        name: Option<String>,
+        timestamp: Timestamp,
+    ) -> Result<Self, String> {
+        let mut transaction = Self {
+            chain,
+            sender_key: keys::encode_public_key(keys).ok_or("key can't sign transactions")?,
+            recipient,
+            amount,
+            fee,
+            timestamp,
+            name,
+            signature: String::new(),
+        };
+        let signature = keys
+            .sign(&transaction.signing_payload())
+            .map_err(|e| format!("could not sign transaction: {}", e))?;
+        transaction.signature = hex::encode(signature);
+        Ok(transaction)
+    }
+
+    // plain transfers sign no name at all
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = format!(
+            "tx:{}:{}:{}:{}:{}:{}",
+            self.chain, self.sender_key, self.recipient, self.amount, self.fee, self.timestamp
+        );
+        if let Some(name) = &self.name {
+            payload.push_str(&format!(":name:{}", name));
+        }
+        payload.into_bytes()
+    }
+
+    pub fn signature_scheme(&self) -> Result<SignatureAlgorithm, String> {
+        let key = keys::decode_public_key(&self.sender_key)?;
+        SignatureAlgorithm::of(&key).ok_or_else(|| "sender key has no signature scheme".to_string())
+    }
+
+    // address of the sender's key
+    pub fn sender(&self) -> Result<String, String> {
+        let key = keys::decode_public_key(&self.sender_key)?;
+        keys::address(&key).ok_or_else(|| "sender key has no address".to_string())
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        let key = keys::decode_public_key(&self.sender_key)?;
+        let signature = hex::decode(&self.signature).map_err(|_| "malformed signature".to_string())?;
+        if !key.verify(&self.signing_payload(), &signature) {
+            return Err("bad signature".to_string());
+        }
+        Ok(())
+    }
+
+    // The txid, what the transaction is tracked by everywhere. It covers the signed
+    // fields only, so a relayer re-encoding the signature can't change it.
+    pub fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_payload());
+        hex::encode(hasher.finalize().as_slice())
+    }
+
+    // The witness txid, which covers the signature as well. Two copies of a transfer
+    // share their txid but not their wtxid if one had its signature malleated.
+    pub fn wtxid(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_payload());
+        hasher.update(b":");
+        hasher.update(self.signature.as_bytes());
+        hex::encode(hasher.finalize().as_slice())
+    }
+
+    // matches either of its ids
+    pub fn has_id(&self, id: &str) -> bool {
+        self.id() == id || self.wtxid() == id
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        serde_json::to_vec(self).expect("can jsonify transaction").len()
+    }
+}
+
+// How much of the network's capacity a single sender can take up, so one scripted
+// account can't crowd everyone else out. Local policy, blocks mined elsewhere may
+// well have more of a sender's transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderLimits {
+    // pending transactions per sender, more are refused
+    pub max_pending: usize,
+    // transactions per sender in a locally mined block, the rest wait for later blocks
+    pub max_per_block: usize,
+}
+
+impl Default for SenderLimits {
+    fn default() -> Self {
+        Self {
+            max_pending: usize::MAX,
+            max_per_block: usize::MAX,
+        }
+    }
+}
+
+impl SenderLimits {
+    // no limits unless MEMPOOL_MAX_PER_SENDER or BLOCK_MAX_PER_SENDER are set
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_pending: parse_env("MEMPOOL_MAX_PER_SENDER")?.unwrap_or(defaults.max_pending),
+            max_per_block: parse_env("BLOCK_MAX_PER_SENDER")?.unwrap_or(defaults.max_per_block),
+        })
+    }
+}
+
+// Pending transactions in arrival order, waiting to be mined into a block.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    pending: Vec<Transaction>,
+    ids: HashSet<String>,
+    // pending transactions of each sender key
+    per_sender: HashMap<String, usize>,
+    limits: SenderLimits,
+}
+
+impl Mempool {
+    pub fn new(limits: SenderLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    pub fn add(&mut self, transaction: Transaction) -> Result<(), String> {
+        transaction.verify()?;
+        self.add_verified(transaction)
+    }
+
+    // for transactions whose signature was already checked, e.g. off the event loop
+    pub fn add_verified(&mut self, transaction: Transaction) -> Result<(), String> {
+        if transaction.amount.is_zero() && transaction.name.is_none() {
+            return Err("transaction moves nothing".to_string());
+        }
+        // by txid, so a copy with a malleated signature is a duplicate too
+        let id = transaction.id();
+        if self.ids.contains(&id) {
+            return Err(format!("transaction {} is already pending", id));
+        }
+        if self.pending.len() >= MEMPOOL_SIZE {
+            return Err("mempool is full".to_string());
+        }
+        if self.per_sender.get(&transaction.sender_key).copied().unwrap_or(0) >= self.limits.max_pending {
+            return Err(format!(
+                "sender already has {} pending transactions",
+                self.limits.max_pending
+            ));
+        }
+        *self.per_sender.entry(transaction.sender_key.clone()).or_default() += 1;
+        self.ids.insert(id);
+        self.pending.push(transaction);
+        Ok(())
+    }
+
+    // The oldest pending transactions, at most `max_transactions` and `max_bytes` of
+    // them and at most the per block limit of each sender.
+    pub fn batch(&self, max_transactions: usize, max_bytes: usize) -> Vec<Transaction> {
+        let mut bytes = 0;
+        let mut per_sender: HashMap<&str, usize> = HashMap::new();
+        self.pending
+            .iter()
+            .filter(|transaction| {
+                let count = per_sender.entry(&transaction.sender_key).or_default();
+                *count += 1;
+                *count <= self.limits.max_per_block
+            })
+            .take(max_transactions)
+            .take_while(|transaction| {
+                bytes += transaction.encoded_size();
+                bytes <= max_bytes
+            })
+            .cloned()
+            .collect()
+    }
+
+    // drops every pending transaction that made it into one of `blocks`
+    pub fn remove_confirmed(&mut self, blocks: &[Block]) {
+        let confirmed: HashSet<String> = blocks
+            .iter()
+            .flat_map(|block| &block.data.transactions)
+            .map(Transaction::id)
+            .filter(|id| self.ids.contains(id))
+            .collect();
+        if confirmed.is_empty() {
+            return;
+        }
+        let per_sender = &mut self.per_sender;
+        self.pending.retain(|transaction| {
+            if !confirmed.contains(&transaction.id()) {
+                return true;
+            }
+            if let Some(count) = per_sender.get_mut(&transaction.sender_key) {
+                *count -= 1;
+                if *count == 0 {
+                    per_sender.remove(&transaction.sender_key);
+                }
+            }
+            false
+        });
+        self.ids.retain(|id| !confirmed.contains(id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.pending.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malleated_signature_keeps_the_txid_only() {
+        let keys = Keypair::generate_ed25519();
+        let transaction = Transaction::new(
+            &keys,
+            "chain".to_string(),
+            "recipient".to_string(),
+            Amount(1),
+            Amount::ZERO,
+        )
+        .unwrap();
+        // hex decodes either case, so this is the same valid signature
+        let mut malleated = transaction.clone();
+        malleated.signature = transaction.signature.to_uppercase();
+        assert!(malleated.verify().is_ok());
+        assert_eq!(malleated.id(), transaction.id());
+        assert_ne!(malleated.wtxid(), transaction.wtxid());
+        assert!(malleated.has_id(&transaction.id()));
+        assert!(!malleated.has_id(&transaction.wtxid()));
+    }
+
+    #[test]
+    fn chain_is_signed() {
+        let keys = Keypair::generate_ed25519();
+        let transaction = Transaction::new(
+            &keys,
+            "chain".to_string(),
+            "recipient".to_string(),
+            Amount(1),
+            Amount::ZERO,
+        )
+        .unwrap();
+        let mut replayed = transaction.clone();
+        replayed.chain = "other chain".to_string();
+        assert!(replayed.verify().is_err());
+        assert_ne!(replayed.id(), transaction.id());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Numbers that consensus depends on. They have no wrapping arithmetic, only checked
+// or saturating operations, so an overflow shows up as an error instead of quietly
+// producing a different chain state. On the wire and on disk they are plain numbers.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct BlockHeight(pub u64);
+
+impl BlockHeight {
+    pub const GENESIS: BlockHeight = BlockHeight(0);
+    pub const MAX: BlockHeight = BlockHeight(u64::MAX);
+
+    // height of the child block, there is none after u64::MAX
+    pub fn next(self) -> Option<BlockHeight> {
+        self.0.checked_add(1).map(BlockHeight)
+    }
+
+    // position in a chain that starts at genesis
+    pub fn index(self) -> usize {
+        usize::try_from(self.0).unwrap_or(usize::MAX)
+    }
+}
+
+impl fmt::Display for BlockHeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Timestamp(pub i64);
+
+impl Timestamp {
+    pub const MIN: Timestamp = Timestamp(i64::MIN);
+
+    pub fn now() -> Timestamp {
+        Timestamp(Utc::now().timestamp())
+    }
+
+    pub fn saturating_add_secs(self, secs: i64) -> Timestamp {
+        Timestamp(self.0.saturating_add(secs))
+    }
+
+    // seconds from `earlier` to this timestamp, negative if it is actually later
+    pub fn saturating_secs_since(self, earlier: Timestamp) -> i64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Coins, in the smallest unit there is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Amount(pub u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    // halved `times` times, nothing left once every bit is shifted out
+    pub fn halved(self, times: u64) -> Amount {
+        u32::try_from(times)
+            .ok()
+            .and_then(|times| self.0.checked_shr(times))
+            .map_or(Amount::ZERO, Amount)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Amount)
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmi::core::Trap;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::error::ChainError;
+use crate::units::BlockHeight;
+
+// most gas the ops of one block can spend together
+pub const MAX_BLOCK_GAS: u64 = 10_000_000;
+// charged against the block's gas per byte of deployed code
+const DEPLOY_GAS_PER_BYTE: u64 = 10;
+// charged per byte of storage written, on top of the instructions executed
+const STORAGE_GAS_PER_BYTE: u64 = 100;
+// longest storage key or value, and what storage_read can write at once
+pub const MAX_STORAGE_ITEM: usize = 4096;
+// most linear memory a contract may have, declared up front or grown to. Fuel
+// doesn't pay for memory, so this is what bounds what a call allocates.
+const MAX_MEMORY_PAGES: usize = 16;
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+const MAX_TABLE_ELEMENTS: u32 = 1024;
+
+// What blocks carry for the execution layer, run in order after the block's
+// transactions, see ContractState::execute.
+//
+// Contracts are WASM modules exporting a `memory` and a `call` function taking
+// and returning nothing. They reach their input and storage through functions
+// imported from "env":
+//   input_len() -> i32, input_read(ptr)
+//   storage_read(key_ptr, key_len, out_ptr) -> i32, the value's length or -1
+//   storage_write(key_ptr, key_len, value_ptr, value_len)
+//   block_height() -> i64
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ContractOp {
+    // hex encoded module, deployed at contract_address
+    Deploy {
+        code: String,
+    },
+    // runs `contract` with the hex encoded `input`, for at most `gas_limit` gas
+    Call {
+        contract: String,
+        input: String,
+        gas_limit: u64,
+    },
+}
+
+impl ContractOp {
+    // what the op takes of the block's gas, whether or not it is all spent
+    fn gas(&self) -> u64 {
+        match self {
+            ContractOp::Deploy { code } => (code.len() as u64 / 2).saturating_mul(DEPLOY_GAS_PER_BYTE),
+            ContractOp::Call { gas_limit, .. } => *gas_limit,
+        }
+    }
+}
+
+// The ops of a block have to decode and fit its gas together. Whether they then
+// succeed is up to the contracts.
+pub fn validate(ops: &[ContractOp]) -> Result<(), ChainError> {
+    let mut gas: u64 = 0;
+    for (index, op) in ops.iter().enumerate() {
+        let bytes = match op {
+            ContractOp::Deploy { code } => code,
+            ContractOp::Call { input, .. } => input,
+        };
+        gas = gas.saturating_add(op.gas());
+        if hex::decode(bytes).is_err() || gas > MAX_BLOCK_GAS {
+            return Err(ChainError::InvalidContractOp(index));
+        }
+    }
+    Ok(())
+}
+
+// address of the contract deployed by the `index`th op of the block at `height`
+pub fn contract_address(height: BlockHeight, index: usize, code: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("contract:{}:{}:", height, index).as_bytes());
+    hasher.update(code);
+    hex::encode(&hasher.finalize()[..20])
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contract {
+    pub code: Vec<u8>,
+    pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+// What an op did. A failed op changes nothing but still spends its gas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Receipt {
+    pub contract: String,
+    pub gas_used: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Every deployed contract and its storage, part of State, along with the receipts
+// of the ops that got them there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContractState {
+    contracts: BTreeMap<String, Contract>,
+    // of every block with ops, one per op in block order
+    receipts: BTreeMap<BlockHeight, Vec<Receipt>>,
+}
+
+impl ContractState {
+    pub fn contract(&self, address: &str) -> Option<&Contract> {
+        self.contracts.get(address)
+    }
+
+    // what the ops of the block at `height` did, none if it had no ops
+    pub fn receipts(&self, height: BlockHeight) -> &[Receipt] {
+        self.receipts.get(&height).map_or(&[], Vec::as_slice)
+    }
+
+    // runs the ops of the block at `height` in order, they are assumed validated,
+    // and keeps their receipts
+    pub fn execute(&mut self, height: BlockHeight, ops: &[ContractOp]) -> &[Receipt] {
+        let receipts = ops
+            .iter()
+            .enumerate()
+            .map(|(index, op)| match op {
+                ContractOp::Deploy { code } => self.deploy(height, index, op.gas(), code),
+                ContractOp::Call {
+                    contract,
+                    input,
+                    gas_limit,
+                } => self.call(height, contract, input, *gas_limit),
+            })
+            .collect();
+        self.receipts.insert(height, receipts);
+        self.receipts(height)
+    }
+
+    fn deploy(&mut self, height: BlockHeight, index: usize, gas: u64, code: &str) -> Receipt {
+        let code = hex::decode(code).unwrap_or_default();
+        let contract = contract_address(height, index, &code);
+        let error = Module::new(&engine(), &code[..]).err().map(|e| e.to_string());
+        if error.is_none() {
+            self.contracts.insert(
+                contract.clone(),
+                Contract {
+                    code,
+                    storage: BTreeMap::new(),
+                },
+            );
+        }
+        Receipt {
+            contract,
+            gas_used: gas,
+            error,
+        }
+    }
+
+    fn call(&mut self, height: BlockHeight, address: &str, input: &str, gas_limit: u64) -> Receipt {
+        let input = hex::decode(input).unwrap_or_default();
+        let result = match self.contracts.get(address) {
+            Some(contract) => run(contract, height, input, gas_limit),
+            None => Err((0, "no such contract".to_string())),
+        };
+        let (gas_used, error) = match result {
+            Ok((storage, gas_used)) => {
+                if let Some(contract) = self.contracts.get_mut(address) {
+                    contract.storage = storage;
+                }
+                (gas_used, None)
+            }
+            Err((gas_used, e)) => (gas_used, Some(e)),
+        };
+        Receipt {
+            contract: address.to_string(),
+            gas_used,
+            error,
+        }
+    }
+}
+
+// fuel is wasmi's instruction count, which is deterministic
+fn engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+// What a running contract sees. Its storage is a copy, only kept if the call succeeds.
+struct Host {
+    input: Vec<u8>,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    height: BlockHeight,
+    storage_gas: u64,
+    gas_limit: u64,
+    limits: StoreLimits,
+}
+
+// one instance with one memory and table, growing past the limits traps
+fn limits() -> StoreLimits {
+    StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_PAGES * WASM_PAGE_SIZE)
+        .table_elements(MAX_TABLE_ELEMENTS)
+        .instances(1)
+        .memories(1)
+        .tables(1)
+        .trap_on_grow_failure(true)
+        .build()
+}
+
+// the contract's storage after the call and the gas it used, or the gas used until
+// it failed and why
+type RunResult = Result<(BTreeMap<Vec<u8>, Vec<u8>>, u64), (u64, String)>;
+
+fn run(contract: &Contract, height: BlockHeight, input: Vec<u8>, gas_limit: u64) -> RunResult {
+    let engine = engine();
+    let module = Module::new(&engine, &contract.code[..]).map_err(|e| (0, e.to_string()))?;
+    let mut store = Store::new(
+        &engine,
+        Host {
+            input,
+            storage: contract.storage.clone(),
+            height,
+            storage_gas: 0,
+            gas_limit,
+            limits: limits(),
+        },
+    );
+    store.limiter(|host| &mut host.limits);
+    store.add_fuel(gas_limit).map_err(|e| (0, e.to_string()))?;
+    let mut linker = Linker::new(&engine);
+    link_host(&mut linker).map_err(|e| (0, e))?;
+    let result = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .and_then(|instance| instance.get_typed_func::<(), ()>(&store, "call"))
+        .and_then(|call| call.call(&mut store, ()).map_err(wasmi::Error::from))
+        .map_err(|e| e.to_string());
+    let host = store.data();
+    let gas_used = store
+        .fuel_consumed()
+        .unwrap_or(0)
+        .saturating_add(host.storage_gas)
+        .min(gas_limit);
+    match result {
+        Ok(()) => Ok((store.into_data().storage, gas_used)),
+        Err(e) => Err((gas_used, e)),
+    }
+}
+
+fn link_host(linker: &mut Linker<Host>) -> Result<(), String> {
+    linker
+        .func_wrap("env", "input_len", |caller: Caller<'_, Host>| -> i32 {
+            caller.data().input.len() as i32
+        })
+        .map_err(|e| e.to_string())?;
+    linker
+        .func_wrap(
+            "env",
+            "input_read",
+            |mut caller: Caller<'_, Host>, ptr: i32| -> Result<(), Trap> {
+                let input = caller.data().input.clone();
+                write_bytes(&mut caller, ptr, &input)
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    linker
+        .func_wrap(
+            "env",
+            "storage_read",
+            |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32, out_ptr: i32| -> Result<i32, Trap> {
+                let key = read_bytes(&caller, key_ptr, key_len)?;
+                match caller.data().storage.get(&key).cloned() {
+                    Some(value) => {
+                        write_bytes(&mut caller, out_ptr, &value)?;
+                        Ok(value.len() as i32)
+                    }
+                    None => Ok(-1),
+                }
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    linker
+        .func_wrap(
+            "env",
+            "storage_write",
+            |mut caller: Caller<'_, Host>,
+             key_ptr: i32,
+             key_len: i32,
+             value_ptr: i32,
+             value_len: i32|
+             -> Result<(), Trap> {
+                let key = read_bytes(&caller, key_ptr, key_len)?;
+                let value = read_bytes(&caller, value_ptr, value_len)?;
+                let host = caller.data_mut();
+                let gas = ((key.len() + value.len()) as u64).saturating_mul(STORAGE_GAS_PER_BYTE);
+                host.storage_gas = host.storage_gas.saturating_add(gas);
+                if host.storage_gas > host.gas_limit {
+                    return Err(Trap::new("out of gas"));
+                }
+                host.storage.insert(key, value);
+                Ok(())
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    linker
+        .func_wrap("env", "block_height", |caller: Caller<'_, Host>| -> i64 {
+            caller.data().height.0 as i64
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("contract exports no memory"))
+}
+
+fn read_bytes(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= MAX_STORAGE_ITEM)
+        .ok_or_else(|| Trap::new("invalid length"))?;
+    let mut bytes = vec![0; len];
+    memory(caller)?
+        .read(caller, ptr as u32 as usize, &mut bytes)
+        .map_err(|e| Trap::new(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn write_bytes(caller: &mut Caller<'_, Host>, ptr: i32, bytes: &[u8]) -> Result<(), Trap> {
+    memory(caller)?
+        .write(caller, ptr as u32 as usize, bytes)
+        .map_err(|e| Trap::new(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (module
+    //   (import "env" "storage_write" (func (param i32 i32 i32 i32)))
+    //   (memory (export "memory") 1)
+    //   (func (export "call") (call 0 (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1)))
+    //   (data (i32.const 0) "kv"))
+    // stores "v" under the key "k"
+    const WRITE_KV: &str = "0061736d01000000010b0260047f7f7f7f0060000002150103656e760d73746f726167655f7772\
+                            6974650000030201010503010001071102066d656d6f727902000463616c6c00010a0e010c0041\
+                            0041014101410110000b0b08010041000b026b76";
+    // as WRITE_KV, then loops forever: (loop (br 0))
+    const WRITE_KV_THEN_LOOP: &str = "0061736d01000000010b0260047f7f7f7f0060000002150103656e760d73746f72616765\
+                                      5f77726974650000030201010503010001071102066d656d6f727902000463616c6c00\
+                                      010a130111004100410141014101100003400c000b0b0b08010041000b026b76";
+    // (module (memory (export "memory") 65536) (func (export "call")))
+    // declares 4 GiB of memory up front
+    const HUGE_MEMORY: &str = "0061736d010000000104016000000302010005050100808004071102066d656d6f727902000463616c6c\
+                               00000a040102000b";
+    // (module
+    //   (memory (export "memory") 1)
+    //   (func (export "call") (loop (drop (memory.grow (i32.const 1))) (br 0))))
+    // grows its memory a page at a time forever
+    const GROW_MEMORY: &str = "0061736d01000000010401600000030201000503010001071102066d656d6f727902000463616c6c\
+                               00000a0e010c000340410140001a0c000b0b";
+
+    // state with `code` deployed by the block at height 1, and its address
+    fn deployed(code: &str) -> (ContractState, String) {
+        let mut state = ContractState::default();
+        let receipts = state.execute(BlockHeight(1), &[ContractOp::Deploy { code: code.to_string() }]);
+        assert_eq!(receipts[0].error, None);
+        assert_eq!(receipts[0].gas_used, (code.len() / 2) as u64 * DEPLOY_GAS_PER_BYTE);
+        let address = receipts[0].contract.clone();
+        (state, address)
+    }
+
+    fn call(state: &mut ContractState, contract: &str, gas_limit: u64) -> Receipt {
+        let op = ContractOp::Call {
+            contract: contract.to_string(),
+            input: String::new(),
+            gas_limit,
+        };
+        state.execute(BlockHeight(2), &[op])[0].clone()
+    }
+
+    #[test]
+    fn deployed_contract_writes_its_storage() {
+        let (mut state, address) = deployed(WRITE_KV);
+        assert_eq!(
+            address,
+            contract_address(BlockHeight(1), 0, &hex::decode(WRITE_KV).unwrap())
+        );
+        let receipt = call(&mut state, &address, 10_000);
+        assert_eq!(receipt.error, None);
+        assert_eq!(state.receipts(BlockHeight(2)), [receipt]);
+        let storage = &state.contract(&address).unwrap().storage;
+        assert_eq!(storage.get(&b"k"[..]), Some(&b"v".to_vec()));
+        assert!(state.receipts(BlockHeight(3)).is_empty());
+    }
+
+    #[test]
+    fn storage_writes_are_charged_gas() {
+        let (mut state, address) = deployed(WRITE_KV);
+        let storage_gas = 2 * STORAGE_GAS_PER_BYTE;
+        let receipt = call(&mut state, &address, 10_000);
+        assert!(receipt.gas_used > storage_gas, "{} gas used", receipt.gas_used);
+
+        // enough for the instructions but not for the write
+        let (mut state, address) = deployed(WRITE_KV);
+        let receipt = call(&mut state, &address, storage_gas - 1);
+        assert!(receipt.error.is_some());
+        assert_eq!(receipt.gas_used, storage_gas - 1);
+        assert!(state.contract(&address).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn out_of_gas_call_is_rolled_back() {
+        let (mut state, address) = deployed(WRITE_KV_THEN_LOOP);
+        let receipt = call(&mut state, &address, 10_000);
+        assert!(receipt.error.is_some());
+        assert_eq!(receipt.gas_used, 10_000);
+        assert!(state.contract(&address).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn memory_is_capped_at_instantiation() {
+        let (mut state, address) = deployed(HUGE_MEMORY);
+        let receipt = call(&mut state, &address, 10_000);
+        assert!(receipt.error.is_some());
+        assert!(receipt.gas_used < 10_000, "{} gas used", receipt.gas_used);
+    }
+
+    #[test]
+    fn memory_growth_is_capped() {
+        let (mut state, address) = deployed(GROW_MEMORY);
+        let receipt = call(&mut state, &address, MAX_BLOCK_GAS);
+        assert!(receipt.error.is_some());
+        // trapped at the cap, having paid about a gas per 64 bytes it grew, rather
+        // than growing until its gas ran out
+        let growth_gas = (MAX_MEMORY_PAGES * WASM_PAGE_SIZE / 64) as u64;
+        assert!(receipt.gas_used < growth_gas, "{} gas used", receipt.gas_used);
+    }
+
+    #[test]
+    fn calls_to_unknown_contracts_fail() {
+        let mut state = ContractState::default();
+        let receipt = call(&mut state, "missing", 10_000);
+        assert_eq!(receipt.error.as_deref(), Some("no such contract"));
+        assert_eq!(receipt.gas_used, 0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::keys;
+use crate::signing::SignatureAlgorithm;
+
+// scrypt cost, 2^15 rounds takes a fraction of a second per keystore
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+// AES-GCM's 96 bit nonce
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletAccount {
+    pub address: String,
+    pub public_key: String,
+    // hex encoded secret key, as read by keys::decode_keypair
+    pub secret_key: String,
+}
+
+impl WalletAccount {
+    pub fn generate(algorithm: SignatureAlgorithm) -> Self {
+        let scheme = algorithm.scheme();
+        let keys = scheme.generate();
+        let secret = hex::encode(scheme.encode_secret_key(&keys).expect("key is of its own scheme"));
+        Self {
+            address: keys::address(&keys.public()).expect("generated keys have an address"),
+            public_key: keys::encode_public_key(&keys).expect("generated keys can be encoded"),
+            secret_key: match algorithm {
+                SignatureAlgorithm::Ed25519 => secret,
+                SignatureAlgorithm::Secp256k1 => format!("secp256k1:{}", secret),
+            },
+        }
+    }
+}
+
+// Accounts encrypted with AES-256-GCM under a key derived from a password with
+// scrypt. Addresses are left in the clear so a keystore can be told apart without
+// the password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub addresses: Vec<String>,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> Result<Vec<u8>, String> {
+    let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P).map_err(|e| e.to_string())?;
+    let mut key = vec![0; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key).map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+impl Keystore {
+    pub fn encrypt(accounts: &[WalletAccount], password: &str) -> Result<Self, String> {
+        let mut rng = rand::thread_rng();
+        let salt: [u8; 16] = rng.gen();
+        let nonce: [u8; NONCE_LEN] = rng.gen();
+        let cipher = Aes256Gcm::new_from_slice(&derive_key(password, &salt)?).map_err(|e| e.to_string())?;
+        let plaintext = serde_json::to_vec(accounts).map_err(|e| format!("could not encode accounts: {}", e))?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|e| format!("could not encrypt keystore: {}", e))?;
+        Ok(Self {
+            addresses: accounts.iter().map(|account| account.address.clone()).collect(),
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Vec<WalletAccount>, String> {
+        let decode = |field: &str| hex::decode(field).map_err(|e| format!("corrupt keystore: {}", e));
+        let nonce = decode(&self.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(format!("corrupt keystore: nonce is {} bytes", nonce.len()));
+        }
+        let cipher =
+            Aes256Gcm::new_from_slice(&derive_key(password, &decode(&self.salt)?)?).map_err(|e| e.to_string())?;
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), decode(&self.ciphertext)?.as_ref())
+            .map_err(|_| "wrong password or corrupt keystore".to_string())?;
+        serde_json::from_slice(&plaintext).map_err(|e| format!("corrupt keystore: {}", e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("could not parse {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| format!("could not encode keystore: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+}
+
+// FILE.addresses for keystore FILE, whatever extension FILE has
+pub fn address_list_path(keystore: &Path) -> PathBuf {
+    let mut path = keystore.as_os_str().to_owned();
+    path.push(".addresses");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keystore() -> (Vec<WalletAccount>, Keystore) {
+        let accounts = vec![WalletAccount::generate(SignatureAlgorithm::Ed25519)];
+        let keystore = Keystore::encrypt(&accounts, "password").unwrap();
+        (accounts, keystore)
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let (accounts, keystore) = keystore();
+        let decrypted = keystore.decrypt("password").unwrap();
+        assert_eq!(decrypted.len(), 1);
+        assert_eq!(decrypted[0].secret_key, accounts[0].secret_key);
+        assert_eq!(keystore.addresses, vec![accounts[0].address.clone()]);
+    }
+
+    #[test]
+    fn rejects_a_wrong_password() {
+        let (_, keystore) = keystore();
+        assert!(keystore.decrypt("wrong").is_err());
+    }
+
+    #[test]
+    fn rejects_a_nonce_of_the_wrong_length() {
+        let (_, mut keystore) = keystore();
+        keystore.nonce = hex::encode([0u8; 4]);
+        let err = keystore.decrypt("password").unwrap_err();
+        assert!(err.starts_with("corrupt keystore"), "{}", err);
+    }
+
+    #[test]
+    fn address_list_is_appended_to_the_keystore_name() {
+        assert_eq!(
+            address_list_path(Path::new("keys.json")),
+            Path::new("keys.json.addresses")
+        );
+        assert_eq!(
+            address_list_path(Path::new("x.addresses")),
+            Path::new("x.addresses.addresses")
+        );
+    }
+}
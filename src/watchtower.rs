@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+
+use log::error;
+use serde::Serialize;
+use tokio::spawn;
+
+use crate::units::BlockHeight;
+use crate::{parse_env, Block, BlockHeader, Payload};
+
+pub const DEFAULT_REORG_ALERT_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    InvalidBlock {
+        peer: String,
+        hash: String,
+        reason: String,
+    },
+    InvalidChain {
+        peer: String,
+        reason: String,
+    },
+    DeepReorg {
+        depth: usize,
+        old_tip: String,
+        new_tip: String,
+    },
+    DifficultyAnomaly {
+        height: BlockHeight,
+        expected: u32,
+        got: u32,
+    },
+}
+
+impl Alert {
+    fn kind(&self) -> &'static str {
+        match self {
+            Alert::InvalidBlock { .. } => "invalid_block",
+            Alert::InvalidChain { .. } => "invalid_chain",
+            Alert::DeepReorg { .. } => "deep_reorg",
+            Alert::DifficultyAnomaly { .. } => "difficulty_anomaly",
+        }
+    }
+}
+
+// Watchtower mode: the node follows the network, validates everything it sees and
+// raises alerts, but never mines or serves its chain. The app keeps full blocks,
+// saving the chain and replaying state on reorgs need them, what the watchtower
+// followed is recorded as headers.
+pub struct Watchtower {
+    pub webhook: Option<String>,
+    pub reorg_alert_depth: usize,
+    // alerts raised so far by kind, for `ls alerts`
+    pub counts: BTreeMap<&'static str, u64>,
+    // of the validated chain, see `follow`
+    pub headers: Vec<BlockHeader>,
+    client: reqwest::Client,
+}
+
+impl Watchtower {
+    pub fn new(webhook: Option<String>, reorg_alert_depth: usize) -> Self {
+        Self {
+            webhook,
+            reorg_alert_depth,
+            counts: BTreeMap::new(),
+            headers: vec![],
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // enabled by WATCHTOWER=1, alerts are POSTed as JSON to WATCHTOWER_WEBHOOK if set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        if std::env::var("WATCHTOWER").as_deref() != Ok("1") {
+            return Ok(None);
+        }
+        let depth = parse_env("WATCHTOWER_REORG_DEPTH")?.unwrap_or(DEFAULT_REORG_ALERT_DEPTH);
+        Ok(Some(Self::new(std::env::var("WATCHTOWER_WEBHOOK").ok(), depth)))
+    }
+
+    pub fn raise(&mut self, alert: Alert) {
+        error!("ALERT {:?}", alert);
+        *self.counts.entry(alert.kind()).or_default() += 1;
+        if let Some(url) = self.webhook.clone() {
+            let request = self.client.post(url.as_str()).json(&alert);
+            spawn(async move {
+                match request.send().await {
+                    Ok(response) if !response.status().is_success() => {
+                        error!("webhook {} answered {}", url, response.status())
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("could not deliver alert to {}: {}", url, e),
+                }
+            });
+        }
+    }
+
+    pub fn check_reorg(&mut self, depth: usize, old_tip: &str, new_tip: &str) {
+        if depth >= self.reorg_alert_depth {
+            self.raise(Alert::DeepReorg {
+                depth,
+                old_tip: old_tip.to_string(),
+                new_tip: new_tip.to_string(),
+            });
+        }
+    }
+
+    // records the headers of `chain`, keeping those up to where it forks off
+    pub fn follow<T: Payload>(&mut self, chain: &[Block<T>]) {
+        let shared = self
+            .headers
+            .iter()
+            .zip(chain)
+            .take_while(|(header, block)| header.hash == block.header.hash)
+            .count();
+        self.headers.truncate(shared);
+        self.headers
+            .extend(chain[shared..].iter().map(|block| block.header.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::ChainParams;
+    use crate::{App, BlockBody};
+
+    fn dev_app() -> App {
+        App::new(ChainParams {
+            initial_difficulty: 1,
+            retarget_interval: 0,
+            ..ChainParams::default()
+        })
+    }
+
+    fn mine(app: &mut App, n: u64, tag: &str) {
+        app.mine_n_blocks(n, |height| BlockBody::new(format!("{} {}", tag, height), vec![]))
+            .unwrap();
+    }
+
+    #[test]
+    fn follow_replaces_headers_after_the_fork() {
+        let mut app = dev_app();
+        mine(&mut app, 3, "a");
+        let mut watchtower = Watchtower::new(None, DEFAULT_REORG_ALERT_DEPTH);
+        watchtower.follow(&app.blocks);
+        assert_eq!(watchtower.headers.len(), 4);
+
+        let mut fork = dev_app();
+        fork.blocks = app.blocks[..2].to_vec();
+        mine(&mut fork, 3, "b");
+        watchtower.follow(&fork.blocks);
+        let hashes: Vec<_> = watchtower.headers.iter().map(|header| &header.hash).collect();
+        let expected: Vec<_> = fork.blocks.iter().map(|block| &block.header.hash).collect();
+        assert_eq!(hashes, expected);
+    }
+
+    // following keeps the app's blocks whole, so the saved chain still loads
+    #[test]
+    fn followed_chain_can_be_saved_and_reloaded() {
+        let mut app = dev_app();
+        mine(&mut app, 3, "a");
+        Watchtower::new(None, DEFAULT_REORG_ALERT_DEPTH).follow(&app.blocks);
+        let path = std::env::temp_dir().join(format!("watchtower-{}.json", std::process::id()));
+        crate::store::save_chain(&path, &app.blocks).unwrap();
+        let mut reloaded = dev_app();
+        let result = reloaded.import_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(reloaded.blocks.len(), app.blocks.len());
+    }
+
+    #[test]
+    fn only_deep_reorgs_raise_alerts() {
+        let mut watchtower = Watchtower::new(None, 3);
+        watchtower.check_reorg(2, "old", "new");
+        assert!(watchtower.counts.is_empty());
+        watchtower.check_reorg(3, "old", "new");
+        assert_eq!(watchtower.counts.get("deep_reorg"), Some(&1));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::{BlockHasher, HashAlgorithm};
+use crate::units::{BlockHeight, Timestamp};
+use crate::{preimage, Block, BlockBody};
+
+// templates handed out and not yet solved, the oldest are forgotten first
+pub const MAX_OUTSTANDING_WORK: usize = 64;
+
+// A block for an external miner to find the nonce of, getwork style, so the
+// hashing can run in a separate process. See NodeApi::get_work and submit_work.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkTemplate {
+    // to submit a nonce for this template with
+    pub work_id: u64,
+    pub id: BlockHeight,
+    pub timestamp: Timestamp,
+    pub previous_hash: String,
+    pub data: BlockBody,
+    pub difficulty: u32,
+    // the largest hash meeting the difficulty, in hex, for miners to compare against
+    pub target: String,
+    // the header as hashed, in hex, its last 8 bytes the nonce as a big endian u64.
+    // Miners hash it with each nonce they try, see preimage::binary.
+    pub preimage: String,
+    pub hash_algorithm: HashAlgorithm,
+}
+
+// The unmined blocks behind the templates handed out, by work id.
+#[derive(Default)]
+pub struct WorkProvider {
+    next_id: u64,
+    outstanding: BTreeMap<u64, Block>,
+}
+
+impl WorkProvider {
+    pub fn issue(&mut self, block: Block, hash_algorithm: HashAlgorithm) -> WorkTemplate {
+        if self.outstanding.len() >= MAX_OUTSTANDING_WORK {
+            self.outstanding.pop_first();
+        }
+        let work_id = self.next_id;
+        self.next_id += 1;
+        let header = &block.header;
+        let template = WorkTemplate {
+            work_id,
+            id: header.id,
+            timestamp: header.timestamp,
+            previous_hash: header.previous_hash.clone(),
+            data: block.data.clone(),
+            difficulty: header.difficulty,
+            target: header.target().to_string(),
+            preimage: hex::encode(preimage::binary(header)),
+            hash_algorithm,
+        };
+        self.outstanding.insert(work_id, block);
+        template
+    }
+
+    // The template's block with `nonce`, if that meets its difficulty. Only then is
+    // the template used up, a wrong nonce can be followed by the right one.
+    pub fn solve(&mut self, work_id: u64, nonce: u64, hasher: &dyn BlockHasher) -> Result<Block, String> {
+        let block = self
+            .outstanding
+            .get(&work_id)
+            .ok_or_else(|| format!("unknown or expired work {}", work_id))?;
+        let mut header = block.header.clone();
+        header.nonce = nonce;
+        let hash = header.calculate_hash(hasher);
+        let target = header.target();
+        if !target.is_met_by(&hash) {
+            return Err(format!("nonce {} does not meet target {}", nonce, target));
+        }
+        header.hash = hex::encode(hash);
+        let mut block = self.outstanding.remove(&work_id).expect("work is outstanding");
+        block.header = header;
+        Ok(block)
+    }
+
+    // templates on top of a tip other than `tip` can only produce stale blocks
+    pub fn retain_tip(&mut self, tip: &str) {
+        self.outstanding.retain(|_, block| block.header.previous_hash == tip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dev_params;
+    use crate::App;
+
+    // the next block on `app`'s chain, before any proof of work
+    fn unmined(app: &App, data: &str) -> Block {
+        let tip = app.get_last_block();
+        Block::unmined(
+            &tip.header,
+            BlockBody::new(data.to_string(), vec![]),
+            None,
+            app.next_difficulty(),
+            Timestamp::now().max(app.min_next_timestamp()),
+        )
+    }
+
+    fn meets(block: &Block, nonce: u64, hasher: &dyn BlockHasher) -> bool {
+        let mut header = block.header.clone();
+        header.nonce = nonce;
+        header.target().is_met_by(&header.calculate_hash(hasher))
+    }
+
+    #[test]
+    fn solved_template_is_a_valid_block() {
+        let mut app: App = App::new(dev_params());
+        let hasher = app.params.hasher();
+        let block = unmined(&app, "work");
+        let nonce = (0..).find(|&nonce| meets(&block, nonce, hasher)).unwrap();
+        let mut work = WorkProvider::default();
+        let template = work.issue(block, app.params.genesis.hash_algorithm);
+
+        let solved = work.solve(template.work_id, nonce, hasher).unwrap();
+        assert_eq!(solved.header.nonce, nonce);
+        app.try_add_block(solved).unwrap();
+        assert_eq!(app.get_last_block().data.data, "work");
+    }
+
+    #[test]
+    fn wrong_nonce_leaves_the_template_outstanding() {
+        let app: App = App::new(dev_params());
+        let hasher = app.params.hasher();
+        let block = unmined(&app, "work");
+        let wrong = (0..).find(|&nonce| !meets(&block, nonce, hasher)).unwrap();
+        let right = (0..).find(|&nonce| meets(&block, nonce, hasher)).unwrap();
+        let mut work = WorkProvider::default();
+        let template = work.issue(block, app.params.genesis.hash_algorithm);
+
+        assert!(work.solve(template.work_id, wrong, hasher).is_err());
+        assert!(work.solve(template.work_id, right, hasher).is_ok());
+        // used up once solved
+        assert!(work.solve(template.work_id, right, hasher).is_err());
+    }
+
+    #[test]
+    fn oldest_template_is_evicted_past_the_limit() {
+        let app: App = App::new(dev_params());
+        let mut work = WorkProvider::default();
+        let templates: Vec<WorkTemplate> = (0..=MAX_OUTSTANDING_WORK)
+            .map(|i| work.issue(unmined(&app, &i.to_string()), app.params.genesis.hash_algorithm))
+            .collect();
+        assert_eq!(work.outstanding.len(), MAX_OUTSTANDING_WORK);
+        assert!(!work.outstanding.contains_key(&templates[0].work_id));
+        assert!(templates[1..]
+            .iter()
+            .all(|template| work.outstanding.contains_key(&template.work_id)));
+    }
+
+    #[test]
+    fn templates_off_the_tip_are_dropped() {
+        let mut app: App = App::new(dev_params());
+        let mut work = WorkProvider::default();
+        let stale = work.issue(unmined(&app, "stale"), app.params.genesis.hash_algorithm);
+        app.mine_n_blocks(1, |height| BlockBody::new(height.to_string(), vec![]))
+            .unwrap();
+        let current = work.issue(unmined(&app, "current"), app.params.genesis.hash_algorithm);
+
+        work.retain_tip(&app.get_last_block().header.hash);
+        assert!(!work.outstanding.contains_key(&stale.work_id));
+        assert!(work.outstanding.contains_key(&current.work_id));
+    }
+}